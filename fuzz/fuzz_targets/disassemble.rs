@@ -0,0 +1,30 @@
+#![no_main]
+
+use gaby_core::disassembler;
+use libfuzzer_sys::fuzz_target;
+
+// The disassembler must handle any bytes, at any address, and the lengths of
+// the instructions must add up.
+fuzz_target!(|data: &[u8]| {
+    let machine = match gaby_fuzz::machine(data) {
+        Some(machine) => machine,
+        None => return,
+    };
+    let mem = &machine.mem;
+    let pc = machine.cpu.pc();
+
+    let instructions = disassembler::disassemble_around(mem, pc, 8, 8);
+    assert!(instructions
+        .iter()
+        .any(|instruction| instruction.address == pc));
+    for pair in instructions.windows(2) {
+        assert!((1..=3).contains(&pair[0].length));
+        assert_eq!(
+            pair[0].address.wrapping_add(pair[0].length),
+            pair[1].address,
+            "{} at {:#06X}",
+            pair[0].text,
+            pair[0].address
+        );
+    }
+});
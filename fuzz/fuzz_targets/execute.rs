@@ -0,0 +1,105 @@
+#![no_main]
+
+use gaby_core::disassembler::{self, Instruction};
+use gaby_core::emulator::Emulator;
+use gaby_core::memory::IORegister;
+use libfuzzer_sys::fuzz_target;
+
+/// Instructions to run for each input.
+const MAX_INSTRUCTIONS: usize = 1000;
+
+// Run random code from a random CPU and memory state. Besides not panicking,
+// which also catches the cycle counter going below 0, every instruction must
+// move PC and SP as its disassembly says.
+fuzz_target!(|data: &[u8]| {
+    let mut machine = match gaby_fuzz::machine(data) {
+        Some(machine) => machine,
+        None => return,
+    };
+
+    for _ in 0..MAX_INSTRUCTIONS {
+        let register = |name| machine.cpu.register(name).unwrap_or_default();
+        let (pc, sp, flags) = (register("pc"), register("sp"), register("f"));
+        let instruction = disassembler::disassemble(&machine.mem, pc);
+        let (requested, enabled) = interrupts(&machine);
+
+        // Invalid and unimplemented opcodes give errors, which is fine.
+        if machine.step().is_err() {
+            return;
+        }
+        // Each step of a halted CPU takes up to a frame, which would slow
+        // down the fuzzing too much.
+        if !machine.cpu.at_instruction_start() {
+            return;
+        }
+        // An interrupt moves PC and SP too, and may be dispatched before or
+        // during the instruction if one is pending or becomes pending.
+        let pending = requested & enabled & 0x1F != 0;
+        if pending || interrupts(&machine) != (requested, enabled) {
+            continue;
+        }
+
+        let register = |name| machine.cpu.register(name).unwrap_or_default();
+        check(
+            &instruction,
+            flags,
+            (pc, sp),
+            (register("pc"), register("sp")),
+        );
+    }
+});
+
+/// Interrupts requested in IF, and enabled in IE.
+fn interrupts(machine: &Emulator) -> (u8, u8) {
+    let mem = &machine.mem;
+    (mem[IORegister::IF], mem[IORegister::IE])
+}
+
+/// Check the new PC and SP after `instruction`, which started with the flags
+/// `flags`.
+fn check(
+    instruction: &Instruction,
+    flags: u16,
+    (pc, sp): (u16, u16),
+    (new_pc, new_sp): (u16, u16),
+) {
+    let mut words = instruction.text.split([' ', ',']);
+    let mnemonic = words.next().unwrap_or_default();
+    let taken = match words.next() {
+        Some("NZ") => flags & 0x80 == 0,
+        Some("Z") => flags & 0x80 != 0,
+        Some("NC") => flags & 0x10 == 0,
+        Some("C") => flags & 0x10 != 0,
+        _ => true,
+    };
+    let context = format!("{} at {:#06X}", instruction.text, pc);
+
+    let jumps = matches!(mnemonic, "JP" | "JR" | "CALL" | "RET" | "RETI" | "RST");
+    if !jumps || !taken {
+        assert_eq!(
+            new_pc,
+            pc.wrapping_add(instruction.length),
+            "PC after {}",
+            context
+        );
+    }
+
+    let sp_change = match mnemonic {
+        "PUSH" | "RST" => Some(-2),
+        "CALL" if taken => Some(-2),
+        "POP" | "RETI" => Some(2),
+        "RET" if taken => Some(2),
+        "CALL" | "RET" => Some(0),
+        // Instructions that set SP from their operands.
+        _ if instruction.text.contains("SP") => None,
+        _ => Some(0),
+    };
+    if let Some(change) = sp_change {
+        assert_eq!(
+            new_sp,
+            sp.wrapping_add(change as u16),
+            "SP after {}",
+            context
+        );
+    }
+}
@@ -0,0 +1,45 @@
+//! Setup shared by the fuzz targets.
+
+use gaby_core::emulator::Emulator;
+use gaby_core::memory::Memory;
+use gaby_core::model::Model;
+use std::convert::TryInto;
+
+/// Number of bytes at the start of the input that set up the CPU and memory:
+/// the byte registers A, F, B, C, D, E, H and L, SP and PC, and the seed for
+/// the contents of memory.
+pub const SETUP_LENGTH: usize = 20;
+const BYTE_REGISTERS: [&str; 8] = ["a", "f", "b", "c", "d", "e", "h", "l"];
+
+/// Make a Game Boy from fuzzer input. The rest of the input after the setup
+/// bytes goes in a 32 kB ROM without an MBC, from address 0, except for the
+/// bytes of the cartridge header that give the type and sizes. Returns
+/// `None` if the input is too short.
+pub fn machine(data: &[u8]) -> Option<Emulator> {
+    if data.len() < SETUP_LENGTH {
+        return None;
+    }
+    let (setup, code) = data.split_at(SETUP_LENGTH);
+
+    let mut rom = vec![0; 0x8000];
+    let length = code.len().min(rom.len());
+    rom[..length].copy_from_slice(&code[..length]);
+    // Cartridge type, ROM size and RAM size.
+    rom[0x147..=0x149].fill(0);
+
+    let seed = u64::from_le_bytes(setup[12..20].try_into().ok()?);
+    let mut mem = Memory::with_seed(Model::DMG, seed);
+    mem.load_rom(&rom).ok()?;
+    let mut machine = Emulator::new(mem, Model::DMG);
+
+    for (name, &value) in BYTE_REGISTERS.iter().zip(setup) {
+        machine.cpu.set_register(name, value.into());
+    }
+    machine
+        .cpu
+        .set_register("sp", u16::from_le_bytes([setup[8], setup[9]]));
+    machine
+        .cpu
+        .set_register("pc", u16::from_le_bytes([setup[10], setup[11]]));
+    Some(machine)
+}
@@ -0,0 +1,543 @@
+use crate::memory::{IORegister, Memory, WriteHook};
+use crate::model::Model;
+use crate::scope::Scope;
+use crate::state::{StateReader, StateWriter};
+#[cfg(feature = "files")]
+use crate::stems::StemWriter;
+
+pub struct Audio {
+    register_writes: WriteHook,
+    output_enabled: [bool; 4],
+    length_counters: [usize; 4],
+    envelope_counters: [u8; 4],
+    envelope_values: [u8; 4],
+    /// Whether the volume envelopes are still changing the volume.
+    envelope_running: [bool; 4],
+    /// Last values written to the DAC registers, i.e. NRx2 or NR30.
+    envelope_registers: [u8; 4],
+    frequency_timers: [u16; 4],
+    waveform_positions: [usize; 4],
+    /// Samples that have not been taken by the frontend yet.
+    sample_buffer: Vec<f32>,
+    current_samples: [f32; 4],
+    /// Ticks left until the next output sample.
+    sample_timer: f64,
+    /// Output sample rate in Hz.
+    sample_rate: i32,
+    /// Charge of the high-pass filter capacitor, for each channel.
+    capacitors: [f32; 4],
+    /// How much of the capacitor charge is kept from one sample to the next.
+    charge_factor: f32,
+    /// Capacitor charge kept per 4 MiHz clock cycle, which differs between
+    /// models.
+    charge_factor_per_cycle: f64,
+    /// Ticks per output sample at the chosen sample rate.
+    nominal_ticks_per_sample: f64,
+    /// Ticks per output sample, adjusted slightly by the frontend to keep the
+    /// audio device from running dry or filling up.
+    ticks_per_sample: f64,
+    /// Writes the output of each channel to separate files, if requested.
+    #[cfg(feature = "files")]
+    pub stems: Option<StemWriter>,
+    /// Keeps the latest output of each channel for drawing, if requested.
+    pub scope: Option<Scope>,
+    /// Channels left out of the mix, e.g. to hear one channel alone. The
+    /// stems still get all of them.
+    pub muted: [bool; 4],
+    /// Bit 4 of DIV at the last tick.
+    div_bit: bool,
+    frame_step: usize,
+    /// Linear-feedback shift register of the noise channel, 15 bits wide.
+    lfsr: u16,
+}
+
+impl Audio {
+    // NR register address tables used when looping over all four channels:
+    const NRX1: [u16; 4] = [
+        IORegister::NR11,
+        IORegister::NR21,
+        IORegister::NR31,
+        IORegister::NR41,
+    ];
+    const NRX2: [u16; 4] = [
+        IORegister::NR12,
+        IORegister::NR22,
+        IORegister::NR32,
+        IORegister::NR42,
+    ];
+    const NRX3: [u16; 4] = [
+        IORegister::NR13,
+        IORegister::NR23,
+        IORegister::NR33,
+        IORegister::NR43,
+    ];
+    const NRX4: [u16; 4] = [
+        IORegister::NR14,
+        IORegister::NR24,
+        IORegister::NR34,
+        IORegister::NR44,
+    ];
+
+    /// Registers that control the DAC of each channel.
+    const DAC_REGISTERS: [u16; 4] = [
+        IORegister::NR12,
+        IORegister::NR22,
+        IORegister::NR30,
+        IORegister::NR42,
+    ];
+
+    /// Output sample rate in Hz, unless another one is chosen.
+    pub const DEFAULT_SAMPLE_RATE: i32 = 65536;
+    const TICKS_PER_SECOND: f64 = 1_048_576.0;
+
+    pub fn tick(&mut self, mem: &mut Memory) -> Result<(), String> {
+        // Handle writes to the sound registers since the last tick.
+        while let Some(event) = mem.next_write(self.register_writes) {
+            if let Some(i) = Audio::NRX4.iter().position(|&a| a == event.address) {
+                // Check if the channel is to be restarted.
+                if event.data & 0b1000_0000 != 0 {
+                    // A channel with its DAC off can't be started.
+                    self.output_enabled[i] = Audio::dac_enabled(mem, i);
+                    tracing::trace!(
+                        target: "apu",
+                        "Channel {} triggered, DAC {}",
+                        i + 1,
+                        if self.output_enabled[i] { "on" } else { "off" }
+                    );
+
+                    if self.length_counters[i] == 0 {
+                        self.length_counters[i] = if i == 2 { 256 } else { 64 };
+                    }
+
+                    self.frequency_timers[i] = 2048
+                        - u16::from_le_bytes([
+                            mem[Audio::NRX3[i]],
+                            mem[Audio::NRX4[i]] & 0b0000_0111,
+                        ]);
+                    self.envelope_counters[i] = mem[Audio::NRX2[i]] & 0b0000_0111;
+                    self.envelope_values[i] = mem[Audio::NRX2[i]] >> 4;
+                    self.envelope_running[i] = true;
+
+                    // The position is advanced before the first sample is
+                    // read, so the first sample played is sample 1, like on
+                    // the hardware.
+                    if i == 2 {
+                        self.waveform_positions[i] = 0;
+                    }
+
+                    if i == 3 {
+                        self.lfsr = 0x7FFF;
+                    }
+
+                    // TODO: Channel 1 does several things:
+                    // - Square 1's frequency is copied to the shadow register.
+                    // - The sweep timer is reloaded.
+                    // - The internal enabled flag is set if either the sweep period or shift are non-zero, cleared otherwise.
+                    // - If the sweep shift is non-zero, frequency calculation and the overflow check are performed immediately.
+                }
+            } else if let Some(i) = Audio::DAC_REGISTERS
+                .iter()
+                .position(|&a| a == event.address)
+            {
+                // Writing the envelope register of a running channel changes
+                // the volume in odd ways, known as zombie mode, that some
+                // games use to change the volume without restarting.
+                if i != 2 && self.output_enabled[i] {
+                    let old = self.envelope_registers[i];
+                    let mut volume = self.envelope_values[i];
+                    if old & 0b0000_0111 == 0 && self.envelope_running[i] {
+                        volume += 1;
+                    } else if old & 0b0000_1000 == 0 {
+                        volume += 2;
+                    }
+                    if (old ^ event.data) & 0b0000_1000 != 0 {
+                        volume = 16u8.wrapping_sub(volume);
+                    }
+                    self.envelope_values[i] = volume & 0b0000_1111;
+                }
+                self.envelope_registers[i] = event.data;
+
+                // Turning the DAC off also turns the channel off.
+                if !Audio::dac_enabled(mem, i) {
+                    self.output_enabled[i] = false;
+                }
+            } else if let Some(i) = Audio::NRX1.iter().position(|&a| a == event.address) {
+                // Load length counter.
+                if i == 2 {
+                    self.length_counters[i] = 256 - event.data as usize;
+                } else {
+                    self.length_counters[i] = 64 - (event.data & 0b0011_1111) as usize;
+                }
+            }
+        }
+
+        // 512 Hz frame sequencer for timing of lengths, volume envelopes and
+        // sweeps. It is clocked when bit 4 of DIV goes from 1 to 0, so writes
+        // to DIV affect its timing.
+        let div_bit = mem[IORegister::DIV] & 0b0001_0000 != 0;
+        let frame_sequencer_clocked = self.div_bit && !div_bit;
+        self.div_bit = div_bit;
+
+        if frame_sequencer_clocked {
+            if self.frame_step % 2 == 0 {
+                // Length counters
+                for i in 0..4 {
+                    if self.length_counters[i] == 0 {
+                        self.output_enabled[i] = false;
+                    } else {
+                        if mem[Audio::NRX4[i]] & 0b0100_0000 != 0 {
+                            self.length_counters[i] -= 1;
+                        }
+                    }
+                }
+
+                // TODO: Sweeps on steps 2 and 6.
+            } else if self.frame_step == 7 {
+                // Volume envelopes. An envelope stops for good when the
+                // volume would go out of range, until the channel is
+                // restarted.
+                for &i in &[0, 1, 3] {
+                    if self.envelope_counters[i] == 0 {
+                        let step_length = mem[Audio::NRX2[i]] & 0b0000_0111;
+                        if step_length != 0 && self.envelope_running[i] {
+                            let increase = mem[Audio::NRX2[i]] & 0b0000_1000 != 0;
+                            match (increase, self.envelope_values[i]) {
+                                (false, 0) | (true, 0xF) => self.envelope_running[i] = false,
+                                (false, volume) => self.envelope_values[i] = volume - 1,
+                                (true, volume) => self.envelope_values[i] = volume + 1,
+                            }
+                        }
+
+                        self.envelope_counters[i] = step_length;
+                    } else {
+                        self.envelope_counters[i] -= 1;
+                    }
+                }
+            }
+
+            self.frame_step = (self.frame_step + 1) % 8;
+        }
+
+        self.synthesize(mem, 1);
+
+        Ok(())
+    }
+
+    /// Run the channels for `ticks` ticks, making the output samples that are
+    /// due in them. The channels are only stepped to where each sample is
+    /// made, rather than tick by tick.
+    fn synthesize(&mut self, mem: &Memory, ticks: u32) {
+        let mut ticks_left = ticks;
+        while ticks_left > 0 {
+            // The sample is made in the tick that takes the timer to 0 or
+            // below.
+            let until_sample = self.sample_timer.ceil().max(1.0) as u32;
+            let ticks = until_sample.min(ticks_left);
+            self.step_tone_channels(mem, ticks);
+            self.step_noise(ticks);
+            self.sample_timer -= f64::from(ticks);
+            ticks_left -= ticks;
+
+            if self.sample_timer <= 0.0 {
+                self.output_sample(mem);
+                self.sample_timer += self.ticks_per_sample;
+            }
+        }
+    }
+
+    /// Mix the current samples of the channels into an output sample.
+    fn output_sample(&mut self, mem: &Memory) {
+        // A DAC that is off outputs silence, unlike a channel that is off
+        // while its DAC is on.
+        let mut channels = [0.0; 4];
+        for (i, channel) in channels.iter_mut().enumerate() {
+            if Audio::dac_enabled(mem, i) {
+                *channel = 0.05 * self.current_samples[i];
+            }
+        }
+
+        // The output goes through a capacitor that removes the DC offset,
+        // unless all DACs are off. Since the filter is linear, filtering each
+        // channel gives the same mix as filtering the mix.
+        if (0..4).any(|i| Audio::dac_enabled(mem, i)) {
+            for (channel, capacitor) in channels.iter_mut().zip(&mut self.capacitors) {
+                let input = *channel;
+                *channel = input - *capacitor;
+                *capacitor = input - *channel * self.charge_factor;
+            }
+        } else {
+            channels = [0.0; 4];
+        }
+
+        #[cfg(feature = "files")]
+        if let Some(stems) = &mut self.stems {
+            stems.write(channels.iter().sum(), channels);
+        }
+        if let Some(scope) = &mut self.scope {
+            scope.write(channels);
+        }
+
+        let mix = channels
+            .iter()
+            .zip(&self.muted)
+            .filter(|(_, &muted)| !muted)
+            .map(|(channel, _)| channel)
+            .sum();
+        self.sample_buffer.push(mix);
+    }
+
+    /// Step the frequency timers of the rectangle and wave channels `ticks`
+    /// times, moving them on to the next sample of their waveform every time
+    /// a timer runs out.
+    fn step_tone_channels(&mut self, mem: &Memory, ticks: u32) {
+        for i in 0..3 {
+            if u32::from(self.frequency_timers[i]) >= ticks {
+                self.frequency_timers[i] -= ticks as u16;
+                continue;
+            }
+
+            let frequency =
+                u16::from_le_bytes([mem[Audio::NRX3[i]], mem[Audio::NRX4[i]] & 0b0000_0111]);
+            // The wave channel steps through its samples twice as fast.
+            let period = if i == 2 {
+                (2048 - frequency) / 2
+            } else {
+                2048 - frequency
+            };
+            let steps = Audio::run_frequency_timer(&mut self.frequency_timers[i], period, ticks);
+
+            if i == 2 {
+                self.waveform_positions[i] = (self.waveform_positions[i] + steps) % 32;
+                self.current_samples[i] = self.wave_sample(mem);
+            } else {
+                self.waveform_positions[i] = (self.waveform_positions[i] + steps) % 8;
+                self.current_samples[i] = self.rectangle_sample(mem, i);
+            }
+        }
+    }
+
+    /// Run a frequency timer for `ticks` ticks, which must be more than it
+    /// has left. It counts down to 0, and is reloaded with `period` on the
+    /// tick after. Returns the number of reloads.
+    fn run_frequency_timer(timer: &mut u16, period: u16, ticks: u32) -> usize {
+        let after_reload = ticks - u32::from(*timer) - 1;
+        let cycle = u32::from(period) + 1;
+        *timer = period - (after_reload % cycle) as u16;
+        1 + (after_reload / cycle) as usize
+    }
+
+    /// Output of rectangle channel `i` at its current position in the duty
+    /// waveform.
+    fn rectangle_sample(&self, mem: &Memory, i: usize) -> f32 {
+        if !self.output_enabled[i] {
+            return -0.25;
+        }
+
+        let duty_id = (mem[Audio::NRX1[i]] & 0b1100_0000) >> 6;
+        let waveform: u8 = match duty_id {
+            0 => 0b0000_0001,
+            1 => 0b1000_0001,
+            2 => 0b1000_0111,
+            3 => 0b0111_1110,
+            _ => panic!("This should never happen!"),
+        };
+
+        0.25 - f32::from(
+            ((waveform >> self.waveform_positions[i]) & 0b0000_0001) * self.envelope_values[i],
+        ) / 30.0
+    }
+
+    /// Output of the wave channel at its current position in the wave
+    /// table.
+    fn wave_sample(&self, mem: &Memory) -> f32 {
+        let i = 2;
+        if !self.output_enabled[i] {
+            return -0.25;
+        }
+
+        let address = (0xFF30 + self.waveform_positions[i] / 2) as u16;
+        let offset = self.waveform_positions[i] % 2;
+        let val = if offset == 0 {
+            mem[address] >> 4
+        } else {
+            mem[address] & 0b0000_1111
+        };
+
+        // The output level in NR32 selects 0%, 100%, 50% or 25% volume.
+        let val = match (mem[IORegister::NR32] & 0b0110_0000) >> 5 {
+            0 => 0,
+            1 => val,
+            2 => val >> 1,
+            _ => val >> 2,
+        };
+
+        0.25 - f32::from(val) / 30.0
+    }
+
+    /// Step the noise channel `ticks` times. The LFSR is stepped every tick,
+    /// rather than at the rate set by NR43.
+    fn step_noise(&mut self, ticks: u32) {
+        self.current_samples[3] = if self.output_enabled[3] {
+            for _ in 0..ticks {
+                let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+                self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+            }
+            let y = (!self.lfsr & 1) as u8;
+
+            0.25 - f32::from(y * self.envelope_values[3]) / 30.0
+        } else {
+            -0.25
+        };
+    }
+
+    /// Number of the coming ticks that can be done at once with `skip`:
+    /// until the registers are written to, or bit 4 of DIV changes, which
+    /// clocks the frame sequencer. Since DIV comes from the timer, that is
+    /// only known once the timer has been ticked, see `Timer::idle_ticks`.
+    pub fn idle_ticks(&self, mem: &Memory) -> u32 {
+        let div_bit = mem[IORegister::DIV] & 0b0001_0000 != 0;
+        if div_bit != self.div_bit || mem.has_writes(self.register_writes) {
+            return 0;
+        }
+
+        u32::MAX
+    }
+
+    /// Do `ticks` ticks at once, making the output samples that are due in
+    /// them. There must be at least that many idle ticks, see `idle_ticks`.
+    pub fn skip(&mut self, mem: &Memory, ticks: u32) {
+        self.synthesize(mem, ticks);
+    }
+
+    /// The DAC of the wave channel is controlled by bit 7 of NR30, and those
+    /// of the other channels are on when the initial volume or the envelope
+    /// direction in NRx2 is non-zero. A channel can only be on when its DAC is.
+    fn dac_enabled(mem: &Memory, channel: usize) -> bool {
+        if channel == 2 {
+            mem[IORegister::NR30] & 0b1000_0000 != 0
+        } else {
+            mem[Audio::NRX2[channel]] & 0b1111_1000 != 0
+        }
+    }
+
+    pub fn sample_rate(&self) -> i32 {
+        self.sample_rate
+    }
+
+    fn charge_factor(charge_factor_per_cycle: f64, sample_rate: i32) -> f32 {
+        charge_factor_per_cycle.powf(4.0 * Audio::TICKS_PER_SECOND / f64::from(sample_rate)) as f32
+    }
+
+    /// Produce samples at `ratio` times the nominal sample rate. Used for
+    /// small adjustments that aren't audible as pitch changes.
+    pub fn set_rate_ratio(&mut self, ratio: f64) {
+        self.ticks_per_sample = self.nominal_ticks_per_sample / ratio;
+    }
+
+    /// Set the output sample rate in Hz.
+    pub fn set_sample_rate(&mut self, sample_rate: i32) {
+        self.sample_rate = sample_rate;
+        self.charge_factor = Audio::charge_factor(self.charge_factor_per_cycle, sample_rate);
+        self.nominal_ticks_per_sample = Audio::TICKS_PER_SECOND / f64::from(sample_rate);
+        self.ticks_per_sample = self.nominal_ticks_per_sample;
+    }
+
+    /// Take the samples produced since the last call.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.sample_buffer)
+    }
+
+    /// Move the samples produced since the last call to the end of
+    /// `samples`, keeping the buffer to produce the next ones in.
+    pub fn drain_samples(&mut self, samples: &mut Vec<f32>) {
+        samples.append(&mut self.sample_buffer);
+    }
+
+    pub fn new(mem: &mut Memory) -> Self {
+        let register_writes = mem.add_write_hook(IORegister::NR10..=IORegister::NR52);
+        let envelope_registers = Audio::DAC_REGISTERS.map(|address| mem[address]);
+        let charge_factor_per_cycle = if mem.model == Model::CGB {
+            0.998943
+        } else {
+            0.999958
+        };
+
+        // Start from the current state of DIV, so that the first clock of
+        // the frame sequencer isn't missed or doubled.
+        let div_bit = mem[IORegister::DIV] & 0b0001_0000 != 0;
+
+        Self {
+            register_writes,
+            frequency_timers: [0; 4],
+            length_counters: [0; 4],
+            envelope_counters: [0; 4],
+            envelope_values: [0; 4],
+            envelope_running: [false; 4],
+            envelope_registers,
+            output_enabled: [false; 4],
+            waveform_positions: [0; 4],
+            sample_buffer: Vec::new(),
+            current_samples: [0.0; 4],
+            sample_timer: 0.0,
+            sample_rate: Audio::DEFAULT_SAMPLE_RATE,
+            capacitors: [0.0; 4],
+            charge_factor: Audio::charge_factor(
+                charge_factor_per_cycle,
+                Audio::DEFAULT_SAMPLE_RATE,
+            ),
+            charge_factor_per_cycle,
+            #[cfg(feature = "files")]
+            stems: None,
+            scope: None,
+            muted: [false; 4],
+            nominal_ticks_per_sample: Audio::TICKS_PER_SECOND
+                / f64::from(Audio::DEFAULT_SAMPLE_RATE),
+            ticks_per_sample: Audio::TICKS_PER_SECOND / f64::from(Audio::DEFAULT_SAMPLE_RATE),
+            frame_step: 0,
+            div_bit,
+            lfsr: 0x7FFF,
+        }
+    }
+
+    /// Save the state of the channels and the frame sequencer. Samples that
+    /// haven't been taken are not saved.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for channel in 0..4 {
+            state.bool(self.output_enabled[channel]);
+            state.u32(self.length_counters[channel] as u32);
+            state.u8(self.envelope_counters[channel]);
+            state.u8(self.envelope_values[channel]);
+            state.bool(self.envelope_running[channel]);
+            state.u8(self.envelope_registers[channel]);
+            state.u16(self.frequency_timers[channel]);
+            state.u32(self.waveform_positions[channel] as u32);
+            state.f32(self.current_samples[channel]);
+            state.f32(self.capacitors[channel]);
+        }
+        state.f64(self.sample_timer);
+        state.bool(self.div_bit);
+        state.u8(self.frame_step as u8);
+        state.u16(self.lfsr);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        for channel in 0..4 {
+            self.output_enabled[channel] = state.bool()?;
+            self.length_counters[channel] = state.u32()? as usize;
+            self.envelope_counters[channel] = state.u8()?;
+            self.envelope_values[channel] = state.u8()?;
+            self.envelope_running[channel] = state.bool()?;
+            self.envelope_registers[channel] = state.u8()?;
+            self.frequency_timers[channel] = state.u16()?;
+            self.waveform_positions[channel] = state.u32()? as usize;
+            self.current_samples[channel] = state.f32()?;
+            self.capacitors[channel] = state.f32()?;
+        }
+        self.sample_timer = state.f64()?;
+        self.div_bit = state.bool()?;
+        self.frame_step = usize::from(state.u8()?);
+        self.lfsr = state.u16()?;
+
+        Ok(())
+    }
+}
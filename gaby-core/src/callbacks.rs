@@ -0,0 +1,74 @@
+//! Functions that programs embedding the core register to be called when
+//! something happens in the Game Boy, instead of polling its state after
+//! every frame. Like the event hooks, they are carried by the memory, so that
+//! the subsystems can call them where it happens.
+//!
+//! The callbacks run in the middle of a frame, so they only get the values
+//! passed to them, and must be `Send` like the rest of the emulator.
+
+/// A registered function taking a `T`, if there is one.
+type Callback<T> = Option<Box<dyn FnMut(T) + Send>>;
+
+/// The registered callbacks. None are registered by default.
+#[derive(Default)]
+pub struct Callbacks {
+    vblank: Callback<u64>,
+    serial_byte: Callback<u8>,
+    breakpoint: Callback<u16>,
+    sram_write: Option<Box<dyn FnMut(u16, u8) + Send>>,
+}
+
+impl Callbacks {
+    /// Call `callback` with the number of the frame when the video system
+    /// enters VBlank, i.e. when the frame is complete.
+    pub fn on_vblank(&mut self, callback: impl FnMut(u64) + Send + 'static) {
+        self.vblank = Some(Box::new(callback));
+    }
+
+    /// Call `callback` with the byte sent by the Game Boy in each serial
+    /// transfer, clocked by either side.
+    pub fn on_serial_byte(&mut self, callback: impl FnMut(u8) + Send + 'static) {
+        self.serial_byte = Some(Box::new(callback));
+    }
+
+    /// Call `callback` with the address of the instruction when the CPU
+    /// reaches one of the breakpoints of the emulator.
+    pub fn on_breakpoint(&mut self, callback: impl FnMut(u16) + Send + 'static) {
+        self.breakpoint = Some(Box::new(callback));
+    }
+
+    /// Call `callback` with the address and the byte when a byte of the
+    /// cartridge RAM is written, e.g. to know when to save it.
+    pub fn on_sram_write(&mut self, callback: impl FnMut(u16, u8) + Send + 'static) {
+        self.sram_write = Some(Box::new(callback));
+    }
+
+    /// Remove all the callbacks.
+    pub fn clear(&mut self) {
+        *self = Callbacks::default();
+    }
+
+    pub(crate) fn vblank(&mut self, frame: u64) {
+        if let Some(callback) = &mut self.vblank {
+            callback(frame);
+        }
+    }
+
+    pub(crate) fn serial_byte(&mut self, byte: u8) {
+        if let Some(callback) = &mut self.serial_byte {
+            callback(byte);
+        }
+    }
+
+    pub(crate) fn breakpoint(&mut self, address: u16) {
+        if let Some(callback) = &mut self.breakpoint {
+            callback(address);
+        }
+    }
+
+    pub(crate) fn sram_write(&mut self, address: u16, data: u8) {
+        if let Some(callback) = &mut self.sram_write {
+            callback(address, data);
+        }
+    }
+}
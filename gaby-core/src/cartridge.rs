@@ -0,0 +1,574 @@
+use crate::eeprom::Eeprom;
+use crate::state::{StateReader, StateWriter};
+
+/// Game Boy cartridge, consisting of ROM, optional external RAM and a memory
+/// bank controller (MBC) that maps banks of these into the address space.
+pub struct Cartridge {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mbc: MBC,
+    /// Size in bytes of the ROM file, before any padding.
+    rom_len: usize,
+    /// Whether RAM has been written to since the last `take_ram_dirty`.
+    ram_dirty: bool,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+enum MBC {
+    /// 32 kB ROM without banking.
+    RomOnly,
+    MBC1(MBC1),
+    MBC7(MBC7),
+}
+
+struct MBC1 {
+    ram_enabled: bool,
+    /// 5-bit ROM bank register (BANK1).
+    rom_bank: u8,
+    /// 2-bit register used for upper ROM bank bits or RAM bank (BANK2).
+    upper_bank: u8,
+    /// Banking mode. In advanced mode, BANK2 also applies to 0x0000-0x3FFF
+    /// and to RAM.
+    advanced_mode: bool,
+    /// Multicart wiring (MBC1M), where BANK2 is shifted in above a 4-bit
+    /// BANK1 so that each game sees its own 256 kB.
+    multicart: bool,
+}
+
+/// MBC7, which has a two-axis accelerometer and a serial EEPROM instead of
+/// RAM, as used by Kirby Tilt 'n' Tumble.
+struct MBC7 {
+    /// The accelerometer and EEPROM registers are only mapped in after
+    /// writing 0x0A to 0x0000-0x1FFF and 0x40 to 0x4000-0x5FFF.
+    ram_enabled: bool,
+    registers_enabled: bool,
+    rom_bank: u8,
+    /// Tilt in g set by the frontend, see `Cartridge::set_tilt`.
+    tilt: (f32, f32),
+    /// Accelerometer values latched by writing 0x55 and then 0xAA.
+    latched: (u16, u16),
+    /// Whether the latch has been erased with 0x55, so 0xAA latches new
+    /// values.
+    latch_erased: bool,
+    eeprom: Eeprom,
+}
+
+impl Cartridge {
+    const ROM_BANK_SIZE: usize = 0x4000;
+    const RAM_BANK_SIZE: usize = 0x2000;
+    const MIN_ROM_SIZE: usize = 0x8000;
+    const HEADER_END: usize = 0x0150;
+    const LOGO: std::ops::Range<usize> = 0x0104..0x0134;
+
+    /// Parse the cartridge header and set up the memory bank controller.
+    pub fn new(rom: &[u8]) -> Result<Self, String> {
+        if rom.len() < Cartridge::HEADER_END {
+            return Err("ROM file is too small to contain a cartridge header.".into());
+        }
+
+        let mut padded_rom = rom.to_vec();
+
+        // Small ROM chips don't decode all address lines, so a power-of-two
+        // sized ROM is mirrored. Other sizes are padded as open bus.
+        for address in rom.len()..Cartridge::MIN_ROM_SIZE {
+            padded_rom.push(if rom.len().is_power_of_two() {
+                rom[address % rom.len()]
+            } else {
+                0xFF
+            });
+        }
+
+        let mut cartridge = Self {
+            rom: padded_rom,
+            ram: Vec::new(),
+            mbc: MBC::RomOnly,
+            rom_len: rom.len(),
+            ram_dirty: false,
+        };
+
+        match cartridge.cartridge_type() {
+            0x00 => {
+                if cartridge.rom_size_code() != 0 {
+                    return Err("Only 32 kB ROMs are supported without an MBC.".into());
+                }
+            }
+            0x01..=0x03 => {
+                cartridge.mbc = MBC::MBC1(MBC1 {
+                    ram_enabled: false,
+                    rom_bank: 1,
+                    upper_bank: 0,
+                    advanced_mode: false,
+                    multicart: cartridge.is_multicart(),
+                });
+            }
+            0x22 => {
+                cartridge.mbc = MBC::MBC7(MBC7 {
+                    ram_enabled: false,
+                    registers_enabled: false,
+                    rom_bank: 1,
+                    tilt: (0.0, 0.0),
+                    latched: (MBC7::CENTER, MBC7::CENTER),
+                    latch_erased: false,
+                    eeprom: Eeprom::new(),
+                });
+            }
+            cartridge_type => {
+                return Err(format!(
+                "Unsupported cartridge type {:#04X}. Supported types are ROM only, MBC1 and MBC7.",
+                cartridge_type
+            ))
+            }
+        }
+
+        if let MBC::MBC7(_) = cartridge.mbc {
+            // An erased EEPROM reads as all ones.
+            cartridge.ram = vec![0xFF; Eeprom::SIZE];
+        } else if cartridge.has_ram() {
+            cartridge.ram = vec![0; cartridge.ram_size()];
+        }
+
+        Ok(cartridge)
+    }
+
+    /// A cartridge without ROM, as when no cartridge is inserted.
+    pub fn empty() -> Self {
+        Self {
+            rom: vec![0xFF; Cartridge::MIN_ROM_SIZE],
+            ram: Vec::new(),
+            mbc: MBC::RomOnly,
+            rom_len: 0,
+            ram_dirty: false,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        let mut title = String::new();
+        let bytes = &self.rom[0x0134..=0x0142];
+        for byte in bytes {
+            if *byte != 0 {
+                title.push(char::from(*byte));
+            }
+        }
+
+        title.trim().into()
+    }
+
+    /// Sum of the title bytes, used by the CGB boot ROM to pick a palette.
+    pub fn title_checksum(&self) -> u8 {
+        self.rom[0x0134..=0x0143]
+            .iter()
+            .fold(0, |sum, byte| sum.wrapping_add(*byte))
+    }
+
+    /// Whether the licensee in the header is Nintendo. The CGB boot ROM only
+    /// colorizes these games.
+    pub fn is_licensed_by_nintendo(&self) -> bool {
+        match self.rom[0x014B] {
+            0x01 => true,
+            0x33 => &self.rom[0x0144..=0x0145] == b"01",
+            _ => false,
+        }
+    }
+
+    /// Whether the CGB flag in the header says that the game only runs on a
+    /// Game Boy Color. Games that also run on the original Game Boy have
+    /// 0x80 there instead.
+    pub fn is_cgb_only(&self) -> bool {
+        self.rom[0x0143] == 0xC0
+    }
+
+    fn cartridge_type(&self) -> u8 {
+        self.rom[0x0147]
+    }
+
+    fn rom_size_code(&self) -> u8 {
+        self.rom[0x0148]
+    }
+
+    /// Size of the ROM given by the header, or `None` for a code that isn't
+    /// documented, as in a corrupt dump.
+    fn header_rom_size(&self) -> Option<usize> {
+        match self.rom_size_code() {
+            code @ 0x00..=0x08 => Some(Cartridge::MIN_ROM_SIZE << code),
+            // Odd sizes used by a few games, in 16 kB banks.
+            0x52 => Some(72 * Cartridge::ROM_BANK_SIZE),
+            0x53 => Some(80 * Cartridge::ROM_BANK_SIZE),
+            0x54 => Some(96 * Cartridge::ROM_BANK_SIZE),
+            _ => None,
+        }
+    }
+
+    fn has_ram(&self) -> bool {
+        matches!(self.cartridge_type(), 0x02 | 0x03)
+    }
+
+    /// Whether the RAM is kept powered by a battery when the Game Boy is off.
+    /// The EEPROM of MBC7 cartridges keeps its contents without one, but is
+    /// saved the same way.
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type(), 0x03 | 0x22) && !self.ram.is_empty()
+    }
+
+    /// Whether the cartridge has an accelerometer, which is controlled with
+    /// `set_tilt`.
+    pub fn has_tilt_sensor(&self) -> bool {
+        matches!(self.mbc, MBC::MBC7(_))
+    }
+
+    /// Tilt the Game Boy by `x` g to the right and `y` g towards the player,
+    /// i.e. with the bottom of the screen down, as measured by the
+    /// accelerometer of an MBC7 cartridge. Other cartridges ignore it.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        if let MBC::MBC7(mbc) = &mut self.mbc {
+            mbc.tilt = (x, y);
+        }
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// RAM contents, for frontends that load and save it themselves. The
+    /// RAM is allocated when the ROM is loaded, and never moves after that.
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    /// Restore RAM contents, e.g. from a save file. Data beyond the RAM size
+    /// is ignored.
+    pub fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    /// Check whether RAM has been written to since the last call.
+    pub fn take_ram_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.ram_dirty, false)
+    }
+
+    fn ram_size(&self) -> usize {
+        match self.rom[0x0149] {
+            0x01 => 0x0800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        }
+    }
+
+    /// MBC1 multicarts have no marking in the header. They are 1 MB and
+    /// contain several games of 256 kB each, so a second copy of the Nintendo
+    /// logo at the start of bank 0x10 gives them away.
+    fn is_multicart(&self) -> bool {
+        const SECOND_GAME: usize = 0x10 * Cartridge::ROM_BANK_SIZE;
+
+        self.rom.len() == 0x100000
+            && self.rom[Cartridge::LOGO] == self.rom[SECOND_GAME + 0x0104..SECOND_GAME + 0x0134]
+    }
+
+    /// Compare the size of the ROM file with the ROM size given in the header.
+    pub fn verify_rom_size(&self) -> Option<String> {
+        let header_size = match self.header_rom_size() {
+            Some(size) => size,
+            None => {
+                return Some(format!(
+                    "Unknown ROM size code {:#04X} in the header, so the size of the ROM file can't be checked.",
+                    self.rom_size_code()
+                ))
+            }
+        };
+        if self.rom_len != header_size {
+            Some(format!(
+                "ROM file is {} bytes, but the header says {} bytes.",
+                self.rom_len, header_size
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Compare the header and global checksums stored in the cartridge header
+    /// with the ones computed from the ROM data. Returns a description of each
+    /// mismatch.
+    pub fn verify_checksums(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        let header_checksum = self.compute_header_checksum();
+        if header_checksum != self.rom[0x014D] {
+            errors.push(format!(
+                "Header checksum mismatch: header says {:#04X}, computed {:#04X}.",
+                self.rom[0x014D], header_checksum
+            ));
+        }
+
+        let global_checksum = self.compute_global_checksum();
+        let stored_global_checksum = u16::from_be_bytes([self.rom[0x014E], self.rom[0x014F]]);
+        if global_checksum != stored_global_checksum {
+            errors.push(format!(
+                "Global checksum mismatch: header says {:#06X}, computed {:#06X}.",
+                stored_global_checksum, global_checksum
+            ));
+        }
+
+        errors
+    }
+
+    /// The header checksum covers 0x0134-0x014C and is verified by the boot ROM.
+    fn compute_header_checksum(&self) -> u8 {
+        self.rom[0x0134..=0x014C]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1))
+    }
+
+    /// The global checksum is the sum of all ROM bytes, except the two checksum
+    /// bytes themselves. It is not verified by the hardware.
+    fn compute_global_checksum(&self) -> u16 {
+        self.rom[..self.rom_len]
+            .iter()
+            .enumerate()
+            .filter(|(address, _)| *address != 0x014E && *address != 0x014F)
+            .fold(0u16, |sum, (_, byte)| sum.wrapping_add(u16::from(*byte)))
+    }
+
+    /// Read from the ROM area, 0x0000-0x7FFF.
+    pub fn read_rom(&self, address: u16) -> u8 {
+        self.rom_byte(self.rom_bank(address), address)
+    }
+
+    /// Number of the ROM bank mapped in at `address` in the ROM area, before
+    /// it wraps around the size of the ROM.
+    pub fn rom_bank(&self, address: u16) -> usize {
+        match &self.mbc {
+            MBC::RomOnly => usize::from(address >> 14),
+            MBC::MBC1(mbc) => {
+                if address < 0x4000 {
+                    mbc.lower_rom_bank()
+                } else {
+                    mbc.upper_rom_bank()
+                }
+            }
+            MBC::MBC7(mbc) => {
+                if address < 0x4000 {
+                    0
+                } else {
+                    usize::from(mbc.rom_bank)
+                }
+            }
+        }
+    }
+
+    fn rom_byte(&self, bank: usize, address: u16) -> u8 {
+        let bank_count = self.rom.len() / Cartridge::ROM_BANK_SIZE;
+        let offset = usize::from(address) % Cartridge::ROM_BANK_SIZE;
+        self.rom[(bank % bank_count) * Cartridge::ROM_BANK_SIZE + offset]
+    }
+
+    /// Write to the ROM area, which controls the memory bank controller.
+    pub fn write_rom(&mut self, address: u16, data: u8) {
+        match &mut self.mbc {
+            MBC::RomOnly => {}
+            MBC::MBC1(mbc) => match address {
+                0x0000..=0x1FFF => mbc.ram_enabled = (data & 0x0F) == 0x0A,
+                0x2000..=0x3FFF => mbc.rom_bank = data & 0b0001_1111,
+                0x4000..=0x5FFF => mbc.upper_bank = data & 0b0000_0011,
+                _ => mbc.advanced_mode = (data & 0b0000_0001) != 0,
+            },
+            MBC::MBC7(mbc) => match address {
+                0x0000..=0x1FFF => mbc.ram_enabled = (data & 0x0F) == 0x0A,
+                0x2000..=0x3FFF => mbc.rom_bank = data & 0b0111_1111,
+                0x4000..=0x5FFF => mbc.registers_enabled = data == 0x40,
+                _ => {}
+            },
+        }
+    }
+
+    /// Offset into RAM for an address in the external RAM area, 0xA000-0xBFFF.
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+
+        let bank = match &self.mbc {
+            MBC::RomOnly => 0,
+            MBC::MBC1(mbc) => {
+                if !mbc.ram_enabled {
+                    return None;
+                }
+                mbc.ram_bank()
+            }
+            // The registers are handled by `read_ram` and `write_ram`.
+            MBC::MBC7(_) => return None,
+        };
+
+        let offset = bank * Cartridge::RAM_BANK_SIZE + usize::from(address - 0xA000);
+        Some(offset % self.ram.len())
+    }
+
+    /// Read from the external RAM area. Disabled or missing RAM reads as open bus.
+    pub fn read_ram(&self, address: u16) -> u8 {
+        if let MBC::MBC7(mbc) = &self.mbc {
+            return mbc.read_register(address);
+        }
+        match self.ram_offset(address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    /// Write to the external RAM area. Returns whether a byte of RAM was
+    /// written, which it isn't when the RAM is disabled or missing.
+    pub fn write_ram(&mut self, address: u16, data: u8) -> bool {
+        if let MBC::MBC7(mbc) = &mut self.mbc {
+            let written = mbc.write_register(address, data, &mut self.ram);
+            self.ram_dirty |= written;
+            return written;
+        }
+        match self.ram_offset(address) {
+            Some(offset) => {
+                self.ram[offset] = data;
+                self.ram_dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Save the RAM and the state of the memory bank controller. The ROM is
+    /// not part of the state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.ram);
+        if let MBC::MBC1(mbc) = &self.mbc {
+            state.bool(mbc.ram_enabled);
+            state.u8(mbc.rom_bank);
+            state.u8(mbc.upper_bank);
+            state.bool(mbc.advanced_mode);
+        }
+        if let MBC::MBC7(mbc) = &self.mbc {
+            state.bool(mbc.ram_enabled);
+            state.bool(mbc.registers_enabled);
+            state.u8(mbc.rom_bank);
+            state.u16(mbc.latched.0);
+            state.u16(mbc.latched.1);
+            state.bool(mbc.latch_erased);
+            mbc.eeprom.save_state(state);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes_into(&mut self.ram)?;
+        self.ram_dirty = true;
+        if let MBC::MBC1(mbc) = &mut self.mbc {
+            mbc.ram_enabled = state.bool()?;
+            mbc.rom_bank = state.u8()?;
+            mbc.upper_bank = state.u8()?;
+            mbc.advanced_mode = state.bool()?;
+        }
+        if let MBC::MBC7(mbc) = &mut self.mbc {
+            mbc.ram_enabled = state.bool()?;
+            mbc.registers_enabled = state.bool()?;
+            mbc.rom_bank = state.u8()?;
+            mbc.latched = (state.u16()?, state.u16()?);
+            mbc.latch_erased = state.bool()?;
+            mbc.eeprom.load_state(state)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl MBC1 {
+    /// Shift of BANK2 when forming ROM bank numbers.
+    fn upper_bank_shift(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    /// ROM bank mapped to 0x0000-0x3FFF.
+    fn lower_rom_bank(&self) -> usize {
+        if self.advanced_mode {
+            usize::from(self.upper_bank << self.upper_bank_shift())
+        } else {
+            0
+        }
+    }
+
+    /// ROM bank mapped to 0x4000-0x7FFF.
+    fn upper_rom_bank(&self) -> usize {
+        // Bank 0 can't be selected here; the zero check sees all five bits of
+        // BANK1, even on multicarts where only four of them are wired.
+        let rom_bank = if self.rom_bank == 0 { 1 } else { self.rom_bank };
+        let rom_bank = if self.multicart {
+            rom_bank & 0b0000_1111
+        } else {
+            rom_bank
+        };
+
+        usize::from(self.upper_bank << self.upper_bank_shift() | rom_bank)
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.advanced_mode {
+            usize::from(self.upper_bank)
+        } else {
+            0
+        }
+    }
+}
+
+impl MBC7 {
+    /// Accelerometer value when level.
+    const CENTER: u16 = 0x81D0;
+    /// Change of the accelerometer values per g.
+    const PER_G: f32 = 112.0;
+
+    /// Read from 0xA000-0xBFFF, where bits 4-7 of the address select a
+    /// register in 0xA000-0xAFFF.
+    fn read_register(&self, address: u16) -> u8 {
+        if !(self.ram_enabled && self.registers_enabled) || address >= 0xB000 {
+            return 0xFF;
+        }
+        match address >> 4 & 0x0F {
+            0x2 => self.latched.0 as u8,
+            0x3 => (self.latched.0 >> 8) as u8,
+            0x4 => self.latched.1 as u8,
+            0x5 => (self.latched.1 >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    /// Write to 0xA000-0xBFFF. Returns whether the EEPROM contents in
+    /// `words` changed.
+    fn write_register(&mut self, address: u16, data: u8, words: &mut [u8]) -> bool {
+        if !(self.ram_enabled && self.registers_enabled) || address >= 0xB000 {
+            return false;
+        }
+        match address >> 4 & 0x0F {
+            0x0 if data == 0x55 => {
+                self.latch_erased = true;
+                self.latched = (0x8000, 0x8000);
+            }
+            0x1 if data == 0xAA && self.latch_erased => {
+                self.latch_erased = false;
+                // The X value goes down when tilting to the right.
+                self.latched = (
+                    MBC7::accelerometer_value(-self.tilt.0),
+                    MBC7::accelerometer_value(self.tilt.1),
+                );
+            }
+            0x8 => return self.eeprom.write(data, words),
+            _ => {}
+        }
+        false
+    }
+
+    fn accelerometer_value(g: f32) -> u16 {
+        let offset = (g * MBC7::PER_G).round().clamp(-4096.0, 4096.0);
+        (i32::from(MBC7::CENTER) + offset as i32) as u16
+    }
+}
@@ -1,14 +1,23 @@
+pub mod decode_cache;
 mod instructions;
 mod operands;
 mod registers;
 
+use crate::events::Event;
 use crate::memory::{IORegister, Memory};
+use crate::model::Model;
+use crate::scheduler::{self, CatchUp};
+use crate::state::{StateReader, StateWriter};
+use decode_cache::{DecodeCache, Decoded};
 use instructions::*;
 use operands::{
     ByteRegister, Immediate, Indirect, IndirectHighImmediate, IndirectImmediate, WordRegister,
 };
 use registers::{Flags, Registers};
-use std::{cell::RefCell, fmt::UpperHex, rc::Rc};
+use std::{
+    fmt::UpperHex,
+    ops::{Deref, DerefMut},
+};
 
 pub trait ReadImmediate<T: UpperHex> {
     fn immediate(&mut self) -> Immediate<T>;
@@ -32,80 +41,263 @@ pub struct CPU {
     ime: bool, // Interrupt Master Enable flag.
     mode: CPUMode,
     cycles_until_done: u32,
-    mem: Rc<RefCell<Memory>>,
     curr_instr: String,
-    pub print_instructions: bool,
+    /// Whether `LD B, B` has been executed, which test ROMs use as a
+    /// breakpoint.
+    software_breakpoint: bool,
+    /// Whether an interrupt has been dispatched, for the lockstep
+    /// comparison.
+    interrupt_dispatched: bool,
+    /// Number of interrupts serviced of each type, by their bit in IF.
+    pub interrupts_serviced: [u64; 5],
+    /// Number of instructions executed at each address, only counted when
+    /// requested since it costs time.
+    pub pc_counts: Option<Vec<u64>>,
+    /// Instructions in ROM decoded ahead, when requested. Without it, every
+    /// instruction is read from memory when it is executed.
+    pub decode_cache: Option<DecodeCache>,
+    /// Addresses of the last instructions executed, for crash reports and
+    /// trace dumps. The next one is written at `history_index` modulo the
+    /// length, which is a power of two so that it is masked off cheaply.
+    history: Box<[u16]>,
+    history_index: usize,
+    /// Whether an instruction has failed, e.g. because of an invalid opcode.
+    crashed: bool,
+}
+
+/// The CPU while it executes an instruction, with the memory it works on.
+/// The instructions are implemented on this, and reach the registers of the
+/// CPU through `Deref`.
+struct Execution<'a> {
+    cpu: &'a mut CPU,
+    mem: &'a mut Memory,
+    /// The other subsystems, when the CPU runs ahead of them.
+    devices: Option<&'a mut dyn CatchUp>,
+    /// The instruction, when it comes from the decode cache. Its opcode and
+    /// operands are taken from here instead of from memory.
+    decoded: Option<Decoded>,
+}
+
+impl Deref for Execution<'_> {
+    type Target = CPU;
+
+    fn deref(&self) -> &CPU {
+        self.cpu
+    }
 }
 
-impl ReadImmediate<u8> for CPU {
+impl DerefMut for Execution<'_> {
+    fn deref_mut(&mut self) -> &mut CPU {
+        self.cpu
+    }
+}
+
+impl ReadImmediate<u8> for Execution<'_> {
     fn immediate(&mut self) -> Immediate<u8> {
         self.cycles_until_done += 1;
-        let data = self.mem.borrow().read_byte(self.reg.pc);
-        self.reg.pc += 1;
+        let data = match &self.decoded {
+            Some(decoded) => {
+                self.record_decoded_read(self.reg.pc);
+                decoded.byte(self.reg.pc)
+            }
+            None => {
+                self.catch_up_for(self.reg.pc, 1);
+                self.mem.read_byte(self.reg.pc)
+            }
+        };
+        self.reg.pc = self.reg.pc.wrapping_add(1);
 
         Immediate(data)
     }
 }
 
-impl ReadImmediate<u16> for CPU {
+impl ReadImmediate<u16> for Execution<'_> {
     fn immediate(&mut self) -> Immediate<u16> {
         self.cycles_until_done += 2;
-        let data = self.mem.borrow().read_word(self.reg.pc);
-        self.reg.pc += 2;
+        let data = match &self.decoded {
+            Some(decoded) => {
+                self.record_decoded_read(self.reg.pc);
+                self.record_decoded_read(self.reg.pc.wrapping_add(1));
+                decoded.word(self.reg.pc)
+            }
+            None => {
+                self.catch_up_for(self.reg.pc, 2);
+                self.mem.read_word(self.reg.pc)
+            }
+        };
+        self.reg.pc = self.reg.pc.wrapping_add(2);
 
         Immediate(data)
     }
 }
 
-impl ReadMem<u8> for CPU {
+impl ReadMem<u8> for Execution<'_> {
     fn read(&mut self, address: u16) -> u8 {
         self.cycles_until_done += 1;
-        self.mem.borrow().read_byte(address)
+        self.catch_up_for(address, 1);
+        self.mem.read_byte(address)
     }
 }
 
-impl ReadMem<u16> for CPU {
+impl ReadMem<u16> for Execution<'_> {
     fn read(&mut self, address: u16) -> u16 {
         self.cycles_until_done += 2;
-        self.mem.borrow().read_word(address)
+        self.catch_up_for(address, 2);
+        self.mem.read_word(address)
     }
 }
 
-impl WriteMem<u8> for CPU {
+impl WriteMem<u8> for Execution<'_> {
     fn write(&mut self, address: u16, data: u8) {
         self.cycles_until_done += 1;
-        self.mem.borrow_mut().write_byte(address, data);
+        self.catch_up_for(address, 1);
+        self.mem.write_byte(address, data);
     }
 }
 
-impl WriteMem<u16> for CPU {
+impl WriteMem<u16> for Execution<'_> {
     fn write(&mut self, address: u16, data: u16) {
         self.cycles_until_done += 2;
-        self.mem.borrow_mut().write_word(address, data);
+        self.catch_up_for(address, 2);
+        self.mem.write_word(address, data);
+    }
+}
+
+impl Execution<'_> {
+    /// Bring the other subsystems up to this cycle, if the CPU runs ahead of
+    /// them.
+    fn catch_up(&mut self) {
+        if let Some(devices) = &mut self.devices {
+            devices.catch_up(self.mem);
+        }
+    }
+
+    /// Catch up before an access to `length` bytes at `address`, if the
+    /// other subsystems may have changed them or may need them.
+    fn catch_up_for(&mut self, address: u16, length: u16) {
+        let last = address.wrapping_add(length - 1);
+        if scheduler::SHARED.contains(&address) || scheduler::SHARED.contains(&last) {
+            self.catch_up();
+        }
+    }
+
+    /// Count a read of an instruction byte taken from the decode cache in
+    /// the heatmap, as if it had been read from memory.
+    fn record_decoded_read(&self, address: u16) {
+        if let Some(heatmap) = &self.mem.heatmap {
+            heatmap.record_read(address);
+        }
+    }
+
+    /// Corrupt OAM if `value` points into it, see `Memory::corrupt_oam`.
+    /// This depends on the row the video is scanning now.
+    fn corrupt_oam(&mut self, value: u16) {
+        if self.mem.oam_bug && (0xFE00..=0xFEFF).contains(&value) {
+            self.catch_up();
+            self.mem.corrupt_oam(value);
+        }
     }
 }
 
 impl CPU {
-    pub fn new(mem: Rc<RefCell<Memory>>) -> Self {
+    /// Number of instructions kept in the history, see `history`.
+    pub const HISTORY_LENGTH: usize = 16384;
+
+    pub fn new(model: Model) -> Self {
         Self {
-            reg: Registers::new(),
+            reg: Registers::new(model),
             ime: false,
             mode: CPUMode::Run,
             cycles_until_done: 0,
-            mem,
             curr_instr: Default::default(),
-            print_instructions: false,
+            software_breakpoint: false,
+            interrupt_dispatched: false,
+            interrupts_serviced: [0; 5],
+            pc_counts: None,
+            decode_cache: None,
+            history: vec![0; CPU::HISTORY_LENGTH].into_boxed_slice(),
+            history_index: 0,
+            crashed: false,
         }
     }
 
-    fn indirect_high_immediate(&mut self) -> IndirectHighImmediate {
-        IndirectHighImmediate(self.immediate().0)
+    pub fn tick(&mut self, mem: &mut Memory) -> Result<(), String> {
+        self.tick_with(mem, None)
     }
 
-    fn indirect_immediate(&mut self) -> IndirectImmediate {
-        IndirectImmediate(self.immediate().0)
+    /// Like `tick`, but running ahead of the other subsystems, which
+    /// `devices` catches up when the CPU needs them to be.
+    pub(crate) fn tick_ahead(
+        &mut self,
+        mem: &mut Memory,
+        devices: &mut dyn CatchUp,
+    ) -> Result<(), String> {
+        self.tick_with(mem, Some(devices))
     }
 
+    fn tick_with(
+        &mut self,
+        mem: &mut Memory,
+        devices: Option<&mut dyn CatchUp>,
+    ) -> Result<(), String> {
+        if mem.cpu_stall_cycles > 0 {
+            mem.cpu_stall_cycles -= 1;
+            return Ok(());
+        }
+
+        let mut execution = Execution {
+            cpu: self,
+            mem,
+            devices: devices.map(|devices| devices as &mut dyn CatchUp),
+            decoded: None,
+        };
+        execution.dispatch_interrupts();
+
+        match execution.mode {
+            CPUMode::Run => {
+                if execution.cycles_until_done == 0 {
+                    if let Err(error) = execution.execute() {
+                        execution.crashed = true;
+                        return Err(error);
+                    }
+                }
+                execution.cycles_until_done -= 1;
+            }
+            CPUMode::Halt => {}
+        }
+
+        Ok(())
+    }
+
+    /// Number of the coming ticks where the CPU only counts down, and that
+    /// can be done at once with `skip`: while it is stalled, finishing an
+    /// instruction or halted, and no interrupt is pending.
+    pub fn idle_ticks(&self, mem: &Memory) -> u32 {
+        if mem.cpu_stall_cycles > 0 {
+            return mem.cpu_stall_cycles;
+        }
+        let interrupt_pending = mem[IORegister::IF] & mem[IORegister::IE] & 0b0001_1111 != 0;
+
+        match self.mode {
+            // The interrupt is dispatched, or wakes the CPU up.
+            _ if interrupt_pending && (self.ime || self.halted()) => 0,
+            CPUMode::Run => self.cycles_until_done,
+            CPUMode::Halt => u32::MAX,
+        }
+    }
+
+    /// Do `ticks` ticks at once. There must be at least that many idle
+    /// ticks, see `idle_ticks`.
+    pub fn skip(&mut self, mem: &mut Memory, ticks: u32) {
+        if mem.cpu_stall_cycles > 0 {
+            mem.cpu_stall_cycles -= ticks;
+        } else if let CPUMode::Run = self.mode {
+            self.cycles_until_done -= ticks;
+        }
+    }
+}
+
+impl Execution<'_> {
     fn dispatch_interrupts(&mut self) {
         let cpu_is_halted = match self.mode {
             CPUMode::Halt => true,
@@ -113,7 +305,7 @@ impl CPU {
         };
 
         if self.ime {
-            let mut mem = self.mem.borrow_mut();
+            let mem = &mut *self.mem;
             let interrupt_handler = if (mem[IORegister::IF] & 0b0000_0001)
                 & (mem[IORegister::IE] & 0b0000_0001)
                 != 0
@@ -148,41 +340,38 @@ impl CPU {
             if let Some(address) = interrupt_handler {
                 self.ime = false;
 
-                self.reg.sp -= 2;
-                mem.write_word(self.reg.sp, self.reg.pc);
+                self.reg.sp = self.reg.sp.wrapping_sub(2);
+                self.catch_up_for(self.reg.sp, 2);
+                let (sp, pc) = (self.reg.sp, self.reg.pc);
+                self.mem.write_word(sp, pc);
 
                 self.reg.pc = address;
 
                 self.cycles_until_done += 5;
+                self.interrupt_dispatched = true;
+                self.interrupts_serviced[usize::from((address - 0x40) / 8)] += 1;
+                self.mem.events.signal(Event::Interrupt);
+                tracing::debug!(target: "cpu", "Interrupt dispatched to {:04X}", address);
 
                 if cpu_is_halted {
                     self.mode = CPUMode::Run;
                 }
             }
-        } else if cpu_is_halted {
-            let mem = self.mem.borrow();
-            if (mem[IORegister::IF] & mem[IORegister::IE] & 0b0001_1111) != 0 {
-                // An interrupt occured in halt mode with IME = 0.
-                // FIXME: HALT bug.
-                self.mode = CPUMode::Run;
-            }
+        } else if cpu_is_halted
+            && (self.mem[IORegister::IF] & self.mem[IORegister::IE] & 0b0001_1111) != 0
+        {
+            // An interrupt occured in halt mode with IME = 0.
+            // FIXME: HALT bug.
+            self.mode = CPUMode::Run;
         }
     }
 
-    pub fn tick(&mut self) -> Result<(), String> {
-        self.dispatch_interrupts();
-
-        match self.mode {
-            CPUMode::Run => {
-                if self.cycles_until_done == 0 {
-                    self.execute()?;
-                }
-                self.cycles_until_done -= 1;
-            }
-            CPUMode::Halt => {}
-        }
+    fn indirect_high_immediate(&mut self) -> IndirectHighImmediate {
+        IndirectHighImmediate(self.immediate().0)
+    }
 
-        Ok(())
+    fn indirect_immediate(&mut self) -> IndirectImmediate {
+        IndirectImmediate(self.immediate().0)
     }
 
     /// Fetch, decode and execute one instruction.
@@ -192,8 +381,16 @@ impl CPU {
         use WordRegister::*;
 
         // Fetch.
-        if self.print_instructions {
-            print!("{:04X}: ", self.reg.pc);
+        let address = self.reg.pc;
+        if let Some(counts) = &mut self.cpu.pc_counts {
+            counts[usize::from(address)] += 1;
+        }
+        self.mem.instruction_address = address;
+        let cpu = &mut *self.cpu;
+        cpu.history[cpu.history_index & (CPU::HISTORY_LENGTH - 1)] = address;
+        cpu.history_index = cpu.history_index.wrapping_add(1);
+        if let Some(cache) = &mut cpu.decode_cache {
+            self.decoded = cache.get(&self.mem.cartridge, address);
         }
         let opcode: u8 = self.immediate().0;
 
@@ -299,7 +496,12 @@ impl CPU {
                 let imm = self.immediate();
                 self.load(A, imm);
             }
-            0x40..=0x7F => self.select_load_or_halt(opcode),
+            0x40..=0x7F => {
+                if opcode == 0x40 {
+                    self.software_breakpoint = true;
+                }
+                self.select_load_or_halt(opcode)
+            }
             0x80 => self.add_byte(B),
             0x81 => self.add_byte(C),
             0x82 => self.add_byte(D),
@@ -499,21 +701,33 @@ impl CPU {
             }
             0xFF => self.restart(0x38),
 
-            _ => return Err(format!["Unimplemented opcode {:#04X}", opcode]),
+            _ => {
+                return Err(format![
+                    "Unimplemented opcode {:#04X} at address {:#06X}",
+                    opcode,
+                    self.reg.pc.wrapping_sub(1)
+                ])
+            }
         }
 
-        if self.print_instructions && opcode != 0xCB {
-            println!(
-                "[opcode {:02X}, cycles: {}] {}",
-                opcode, self.cycles_until_done, self.curr_instr
-            );
-        }
+        tracing::trace!(
+            target: "cpu",
+            opcode = %format_args!("{:02X}", opcode),
+            cycles = self.cycles_until_done,
+            "{:04X}: {}",
+            address,
+            self.curr_instr
+        );
 
         Ok(())
     }
 
     fn invalid_opcode(&self, opcode: u8) -> Result<(), String> {
-        Err(format!["Invalid opcode {:#04X}", opcode])
+        Err(format![
+            "Invalid opcode {:#04X} at address {:#06X}",
+            opcode,
+            self.reg.pc.wrapping_sub(1)
+        ])
     }
 
     fn execute_cb(&mut self) -> Result<(), String> {
@@ -593,13 +807,6 @@ impl CPU {
             0xC0..=0xFF => self.select_set_bit(opcode),
         }
 
-        if self.print_instructions {
-            println!(
-                "[opcode CB {:02X}, cycles: {}] {}",
-                opcode, self.cycles_until_done, self.curr_instr
-            );
-        }
-
         Ok(())
     }
 
@@ -709,3 +916,136 @@ impl CPU {
         }
     }
 }
+
+impl CPU {
+    /// Whether the next tick starts a new instruction, i.e. the CPU isn't
+    /// halted or in the middle of an instruction.
+    pub fn at_instruction_start(&self) -> bool {
+        matches!(self.mode, CPUMode::Run) && self.cycles_until_done == 0
+    }
+
+    /// Whether `LD B, B` has been executed since the last call.
+    pub fn take_software_breakpoint(&mut self) -> bool {
+        std::mem::take(&mut self.software_breakpoint)
+    }
+
+    /// Whether an interrupt has been dispatched since the last call.
+    pub fn take_interrupt_dispatched(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt_dispatched)
+    }
+
+    /// Addresses of the last instructions executed, oldest first, up to
+    /// `HISTORY_LENGTH` of them. The last one is the current instruction, or
+    /// the one that failed after a crash.
+    pub fn history(&self) -> Vec<u16> {
+        let start = self.history_index.saturating_sub(CPU::HISTORY_LENGTH);
+        (start..self.history_index)
+            .map(|index| self.history[index & (CPU::HISTORY_LENGTH - 1)])
+            .collect()
+    }
+
+    /// Whether an instruction has failed, e.g. because of an invalid opcode.
+    pub fn crashed(&self) -> bool {
+        self.crashed
+    }
+
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn halted(&self) -> bool {
+        matches!(self.mode, CPUMode::Halt)
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.reg.pc
+    }
+
+    /// Names of the registers that `register` knows.
+    pub const REGISTER_NAMES: [&'static str; 14] = [
+        "a", "f", "b", "c", "d", "e", "h", "l", "af", "bc", "de", "hl", "sp", "pc",
+    ];
+
+    /// Value of the register with the given name, see `REGISTER_NAMES`.
+    pub fn register(&self, name: &str) -> Option<u16> {
+        let reg = &self.reg;
+        let value = match name {
+            "a" => u16::from(reg.a),
+            "f" => u16::from(reg.f),
+            "b" => u16::from(reg.b),
+            "c" => u16::from(reg.c),
+            "d" => u16::from(reg.d),
+            "e" => u16::from(reg.e),
+            "h" => u16::from(reg.h),
+            "l" => u16::from(reg.l),
+            "af" => reg.af(),
+            "bc" => reg.bc(),
+            "de" => reg.de(),
+            "hl" => reg.hl(),
+            "sp" => reg.sp,
+            "pc" => reg.pc,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Set the register with the given name, see `REGISTER_NAMES`. Byte
+    /// registers get the low byte of `value`. Returns false if there is no
+    /// such register.
+    pub fn set_register(&mut self, name: &str, value: u16) -> bool {
+        let reg = &mut self.reg;
+        let byte = value as u8;
+        match name {
+            "a" => reg.a = byte,
+            // The low nibble of F is always 0.
+            "f" => reg.f = byte & 0xF0,
+            "b" => reg.b = byte,
+            "c" => reg.c = byte,
+            "d" => reg.d = byte,
+            "e" => reg.e = byte,
+            "h" => reg.h = byte,
+            "l" => reg.l = byte,
+            "af" => reg.set_af(value & 0xFFF0),
+            "bc" => reg.set_bc(value),
+            "de" => reg.set_de(value),
+            "hl" => reg.set_hl(value),
+            "sp" => reg.sp = value,
+            "pc" => reg.pc = value,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        let reg = &self.reg;
+        for value in [reg.a, reg.f, reg.b, reg.c, reg.d, reg.e, reg.h, reg.l] {
+            state.u8(value);
+        }
+        state.u16(reg.sp);
+        state.u16(reg.pc);
+        state.bool(self.ime);
+        state.bool(matches!(self.mode, CPUMode::Halt));
+        state.u32(self.cycles_until_done);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let reg = &mut self.reg;
+        for value in [
+            &mut reg.a, &mut reg.f, &mut reg.b, &mut reg.c, &mut reg.d, &mut reg.e, &mut reg.h,
+            &mut reg.l,
+        ] {
+            *value = state.u8()?;
+        }
+        reg.sp = state.u16()?;
+        reg.pc = state.u16()?;
+        self.ime = state.bool()?;
+        self.mode = if state.bool()? {
+            CPUMode::Halt
+        } else {
+            CPUMode::Run
+        };
+        self.cycles_until_done = state.u32()?;
+
+        Ok(())
+    }
+}
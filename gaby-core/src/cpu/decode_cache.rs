@@ -0,0 +1,90 @@
+//! Instructions in ROM decoded ahead of executing them, so that the CPU
+//! doesn't have to read the opcode and operands of an instruction through
+//! the memory bank controller every time it executes it.
+//!
+//! Only the ROM area is cached, since it can't be written to. Code in RAM
+//! may modify itself, so it is read from memory every time. ROM banks are
+//! switched often, so instead of throwing the cache away when they are, each
+//! decoded instruction remembers the bank it was decoded from, and is
+//! decoded again when another bank is mapped in at its address.
+
+use crate::cartridge::Cartridge;
+
+/// Size of the ROM area, 0x0000-0x7FFF.
+const ROM_AREA: usize = 0x8000;
+const BANK_SIZE: usize = 0x4000;
+
+/// An instruction in ROM, with its operands.
+#[derive(Clone, Copy)]
+pub struct Decoded {
+    /// ROM bank the instruction was decoded from, see `Cartridge::rom_bank`.
+    bank: usize,
+    address: u16,
+    /// The opcode followed by the operands, and whatever follows a shorter
+    /// instruction.
+    bytes: [u8; 3],
+}
+
+impl Decoded {
+    /// Byte of the instruction at `address`.
+    pub fn byte(&self, address: u16) -> u8 {
+        self.bytes[usize::from(address.wrapping_sub(self.address))]
+    }
+
+    /// Little-endian word of the instruction at `address`.
+    pub fn word(&self, address: u16) -> u16 {
+        u16::from_le_bytes([self.byte(address), self.byte(address.wrapping_add(1))])
+    }
+}
+
+/// Decoded instructions of the ROM area, by address.
+pub struct DecodeCache {
+    instructions: Vec<Option<Decoded>>,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        DecodeCache::new()
+    }
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self {
+            instructions: vec![None; ROM_AREA],
+        }
+    }
+
+    /// The instruction at `address` in the banks mapped in now, decoding it
+    /// if it hasn't been decoded from them yet. None outside the ROM area,
+    /// and at the end of a bank.
+    pub fn get(&mut self, cartridge: &Cartridge, address: u16) -> Option<Decoded> {
+        let entry = self.instructions.get_mut(usize::from(address))?;
+        let bank = cartridge.rom_bank(address);
+        if let Some(decoded) = entry {
+            if decoded.bank == bank {
+                return Some(*decoded);
+            }
+        }
+
+        // The longest instructions are three bytes. Those that may run into
+        // the next bank are left alone, which only leaves out the last two
+        // addresses of each bank.
+        if usize::from(address) % BANK_SIZE > BANK_SIZE - 3 {
+            return None;
+        }
+
+        let mut bytes = [0; 3];
+        for (offset, byte) in (0..).zip(&mut bytes) {
+            *byte = cartridge.read_rom(address + offset);
+        }
+        let decoded = Decoded {
+            bank,
+            address,
+            bytes,
+        };
+        *entry = Some(decoded);
+
+        Some(decoded)
+    }
+}
@@ -1,6 +1,6 @@
 use crate::cpu::{
     operands::{Indirect, Source, Target, WordRegister},
-    CPUMode, Flags, ReadImmediate, CPU,
+    CPUMode, Execution, Flags, ReadImmediate, CPU,
 };
 use std::fmt::{Display, Formatter};
 
@@ -43,7 +43,7 @@ impl Display for Condition {
     }
 }
 
-impl CPU {
+impl Execution<'_> {
     /// ADC
     pub fn add_with_carry(&mut self, byte: impl Source<u8>) {
         self.curr_instr = "ADC ".to_string() + &byte.to_string();
@@ -193,7 +193,10 @@ impl CPU {
     pub fn decrement_word(&mut self, data: impl Source<u16> + Target<u16>) {
         self.curr_instr = "DEC ".to_string() + &data.to_string();
 
-        let result = data.read(self).wrapping_sub(1);
+        let value = data.read(self);
+        self.corrupt_oam(value);
+
+        let result = value.wrapping_sub(1);
         data.write(self, result);
 
         self.cycles_until_done += 1;
@@ -234,7 +237,10 @@ impl CPU {
     pub fn increment_word(&mut self, data: impl Source<u16> + Target<u16>) {
         self.curr_instr = "INC ".to_string() + &data.to_string();
 
-        let result = data.read(self).wrapping_add(1);
+        let value = data.read(self);
+        self.corrupt_oam(value);
+
+        let result = value.wrapping_add(1);
         data.write(self, result);
 
         self.cycles_until_done += 1;
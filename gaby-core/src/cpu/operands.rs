@@ -1,12 +1,12 @@
-use crate::cpu::{ReadMem, WriteMem, CPU};
+use crate::cpu::{Execution, ReadMem, WriteMem};
 use std::fmt::{Display, Formatter, UpperHex};
 
 pub trait Source<T>: ToString {
-    fn read(&self, cpu: &mut CPU) -> T;
+    fn read(&self, cpu: &mut Execution) -> T;
 }
 
 pub trait Target<T>: ToString {
-    fn write(&self, cpu: &mut CPU, data: T);
+    fn write(&self, cpu: &mut Execution, data: T);
 }
 
 pub struct Immediate<T: UpperHex>(pub T);
@@ -18,7 +18,7 @@ impl<T: UpperHex> Display for Immediate<T> {
 }
 
 impl<T: Copy + UpperHex> Source<T> for Immediate<T> {
-    fn read(&self, _cpu: &mut CPU) -> T {
+    fn read(&self, _cpu: &mut Execution) -> T {
         self.0
     }
 }
@@ -50,13 +50,13 @@ impl Display for ByteRegister {
 }
 
 impl Source<u8> for ByteRegister {
-    fn read(&self, cpu: &mut CPU) -> u8 {
+    fn read(&self, cpu: &mut Execution) -> u8 {
         cpu.reg.byte_register(self)
     }
 }
 
 impl Target<u8> for ByteRegister {
-    fn write(&self, cpu: &mut CPU, data: u8) {
+    fn write(&self, cpu: &mut Execution, data: u8) {
         cpu.reg.set_byte_register(self, data);
     }
 }
@@ -86,13 +86,13 @@ impl Display for WordRegister {
 }
 
 impl Source<u16> for WordRegister {
-    fn read(&self, cpu: &mut CPU) -> u16 {
+    fn read(&self, cpu: &mut Execution) -> u16 {
         cpu.reg.word_register(self)
     }
 }
 
 impl Target<u16> for WordRegister {
-    fn write(&self, cpu: &mut CPU, data: u16) {
+    fn write(&self, cpu: &mut Execution, data: u16) {
         cpu.reg.set_word_register(self, data);
     }
 }
@@ -106,7 +106,7 @@ pub enum Indirect {
 }
 
 impl Indirect {
-    fn address(&self, cpu: &mut CPU) -> u16 {
+    fn address(&self, cpu: &mut Execution) -> u16 {
         use Indirect::*;
         match self {
             BC => cpu.reg.word_register(&WordRegister::BC),
@@ -134,9 +134,9 @@ impl Display for Indirect {
 
 impl<T> Source<T> for Indirect
 where
-    CPU: ReadMem<T>,
+    for<'a> Execution<'a>: ReadMem<T>,
 {
-    fn read(&self, cpu: &mut CPU) -> T {
+    fn read(&self, cpu: &mut Execution) -> T {
         let address = self.address(cpu);
         cpu.read(address)
     }
@@ -144,9 +144,9 @@ where
 
 impl<T> Target<T> for Indirect
 where
-    CPU: WriteMem<T>,
+    for<'a> Execution<'a>: WriteMem<T>,
 {
-    fn write(&self, cpu: &mut CPU, data: T) {
+    fn write(&self, cpu: &mut Execution, data: T) {
         let address = self.address(cpu);
         cpu.write(address, data);
     }
@@ -181,9 +181,9 @@ impl Display for IndirectImmediate {
 
 impl<T> Source<T> for IndirectHighImmediate
 where
-    CPU: ReadMem<T>,
+    for<'a> Execution<'a>: ReadMem<T>,
 {
-    fn read(&self, cpu: &mut CPU) -> T {
+    fn read(&self, cpu: &mut Execution) -> T {
         let address = self.address();
         cpu.read(address)
     }
@@ -191,9 +191,9 @@ where
 
 impl<T> Source<T> for IndirectImmediate
 where
-    CPU: ReadMem<T>,
+    for<'a> Execution<'a>: ReadMem<T>,
 {
-    fn read(&self, cpu: &mut CPU) -> T {
+    fn read(&self, cpu: &mut Execution) -> T {
         let address = self.address();
         cpu.read(address)
     }
@@ -201,9 +201,9 @@ where
 
 impl<T> Target<T> for IndirectHighImmediate
 where
-    CPU: WriteMem<T>,
+    for<'a> Execution<'a>: WriteMem<T>,
 {
-    fn write(&self, cpu: &mut CPU, data: T) {
+    fn write(&self, cpu: &mut Execution, data: T) {
         let address = self.address();
         cpu.write(address, data)
     }
@@ -211,9 +211,9 @@ where
 
 impl<T> Target<T> for IndirectImmediate
 where
-    CPU: WriteMem<T>,
+    for<'a> Execution<'a>: WriteMem<T>,
 {
-    fn write(&self, cpu: &mut CPU, data: T) {
+    fn write(&self, cpu: &mut Execution, data: T) {
         let address = self.address();
         cpu.write(address, data)
     }
@@ -1,4 +1,5 @@
 use crate::cpu::operands::{ByteRegister, WordRegister};
+use crate::model::Model;
 use bitflags::bitflags;
 
 bitflags! {
@@ -24,16 +25,19 @@ pub struct Registers {
 }
 
 impl Registers {
-    pub fn new() -> Self {
+    /// Initialize the registers with the values the boot ROM of the given
+    /// model leaves behind.
+    pub fn new(model: Model) -> Self {
+        let [a, f, b, c, d, e, h, l] = model.cpu_registers();
         Self {
-            a: 0x01,
-            f: 0xB0,
-            b: 0x00,
-            c: 0x13,
-            d: 0x00,
-            e: 0xD8,
-            h: 0x01,
-            l: 0x4D,
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
             sp: 0xFFFE,
             pc: 0x0100,
         }
@@ -0,0 +1,169 @@
+//! Translation of machine code back to assembly, for debuggers.
+
+use crate::memory::Memory;
+
+/// One disassembled instruction.
+pub struct Instruction {
+    pub address: u16,
+    /// Number of bytes in the instruction, including the operands.
+    pub length: u16,
+    /// The instruction in assembly, e.g. `LD A, (HL)`.
+    pub text: String,
+}
+
+const BYTE_REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const WORD_REGISTERS: [&str; 4] = ["BC", "DE", "HL", "SP"];
+/// Word registers of PUSH and POP.
+const STACK_REGISTERS: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU_OPERATIONS: [&str; 8] = [
+    "ADD A,", "ADC A,", "SUB", "SBC A,", "AND", "XOR", "OR", "CP",
+];
+const CB_OPERATIONS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+/// Disassemble the instruction at `address`. Bytes that aren't valid opcodes
+/// are shown as data.
+pub fn disassemble(mem: &Memory, address: u16) -> Instruction {
+    let byte = |offset: u16| mem.read_byte(address.wrapping_add(offset));
+    let word = |offset: u16| mem.read_word(address.wrapping_add(offset));
+
+    let opcode = byte(0);
+    // The opcode is decoded from its bit fields, xxyyyzzz, where yyy is
+    // also split into ppq.
+    let x = opcode >> 6;
+    let y = usize::from((opcode >> 3) & 0b111);
+    let z = usize::from(opcode & 0b111);
+    let p = y >> 1;
+    let q = y & 1;
+    let r = BYTE_REGISTERS;
+
+    let (length, text) = match (x, z) {
+        _ if opcode == 0xCB => {
+            let opcode = byte(1);
+            let bit = (opcode >> 3) & 0b111;
+            let register = r[usize::from(opcode & 0b111)];
+            let text = match opcode >> 6 {
+                0 => format!("{} {}", CB_OPERATIONS[usize::from(bit)], register),
+                1 => format!("BIT {}, {}", bit, register),
+                2 => format!("RES {}, {}", bit, register),
+                _ => format!("SET {}, {}", bit, register),
+            };
+            (2, text)
+        }
+        (0, 0) => match y {
+            0 => (1, "NOP".to_string()),
+            1 => (3, format!("LD ({:#06X}), SP", word(1))),
+            2 => (2, "STOP".to_string()),
+            _ => {
+                let target = address.wrapping_add(2).wrapping_add(byte(1) as i8 as u16);
+                let text = if y == 3 {
+                    format!("JR {:#06X}", target)
+                } else {
+                    format!("JR {}, {:#06X}", CONDITIONS[y - 4], target)
+                };
+                (2, text)
+            }
+        },
+        (0, 1) if q == 0 => (3, format!("LD {}, {:#06X}", WORD_REGISTERS[p], word(1))),
+        (0, 1) => (1, format!("ADD HL, {}", WORD_REGISTERS[p])),
+        (0, 2) => {
+            let indirect = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            let text = if q == 0 {
+                format!("LD {}, A", indirect)
+            } else {
+                format!("LD A, {}", indirect)
+            };
+            (1, text)
+        }
+        (0, 3) if q == 0 => (1, format!("INC {}", WORD_REGISTERS[p])),
+        (0, 3) => (1, format!("DEC {}", WORD_REGISTERS[p])),
+        (0, 4) => (1, format!("INC {}", r[y])),
+        (0, 5) => (1, format!("DEC {}", r[y])),
+        (0, 6) => (2, format!("LD {}, {:#04X}", r[y], byte(1))),
+        (0, _) => {
+            let text = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"][y];
+            (1, text.to_string())
+        }
+        (1, _) if opcode == 0x76 => (1, "HALT".to_string()),
+        (1, _) => (1, format!("LD {}, {}", r[y], r[z])),
+        (2, _) => (1, format!("{} {}", ALU_OPERATIONS[y], r[z])),
+        (_, 0) => match y {
+            0..=3 => (1, format!("RET {}", CONDITIONS[y])),
+            4 => (2, format!("LDH ({:#06X}), A", 0xFF00 | u16::from(byte(1)))),
+            5 => (2, format!("ADD SP, {}", byte(1) as i8)),
+            6 => (2, format!("LDH A, ({:#06X})", 0xFF00 | u16::from(byte(1)))),
+            _ => (2, format!("LD HL, SP{:+}", byte(1) as i8)),
+        },
+        (_, 1) if q == 0 => (1, format!("POP {}", STACK_REGISTERS[p])),
+        (_, 1) => {
+            let text = ["RET", "RETI", "JP HL", "LD SP, HL"][p];
+            (1, text.to_string())
+        }
+        (_, 2) => match y {
+            0..=3 => (3, format!("JP {}, {:#06X}", CONDITIONS[y], word(1))),
+            4 => (1, "LD (0xFF00 + C), A".to_string()),
+            5 => (3, format!("LD ({:#06X}), A", word(1))),
+            6 => (1, "LD A, (0xFF00 + C)".to_string()),
+            _ => (3, format!("LD A, ({:#06X})", word(1))),
+        },
+        (_, 3) => match y {
+            0 => (3, format!("JP {:#06X}", word(1))),
+            6 => (1, "DI".to_string()),
+            7 => (1, "EI".to_string()),
+            _ => invalid(opcode),
+        },
+        (_, 4) if y < 4 => (3, format!("CALL {}, {:#06X}", CONDITIONS[y], word(1))),
+        (_, 5) if q == 0 => (1, format!("PUSH {}", STACK_REGISTERS[p])),
+        (_, 5) if y == 1 => (3, format!("CALL {:#06X}", word(1))),
+        (_, 6) => (2, format!("{} {:#04X}", ALU_OPERATIONS[y], byte(1))),
+        (_, 7) => (1, format!("RST {:#04X}", y * 8)),
+        _ => invalid(opcode),
+    };
+
+    Instruction {
+        address,
+        length,
+        text,
+    }
+}
+
+/// Disassemble `before` instructions before `address`, the instruction at
+/// it, and `after` instructions after it. Since instructions have different
+/// lengths, the ones before are a guess: they are decoded from the furthest
+/// address that leads up to `address`.
+pub fn disassemble_around(
+    mem: &Memory,
+    address: u16,
+    before: usize,
+    after: usize,
+) -> Vec<Instruction> {
+    // Instructions are at most 3 bytes long.
+    let furthest = (3 * before as u16).min(address);
+    let mut instructions = Vec::new();
+    for start in (0..=furthest).rev().map(|distance| address - distance) {
+        let mut next = u32::from(start);
+        instructions.clear();
+        while next < u32::from(address) {
+            let instruction = disassemble(mem, next as u16);
+            next += u32::from(instruction.length);
+            instructions.push(instruction);
+        }
+        if next == u32::from(address) {
+            break;
+        }
+    }
+    let skip = instructions.len().saturating_sub(before);
+    instructions.drain(..skip);
+
+    let mut next = address;
+    for _ in 0..=after {
+        let instruction = disassemble(mem, next);
+        next = next.wrapping_add(instruction.length);
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+fn invalid(opcode: u8) -> (u16, String) {
+    (1, format!("DB {:#04X}", opcode))
+}
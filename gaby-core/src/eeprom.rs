@@ -0,0 +1,256 @@
+//! The 93LC56 serial EEPROM of MBC7 cartridges, which holds the save data
+//! instead of RAM. The game drives its chip select, clock and data in pins
+//! through a register, and reads its data out pin back.
+//!
+//! The EEPROM has 128 words of 16 bits, kept in the cartridge RAM as bytes
+//! with the low byte first, so they are saved like RAM.
+
+use crate::state::{StateReader, StateWriter};
+
+/// Number of bits in a command after the start bit: two for the opcode and
+/// eight for the address, of which the highest isn't used.
+const COMMAND_BITS: u8 = 10;
+
+/// What the EEPROM expects on the next rising clock edge.
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    /// Waiting for a start bit, a 1 on data in.
+    Idle,
+    /// Shifting in the opcode and address.
+    Command,
+    /// Shifting in the 16 bits to write, to the address, or to all of them
+    /// if there is none.
+    Data(Option<u8>),
+    /// Shifting out the word at the address, followed by the next ones.
+    Read(u8),
+}
+
+pub(crate) struct Eeprom {
+    phase: Phase,
+    /// Bits shifted in, or still to be shifted out in a read.
+    shift: u16,
+    /// Number of bits shifted in or out in this phase.
+    bits: u8,
+    select: bool,
+    clock: bool,
+    data_in: bool,
+    data_out: bool,
+    /// Whether writes and erases are allowed, after an EWEN command.
+    write_enabled: bool,
+}
+
+impl Eeprom {
+    /// Size of the contents in bytes.
+    pub const SIZE: usize = 0x100;
+
+    pub fn new() -> Self {
+        Self {
+            phase: Phase::Idle,
+            shift: 0,
+            bits: 0,
+            select: false,
+            clock: false,
+            data_in: false,
+            data_out: true,
+            write_enabled: false,
+        }
+    }
+
+    /// The pins as read from the register: chip select in bit 7, clock in
+    /// bit 6, data in in bit 1 and data out in bit 0.
+    pub fn read(&self) -> u8 {
+        u8::from(self.select) << 7
+            | u8::from(self.clock) << 6
+            | u8::from(self.data_in) << 1
+            | u8::from(self.data_out)
+    }
+
+    /// Set the pins from a write to the register. Returns whether `words`
+    /// was changed.
+    pub fn write(&mut self, data: u8, words: &mut [u8]) -> bool {
+        let select = data & 0x80 != 0;
+        let clock = data & 0x40 != 0;
+        self.data_in = data & 0x02 != 0;
+
+        let mut written = false;
+        if !select {
+            // Deselecting the chip aborts the command.
+            self.phase = Phase::Idle;
+        } else if clock && !self.clock {
+            written = self.clock_in(words);
+        }
+        self.select = select;
+        self.clock = clock;
+
+        written
+    }
+
+    /// Handle a rising clock edge. Returns whether `words` was changed.
+    fn clock_in(&mut self, words: &mut [u8]) -> bool {
+        let bit = u16::from(self.data_in);
+        match self.phase {
+            Phase::Idle => {
+                if self.data_in {
+                    self.phase = Phase::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+                false
+            }
+            Phase::Command => {
+                self.shift = self.shift << 1 | bit;
+                self.bits += 1;
+                if self.bits >= COMMAND_BITS {
+                    self.bits = 0;
+                    self.execute(words)
+                } else {
+                    false
+                }
+            }
+            Phase::Data(address) => {
+                self.shift = self.shift << 1 | bit;
+                self.bits += 1;
+                if self.bits < 16 {
+                    return false;
+                }
+                self.phase = Phase::Idle;
+                self.data_out = true;
+                match address {
+                    Some(address) => self.store(words, address, self.shift),
+                    None => self.store_all(words, self.shift),
+                }
+            }
+            Phase::Read(address) => {
+                self.data_out = self.shift & 0x8000 != 0;
+                self.shift <<= 1;
+                self.bits += 1;
+                if self.bits >= 16 {
+                    // Reading goes on with the next word.
+                    let next = (address + 1) & 0x7F;
+                    self.phase = Phase::Read(next);
+                    self.shift = Eeprom::word(words, next);
+                    self.bits = 0;
+                }
+                false
+            }
+        }
+    }
+
+    /// Run the command that has been shifted in.
+    fn execute(&mut self, words: &mut [u8]) -> bool {
+        let opcode = self.shift >> 8 & 0b11;
+        let address = (self.shift & 0x7F) as u8;
+        self.phase = Phase::Idle;
+        match opcode {
+            // READ, which starts with a dummy 0.
+            0b10 => {
+                self.phase = Phase::Read(address);
+                self.shift = Eeprom::word(words, address);
+                self.data_out = false;
+                false
+            }
+            // WRITE
+            0b01 => {
+                self.phase = Phase::Data(Some(address));
+                false
+            }
+            // ERASE
+            0b11 => {
+                self.data_out = true;
+                self.store(words, address, 0xFFFF)
+            }
+            // The other commands are told apart by the two highest address
+            // bits.
+            _ => match self.shift >> 6 & 0b11 {
+                // EWDS
+                0b00 => {
+                    self.write_enabled = false;
+                    false
+                }
+                // WRAL
+                0b01 => {
+                    self.phase = Phase::Data(None);
+                    false
+                }
+                // ERAL
+                0b10 => {
+                    self.data_out = true;
+                    self.store_all(words, 0xFFFF)
+                }
+                // EWEN
+                _ => {
+                    self.write_enabled = true;
+                    false
+                }
+            },
+        }
+    }
+
+    fn word(words: &[u8], address: u8) -> u16 {
+        let offset = usize::from(address) * 2;
+        u16::from_le_bytes([words[offset], words[offset + 1]])
+    }
+
+    /// Write a word, unless writes are disabled. Returns whether it was
+    /// written.
+    fn store(&self, words: &mut [u8], address: u8, word: u16) -> bool {
+        if !self.write_enabled {
+            return false;
+        }
+        let offset = usize::from(address) * 2;
+        words[offset..offset + 2].copy_from_slice(&word.to_le_bytes());
+        true
+    }
+
+    /// Write a word to every address, unless writes are disabled. Returns
+    /// whether they were written.
+    fn store_all(&self, words: &mut [u8], word: u16) -> bool {
+        (0..0x80).all(|address| self.store(words, address, word))
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        let (phase, address) = match self.phase {
+            Phase::Idle => (0, 0),
+            Phase::Command => (1, 0),
+            Phase::Data(Some(address)) => (2, address),
+            Phase::Data(None) => (3, 0),
+            Phase::Read(address) => (4, address),
+        };
+        state.u8(phase);
+        state.u8(address);
+        state.u16(self.shift);
+        state.u8(self.bits);
+        state.bool(self.select);
+        state.bool(self.clock);
+        state.bool(self.data_in);
+        state.bool(self.data_out);
+        state.bool(self.write_enabled);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let phase = state.u8()?;
+        let address = state.u8()? & 0x7F;
+        self.phase = match phase {
+            0 => Phase::Idle,
+            1 => Phase::Command,
+            2 => Phase::Data(Some(address)),
+            3 => Phase::Data(None),
+            4 => Phase::Read(address),
+            _ => return Err(format!("Invalid EEPROM phase {} in the state.", phase)),
+        };
+        self.shift = state.u16()?;
+        self.bits = state.u8()?;
+        if self.bits > 16 {
+            return Err(format!(
+                "Invalid EEPROM bit count {} in the state.",
+                self.bits
+            ));
+        }
+        self.select = state.bool()?;
+        self.clock = state.bool()?;
+        self.data_in = state.bool()?;
+        self.data_out = state.bool()?;
+        self.write_enabled = state.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,418 @@
+use crate::audio::Audio;
+use crate::cpu::CPU;
+use crate::events::Event;
+use crate::joypad::{Buttons, Joypad};
+use crate::memory::{IORegister, Memory};
+use crate::model::Model;
+use crate::profile::{FrameTimes, Stopwatch};
+use crate::scheduler::Devices;
+use crate::serial::Serial;
+use crate::state::{self, StateReader, StateWriter};
+use crate::timer::Timer;
+use crate::video::Video;
+use crate::watch::Watches;
+use std::{collections::BTreeSet, error::Error};
+
+/// The emulated Game Boy, independent of any frontend. Video is rendered to
+/// the framebuffer of `video`, and audio to the sample buffer of `audio`.
+///
+/// The emulator owns the memory, and lends it to each subsystem when it
+/// ticks.
+pub struct Emulator {
+    /// Boxed, since it is large and the emulator is moved around.
+    pub mem: Box<Memory>,
+    pub cpu: CPU,
+    pub video: Video,
+    pub audio: Audio,
+    pub timer: Timer,
+    pub serial: Serial,
+    pub joypad: Joypad,
+    /// Addresses of instructions where a frame stops before they are
+    /// executed.
+    pub breakpoints: BTreeSet<u16>,
+    breakpoint_hit: bool,
+    /// The event that stopped the last frame, if one did. The events to stop
+    /// at are subscribed to in `mem.events`.
+    event_hit: Option<Event>,
+    /// Values in memory whose changes are reported to the debugger.
+    pub watches: Watches,
+    /// Time spent in the subsystems during the last frame, only measured
+    /// when requested since it costs time. Set it to start measuring.
+    pub frame_times: Option<FrameTimes>,
+}
+
+// The emulator owns all of its state, so that it can be moved to another
+// thread.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Emulator>();
+};
+
+impl Emulator {
+    /// Number of ticks in one frame, i.e. 154 lines of 114 machine cycles.
+    pub const TICKS_PER_FRAME: u32 = 17556;
+    /// Frame rate of the Game Boy, about 59.7275 Hz, with 1048576 ticks per
+    /// second.
+    pub const FRAMES_PER_SECOND: f64 = 1_048_576.0 / Emulator::TICKS_PER_FRAME as f64;
+    /// Start of every save state, with a version number.
+    const STATE_MAGIC: &'static [u8] = b"GABY STATE 2";
+
+    pub fn new(mut mem: Memory, model: Model) -> Self {
+        Self {
+            cpu: CPU::new(model),
+            video: Video::new(&mut mem),
+            audio: Audio::new(&mut mem),
+            timer: Timer::new(&mut mem),
+            serial: Serial::new(&mut mem),
+            joypad: Joypad::new(),
+            breakpoints: BTreeSet::new(),
+            breakpoint_hit: false,
+            event_hit: None,
+            watches: Watches::default(),
+            frame_times: None,
+            mem: Box::new(mem),
+        }
+    }
+
+    /// Power on a Game Boy of the given model with the ROM inserted.
+    pub fn from_rom(rom: &[u8], model: Model) -> Result<Self, Box<dyn Error>> {
+        let mut mem = Memory::new(model);
+        mem.load_rom(rom)?;
+        Ok(Emulator::new(mem, model))
+    }
+
+    /// Perform one tick of the system clock.
+    pub fn tick(&mut self) -> Result<(), String> {
+        self.mem.cycles += 1;
+        let Emulator {
+            mem,
+            cpu,
+            timer,
+            video,
+            audio,
+            serial,
+            ..
+        } = self;
+        Devices::new(mem, timer, video, audio, serial).tick(mem)?;
+        cpu.tick(mem)
+    }
+
+    /// Execute one instruction, and stop at the start of the next one. When
+    /// the CPU is halted, run until it wakes up, but at most for a frame.
+    pub fn step(&mut self) -> Result<(), String> {
+        for _ in 0..Emulator::TICKS_PER_FRAME {
+            let starts_instruction = Emulator::starts_instruction(&self.cpu, &self.mem);
+            self.tick()?;
+            if starts_instruction {
+                break;
+            }
+        }
+        for _ in 0..Emulator::TICKS_PER_FRAME {
+            if Emulator::starts_instruction(&self.cpu, &self.mem) {
+                break;
+            }
+            self.tick()?;
+        }
+        // Stepping shows what happens, so the events it goes past don't
+        // stop the next frame.
+        self.mem.events.take_fired();
+
+        Ok(())
+    }
+
+    /// Run for the duration of one frame, with the buttons of `joypad`
+    /// pressed. The frame ends early if a breakpoint is reached, or after an
+    /// event subscribed to in `mem.events`, see `take_breakpoint_hit`.
+    pub fn run_frame(&mut self) -> Result<(), String> {
+        // Events logged during the frame are tagged with its number.
+        let _span =
+            tracing::debug_span!(target: "emulator", "frame", number = self.video.frame_count() + 1)
+                .entered();
+        self.mem.set_buttons(self.joypad.buttons());
+        self.joypad.next_frame();
+
+        // The clock is only read when profiling, since there may be none,
+        // e.g. on WebAssembly.
+        let mut stopwatch = Stopwatch::start(self.frame_times.is_some());
+        let end = self.mem.cycles + u64::from(Emulator::TICKS_PER_FRAME);
+        let Emulator {
+            mem,
+            cpu,
+            timer,
+            video,
+            audio,
+            serial,
+            breakpoints,
+            ..
+        } = self;
+        let mut devices = Devices::new(mem, timer, video, audio, serial);
+        devices.times = self.frame_times.map(|_| FrameTimes::default());
+        let result = Emulator::run_ahead(cpu, mem, &mut devices, breakpoints, end);
+        // The other subsystems are left at the cycle of the CPU, also after
+        // an error, for the crash report.
+        let synced = devices.sync(mem, mem.cycles);
+        if let Some(mut times) = devices.times {
+            // The CPU gets the rest of the time.
+            times.cpu = stopwatch.lap().saturating_sub(times.video + times.audio);
+            self.frame_times = Some(times);
+        }
+        if result? {
+            self.breakpoint_hit = true;
+            self.event_hit = self.mem.events.take_fired();
+        }
+        synced
+    }
+
+    /// Run until cycle `end`, with the CPU running ahead of the other
+    /// subsystems, see `scheduler`. Returns whether it stopped early at a
+    /// breakpoint or after an event.
+    fn run_ahead(
+        cpu: &mut CPU,
+        mem: &mut Memory,
+        devices: &mut Devices,
+        breakpoints: &BTreeSet<u16>,
+        end: u64,
+    ) -> Result<bool, String> {
+        let mut next_event = devices.next_event(mem);
+        while mem.cycles < end {
+            // Ticks where the CPU only counts down are done at once, unless
+            // each one must be checked for breakpoints, or the CPU is to stop
+            // after an event.
+            let idle = if breakpoints.is_empty() && !mem.events.has_fired() {
+                u64::from(cpu.idle_ticks(mem))
+            } else {
+                0
+            };
+            let ticks = idle.min(next_event - 1 - mem.cycles).min(end - mem.cycles);
+            if ticks > 0 {
+                mem.cycles += ticks;
+                cpu.skip(mem, ticks as u32);
+                continue;
+            }
+
+            mem.cycles += 1;
+            if mem.cycles >= next_event {
+                devices.sync(mem, mem.cycles)?;
+            }
+            cpu.tick_ahead(mem, devices)?;
+            devices.take_error()?;
+
+            // The CPU may have caught the other subsystems up and changed
+            // what they do next.
+            if devices.synced() == mem.cycles {
+                next_event = devices.next_event(mem);
+            }
+            if Emulator::at_breakpoint(breakpoints, cpu, mem) {
+                mem.callbacks.breakpoint(cpu.pc());
+                return Ok(true);
+            }
+            if Emulator::after_event(cpu, mem) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Like `run_frame`, but call `on_line` with the new value of LY every
+    /// time it changes. This runs tick by tick, which is slower, and all of
+    /// the time of the frame counts as CPU time in `frame_times`.
+    pub fn run_frame_with(
+        &mut self,
+        mut on_line: impl FnMut(&mut Emulator, u8) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let _span =
+            tracing::debug_span!(target: "emulator", "frame", number = self.video.frame_count() + 1)
+                .entered();
+        self.mem.set_buttons(self.joypad.buttons());
+        self.joypad.next_frame();
+
+        let mut stopwatch = Stopwatch::start(self.frame_times.is_some());
+        let mut line = self.mem[IORegister::LY];
+        for _ in 0..Emulator::TICKS_PER_FRAME {
+            self.tick()?;
+
+            let new_line = self.mem[IORegister::LY];
+            if new_line != line {
+                line = new_line;
+                on_line(self, line)?;
+            }
+            if Emulator::at_breakpoint(&self.breakpoints, &self.cpu, &self.mem) {
+                self.mem.callbacks.breakpoint(self.cpu.pc());
+                self.breakpoint_hit = true;
+                break;
+            }
+            if Emulator::after_event(&self.cpu, &self.mem) {
+                self.breakpoint_hit = true;
+                self.event_hit = self.mem.events.take_fired();
+                break;
+            }
+        }
+        if self.frame_times.is_some() {
+            self.frame_times = Some(FrameTimes {
+                cpu: stopwatch.lap(),
+                ..FrameTimes::default()
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Whether the last frame stopped at a breakpoint or after an event.
+    /// This is reset by the call.
+    pub fn take_breakpoint_hit(&mut self) -> bool {
+        std::mem::take(&mut self.breakpoint_hit)
+    }
+
+    /// The event that the last frame stopped after, if it did. This is reset
+    /// by the call.
+    pub fn take_event_hit(&mut self) -> Option<Event> {
+        self.event_hit.take()
+    }
+
+    /// The last frame, see `Video::framebuffer`.
+    pub fn framebuffer(&self) -> &[u32] {
+        self.video.framebuffer()
+    }
+
+    /// Move the audio samples produced since the last call, at the sample
+    /// rate of `audio`, to the end of `samples`.
+    pub fn audio_drain(&mut self, samples: &mut Vec<f32>) {
+        self.audio.drain_samples(samples);
+    }
+
+    /// Hold down `buttons` from the next frame on, and release the others.
+    /// The Game Boy sees the buttons change at the start of a frame, so the
+    /// input of a frame is the same however it is set before it runs.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.joypad.held = buttons;
+    }
+
+    /// Hold down `buttons` from the next frame on, along with the buttons
+    /// already held.
+    pub fn press(&mut self, buttons: Buttons) {
+        self.joypad.held.insert(buttons);
+    }
+
+    /// Release `buttons` from the next frame on, keeping the other buttons
+    /// held.
+    pub fn release(&mut self, buttons: Buttons) {
+        self.joypad.held.remove(buttons);
+    }
+
+    /// Tilt the Game Boy by `x` g to the right and `y` g towards the player,
+    /// for games with an accelerometer in the cartridge, see
+    /// `Cartridge::has_tilt_sensor`. The game reads it when it wants to, so
+    /// it can be set at any time; other games ignore it.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mem.cartridge.set_tilt(x, y);
+    }
+
+    /// Whether the next tick starts executing an instruction.
+    fn starts_instruction(cpu: &CPU, mem: &Memory) -> bool {
+        cpu.at_instruction_start() && mem.cpu_stall_cycles == 0
+    }
+
+    /// Whether a subscribed event has happened, and the CPU can stop: at
+    /// the start of the next instruction, or while halted, since a halted
+    /// CPU may not start another one for a long time.
+    fn after_event(cpu: &CPU, mem: &Memory) -> bool {
+        mem.events.has_fired()
+            && (Emulator::starts_instruction(cpu, mem)
+                || (cpu.halted() && mem.cpu_stall_cycles == 0))
+    }
+
+    fn at_breakpoint(breakpoints: &BTreeSet<u16>, cpu: &CPU, mem: &Memory) -> bool {
+        !breakpoints.is_empty()
+            && Emulator::starts_instruction(cpu, mem)
+            && breakpoints.contains(&cpu.pc())
+    }
+
+    /// Save the state of the whole Game Boy, except for the ROM.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mem = &self.mem;
+        let mut state = StateWriter::new();
+        state.bytes(Emulator::STATE_MAGIC);
+        state.u8(mem.model as u8);
+        state.bytes(mem.cartridge.title().as_bytes());
+
+        mem.save_state(&mut state);
+        self.cpu.save_state(&mut state);
+        self.video.save_state(&mut state);
+        self.audio.save_state(&mut state);
+        self.timer.save_state(&mut state);
+        self.serial.save_state(&mut state);
+        self.joypad.save_state(&mut state);
+
+        state.into_bytes()
+    }
+
+    /// Hash of the state saved by `save_state`.
+    pub fn state_hash(&self) -> u64 {
+        state::hash(&self.save_state())
+    }
+
+    /// Hash of the state of each subsystem, by name, to find out where two
+    /// runs that should be identical differ.
+    pub fn state_hashes(&self) -> Vec<(&'static str, u64)> {
+        let mem = &self.mem;
+        let mut hashes = Vec::new();
+        let mut add = |name, save: &dyn Fn(&mut StateWriter)| {
+            let mut state = StateWriter::new();
+            save(&mut state);
+            hashes.push((name, state::hash(&state.into_bytes())));
+        };
+
+        add("memory", &|state| mem.save_state(state));
+        add("CPU", &|state| self.cpu.save_state(state));
+        add("video", &|state| self.video.save_state(state));
+        add("audio", &|state| self.audio.save_state(state));
+        add("timer", &|state| self.timer.save_state(state));
+        add("serial", &|state| self.serial.save_state(state));
+        add("joypad", &|state| self.joypad.save_state(state));
+
+        hashes
+    }
+
+    /// Restore a state saved by `save_state`, which must have been saved
+    /// with the same model and game. If the state can't be loaded, the
+    /// emulator is left as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        // The subsystems are restored one after another, so a state that
+        // turns out to be truncated or broken partway would leave the
+        // machine half restored. Go back to the current state then.
+        let current = self.save_state();
+        let result = self.restore_state(data);
+        if result.is_err() {
+            self.restore_state(&current)
+                .expect("Can't restore the state before a failed load");
+        }
+        result
+    }
+
+    fn restore_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new(data);
+        if state.bytes()? != Emulator::STATE_MAGIC {
+            return Err("Not a save state for this version of Gaby.".into());
+        }
+
+        {
+            let mem = &mut self.mem;
+            if state.u8()? != mem.model as u8 {
+                return Err("The save state is for another Game Boy model.".into());
+            }
+            if state.bytes()? != mem.cartridge.title().as_bytes() {
+                return Err("The save state is for another game.".into());
+            }
+            mem.load_state(&mut state)?;
+        }
+        self.cpu.load_state(&mut state)?;
+        self.video.load_state(&mut state)?;
+        self.audio.load_state(&mut state)?;
+        self.timer.load_state(&mut state)?;
+        self.serial.load_state(&mut state)?;
+        self.joypad.load_state(&mut state)?;
+        self.watches.refresh(&self.mem);
+        Ok(())
+    }
+}
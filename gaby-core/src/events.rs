@@ -0,0 +1,90 @@
+//! Events that the subsystems signal while they run, for the debugger to
+//! break on besides reaching an instruction. The hooks are carried by the
+//! memory, since it is lent to every subsystem, and only the events that are
+//! subscribed to are kept, so signalling costs little when nothing is.
+
+use std::{collections::BTreeSet, fmt};
+
+/// Something that happens in the Game Boy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    /// LY changes to this line.
+    Line(u8),
+    /// The video system enters this mode: 0 for HBlank, 1 for VBlank, 2 for
+    /// the OAM scan and 3 for the transfer to the LCD.
+    Mode(u8),
+    /// The CPU dispatches an interrupt, of any kind.
+    Interrupt,
+    /// A byte is exchanged over the serial port, when a transfer with the
+    /// internal clock starts or the peer clocks one with the external clock.
+    SerialTransfer,
+    /// An OAM DMA transfer starts, or a VRAM DMA transfer on the CGB.
+    Dma,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Line(line) => write!(f, "LY = {}", line),
+            Event::Mode(mode) => write!(f, "PPU mode {}", mode),
+            Event::Interrupt => write!(f, "Interrupt"),
+            Event::SerialTransfer => write!(f, "Serial transfer"),
+            Event::Dma => write!(f, "DMA"),
+        }
+    }
+}
+
+/// The events subscribed to, and the first of them that has happened since
+/// it was last taken.
+#[derive(Default)]
+pub struct EventHooks {
+    subscriptions: BTreeSet<Event>,
+    fired: Option<Event>,
+}
+
+impl EventHooks {
+    /// Keep `event` when it is signalled. Returns whether it wasn't
+    /// subscribed to already.
+    pub fn subscribe(&mut self, event: Event) -> bool {
+        self.subscriptions.insert(event)
+    }
+
+    /// Stop keeping `event`. Returns whether it was subscribed to.
+    pub fn unsubscribe(&mut self, event: Event) -> bool {
+        if self.fired == Some(event) {
+            self.fired = None;
+        }
+        self.subscriptions.remove(&event)
+    }
+
+    pub fn is_subscribed(&self, event: Event) -> bool {
+        self.subscriptions.contains(&event)
+    }
+
+    /// The events subscribed to, in order.
+    pub fn subscriptions(&self) -> impl Iterator<Item = Event> + '_ {
+        self.subscriptions.iter().copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    /// Called by the subsystems when `event` happens.
+    pub fn signal(&mut self, event: Event) {
+        if self.fired.is_none() && self.subscriptions.contains(&event) {
+            self.fired = Some(event);
+        }
+    }
+
+    /// Whether a subscribed event has happened since the last call to
+    /// `take_fired`.
+    pub fn has_fired(&self) -> bool {
+        self.fired.is_some()
+    }
+
+    /// The first subscribed event that has happened since the last call.
+    pub fn take_fired(&mut self) -> Option<Event> {
+        self.fired.take()
+    }
+}
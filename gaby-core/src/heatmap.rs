@@ -0,0 +1,132 @@
+use std::cell::Cell;
+#[cfg(feature = "files")]
+use std::{error::Error, fs::File, io::BufWriter, io::Write};
+
+/// Per-address counters of memory reads and writes done over the bus.
+pub struct Heatmap {
+    reads: Vec<Cell<u32>>,
+    writes: Vec<u32>,
+}
+
+/// Named regions of the address space, used for summarizing the counters.
+#[cfg(feature = "files")]
+const REGIONS: [(&str, u16, u16); 11] = [
+    ("ROM bank 0", 0x0000, 0x3FFF),
+    ("ROM bank N", 0x4000, 0x7FFF),
+    ("VRAM", 0x8000, 0x9FFF),
+    ("External RAM", 0xA000, 0xBFFF),
+    ("WRAM", 0xC000, 0xDFFF),
+    ("Echo RAM", 0xE000, 0xFDFF),
+    ("OAM", 0xFE00, 0xFE9F),
+    ("Unusable", 0xFEA0, 0xFEFF),
+    ("I/O registers", 0xFF00, 0xFF7F),
+    ("HRAM", 0xFF80, 0xFFFE),
+    ("IE", 0xFFFF, 0xFFFF),
+];
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap::new()
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Self {
+            reads: vec![Cell::new(0); 0x10000],
+            writes: vec![0; 0x10000],
+        }
+    }
+
+    /// Reads happen through shared references, so the read counters use
+    /// interior mutability.
+    pub fn record_read(&self, address: u16) {
+        let counter = &self.reads[usize::from(address)];
+        counter.set(counter.get().saturating_add(1));
+    }
+
+    pub fn record_write(&mut self, address: u16) {
+        let counter = &mut self.writes[usize::from(address)];
+        *counter = counter.saturating_add(1);
+    }
+}
+
+#[cfg(feature = "files")]
+impl Heatmap {
+    /// Write the heatmap as `<base_path>.csv` with one line per accessed
+    /// address, `<base_path>-regions.csv` with totals per memory region, and
+    /// `<base_path>.png` as an image.
+    pub fn export(&self, base_path: &str) -> Result<(), Box<dyn Error>> {
+        self.write_csv(&format!("{}.csv", base_path))?;
+        self.write_region_csv(&format!("{}-regions.csv", base_path))?;
+        self.write_image(&format!("{}.png", base_path))?;
+        Ok(())
+    }
+
+    fn write_csv(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "address,reads,writes")?;
+        for address in 0..0x10000 {
+            let (reads, writes) = (self.reads[address].get(), self.writes[address]);
+            if reads != 0 || writes != 0 {
+                writeln!(file, "{:#06X},{},{}", address, reads, writes)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_region_csv(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "region,start,end,reads,writes")?;
+        for (name, start, end) in REGIONS.iter() {
+            let range = usize::from(*start)..=usize::from(*end);
+            let reads: u64 = self.reads[range.clone()]
+                .iter()
+                .map(|count| u64::from(count.get()))
+                .sum();
+            let writes: u64 = self.writes[range]
+                .iter()
+                .map(|&count| u64::from(count))
+                .sum();
+            writeln!(
+                file,
+                "{},{:#06X},{:#06X},{},{}",
+                name, start, end, reads, writes
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a 256×256 image where each pixel is one address, with the high
+    /// byte as row. Reads are shown in green and writes in red, on a
+    /// logarithmic scale.
+    fn write_image(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let max_reads = self.reads.iter().map(Cell::get).max().unwrap_or(0);
+        let max_writes = self.writes.iter().copied().max().unwrap_or(0);
+
+        let intensity = |count: u32, max: u32| -> u8 {
+            if count == 0 {
+                0
+            } else {
+                let scale = f64::from(count).ln_1p() / f64::from(max).ln_1p();
+                (63.0 + 192.0 * scale) as u8
+            }
+        };
+
+        let mut pixels = Vec::with_capacity(0x10000 * 3);
+        for address in 0..0x10000 {
+            pixels.push(intensity(self.writes[address], max_writes));
+            pixels.push(intensity(self.reads[address].get(), max_reads));
+            pixels.push(0);
+        }
+
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), 256, 256);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)?;
+
+        Ok(())
+    }
+}
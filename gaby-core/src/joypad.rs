@@ -0,0 +1,91 @@
+use crate::state::{StateReader, StateWriter};
+use bitflags::bitflags;
+
+bitflags! {
+    /// The eight buttons of the Game Boy. The direction buttons are in the
+    /// lower nibble and the action buttons in the upper, so that each nibble
+    /// matches the lower nibble of P1 when that group is selected.
+    pub struct Buttons: u8 {
+        const RIGHT = 0b0000_0001;
+        const LEFT = 0b0000_0010;
+        const UP = 0b0000_0100;
+        const DOWN = 0b0000_1000;
+        const A = 0b0001_0000;
+        const B = 0b0010_0000;
+        const SELECT = 0b0100_0000;
+        const START = 0b1000_0000;
+    }
+}
+
+impl Buttons {
+    /// Value read from P1 when these buttons are pressed. Bits 4 and 5 of
+    /// `select` select the direction and action buttons, and like the button
+    /// bits they are active low.
+    pub fn p1_value(self, select: u8) -> u8 {
+        let mut pressed = 0;
+        if select & 0b0001_0000 == 0 {
+            pressed |= self.bits() & 0b0000_1111;
+        }
+        if select & 0b0010_0000 == 0 {
+            pressed |= self.bits() >> 4;
+        }
+
+        0b1100_0000 | (select & 0b0011_0000) | (!pressed & 0b0000_1111)
+    }
+}
+
+/// Input from the frontend, applied to memory at the start of each frame.
+/// Turbo buttons are pressed and released automatically while they are held.
+pub struct Joypad {
+    /// Buttons held down.
+    pub held: Buttons,
+    /// Turbo buttons held down.
+    pub turbo: Buttons,
+    /// Number of frames a turbo button stays pressed, and then released.
+    pub turbo_frames: u32,
+    frame: u32,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad::new()
+    }
+}
+
+impl Joypad {
+    /// Frames per half period of the turbo buttons, giving 15 presses per
+    /// second.
+    pub const DEFAULT_TURBO_FRAMES: u32 = 2;
+
+    pub fn new() -> Self {
+        Self {
+            held: Buttons::empty(),
+            turbo: Buttons::empty(),
+            turbo_frames: Joypad::DEFAULT_TURBO_FRAMES,
+            frame: 0,
+        }
+    }
+
+    /// Buttons pressed in the current frame.
+    pub fn buttons(&self) -> Buttons {
+        if (self.frame / self.turbo_frames) & 1 == 0 {
+            self.held | self.turbo
+        } else {
+            self.held
+        }
+    }
+
+    pub fn next_frame(&mut self) {
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    /// Save the turbo phase. The buttons are input, so they are not saved.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u32(self.frame);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.frame = state.u32()?;
+        Ok(())
+    }
+}
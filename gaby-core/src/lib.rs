@@ -0,0 +1,43 @@
+//! The emulated Game Boy, without any frontend. An `Emulator` renders video
+//! to a framebuffer and audio to a sample buffer, and takes the state of the
+//! buttons through its joypad, so it can be driven by any frontend.
+//!
+//! ```no_run
+//! use gaby_core::{emulator::Emulator, joypad::Buttons, model::Model};
+//!
+//! let rom = std::fs::read("game.gb").unwrap();
+//! let mut emulator = Emulator::from_rom(&rom, Model::DMG).unwrap();
+//! emulator.set_buttons(Buttons::START);
+//! emulator.run_frame().unwrap();
+//! let pixels = emulator.framebuffer();
+//! let mut samples = Vec::new();
+//! emulator.audio_drain(&mut samples);
+//! ```
+
+pub mod audio;
+pub mod callbacks;
+pub mod cartridge;
+pub mod cpu;
+pub mod disassembler;
+mod eeprom;
+pub mod emulator;
+pub mod events;
+pub mod heatmap;
+pub mod joypad;
+pub mod lockstep;
+pub mod memory;
+pub mod model;
+pub mod movie;
+pub mod palette;
+pub mod profile;
+pub mod reference;
+mod scheduler;
+pub mod scope;
+pub mod search;
+pub mod serial;
+pub mod state;
+#[cfg(feature = "files")]
+pub mod stems;
+pub mod timer;
+pub mod video;
+pub mod watch;
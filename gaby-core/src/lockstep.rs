@@ -0,0 +1,208 @@
+//! Differential testing of the CPU: running it in lockstep with a reference
+//! core, and stopping at the first instruction where the two disagree.
+
+use crate::cpu::CPU;
+use crate::disassembler;
+use crate::emulator::Emulator;
+use crate::memory::{Memory, WriteHook};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The registers compared between the cores.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl Registers {
+    pub fn of(cpu: &CPU) -> Self {
+        let register = |name| cpu.register(name).unwrap_or_default();
+        Self {
+            a: register("a") as u8,
+            f: register("f") as u8,
+            b: register("b") as u8,
+            c: register("c") as u8,
+            d: register("d") as u8,
+            e: register("e") as u8,
+            h: register("h") as u8,
+            l: register("l") as u8,
+            sp: register("sp"),
+            pc: register("pc"),
+        }
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "A={:02X} F={:02X} B={:02X} C={:02X} D={:02X} E={:02X} H={:02X} L={:02X} SP={:04X} PC={:04X}",
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l, self.sp, self.pc
+        )
+    }
+}
+
+/// Memory as seen by a reference core.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, data: u8);
+}
+
+/// Another implementation of the CPU instructions, to compare the CPU of
+/// Gaby against. It only needs to execute instructions; interrupts and HALT
+/// are left to Gaby, and the reference core continues from its registers.
+pub trait ReferenceCore {
+    fn registers(&self) -> Registers;
+    fn set_registers(&mut self, registers: Registers);
+    /// Execute the instruction at PC. Returns an error for invalid opcodes.
+    fn step(&mut self, bus: &mut dyn Bus) -> Result<(), String>;
+}
+
+/// Memory of the Game Boy as it is before an instruction, for the reference
+/// core. Writes are recorded instead of done, since the CPU of Gaby does
+/// them.
+struct SnapshotBus<'a> {
+    mem: &'a Memory,
+    writes: Vec<(u16, u8)>,
+    /// Whether an I/O register has been read. Gaby's CPU may read it at
+    /// another cycle of the instruction, and get another value.
+    read_io: bool,
+}
+
+impl Bus for SnapshotBus<'_> {
+    fn read(&mut self, address: u16) -> u8 {
+        if address >= 0xFF00 && !(0xFF80..=0xFFFE).contains(&address) {
+            self.read_io = true;
+        }
+        self.mem.read_byte(address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.writes.push((address, data));
+    }
+}
+
+/// An instruction that has been executed.
+pub struct Step {
+    /// Address and disassembly of the instruction.
+    pub instruction: String,
+    /// Registers before the instruction.
+    pub registers: Registers,
+}
+
+/// An instruction after which the cores disagree.
+pub struct Divergence {
+    pub step: Step,
+    /// Registers of the reference core after the instruction.
+    pub expected: Registers,
+    /// Registers of Gaby after the instruction.
+    pub actual: Registers,
+    /// Writes to memory by the reference core, as addresses and bytes.
+    pub expected_writes: Vec<(u16, u8)>,
+    /// Writes to memory by Gaby.
+    pub actual_writes: Vec<(u16, u8)>,
+}
+
+/// Runs an emulator instruction by instruction alongside a reference core,
+/// and compares the registers and memory writes after each instruction.
+pub struct Lockstep {
+    core: Box<dyn ReferenceCore>,
+    writes: WriteHook,
+    /// The last instructions executed, oldest first.
+    pub history: VecDeque<Step>,
+    history_length: usize,
+    /// Number of instructions compared so far.
+    pub compared: u64,
+}
+
+impl Lockstep {
+    /// Start the comparison from the current state of `machine`, keeping the
+    /// last `history_length` instructions.
+    pub fn new(
+        machine: &mut Emulator,
+        mut core: Box<dyn ReferenceCore>,
+        history_length: usize,
+    ) -> Self {
+        core.set_registers(Registers::of(&machine.cpu));
+        let writes = machine.mem.add_write_hook(0x0000..=0xFFFF);
+
+        Self {
+            core,
+            writes,
+            history: VecDeque::new(),
+            history_length,
+            compared: 0,
+        }
+    }
+
+    /// Execute one instruction on both cores. Returns where they disagree,
+    /// if they do.
+    pub fn step(&mut self, machine: &mut Emulator) -> Result<Option<Divergence>, String> {
+        let registers = Registers::of(&machine.cpu);
+        let (instruction, mut expected_writes, read_io, reference_result) = {
+            let mem = &machine.mem;
+            let instruction = disassembler::disassemble(mem, registers.pc);
+            let mut bus = SnapshotBus {
+                mem,
+                writes: Vec::new(),
+                read_io: false,
+            };
+            let result = self.core.step(&mut bus);
+            (instruction, bus.writes, bus.read_io, result)
+        };
+
+        machine.step()?;
+        reference_result.map_err(|error| format!("The reference core failed: {}", error))?;
+
+        let mut actual_writes = Vec::new();
+        while let Some(event) = machine.mem.next_write(self.writes) {
+            actual_writes.push((event.address, event.data));
+        }
+        let step = Step {
+            instruction: format!("{:04X}: {}", instruction.address, instruction.text),
+            registers,
+        };
+
+        // The order of the writes within an instruction only matters for I/O
+        // registers, and differs for e.g. PUSH, which Gaby writes as a word.
+        expected_writes.sort_by_key(|&(address, _)| address);
+        actual_writes.sort_by_key(|&(address, _)| address);
+
+        let expected = self.core.registers();
+        let actual = Registers::of(&machine.cpu);
+        // The reference core doesn't know about interrupts, and the I/O
+        // registers may have changed before Gaby read them, so in those cases
+        // it just continues from where Gaby is.
+        let interrupted = machine.cpu.take_interrupt_dispatched();
+        let differs = expected != actual || expected_writes != actual_writes;
+        if differs && !interrupted && !read_io {
+            return Ok(Some(Divergence {
+                step,
+                expected,
+                actual,
+                expected_writes,
+                actual_writes,
+            }));
+        }
+        if differs {
+            self.core.set_registers(actual);
+        } else {
+            self.compared += 1;
+        }
+
+        if self.history.len() == self.history_length {
+            self.history.pop_front();
+        }
+        self.history.push_back(step);
+        Ok(None)
+    }
+}
@@ -0,0 +1,580 @@
+use crate::callbacks::Callbacks;
+use crate::cartridge::Cartridge;
+use crate::events::{Event, EventHooks};
+use crate::heatmap::Heatmap;
+use crate::joypad::Buttons;
+use crate::model::Model;
+use crate::state::{StateReader, StateWriter};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    ops::{Index, IndexMut, RangeInclusive},
+};
+
+pub struct IORegister;
+
+impl IORegister {
+    pub const P1: u16 = 0xFF00;
+    pub const SB: u16 = 0xFF01;
+    pub const SC: u16 = 0xFF02;
+    pub const DIV: u16 = 0xFF04;
+    pub const TIMA: u16 = 0xFF05;
+    pub const TMA: u16 = 0xFF06;
+    pub const TAC: u16 = 0xFF07;
+    pub const IF: u16 = 0xFF0F;
+    pub const NR10: u16 = 0xFF10;
+    pub const NR11: u16 = 0xFF11;
+    pub const NR12: u16 = 0xFF12;
+    pub const NR13: u16 = 0xFF13;
+    pub const NR14: u16 = 0xFF14;
+    pub const NR21: u16 = 0xFF16;
+    pub const NR22: u16 = 0xFF17;
+    pub const NR23: u16 = 0xFF18;
+    pub const NR24: u16 = 0xFF19;
+    pub const NR30: u16 = 0xFF1A;
+    pub const NR31: u16 = 0xFF1B;
+    pub const NR32: u16 = 0xFF1C;
+    pub const NR33: u16 = 0xFF1D;
+    pub const NR34: u16 = 0xFF1E;
+    pub const NR41: u16 = 0xFF20;
+    pub const NR42: u16 = 0xFF21;
+    pub const NR43: u16 = 0xFF22;
+    pub const NR44: u16 = 0xFF23;
+    pub const NR50: u16 = 0xFF24;
+    pub const NR51: u16 = 0xFF25;
+    pub const NR52: u16 = 0xFF26;
+    pub const LCDC: u16 = 0xFF40;
+    pub const STAT: u16 = 0xFF41;
+    pub const SCY: u16 = 0xFF42;
+    pub const SCX: u16 = 0xFF43;
+    pub const LY: u16 = 0xFF44;
+    pub const LYC: u16 = 0xFF45;
+    pub const DMA: u16 = 0xFF46;
+    pub const BGP: u16 = 0xFF47;
+    pub const OBP0: u16 = 0xFF48;
+    pub const OBP1: u16 = 0xFF49;
+    pub const WY: u16 = 0xFF4A;
+    pub const WX: u16 = 0xFF4B;
+    pub const HDMA1: u16 = 0xFF51;
+    pub const HDMA2: u16 = 0xFF52;
+    pub const HDMA3: u16 = 0xFF53;
+    pub const HDMA4: u16 = 0xFF54;
+    pub const HDMA5: u16 = 0xFF55;
+    pub const IE: u16 = 0xFFFF;
+}
+
+/// A write to an address watched by a write hook.
+#[derive(Clone, Copy)]
+pub struct WriteEvent {
+    pub address: u16,
+    pub data: u8,
+    /// Machine cycle at which the write happened.
+    pub cycle: u64,
+    /// Address of the instruction that made the write. This isn't kept in
+    /// save states, since only the debugger uses it.
+    pub pc: u16,
+}
+
+/// Handle to a write hook registered with `Memory::add_write_hook`.
+#[derive(Clone, Copy)]
+pub struct WriteHook(usize);
+
+/// Queue of writes to an address range, consumed by one subscriber.
+struct WriteSubscription {
+    addresses: RangeInclusive<u16>,
+    events: VecDeque<WriteEvent>,
+    /// Whether the hook is for the debugger, see `add_watch_hook`.
+    watch: bool,
+}
+
+/// State of the CGB VRAM DMA, which copies blocks of 16 bytes to VRAM.
+struct VramDma {
+    source: u16,
+    destination: u16,
+    /// Number of blocks left to copy, minus one.
+    blocks_left: u8,
+    /// Whether an HBlank DMA is in progress.
+    active: bool,
+}
+
+pub struct Memory {
+    pub data: [u8; 0x10000],
+    pub model: Model,
+    /// Number of machine cycles since power-on, used to timestamp writes.
+    pub cycles: u64,
+    pub cartridge: Cartridge,
+    /// Number of cycles the CPU is stopped for while DMA is copying data.
+    pub cpu_stall_cycles: u32,
+    vram_dma: VramDma,
+    /// Emulate the OAM corruption bug of the DMG.
+    pub oam_bug: bool,
+    /// Row of OAM that the video system is reading while scanning for
+    /// sprites, if it is.
+    pub oam_scan_row: Option<u8>,
+    /// Access counters, only kept when requested since they slow down every
+    /// memory access.
+    pub heatmap: Option<Heatmap>,
+    /// Address of the instruction the CPU is executing, to tell which one
+    /// made a write.
+    pub instruction_address: u16,
+    /// Events signalled by the subsystems, for the debugger.
+    pub events: EventHooks,
+    /// Functions registered by the embedding program, see the `callbacks`
+    /// module.
+    pub callbacks: Callbacks,
+    /// Buttons currently held down.
+    buttons: Buttons,
+    write_subscriptions: Vec<WriteSubscription>,
+}
+
+impl Index<u16> for Memory {
+    type Output = u8;
+
+    fn index(&self, index: u16) -> &Self::Output {
+        &self.data[index as usize]
+    }
+}
+
+impl IndexMut<u16> for Memory {
+    fn index_mut(&mut self, index: u16) -> &mut Self::Output {
+        &mut self.data[index as usize]
+    }
+}
+
+impl Memory {
+    const OAM: u16 = 0xFE00;
+    const OAM_SIZE: u16 = 160;
+
+    /// Bits that always read as 1 in the sound registers 0xFF10-0xFF2F, since
+    /// they are write-only or unused.
+    const SOUND_READ_MASKS: [u8; 0x20] = [
+        0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+        0xFF, 0x3F, 0x00, 0xFF, 0xBF, // Unused, NR21-NR24
+        0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+        0xFF, 0xFF, 0x00, 0x00, 0xBF, // Unused, NR41-NR44
+        0x00, 0x00, 0x70, // NR50-NR52
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // Unused
+    ];
+
+    /// Initialize memory with random data, and the I/O registers with the
+    /// values the boot ROM of the given model leaves behind.
+    pub fn new(model: Model) -> Self {
+        Memory::with_seed(model, rand::random())
+    }
+
+    /// Like `new`, but with the random data made from `seed`, so that the
+    /// same seed always gives the same memory.
+    pub fn with_seed(model: Model, seed: u64) -> Self {
+        let mut data = [0u8; 0x10000];
+        StdRng::seed_from_u64(seed).fill(&mut data[..]);
+
+        let mut mem = Self {
+            data,
+            model,
+            cycles: 0,
+            cartridge: Cartridge::empty(),
+            cpu_stall_cycles: 0,
+            vram_dma: VramDma {
+                source: 0,
+                destination: 0x8000,
+                blocks_left: 0x7F,
+                active: false,
+            },
+            oam_bug: false,
+            oam_scan_row: None,
+            heatmap: None,
+            instruction_address: 0,
+            events: EventHooks::default(),
+            callbacks: Callbacks::default(),
+            buttons: Buttons::empty(),
+            write_subscriptions: Vec::new(),
+        };
+
+        for (address, value) in model.io_registers() {
+            mem[address] = value;
+        }
+
+        mem
+    }
+
+    /// Set the buttons that are held down, and request the joypad interrupt
+    /// if any button in a selected group was pressed.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        let select = self[IORegister::P1];
+        // The interrupt is requested when a bit of P1 goes from 1 to 0.
+        if self.buttons.p1_value(select) & !buttons.p1_value(select) & 0b0000_1111 != 0 {
+            self[IORegister::IF] |= 0b0001_0000;
+        }
+        self.buttons = buttons;
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.cartridge = Cartridge::new(rom)?;
+        Ok(())
+    }
+
+    /// Start recording writes to the given address range. The writes are
+    /// queued until taken with `next_write`.
+    pub fn add_write_hook(&mut self, addresses: RangeInclusive<u16>) -> WriteHook {
+        self.write_subscriptions.push(WriteSubscription {
+            addresses,
+            events: VecDeque::new(),
+            watch: false,
+        });
+
+        WriteHook(self.write_subscriptions.len() - 1)
+    }
+
+    /// Like `add_write_hook`, but for the debugger: the hook can be removed,
+    /// and the writes it has recorded aren't kept in save states, so that
+    /// states saved with and without it are the same.
+    pub fn add_watch_hook(&mut self, addresses: RangeInclusive<u16>) -> WriteHook {
+        let subscription = WriteSubscription {
+            addresses,
+            events: VecDeque::new(),
+            watch: true,
+        };
+
+        // Reuse the place of a removed hook, since the handles are indices.
+        let removed = self
+            .write_subscriptions
+            .iter()
+            .position(|subscription| subscription.watch && subscription.addresses.is_empty());
+        match removed {
+            Some(index) => {
+                self.write_subscriptions[index] = subscription;
+                WriteHook(index)
+            }
+            None => {
+                self.write_subscriptions.push(subscription);
+                WriteHook(self.write_subscriptions.len() - 1)
+            }
+        }
+    }
+
+    /// Stop recording writes with a hook added by `add_watch_hook`.
+    pub fn remove_watch_hook(&mut self, hook: WriteHook) {
+        let subscription = &mut self.write_subscriptions[hook.0];
+        if subscription.watch {
+            subscription.addresses = RangeInclusive::new(1, 0);
+            subscription.events.clear();
+        }
+    }
+
+    /// Take the oldest write recorded by the hook that hasn't been taken yet.
+    pub fn next_write(&mut self, hook: WriteHook) -> Option<WriteEvent> {
+        self.write_subscriptions[hook.0].events.pop_front()
+    }
+
+    /// Whether the hook has recorded writes that haven't been taken yet.
+    pub fn has_writes(&self, hook: WriteHook) -> bool {
+        !self.write_subscriptions[hook.0].events.is_empty()
+    }
+
+    fn notify_write(&mut self, address: u16, data: u8) {
+        let event = WriteEvent {
+            address,
+            data,
+            cycle: self.cycles,
+            pc: self.instruction_address,
+        };
+
+        for subscription in &mut self.write_subscriptions {
+            if subscription.addresses.contains(&address) {
+                subscription.events.push_back(event);
+            }
+        }
+    }
+
+    pub fn read_byte(&self, address: u16) -> u8 {
+        if let Some(heatmap) = &self.heatmap {
+            heatmap.record_read(address);
+        }
+
+        self.peek(address)
+    }
+
+    /// Read a byte like the CPU, but for the debugger, so it isn't counted
+    /// in the heatmap.
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x7FFF => self.cartridge.read_rom(address),
+            0xA000..=0xBFFF => self.cartridge.read_ram(address),
+            IORegister::P1 => self.buttons.p1_value(self[IORegister::P1]),
+            IORegister::NR10..=0xFF2F => {
+                self[address] | Memory::SOUND_READ_MASKS[usize::from(address - IORegister::NR10)]
+            }
+            IORegister::HDMA1..=IORegister::HDMA5 if self.model == Model::CGB => {
+                if address == IORegister::HDMA5 {
+                    // Bit 7 is clear while an HBlank DMA is in progress.
+                    let inactive = if self.vram_dma.active { 0 } else { 0b1000_0000 };
+                    inactive | self.vram_dma.blocks_left
+                } else {
+                    0xFF // Write-only.
+                }
+            }
+            _ => self[address],
+        }
+    }
+
+    pub fn read_word(&self, address: u16) -> u16 {
+        u16::from_le_bytes([
+            self.read_byte(address),
+            self.read_byte(address.wrapping_add(1)),
+        ])
+    }
+
+    pub fn write_byte(&mut self, address: u16, data: u8) {
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record_write(address);
+        }
+
+        self.store(address, data);
+    }
+
+    /// Change a byte for the debugger, like the CPU writing it, except that
+    /// writes to ROM are ignored instead of going to the memory bank
+    /// controller. Returns whether the byte is writable, i.e. not in ROM.
+    pub fn poke(&mut self, address: u16, data: u8) -> bool {
+        if address < 0x8000 {
+            return false;
+        }
+
+        self.store(address, data);
+        true
+    }
+
+    /// Write a byte, telling the write hooks about it.
+    fn store(&mut self, address: u16, data: u8) {
+        self.notify_write(address, data);
+
+        match address {
+            0x0000..=0x7FFF => {
+                self.cartridge.write_rom(address, data);
+                return;
+            }
+            0xA000..=0xBFFF => {
+                if self.cartridge.write_ram(address, data) {
+                    self.callbacks.sram_write(address, data);
+                }
+                return;
+            }
+            0xC000..=0xDDFF => self[address + 0x2000] = data, // Write to echo area.
+            0xE000..=0xFDFF => self[address - 0x2000] = data, // Write to echo area.
+            0xFF00..=0xFFFF => {
+                self.write_io(address, data);
+                return;
+            }
+            _ => {}
+        }
+
+        self[address] = data;
+    }
+
+    pub fn write_word(&mut self, address: u16, data: u16) {
+        let bytes = data.to_le_bytes();
+        self.write_byte(address, bytes[0]);
+        self.write_byte(address.wrapping_add(1), bytes[1]);
+    }
+
+    fn write_io(&mut self, address: u16, data: u8) {
+        match address {
+            // Only the bits that select the button group are writable.
+            IORegister::P1 => self[IORegister::P1] = data & 0b0011_0000,
+            IORegister::DIV => self[IORegister::DIV] = 0,
+            IORegister::LY => {} // Read-only.
+            // The mode and coincidence bits are read-only.
+            IORegister::STAT => {
+                self[IORegister::STAT] =
+                    (data & 0b0111_1000) | (self[IORegister::STAT] & 0b0000_0111)
+            }
+            IORegister::DMA => self.dma_transfer(data),
+            IORegister::HDMA1..=IORegister::HDMA5 if self.model == Model::CGB => {
+                self.write_vram_dma(address, data)
+            }
+            _ => self[address] = data,
+        };
+    }
+
+    fn write_vram_dma(&mut self, address: u16, data: u8) {
+        let dma = &mut self.vram_dma;
+        match address {
+            IORegister::HDMA1 => dma.source = (dma.source & 0x00FF) | (u16::from(data) << 8),
+            IORegister::HDMA2 => dma.source = (dma.source & 0xFF00) | u16::from(data & 0xF0),
+            IORegister::HDMA3 => {
+                dma.destination =
+                    0x8000 | (dma.destination & 0x00FF) | (u16::from(data & 0x1F) << 8)
+            }
+            IORegister::HDMA4 => {
+                dma.destination = (dma.destination & 0xFF00) | u16::from(data & 0xF0)
+            }
+            _ => {
+                dma.blocks_left = data & 0b0111_1111;
+
+                if data & 0b1000_0000 != 0 {
+                    // HBlank DMA, one block per HBlank.
+                    dma.active = true;
+                    self.events.signal(Event::Dma);
+                } else if dma.active {
+                    // Writing bit 7 = 0 during an HBlank DMA stops it.
+                    dma.active = false;
+                } else {
+                    // General purpose DMA, everything at once.
+                    self.events.signal(Event::Dma);
+                    let blocks = dma.blocks_left + 1;
+                    for _ in 0..blocks {
+                        self.copy_vram_dma_block();
+                    }
+                    self.vram_dma.blocks_left = 0x7F;
+                }
+            }
+        }
+    }
+
+    /// Copy the next block of an HBlank DMA. Called by the video system at the
+    /// start of each HBlank. Returns the address the block was copied to, if
+    /// one was.
+    pub fn hblank_dma(&mut self) -> Option<u16> {
+        if !self.vram_dma.active {
+            return None;
+        }
+
+        let destination = self.vram_dma.destination;
+        self.copy_vram_dma_block();
+
+        if self.vram_dma.blocks_left == 0 {
+            self.vram_dma.active = false;
+            self.vram_dma.blocks_left = 0x7F;
+        } else {
+            self.vram_dma.blocks_left -= 1;
+        }
+        Some(destination)
+    }
+
+    /// Copy 16 bytes to VRAM, which takes 8 cycles during which the CPU is
+    /// stopped.
+    fn copy_vram_dma_block(&mut self) {
+        for _ in 0..16 {
+            let (source, destination) = (self.vram_dma.source, self.vram_dma.destination);
+            self[destination] = self.read_byte(source);
+
+            self.vram_dma.source = source.wrapping_add(1);
+            // The destination wraps around inside VRAM.
+            self.vram_dma.destination = 0x8000 | (destination.wrapping_add(1) & 0x1FFF);
+        }
+
+        self.cpu_stall_cycles += 8;
+    }
+
+    /// On the DMG, a 16-bit increment or decrement puts the value on the
+    /// address bus. If it points into OAM while the video system is scanning
+    /// OAM, the row being scanned is corrupted.
+    ///
+    /// OAM consists of 20 rows of 8 bytes. The first word of the scanned row
+    /// is mixed with the first and third word of the preceding row, and the
+    /// other three words are copied from the preceding row. The first row is
+    /// never corrupted.
+    pub fn corrupt_oam(&mut self, value: u16) {
+        if !self.oam_bug || !(0xFE00..=0xFEFF).contains(&value) {
+            return;
+        }
+
+        let row = match self.oam_scan_row {
+            Some(row) if row > 0 => u16::from(row),
+            _ => return,
+        };
+
+        let word =
+            |mem: &Memory, address: u16| u16::from_le_bytes([mem[address], mem[address + 1]]);
+
+        let row_address = Memory::OAM + row * 8;
+        let previous_row_address = row_address - 8;
+
+        let a = word(self, row_address);
+        let b = word(self, previous_row_address);
+        let c = word(self, previous_row_address + 4);
+        let [low, high] = (((a ^ c) & (b ^ c)) ^ c).to_le_bytes();
+
+        self[row_address] = low;
+        self[row_address + 1] = high;
+        for offset in 2..8 {
+            self[row_address + offset] = self[previous_row_address + offset];
+        }
+    }
+
+    // Transfer 160 bytes to OAM memory.
+    fn dma_transfer(&mut self, source_address: u8) {
+        self.events.signal(Event::Dma);
+        let address = u16::from(source_address) << 8;
+
+        for offset in 0..Memory::OAM_SIZE {
+            self[Memory::OAM + offset] = self.read_byte(address + offset);
+        }
+    }
+
+    /// Save the memory, the cartridge and the writes that haven't been
+    /// handled by the subsystems yet.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.data);
+        state.u64(self.cycles);
+        state.u32(self.cpu_stall_cycles);
+        state.u16(self.vram_dma.source);
+        state.u16(self.vram_dma.destination);
+        state.u8(self.vram_dma.blocks_left);
+        state.bool(self.vram_dma.active);
+        state.bool(self.oam_scan_row.is_some());
+        state.u8(self.oam_scan_row.unwrap_or(0));
+        state.u8(self.buttons.bits());
+
+        for subscription in self.subsystem_hooks() {
+            state.u32(subscription.events.len() as u32);
+            for event in &subscription.events {
+                state.u16(event.address);
+                state.u8(event.data);
+                state.u64(event.cycle);
+            }
+        }
+
+        self.cartridge.save_state(state);
+    }
+
+    /// The write hooks of the subsystems, whose writes are saved in save
+    /// states, unlike those of the debugger.
+    fn subsystem_hooks(&self) -> impl Iterator<Item = &WriteSubscription> {
+        self.write_subscriptions
+            .iter()
+            .filter(|subscription| !subscription.watch)
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes_into(&mut self.data)?;
+        self.cycles = state.u64()?;
+        self.cpu_stall_cycles = state.u32()?;
+        self.vram_dma.source = state.u16()?;
+        self.vram_dma.destination = state.u16()?;
+        self.vram_dma.blocks_left = state.u8()?;
+        self.vram_dma.active = state.bool()?;
+        let scanning = state.bool()?;
+        let row = state.u8()?;
+        self.oam_scan_row = if scanning { Some(row) } else { None };
+        self.buttons = Buttons::from_bits_truncate(state.u8()?);
+
+        for subscription in &mut self.write_subscriptions {
+            subscription.events.clear();
+            if subscription.watch {
+                continue;
+            }
+            for _ in 0..state.u32()? {
+                subscription.events.push_back(WriteEvent {
+                    address: state.u16()?,
+                    data: state.u8()?,
+                    cycle: state.u64()?,
+                    pc: 0,
+                });
+            }
+        }
+
+        self.cartridge.load_state(state)
+    }
+}
@@ -0,0 +1,115 @@
+use crate::memory::IORegister;
+use std::str::FromStr;
+
+/// Game Boy hardware models. They differ in the state the boot ROM leaves
+/// the registers in when it hands over control to the cartridge.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    /// Early original Game Boy with the first boot ROM revision.
+    DMG0,
+    /// Original Game Boy.
+    DMG,
+    /// Game Boy Pocket.
+    MGB,
+    /// Super Game Boy.
+    SGB,
+    /// Game Boy Color.
+    CGB,
+}
+
+impl Model {
+    /// CPU register values after the boot ROM has finished, in the order
+    /// A, F, B, C, D, E, H, L.
+    pub fn cpu_registers(self) -> [u8; 8] {
+        use Model::*;
+        match self {
+            DMG0 => [0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03],
+            DMG => [0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            MGB => [0xFF, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            SGB => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+            CGB => [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+        }
+    }
+
+    /// I/O register values after the boot ROM has finished. Registers that
+    /// aren't listed are left uninitialized.
+    pub fn io_registers(self) -> Vec<(u16, u8)> {
+        use Model::*;
+
+        let div = match self {
+            DMG0 => 0x18,
+            DMG | MGB => 0xAB,
+            SGB | CGB => 0x00, // Depends on boot ROM timing.
+        };
+        let serial_control = if self == CGB { 0x7F } else { 0x7E };
+        let sound_on = if self == SGB { 0xF0 } else { 0xF1 };
+        let stat = if self == DMG0 { 0x81 } else { 0x85 };
+        let ly = if self == DMG0 { 0x91 } else { 0x00 };
+        let dma = if self == CGB { 0x00 } else { 0xFF };
+
+        vec![
+            (IORegister::P1, 0xCF),
+            (IORegister::SB, 0x00),
+            (IORegister::SC, serial_control),
+            (IORegister::DIV, div),
+            (IORegister::TIMA, 0x00),
+            (IORegister::TMA, 0x00),
+            (IORegister::TAC, 0xF8),
+            (IORegister::IF, 0xE1),
+            (IORegister::NR10, 0x80),
+            (IORegister::NR11, 0xBF),
+            (IORegister::NR12, 0xF3),
+            (IORegister::NR13, 0xFF),
+            (IORegister::NR14, 0xBF),
+            (IORegister::NR21, 0x3F),
+            (IORegister::NR22, 0x00),
+            (IORegister::NR23, 0xFF),
+            (IORegister::NR24, 0xBF),
+            (IORegister::NR30, 0x7F),
+            (IORegister::NR31, 0xFF),
+            (IORegister::NR32, 0x9F),
+            (IORegister::NR33, 0xFF),
+            (IORegister::NR34, 0xBF),
+            (IORegister::NR41, 0xFF),
+            (IORegister::NR42, 0x00),
+            (IORegister::NR43, 0x00),
+            (IORegister::NR44, 0xBF),
+            (IORegister::NR50, 0x77),
+            (IORegister::NR51, 0xF3),
+            (IORegister::NR52, sound_on),
+            (IORegister::LCDC, 0x91),
+            (IORegister::STAT, stat),
+            (IORegister::SCY, 0x00),
+            (IORegister::SCX, 0x00),
+            (IORegister::LY, ly),
+            (IORegister::LYC, 0x00),
+            (IORegister::DMA, dma),
+            (IORegister::BGP, 0xFC),
+            (IORegister::OBP0, 0xFF),
+            (IORegister::OBP1, 0xFF),
+            (IORegister::WY, 0x00),
+            (IORegister::WX, 0x00),
+            (IORegister::IE, 0x00),
+        ]
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use Model::*;
+        match s.to_ascii_lowercase().as_str() {
+            "dmg0" => Ok(DMG0),
+            "dmg" => Ok(DMG),
+            "mgb" => Ok(MGB),
+            "sgb" => Ok(SGB),
+            "cgb" => Ok(CGB),
+            _ => Err(format!(
+                "Unknown model {}. Valid models are DMG0, DMG, MGB, SGB and CGB.",
+                s
+            )),
+        }
+    }
+}
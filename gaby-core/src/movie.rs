@@ -0,0 +1,110 @@
+//! Input movies, which replay the buttons pressed in every frame to play a
+//! game back exactly as it was played.
+
+use crate::joypad::Buttons;
+use crate::model::Model;
+use crate::state::{StateReader, StateWriter};
+#[cfg(feature = "files")]
+use std::fs;
+
+/// The buttons held in each frame, and what the Game Boy looked like before
+/// the first one. Played back from the same start, the emulation takes the
+/// same course every time.
+pub struct Movie {
+    /// Title of the game the movie was recorded with.
+    pub title: String,
+    pub model: Model,
+    /// Seed for the random contents of memory at power-on.
+    pub seed: u64,
+    /// Save state to start from, instead of power-on.
+    pub start_state: Option<Vec<u8>>,
+    /// Buttons given to `Emulator::run_frame` in each frame.
+    pub inputs: Vec<Buttons>,
+    /// Hash of the state after the last frame when the movie was recorded,
+    /// see `Emulator::state_hash`.
+    pub final_hash: Option<u64>,
+}
+
+impl Movie {
+    /// Start of every movie file, with a version number.
+    const MAGIC: &'static [u8] = b"GABY MOVIE 2";
+
+    pub fn new(title: String, model: Model, seed: u64, start_state: Option<Vec<u8>>) -> Self {
+        Self {
+            title,
+            model,
+            seed,
+            start_state,
+            inputs: Vec::new(),
+            final_hash: None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.bytes(Movie::MAGIC);
+        writer.bytes(self.title.as_bytes());
+        writer.u8(self.model as u8);
+        writer.u64(self.seed);
+        writer.bytes(self.start_state.as_deref().unwrap_or_default());
+        writer.u32(self.inputs.len() as u32);
+        for buttons in &self.inputs {
+            writer.u8(buttons.bits());
+        }
+        writer.bool(self.final_hash.is_some());
+        writer.u64(self.final_hash.unwrap_or_default());
+
+        writer.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let mut reader = StateReader::new(data);
+        if reader.bytes()? != Movie::MAGIC {
+            return Err("Not a movie for this version of Gaby.".into());
+        }
+
+        let title = String::from_utf8_lossy(reader.bytes()?).into_owned();
+        let model = reader.u8()?;
+        let model = [Model::DMG0, Model::DMG, Model::MGB, Model::SGB, Model::CGB]
+            .iter()
+            .copied()
+            .find(|&known| known as u8 == model)
+            .ok_or("The movie is for an unknown Game Boy model.")?;
+        let seed = reader.u64()?;
+        let start_state = match reader.bytes()? {
+            [] => None,
+            state => Some(state.to_vec()),
+        };
+        let inputs = (0..reader.u32()?)
+            .map(|_| reader.u8().map(Buttons::from_bits_truncate))
+            .collect::<Result<_, _>>()?;
+        let has_final_hash = reader.bool()?;
+        let final_hash = reader.u64()?;
+
+        Ok(Self {
+            title,
+            model,
+            seed,
+            start_state,
+            inputs,
+            final_hash: if has_final_hash {
+                Some(final_hash)
+            } else {
+                None
+            },
+        })
+    }
+
+    #[cfg(feature = "files")]
+    pub fn load(path: &str) -> Result<Self, String> {
+        let data =
+            fs::read(path).map_err(|error| format!("Can't read movie {}: {}", path, error))?;
+        Movie::from_bytes(&data).map_err(|error| format!("In movie {}: {}", path, error))
+    }
+
+    #[cfg(feature = "files")]
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_bytes())
+            .map_err(|error| format!("Can't write movie {}: {}", path, error))
+    }
+}
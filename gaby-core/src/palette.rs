@@ -0,0 +1,249 @@
+use crate::cartridge::Cartridge;
+use std::str::FromStr;
+
+/// Colors used for the four DMG shades, from lightest to darkest.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+impl Palette {
+    pub const GRAY: Palette = Palette([[255, 255, 255], [170, 170, 170], [85, 85, 85], [0, 0, 0]]);
+
+    /// The green tint of the original Game Boy screen.
+    pub const GREEN: Palette = Palette([
+        [0xE0, 0xF8, 0xD0],
+        [0x88, 0xC0, 0x70],
+        [0x34, 0x68, 0x56],
+        [0x08, 0x18, 0x20],
+    ]);
+
+    /// The olive gray of the Game Boy Pocket screen.
+    pub const POCKET: Palette = Palette([
+        [0xC4, 0xCF, 0xA1],
+        [0x8B, 0x95, 0x6D],
+        [0x4D, 0x53, 0x3C],
+        [0x1F, 0x1F, 0x1F],
+    ]);
+
+    const fn from_hex(colors: [u32; 4]) -> Self {
+        let mut palette = [[0; 3]; 4];
+        let mut shade = 0;
+        while shade < 4 {
+            let rgb = colors[shade];
+            palette[shade] = [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8];
+            shade += 1;
+        }
+        Palette(palette)
+    }
+}
+
+/// How the colors of CGB palettes are turned into the colors on the screen.
+///
+/// The raw colors look much more saturated than on the CGB's LCD, where the
+/// channels bleed into each other and the mid tones are darker.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ColorCorrection {
+    /// Mix the channels with the standard color correction curve.
+    pub lcd_colors: bool,
+    /// Gamma of the LCD. Values above `DISPLAY_GAMMA` darken the mid tones.
+    pub gamma: f64,
+}
+
+impl Default for ColorCorrection {
+    fn default() -> Self {
+        ColorCorrection::NONE
+    }
+}
+
+impl ColorCorrection {
+    /// Gamma of ordinary displays, which the colors are shown on.
+    pub const DISPLAY_GAMMA: f64 = 2.2;
+
+    /// The raw colors.
+    pub const NONE: ColorCorrection = ColorCorrection {
+        lcd_colors: false,
+        gamma: ColorCorrection::DISPLAY_GAMMA,
+    };
+
+    /// Correct a color given with 8 bits per channel, scaled up from the 5
+    /// bits per channel of a CGB palette entry.
+    pub fn apply(self, color: [u8; 3]) -> [u8; 3] {
+        let [red, green, blue] = color.map(|channel| (u32::from(channel) * 31 + 127) / 255);
+        let mixed = if self.lcd_colors {
+            // Each channel gets some of the others, and the result is scaled
+            // so that white stays white.
+            [
+                red * 26 + green * 4 + blue * 2,
+                green * 24 + blue * 8,
+                red * 6 + green * 4 + blue * 22,
+            ]
+            .map(|channel| f64::from(channel.min(960)) / 960.0)
+        } else {
+            [red, green, blue].map(|channel| f64::from(channel) / 31.0)
+        };
+
+        let exponent = self.gamma / ColorCorrection::DISPLAY_GAMMA;
+        mixed.map(|channel| (channel.powf(exponent) * 255.0).round() as u8)
+    }
+}
+
+/// Separate palettes for the background and the two sprite palettes, like the
+/// CGB uses when running DMG games.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Colorization {
+    pub background: Palette,
+    pub objects: [Palette; 2],
+}
+
+impl Colorization {
+    /// The palettes that can be chosen with button combinations while the CGB
+    /// boot ROM shows the logo. The last one is the default.
+    const BUILT_IN: [Colorization; 12] = [
+        // Up: brown.
+        Colorization::uniform(Palette::from_hex([0xFFFFFF, 0xFFAD63, 0x843100, 0x000000])),
+        // Up + A: red.
+        Colorization::uniform(Palette::from_hex([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000])),
+        // Up + B: dark brown.
+        Colorization::uniform(Palette::from_hex([0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108])),
+        // Left: blue.
+        Colorization {
+            background: Palette::from_hex([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000]),
+            objects: [
+                Palette::from_hex([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]),
+                Palette::from_hex([0xFFFFFF, 0x7BFF31, 0x008400, 0x000000]),
+            ],
+        },
+        // Left + A: dark blue.
+        Colorization::uniform(Palette::from_hex([0xFFFFFF, 0x8C8CDE, 0x52528C, 0x000000])),
+        // Left + B: grayscale.
+        Colorization::uniform(Palette::from_hex([0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000])),
+        // Down: pastel.
+        Colorization::uniform(Palette::from_hex([0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000])),
+        // Down + A: orange.
+        Colorization::uniform(Palette::from_hex([0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000])),
+        // Down + B: yellow.
+        Colorization {
+            background: Palette::from_hex([0xFFFFFF, 0xFFFF00, 0x7B4A00, 0x000000]),
+            objects: [
+                Palette::from_hex([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000]),
+                Palette::from_hex([0xFFFFFF, 0x7BFF31, 0x008400, 0x000000]),
+            ],
+        },
+        // Right: green.
+        Colorization::uniform(Palette::from_hex([0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000])),
+        // Right + B: inverted.
+        Colorization::uniform(Palette::from_hex([0x000000, 0x008484, 0xFFDE00, 0xFFFFFF])),
+        // Right + A: dark green.
+        Colorization {
+            background: Palette::from_hex([0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000]),
+            objects: [
+                Palette::from_hex([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]),
+                Palette::from_hex([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]),
+            ],
+        },
+    ];
+
+    /// The colors with `correction` applied to each of them.
+    pub fn corrected(self, correction: ColorCorrection) -> Self {
+        if correction == ColorCorrection::NONE {
+            return self;
+        }
+        let correct = |palette: Palette| Palette(palette.0.map(|color| correction.apply(color)));
+        Self {
+            background: correct(self.background),
+            objects: self.objects.map(correct),
+        }
+    }
+
+    /// Use the same palette for the background and the sprites.
+    pub const fn uniform(palette: Palette) -> Self {
+        Self {
+            background: palette,
+            objects: [palette, palette],
+        }
+    }
+
+    /// Pick a built-in colorization from the title checksum, like the CGB boot
+    /// ROM does. The boot ROM looks the checksum up in a table of known
+    /// games, while this uses it to pick directly among the built-in
+    /// palettes, so every game made by Nintendo gets colors.
+    pub fn for_cartridge(cartridge: &Cartridge) -> Self {
+        let default = Colorization::BUILT_IN[Colorization::BUILT_IN.len() - 1];
+        if !cartridge.is_licensed_by_nintendo() {
+            return default;
+        }
+
+        let index = usize::from(cartridge.title_checksum()) % Colorization::BUILT_IN.len();
+        Colorization::BUILT_IN[index]
+    }
+}
+
+/// The palette setting given on the command line or in the config file.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PaletteSetting {
+    /// Pick colors from the cartridge header, see `Colorization::for_cartridge`.
+    Auto,
+    Fixed(Palette),
+}
+
+impl PaletteSetting {
+    /// The colors to use for `cartridge`. The CGB palettes of the automatic
+    /// colorization get `correction`, but the fixed palettes are already the
+    /// colors to show.
+    pub fn colorization(self, cartridge: &Cartridge, correction: ColorCorrection) -> Colorization {
+        match self {
+            PaletteSetting::Auto => Colorization::for_cartridge(cartridge).corrected(correction),
+            PaletteSetting::Fixed(palette) => Colorization::uniform(palette),
+        }
+    }
+}
+
+impl FromStr for PaletteSetting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            Ok(PaletteSetting::Auto)
+        } else {
+            s.parse().map(PaletteSetting::Fixed)
+        }
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Parse a palette name, or four comma-separated hex colors like
+    /// `E0F8D0,88C070,346856,081820`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gray" | "grey" => return Ok(Palette::GRAY),
+            "green" => return Ok(Palette::GREEN),
+            "pocket" => return Ok(Palette::POCKET),
+            _ => {}
+        }
+
+        let error = || {
+            format!(
+                "Invalid palette {}. Use auto, gray, green, pocket or four hex colors like E0F8D0,88C070,346856,081820.",
+                s
+            )
+        };
+
+        let colors: Vec<&str> = s.split(',').map(str::trim).collect();
+        if colors.len() != 4 {
+            return Err(error());
+        }
+
+        let mut palette = [[0; 3]; 4];
+        for (shade, color) in palette.iter_mut().zip(colors) {
+            let hex = color.trim_start_matches('#');
+            if hex.len() != 6 {
+                return Err(error());
+            }
+            let rgb = u32::from_str_radix(hex, 16).map_err(|_| error())?;
+            *shade = [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8];
+        }
+
+        Ok(Palette(palette))
+    }
+}
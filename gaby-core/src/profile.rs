@@ -0,0 +1,40 @@
+//! Measuring where the time of a frame goes, to find out what is worth
+//! making faster.
+
+use std::time::{Duration, Instant};
+
+/// Time spent in the subsystems during a frame.
+#[derive(Clone, Copy, Default)]
+pub struct FrameTimes {
+    /// Running the CPU, and everything that isn't counted below, like the
+    /// serial port.
+    pub cpu: Duration,
+    /// Catching the video system up.
+    pub video: Duration,
+    /// Catching the audio and the timer up, which go together since the
+    /// audio is clocked by DIV.
+    pub audio: Duration,
+}
+
+/// Measures the time between laps, or nothing when it isn't needed.
+pub(crate) struct Stopwatch(Option<Instant>);
+
+impl Stopwatch {
+    /// Start measuring, if `enabled`.
+    pub fn start(enabled: bool) -> Self {
+        Stopwatch(if enabled { Some(Instant::now()) } else { None })
+    }
+
+    /// Time since the start or the last lap, or zero if not enabled.
+    pub fn lap(&mut self) -> Duration {
+        match &mut self.0 {
+            Some(last) => {
+                let now = Instant::now();
+                let elapsed = now - *last;
+                *last = now;
+                elapsed
+            }
+            None => Duration::ZERO,
+        }
+    }
+}
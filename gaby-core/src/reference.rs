@@ -0,0 +1,473 @@
+//! A simple reference implementation of the CPU instructions, written
+//! separately from the CPU of Gaby to compare it against, see `lockstep`.
+//! It decodes the opcodes from their bit fields, like the disassembler, and
+//! only keeps the registers; timing, interrupts and HALT are not emulated.
+
+use crate::lockstep::{Bus, ReferenceCore, Registers};
+
+const ZERO: u8 = 0b1000_0000;
+const SUBTRACT: u8 = 0b0100_0000;
+const HALF_CARRY: u8 = 0b0010_0000;
+const CARRY: u8 = 0b0001_0000;
+
+pub struct SimpleCore {
+    reg: Registers,
+}
+
+impl SimpleCore {
+    pub fn new(registers: Registers) -> Self {
+        Self { reg: registers }
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.reg.f & flag != 0
+    }
+
+    fn set_flags(&mut self, zero: bool, subtract: bool, half_carry: bool, carry: bool) {
+        self.reg.f = [
+            (zero, ZERO),
+            (subtract, SUBTRACT),
+            (half_carry, HALF_CARRY),
+            (carry, CARRY),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |f, (_, flag)| f | flag);
+    }
+
+    fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.reg.h, self.reg.l])
+    }
+
+    fn set_hl(&mut self, value: u16) {
+        let [h, l] = value.to_be_bytes();
+        self.reg.h = h;
+        self.reg.l = l;
+    }
+
+    fn fetch(&mut self, bus: &mut dyn Bus) -> u8 {
+        let byte = bus.read(self.reg.pc);
+        self.reg.pc = self.reg.pc.wrapping_add(1);
+        byte
+    }
+
+    fn fetch_word(&mut self, bus: &mut dyn Bus) -> u16 {
+        let low = self.fetch(bus);
+        let high = self.fetch(bus);
+        u16::from_le_bytes([low, high])
+    }
+
+    /// Byte register by its number in opcodes: B, C, D, E, H, L, (HL), A.
+    fn r(&self, bus: &mut dyn Bus, index: u8) -> u8 {
+        match index {
+            0 => self.reg.b,
+            1 => self.reg.c,
+            2 => self.reg.d,
+            3 => self.reg.e,
+            4 => self.reg.h,
+            5 => self.reg.l,
+            6 => bus.read(self.hl()),
+            _ => self.reg.a,
+        }
+    }
+
+    fn set_r(&mut self, bus: &mut dyn Bus, index: u8, value: u8) {
+        match index {
+            0 => self.reg.b = value,
+            1 => self.reg.c = value,
+            2 => self.reg.d = value,
+            3 => self.reg.e = value,
+            4 => self.reg.h = value,
+            5 => self.reg.l = value,
+            6 => bus.write(self.hl(), value),
+            _ => self.reg.a = value,
+        }
+    }
+
+    /// Word register by its number in opcodes: BC, DE, HL, and SP, or AF
+    /// for PUSH and POP.
+    fn rp(&self, index: u8, af: bool) -> u16 {
+        let reg = &self.reg;
+        match index {
+            0 => u16::from_be_bytes([reg.b, reg.c]),
+            1 => u16::from_be_bytes([reg.d, reg.e]),
+            2 => self.hl(),
+            _ if af => u16::from_be_bytes([reg.a, reg.f]),
+            _ => reg.sp,
+        }
+    }
+
+    fn set_rp(&mut self, index: u8, af: bool, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        match index {
+            0 => {
+                self.reg.b = high;
+                self.reg.c = low;
+            }
+            1 => {
+                self.reg.d = high;
+                self.reg.e = low;
+            }
+            2 => self.set_hl(value),
+            _ if af => {
+                self.reg.a = high;
+                self.reg.f = low & 0xF0;
+            }
+            _ => self.reg.sp = value,
+        }
+    }
+
+    fn condition(&self, index: u8) -> bool {
+        match index {
+            0 => !self.flag(ZERO),
+            1 => self.flag(ZERO),
+            2 => !self.flag(CARRY),
+            _ => self.flag(CARRY),
+        }
+    }
+
+    fn push(&mut self, bus: &mut dyn Bus, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        bus.write(self.reg.sp, high);
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        bus.write(self.reg.sp, low);
+    }
+
+    fn pop(&mut self, bus: &mut dyn Bus) -> u16 {
+        let low = bus.read(self.reg.sp);
+        self.reg.sp = self.reg.sp.wrapping_add(1);
+        let high = bus.read(self.reg.sp);
+        self.reg.sp = self.reg.sp.wrapping_add(1);
+        u16::from_le_bytes([low, high])
+    }
+
+    /// ADD, ADC, SUB, SBC, AND, XOR, OR or CP of A and `value`.
+    fn alu(&mut self, operation: u8, value: u8) {
+        let a = self.reg.a;
+        let carry = u8::from(self.flag(CARRY) && (operation == 1 || operation == 3));
+        let result = match operation {
+            0 | 1 => {
+                let result = a.wrapping_add(value).wrapping_add(carry);
+                let half_carry = (a & 0xF) + (value & 0xF) + carry > 0xF;
+                let carry = u16::from(a) + u16::from(value) + u16::from(carry) > 0xFF;
+                self.set_flags(result == 0, false, half_carry, carry);
+                result
+            }
+            2 | 3 | 7 => {
+                let result = a.wrapping_sub(value).wrapping_sub(carry);
+                let half_carry = (a & 0xF) < (value & 0xF) + carry;
+                let carry = u16::from(a) < u16::from(value) + u16::from(carry);
+                self.set_flags(result == 0, true, half_carry, carry);
+                if operation == 7 {
+                    return;
+                }
+                result
+            }
+            4 => {
+                let result = a & value;
+                self.set_flags(result == 0, false, true, false);
+                result
+            }
+            5 => {
+                let result = a ^ value;
+                self.set_flags(result == 0, false, false, false);
+                result
+            }
+            _ => {
+                let result = a | value;
+                self.set_flags(result == 0, false, false, false);
+                result
+            }
+        };
+        self.reg.a = result;
+    }
+
+    /// SP plus a signed byte, with the flags of ADD SP and LD HL, SP+.
+    fn sp_plus(&mut self, offset: u8) -> u16 {
+        let sp = self.reg.sp;
+        let half_carry = (sp & 0xF) + u16::from(offset & 0xF) > 0xF;
+        let carry = (sp & 0xFF) + u16::from(offset) > 0xFF;
+        self.set_flags(false, false, half_carry, carry);
+        sp.wrapping_add(offset as i8 as u16)
+    }
+
+    fn daa(&mut self) {
+        let mut a = self.reg.a;
+        let mut carry = self.flag(CARRY);
+        if self.flag(SUBTRACT) {
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if self.flag(HALF_CARRY) {
+                a = a.wrapping_sub(0x06);
+            }
+        } else {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.flag(HALF_CARRY) || a & 0xF > 0x9 {
+                a = a.wrapping_add(0x06);
+            }
+        }
+        self.reg.a = a;
+        let subtract = self.flag(SUBTRACT);
+        self.set_flags(a == 0, subtract, false, carry);
+    }
+
+    /// Rotations and shifts, the first quarter of the CB table. The rotations
+    /// of A without CB always clear the zero flag.
+    fn shift(&mut self, operation: u8, value: u8) -> u8 {
+        let carry_in = u8::from(self.flag(CARRY));
+        let (result, carry) = match operation {
+            0 => (value.rotate_left(1), value & 0x80 != 0),
+            1 => (value.rotate_right(1), value & 0x01 != 0),
+            2 => (value << 1 | carry_in, value & 0x80 != 0),
+            3 => (value >> 1 | carry_in << 7, value & 0x01 != 0),
+            4 => (value << 1, value & 0x80 != 0),
+            5 => (value >> 1 | (value & 0x80), value & 0x01 != 0),
+            6 => (value.rotate_left(4), false),
+            _ => (value >> 1, value & 0x01 != 0),
+        };
+        self.set_flags(result == 0, false, false, carry);
+        result
+    }
+
+    fn execute_cb(&mut self, bus: &mut dyn Bus) {
+        let opcode = self.fetch(bus);
+        let bit = (opcode >> 3) & 0b111;
+        let z = opcode & 0b111;
+        let value = self.r(bus, z);
+        match opcode >> 6 {
+            0 => {
+                let result = self.shift(bit, value);
+                self.set_r(bus, z, result);
+            }
+            1 => {
+                let carry = self.flag(CARRY);
+                self.set_flags(value & (1 << bit) == 0, false, true, carry);
+            }
+            2 => self.set_r(bus, z, value & !(1 << bit)),
+            _ => self.set_r(bus, z, value | (1 << bit)),
+        }
+    }
+}
+
+impl ReferenceCore for SimpleCore {
+    fn registers(&self) -> Registers {
+        self.reg
+    }
+
+    fn set_registers(&mut self, registers: Registers) {
+        self.reg = registers;
+    }
+
+    fn step(&mut self, bus: &mut dyn Bus) -> Result<(), String> {
+        let address = self.reg.pc;
+        let opcode = self.fetch(bus);
+        // The opcode is decoded from its bit fields, xxyyyzzz, where yyy is
+        // also split into ppq.
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 0b111;
+        let z = opcode & 0b111;
+        let p = y >> 1;
+        let q = y & 1;
+
+        match (x, z) {
+            (0, 0) => match y {
+                0 => {}
+                1 => {
+                    let address = self.fetch_word(bus);
+                    let [low, high] = self.reg.sp.to_le_bytes();
+                    bus.write(address, low);
+                    bus.write(address.wrapping_add(1), high);
+                }
+                2 => {
+                    self.fetch(bus);
+                }
+                _ => {
+                    let offset = self.fetch(bus) as i8 as u16;
+                    if y == 3 || self.condition(y - 4) {
+                        self.reg.pc = self.reg.pc.wrapping_add(offset);
+                    }
+                }
+            },
+            (0, 1) if q == 0 => {
+                let value = self.fetch_word(bus);
+                self.set_rp(p, false, value);
+            }
+            (0, 1) => {
+                let hl = self.hl();
+                let value = self.rp(p, false);
+                let half_carry = (hl & 0xFFF) + (value & 0xFFF) > 0xFFF;
+                let carry = u32::from(hl) + u32::from(value) > 0xFFFF;
+                let zero = self.flag(ZERO);
+                self.set_flags(zero, false, half_carry, carry);
+                self.set_hl(hl.wrapping_add(value));
+            }
+            (0, 2) => {
+                let address = match p {
+                    0 | 1 => self.rp(p, false),
+                    _ => self.hl(),
+                };
+                if q == 0 {
+                    bus.write(address, self.reg.a);
+                } else {
+                    self.reg.a = bus.read(address);
+                }
+                match p {
+                    2 => self.set_hl(address.wrapping_add(1)),
+                    3 => self.set_hl(address.wrapping_sub(1)),
+                    _ => {}
+                }
+            }
+            (0, 3) => {
+                let value = self.rp(p, false);
+                let value = if q == 0 {
+                    value.wrapping_add(1)
+                } else {
+                    value.wrapping_sub(1)
+                };
+                self.set_rp(p, false, value);
+            }
+            (0, 4) | (0, 5) => {
+                let value = self.r(bus, y);
+                let carry = self.flag(CARRY);
+                let result = if z == 4 {
+                    let result = value.wrapping_add(1);
+                    self.set_flags(result == 0, false, value & 0xF == 0xF, carry);
+                    result
+                } else {
+                    let result = value.wrapping_sub(1);
+                    self.set_flags(result == 0, true, value & 0xF == 0, carry);
+                    result
+                };
+                self.set_r(bus, y, result);
+            }
+            (0, 6) => {
+                let value = self.fetch(bus);
+                self.set_r(bus, y, value);
+            }
+            (0, _) => match y {
+                0..=3 => {
+                    self.reg.a = self.shift(y, self.reg.a);
+                    self.reg.f &= !ZERO;
+                }
+                4 => self.daa(),
+                5 => {
+                    self.reg.a = !self.reg.a;
+                    self.reg.f |= SUBTRACT | HALF_CARRY;
+                }
+                6 => {
+                    let zero = self.flag(ZERO);
+                    self.set_flags(zero, false, false, true);
+                }
+                _ => {
+                    let (zero, carry) = (self.flag(ZERO), self.flag(CARRY));
+                    self.set_flags(zero, false, false, !carry);
+                }
+            },
+            // HALT only stops the CPU, which is left to Gaby.
+            (1, _) if opcode == 0x76 => {}
+            (1, _) => {
+                let value = self.r(bus, z);
+                self.set_r(bus, y, value);
+            }
+            (2, _) => {
+                let value = self.r(bus, z);
+                self.alu(y, value);
+            }
+            (_, 0) => match y {
+                0..=3 => {
+                    if self.condition(y) {
+                        self.reg.pc = self.pop(bus);
+                    }
+                }
+                4 => {
+                    let address = 0xFF00 | u16::from(self.fetch(bus));
+                    bus.write(address, self.reg.a);
+                }
+                5 => {
+                    let offset = self.fetch(bus);
+                    self.reg.sp = self.sp_plus(offset);
+                }
+                6 => {
+                    let address = 0xFF00 | u16::from(self.fetch(bus));
+                    self.reg.a = bus.read(address);
+                }
+                _ => {
+                    let offset = self.fetch(bus);
+                    let value = self.sp_plus(offset);
+                    self.set_hl(value);
+                }
+            },
+            (_, 1) if q == 0 => {
+                let value = self.pop(bus);
+                self.set_rp(p, true, value);
+            }
+            (_, 1) => match p {
+                // RET and RETI.
+                0 | 1 => self.reg.pc = self.pop(bus),
+                2 => self.reg.pc = self.hl(),
+                _ => self.reg.sp = self.hl(),
+            },
+            (_, 2) => match y {
+                0..=3 => {
+                    let target = self.fetch_word(bus);
+                    if self.condition(y) {
+                        self.reg.pc = target;
+                    }
+                }
+                4 => bus.write(0xFF00 | u16::from(self.reg.c), self.reg.a),
+                5 => {
+                    let address = self.fetch_word(bus);
+                    bus.write(address, self.reg.a);
+                }
+                6 => self.reg.a = bus.read(0xFF00 | u16::from(self.reg.c)),
+                _ => {
+                    let address = self.fetch_word(bus);
+                    self.reg.a = bus.read(address);
+                }
+            },
+            (_, 3) => match y {
+                0 => self.reg.pc = self.fetch_word(bus),
+                1 => self.execute_cb(bus),
+                // DI and EI only change IME, which isn't compared.
+                6 | 7 => {}
+                _ => return Err(invalid(opcode, address)),
+            },
+            (_, 4) if y < 4 => {
+                let target = self.fetch_word(bus);
+                if self.condition(y) {
+                    self.push(bus, self.reg.pc);
+                    self.reg.pc = target;
+                }
+            }
+            (_, 5) if q == 0 => {
+                let value = self.rp(p, true);
+                self.push(bus, value);
+            }
+            (_, 5) if y == 1 => {
+                let target = self.fetch_word(bus);
+                self.push(bus, self.reg.pc);
+                self.reg.pc = target;
+            }
+            (_, 6) => {
+                let value = self.fetch(bus);
+                self.alu(y, value);
+            }
+            (_, 7) => {
+                self.push(bus, self.reg.pc);
+                self.reg.pc = u16::from(y) * 8;
+            }
+            _ => return Err(invalid(opcode, address)),
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(opcode: u8, address: u16) -> String {
+    format!("Invalid opcode {:#04X} at address {:#06X}", opcode, address)
+}
@@ -0,0 +1,232 @@
+//! Running the CPU ahead of the other subsystems. Ticking every subsystem
+//! every tick is slow, and most of those ticks only count down. Instead, the
+//! CPU runs alone until the next tick where the video, timer or serial port
+//! may do something that it can see without reading an I/O register, like
+//! requesting an interrupt or reading VRAM to render a line. The other
+//! subsystems are caught up at that tick, skipping the ticks where they only
+//! count down, or earlier if the CPU accesses the I/O registers.
+//!
+//! While catching up, the video system and the serial port go on their own,
+//! from one event of theirs to the next, since they don't look at each other
+//! or at the timer and audio. Those two go together, since the frame
+//! sequencer of the audio is clocked by DIV.
+
+use crate::audio::Audio;
+use crate::memory::Memory;
+use crate::profile::{FrameTimes, Stopwatch};
+use crate::serial::Serial;
+use crate::timer::Timer;
+use crate::video::Video;
+use std::ops::RangeInclusive;
+
+/// Addresses the CPU can only access once the other subsystems have caught
+/// up, since they read and write them behind its back.
+pub const SHARED: RangeInclusive<u16> = 0xFF00..=0xFF7F;
+
+/// Something the CPU runs ahead of.
+pub trait CatchUp {
+    /// Bring it up to the current cycle, `mem.cycles`.
+    fn catch_up(&mut self, mem: &mut Memory);
+}
+
+/// The subsystems other than the CPU, lent by the emulator while the CPU
+/// runs ahead of them.
+pub struct Devices<'a> {
+    pub timer: &'a mut Timer,
+    pub video: &'a mut Video,
+    pub audio: &'a mut Audio,
+    pub serial: &'a mut Serial,
+    /// The last cycle the subsystems have done.
+    synced: u64,
+    /// An error from catching up on behalf of the CPU, which can't return
+    /// it in the middle of an instruction.
+    error: Option<String>,
+    /// Time spent catching up the video and the audio, when measured.
+    pub times: Option<FrameTimes>,
+}
+
+impl<'a> Devices<'a> {
+    /// The subsystems have done all cycles up to `mem.cycles`.
+    pub fn new(
+        mem: &Memory,
+        timer: &'a mut Timer,
+        video: &'a mut Video,
+        audio: &'a mut Audio,
+        serial: &'a mut Serial,
+    ) -> Self {
+        Self {
+            timer,
+            video,
+            audio,
+            serial,
+            synced: mem.cycles,
+            error: None,
+            times: None,
+        }
+    }
+
+    /// Perform one tick, the one at `mem.cycles`.
+    pub fn tick(&mut self, mem: &mut Memory) -> Result<(), String> {
+        // The timer goes first, since the frame sequencer of the audio is
+        // clocked by DIV.
+        self.timer.tick(mem)?;
+        self.video.tick(mem)?;
+        self.audio.tick(mem)?;
+        self.serial.tick(mem)
+    }
+
+    /// The last cycle the subsystems have done.
+    pub fn synced(&self) -> u64 {
+        self.synced
+    }
+
+    /// The first cycle after `synced` where the CPU can't run ahead, since
+    /// the video, timer or serial port may do something it can see.
+    pub fn next_event(&self, mem: &Memory) -> u64 {
+        let idle = self
+            .video
+            .idle_ticks(mem)
+            .min(self.timer.idle_ticks(mem))
+            .min(self.serial.idle_ticks(mem));
+        self.synced + u64::from(idle) + 1
+    }
+
+    /// Do the cycles up to and including `cycle`. The ticks where a
+    /// subsystem only counts down are done at once.
+    pub fn sync(&mut self, mem: &mut Memory, cycle: u64) -> Result<(), String> {
+        let cpu_cycles = mem.cycles;
+        let result = self.sync_to(mem, cycle);
+        mem.cycles = cpu_cycles;
+        result
+    }
+
+    fn sync_to(&mut self, mem: &mut Memory, cycle: u64) -> Result<(), String> {
+        if self.synced >= cycle {
+            return Ok(());
+        }
+
+        let mut clocked = Clocked {
+            timer: &mut *self.timer,
+            audio: &mut *self.audio,
+        };
+        let mut stopwatch = Stopwatch::start(self.times.is_some());
+        Devices::run(&mut clocked, mem, self.synced, cycle)?;
+        let audio = stopwatch.lap();
+        Devices::run(&mut *self.video, mem, self.synced, cycle)?;
+        let video = stopwatch.lap();
+        Devices::run(&mut *self.serial, mem, self.synced, cycle)?;
+        self.synced = cycle;
+
+        if let Some(times) = &mut self.times {
+            times.audio += audio;
+            times.video += video;
+        }
+
+        Ok(())
+    }
+
+    /// Do the cycles after `synced` up to and including `cycle` of one
+    /// subsystem.
+    fn run(
+        subsystem: &mut impl Steps,
+        mem: &mut Memory,
+        mut synced: u64,
+        cycle: u64,
+    ) -> Result<(), String> {
+        while synced < cycle {
+            let idle = subsystem.idle_ticks(mem);
+            let ticks = u64::from(idle).min(cycle - synced) as u32;
+
+            if ticks > 0 {
+                subsystem.skip(mem, ticks);
+                synced += u64::from(ticks);
+            } else {
+                // Subsystems that timestamp what they do see the cycle they
+                // are at, not the one of the CPU.
+                synced += 1;
+                mem.cycles = synced;
+                subsystem.tick(mem)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take the error from catching up on behalf of the CPU, if there was
+    /// one.
+    pub fn take_error(&mut self) -> Result<(), String> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl CatchUp for Devices<'_> {
+    fn catch_up(&mut self, mem: &mut Memory) {
+        if self.synced < mem.cycles && self.error.is_none() {
+            if let Err(error) = self.sync(mem, mem.cycles) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+/// A subsystem that can be caught up on its own.
+trait Steps {
+    fn idle_ticks(&self, mem: &Memory) -> u32;
+    fn skip(&mut self, mem: &mut Memory, ticks: u32);
+    fn tick(&mut self, mem: &mut Memory) -> Result<(), String>;
+}
+
+impl Steps for Video {
+    fn idle_ticks(&self, mem: &Memory) -> u32 {
+        Video::idle_ticks(self, mem)
+    }
+
+    fn skip(&mut self, mem: &mut Memory, ticks: u32) {
+        Video::skip(self, mem, ticks);
+    }
+
+    fn tick(&mut self, mem: &mut Memory) -> Result<(), String> {
+        Video::tick(self, mem)
+    }
+}
+
+impl Steps for Serial {
+    fn idle_ticks(&self, mem: &Memory) -> u32 {
+        Serial::idle_ticks(self, mem)
+    }
+
+    fn skip(&mut self, _mem: &mut Memory, ticks: u32) {
+        Serial::skip(self, ticks);
+    }
+
+    fn tick(&mut self, mem: &mut Memory) -> Result<(), String> {
+        Serial::tick(self, mem)
+    }
+}
+
+/// The timer and the audio, which have to be ticked together since the audio
+/// reads DIV.
+struct Clocked<'a> {
+    timer: &'a mut Timer,
+    audio: &'a mut Audio,
+}
+
+impl Steps for Clocked<'_> {
+    fn idle_ticks(&self, mem: &Memory) -> u32 {
+        self.timer.idle_ticks(mem).min(self.audio.idle_ticks(mem))
+    }
+
+    fn skip(&mut self, mem: &mut Memory, ticks: u32) {
+        self.timer.skip(mem, ticks);
+        self.audio.skip(mem, ticks);
+    }
+
+    fn tick(&mut self, mem: &mut Memory) -> Result<(), String> {
+        // The timer goes first, like in `Devices::tick`.
+        self.timer.tick(mem)?;
+        self.audio.tick(mem)
+    }
+}
@@ -0,0 +1,60 @@
+/// The latest output samples of each of the four channels, as they go into
+/// the mix, for drawing their waveforms and levels while debugging the
+/// audio.
+pub struct Scope {
+    /// Samples of each channel, in a ring buffer starting at `next`.
+    samples: [Vec<f32>; 4],
+    next: usize,
+}
+
+impl Scope {
+    /// Number of samples kept of each channel, about a frame at the default
+    /// sample rate.
+    pub const LENGTH: usize = 1024;
+    /// Largest swing of a channel's output, from its lowest to its highest
+    /// value.
+    pub const FULL_SCALE: f32 = 0.025;
+
+    pub fn new() -> Self {
+        Self {
+            samples: [
+                vec![0.0; Scope::LENGTH],
+                vec![0.0; Scope::LENGTH],
+                vec![0.0; Scope::LENGTH],
+                vec![0.0; Scope::LENGTH],
+            ],
+            next: 0,
+        }
+    }
+
+    pub fn write(&mut self, channels: [f32; 4]) {
+        for (samples, sample) in self.samples.iter_mut().zip(channels) {
+            samples[self.next] = sample;
+        }
+        self.next = (self.next + 1) % Scope::LENGTH;
+    }
+
+    /// The kept samples of `channel`, from the oldest to the newest.
+    pub fn waveform(&self, channel: usize) -> impl Iterator<Item = f32> + '_ {
+        let samples = &self.samples[channel];
+        samples[self.next..]
+            .iter()
+            .chain(&samples[..self.next])
+            .copied()
+    }
+
+    /// Peak-to-peak level of `channel` over the kept samples, from 0 to 1,
+    /// where a channel playing at full volume is at 1.
+    pub fn level(&self, channel: usize) -> f32 {
+        let samples = &self.samples[channel];
+        let min = samples.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = samples.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        ((max - min) / Scope::FULL_SCALE).min(1.0)
+    }
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
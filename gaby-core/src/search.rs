@@ -0,0 +1,81 @@
+//! Searching RAM for the address of a game variable, like the cheat searches
+//! of game enhancers: start with every address, and keep narrowing them down
+//! by how the value has changed since the last step.
+
+use crate::memory::Memory;
+use std::ops::RangeInclusive;
+
+/// How the byte at an address must compare for it to stay a candidate.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SearchFilter {
+    /// The byte is this value.
+    Equal(u8),
+    /// The byte has changed since the last step.
+    Changed,
+    Unchanged,
+    /// The byte is greater than at the last step.
+    Greater,
+    Less,
+}
+
+/// Addresses whose bytes match all the filters so far, with their values at
+/// the last step.
+pub struct MemorySearch {
+    candidates: Vec<(u16, u8)>,
+}
+
+impl MemorySearch {
+    /// Where games keep their variables: work RAM and high RAM, and the
+    /// external RAM of the cartridge, if it has any.
+    const WORK_RAM: RangeInclusive<u16> = 0xC000..=0xDFFF;
+    const HIGH_RAM: RangeInclusive<u16> = 0xFF80..=0xFFFE;
+    const EXTERNAL_RAM: RangeInclusive<u16> = 0xA000..=0xBFFF;
+
+    /// Start a search with every address in RAM.
+    pub fn new(mem: &Memory) -> Self {
+        let external = if mem.cartridge.ram().is_empty() {
+            None
+        } else {
+            Some(MemorySearch::EXTERNAL_RAM)
+        };
+        let candidates = external
+            .into_iter()
+            .flatten()
+            .chain(MemorySearch::WORK_RAM)
+            .chain(MemorySearch::HIGH_RAM)
+            .map(|address| (address, mem.peek(address)))
+            .collect();
+
+        Self { candidates }
+    }
+
+    /// Keep the candidates whose bytes match `filter`, and remember their
+    /// values for the next step.
+    pub fn filter(&mut self, mem: &Memory, filter: SearchFilter) {
+        self.candidates.retain_mut(|(address, last)| {
+            let value = mem.peek(*address);
+            let keep = match filter {
+                SearchFilter::Equal(wanted) => value == wanted,
+                SearchFilter::Changed => value != *last,
+                SearchFilter::Unchanged => value == *last,
+                SearchFilter::Greater => value > *last,
+                SearchFilter::Less => value < *last,
+            };
+            *last = value;
+            keep
+        });
+    }
+
+    /// The addresses left, in order.
+    pub fn addresses(&self) -> impl Iterator<Item = u16> + '_ {
+        self.candidates.iter().map(|&(address, _)| address)
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
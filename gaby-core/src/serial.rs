@@ -0,0 +1,257 @@
+use crate::events::Event;
+use crate::memory::{IORegister, Memory, WriteHook};
+use crate::model::Model;
+use crate::state::{StateReader, StateWriter};
+use std::io::{self, Write};
+#[cfg(feature = "files")]
+use std::{fs::File, io::BufWriter};
+
+/// Something connected to the serial port. It must be `Send`, like the rest
+/// of the emulator, so that the emulator can be moved to another thread.
+pub trait SerialPeer: Send {
+    /// Exchange bytes in a transfer clocked by this side. `outgoing` is sent
+    /// to the peer, and the byte the peer sends back is returned.
+    fn transfer(&mut self, outgoing: u8) -> u8;
+
+    /// Check if the peer has clocked a transfer, and if so, send `outgoing`
+    /// back and return the byte the peer sent.
+    fn poll(&mut self, outgoing: u8) -> Option<u8>;
+}
+
+/// The serial port. A transfer is started by setting bit 7 of SC, and with
+/// the internal clock the 8 bits of SB are shifted out at 8192 Hz, while the
+/// bits coming in are shifted in. Without a peer, the incoming bits are all
+/// 1, and a transfer with the external clock waits for a clock that never
+/// comes.
+pub struct Serial {
+    register_writes: WriteHook,
+    pub peer: Option<Box<dyn SerialPeer>>,
+    pub log: Option<SerialLog>,
+    /// Number of bytes sent and received, in transfers clocked by either
+    /// side.
+    pub bytes_transferred: u64,
+    /// Bits left of the transfer in progress.
+    bits_left: u8,
+    /// Ticks until the next bit is shifted.
+    bit_timer: u32,
+    /// Byte being shifted in.
+    incoming: u8,
+    /// Ticks until the peer is checked for a transfer clocked by it.
+    poll_timer: u32,
+}
+
+impl Serial {
+    /// Ticks per bit at 8192 Hz.
+    const TICKS_PER_BIT: u32 = 128;
+    /// Ticks per bit with the fast clock of the CGB, at 262144 Hz.
+    const FAST_TICKS_PER_BIT: u32 = 4;
+    /// Ticks between checks for transfers clocked by the peer.
+    const POLL_INTERVAL: u32 = 128;
+
+    pub fn new(mem: &mut Memory) -> Self {
+        let register_writes = mem.add_write_hook(IORegister::SC..=IORegister::SC);
+
+        Self {
+            register_writes,
+            peer: None,
+            log: None,
+            bytes_transferred: 0,
+            bits_left: 0,
+            bit_timer: 0,
+            incoming: 0xFF,
+            poll_timer: Serial::POLL_INTERVAL,
+        }
+    }
+
+    pub fn tick(&mut self, mem: &mut Memory) -> Result<(), String> {
+        while let Some(event) = mem.next_write(self.register_writes) {
+            let start = event.data & 0b1000_0000 != 0;
+            let internal_clock = event.data & 0b0000_0001 != 0;
+            if start && internal_clock {
+                // The whole byte is exchanged with the peer at the start, and
+                // then shifted in bit by bit.
+                let outgoing = mem[IORegister::SB];
+                self.incoming = match &mut self.peer {
+                    Some(peer) => peer.transfer(outgoing),
+                    None => 0xFF,
+                };
+                if let Some(log) = &mut self.log {
+                    log.write(mem, outgoing, self.incoming);
+                }
+                tracing::debug!(
+                    target: "serial",
+                    "Sent {:02X}, received {:02X} with the internal clock",
+                    outgoing,
+                    self.incoming
+                );
+                self.bits_left = 8;
+                self.bytes_transferred += 1;
+                mem.events.signal(Event::SerialTransfer);
+                mem.callbacks.serial_byte(outgoing);
+                self.bit_timer = Serial::ticks_per_bit(mem);
+            } else {
+                self.bits_left = 0;
+            }
+        }
+
+        if self.bits_left == 0 {
+            self.poll_peer(mem);
+            return Ok(());
+        }
+
+        self.bit_timer -= 1;
+        if self.bit_timer == 0 {
+            mem[IORegister::SB] = (mem[IORegister::SB] << 1) | (self.incoming >> 7);
+            self.incoming <<= 1;
+            self.bits_left -= 1;
+
+            if self.bits_left == 0 {
+                Serial::complete_transfer(mem);
+            } else {
+                self.bit_timer = Serial::ticks_per_bit(mem);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of the coming ticks that only count down, and can be done at
+    /// once with `skip`: until the next bit is shifted or the peer is
+    /// checked.
+    pub fn idle_ticks(&self, mem: &Memory) -> u32 {
+        if mem.has_writes(self.register_writes) {
+            0
+        } else if self.bits_left > 0 {
+            self.bit_timer - 1
+        } else if self.peer.is_some() {
+            self.poll_timer - 1
+        } else {
+            u32::MAX
+        }
+    }
+
+    /// Do `ticks` ticks at once. There must be at least that many idle
+    /// ticks, see `idle_ticks`.
+    pub fn skip(&mut self, ticks: u32) {
+        if self.bits_left > 0 {
+            self.bit_timer -= ticks;
+        } else if self.peer.is_some() {
+            self.poll_timer -= ticks;
+        }
+    }
+
+    /// Check now and then if the peer has clocked a transfer, and complete it
+    /// if one with the external clock is waiting.
+    fn poll_peer(&mut self, mem: &mut Memory) {
+        let peer = match &mut self.peer {
+            Some(peer) => peer,
+            None => return,
+        };
+
+        self.poll_timer -= 1;
+        if self.poll_timer > 0 {
+            return;
+        }
+        self.poll_timer = Serial::POLL_INTERVAL;
+
+        if let Some(incoming) = peer.poll(mem[IORegister::SB]) {
+            let waiting = mem[IORegister::SC] & 0b1000_0001 == 0b1000_0000;
+            if waiting {
+                tracing::debug!(
+                    target: "serial",
+                    "Sent {:02X}, received {:02X} with the external clock",
+                    mem[IORegister::SB],
+                    incoming
+                );
+                if let Some(log) = &mut self.log {
+                    log.write(mem, mem[IORegister::SB], incoming);
+                }
+                mem.callbacks.serial_byte(mem[IORegister::SB]);
+                mem[IORegister::SB] = incoming;
+                Serial::complete_transfer(mem);
+                self.bytes_transferred += 1;
+                mem.events.signal(Event::SerialTransfer);
+            }
+        }
+    }
+
+    /// Clear the start bit of SC and request the serial interrupt.
+    pub fn complete_transfer(mem: &mut Memory) {
+        mem[IORegister::SC] &= 0b0111_1111;
+        mem[IORegister::IF] |= 0b0000_1000;
+    }
+
+    /// The CGB can select a fast clock with bit 1 of SC.
+    fn ticks_per_bit(mem: &Memory) -> u32 {
+        if mem.model == Model::CGB && mem[IORegister::SC] & 0b0000_0010 != 0 {
+            Serial::FAST_TICKS_PER_BIT
+        } else {
+            Serial::TICKS_PER_BIT
+        }
+    }
+
+    /// Save the state of the transfer in progress. The peer is not part of
+    /// the state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.bits_left);
+        state.u32(self.bit_timer);
+        state.u8(self.incoming);
+        state.u32(self.poll_timer);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.bits_left = state.u8()?;
+        self.bit_timer = state.u32()?;
+        self.incoming = state.u8()?;
+        self.poll_timer = state.u32()?;
+
+        Ok(())
+    }
+}
+
+/// Log of the bytes exchanged in serial transfers, one transfer per line.
+pub struct SerialLog {
+    file: Option<Box<dyn Write + Send>>,
+}
+
+impl SerialLog {
+    /// Log to `file`, which may be anything written to, not just a file.
+    pub fn new(mut file: impl Write + Send + 'static) -> io::Result<Self> {
+        writeln!(file, "# Cycle, byte sent, byte received, SC, clock")?;
+
+        Ok(Self {
+            file: Some(Box::new(file)),
+        })
+    }
+
+    #[cfg(feature = "files")]
+    pub fn create(path: &str) -> io::Result<Self> {
+        SerialLog::new(BufWriter::new(File::create(path)?))
+    }
+
+    fn write(&mut self, mem: &Memory, sent: u8, received: u8) {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return,
+        };
+
+        let sc = mem[IORegister::SC];
+        let clock = if sc & 0b0000_0001 != 0 {
+            "internal"
+        } else {
+            "external"
+        };
+        let result = writeln!(
+            file,
+            "{:12} sent {:02X} received {:02X} SC {:02X} {}",
+            mem.cycles, sent, received, sc, clock
+        );
+
+        // Stop logging after an error, instead of reporting it for every
+        // transfer.
+        if let Err(error) = result {
+            tracing::error!(target: "serial", "Failed to write serial log: {}", error);
+            self.file = None;
+        }
+    }
+}
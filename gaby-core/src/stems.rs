@@ -0,0 +1,110 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const FILE_NAMES: [&str; 5] = [
+    "mix.wav",
+    "channel1.wav",
+    "channel2.wav",
+    "channel3.wav",
+    "channel4.wav",
+];
+
+/// Writes the audio output to WAV files, with the mix and each of the four
+/// channels in separate files. The channel files contain each channel's
+/// contribution to the mix, so they add up to the mix.
+pub struct StemWriter {
+    files: Vec<WavWriter>,
+    /// First error while writing, reported by `finish`.
+    error: Option<io::Error>,
+}
+
+impl StemWriter {
+    /// Create the WAV files in `dir`, which is created if it doesn't exist.
+    pub fn create(dir: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let files = FILE_NAMES
+            .iter()
+            .map(|name| WavWriter::create(&Path::new(dir).join(name)))
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self { files, error: None })
+    }
+
+    pub fn write(&mut self, mix: f32, channels: [f32; 4]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let samples = [mix, channels[0], channels[1], channels[2], channels[3]];
+        for (file, sample) in self.files.iter_mut().zip(samples) {
+            if let Err(error) = file.write(sample) {
+                self.error = Some(error);
+                return;
+            }
+        }
+    }
+
+    /// Fill in the sizes in the WAV headers, and close the files.
+    pub fn finish(self, sample_rate: u32) -> io::Result<()> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        for file in self.files {
+            file.finish(sample_rate)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// 16-bit mono PCM WAV file.
+struct WavWriter {
+    file: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavWriter {
+    const HEADER_SIZE: u32 = 44;
+
+    fn create(path: &Path) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        // The header is written by `finish`, when the sizes are known.
+        file.write_all(&[0; WavWriter::HEADER_SIZE as usize])?;
+
+        Ok(Self { file, samples: 0 })
+    }
+
+    fn write(&mut self, sample: f32) -> io::Result<()> {
+        let value = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+        self.samples += 1;
+        self.file.write_all(&value.to_le_bytes())
+    }
+
+    fn finish(mut self, sample_rate: u32) -> io::Result<()> {
+        let data_size = 2 * self.samples;
+
+        let mut header = Vec::with_capacity(WavWriter::HEADER_SIZE as usize);
+        header.extend(b"RIFF");
+        header.extend((WavWriter::HEADER_SIZE - 8 + data_size).to_le_bytes());
+        header.extend(b"WAVE");
+        header.extend(b"fmt ");
+        header.extend(16u32.to_le_bytes()); // Size of the format chunk.
+        header.extend(1u16.to_le_bytes()); // PCM.
+        header.extend(1u16.to_le_bytes()); // Channels.
+        header.extend(sample_rate.to_le_bytes());
+        header.extend((2 * sample_rate).to_le_bytes()); // Bytes per second.
+        header.extend(2u16.to_le_bytes()); // Bytes per sample.
+        header.extend(16u16.to_le_bytes()); // Bits per sample.
+        header.extend(b"data");
+        header.extend(data_size.to_le_bytes());
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.flush()
+    }
+}
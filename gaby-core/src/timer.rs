@@ -0,0 +1,147 @@
+use crate::memory::{IORegister, Memory, WriteHook};
+use crate::state::{StateReader, StateWriter};
+
+/// The timer is driven by a 16-bit system counter that is incremented every
+/// 4 MiHz clock cycle, and DIV is the upper 8 bits of it. TIMA is incremented
+/// when the counter bit selected by TAC, AND-ed with the timer enable bit,
+/// goes from 1 to 0. This means that resetting DIV or changing TAC can also
+/// increment TIMA, like on the hardware.
+pub struct Timer {
+    register_writes: WriteHook,
+    system_counter: u16,
+    /// Input to the falling edge detector at the last update.
+    timer_signal: bool,
+    /// TIMA overflowed in the last cycle, and is reloaded from TMA in this
+    /// one.
+    reload_pending: bool,
+}
+
+impl Timer {
+    /// System counter clock cycles per tick.
+    const CYCLES_PER_TICK: u16 = 4;
+
+    pub fn new(mem: &mut Memory) -> Self {
+        let register_writes = mem.add_write_hook(IORegister::DIV..=IORegister::TAC);
+        let system_counter = u16::from(mem[IORegister::DIV]) << 8;
+
+        Self {
+            register_writes,
+            system_counter,
+            timer_signal: false,
+            reload_pending: false,
+        }
+    }
+
+    pub fn tick(&mut self, mem: &mut Memory) -> Result<(), String> {
+        while let Some(event) = mem.next_write(self.register_writes) {
+            match event.address {
+                IORegister::DIV => {
+                    // Writing to DIV resets the whole counter. Account for the
+                    // cycles that have passed since the write happened.
+                    let elapsed = (mem.cycles - event.cycle).saturating_sub(1) as u16;
+                    self.system_counter = elapsed.wrapping_mul(Timer::CYCLES_PER_TICK);
+                }
+                // Writing TIMA right after it overflowed cancels the reload.
+                IORegister::TIMA => self.reload_pending = false,
+                _ => {}
+            }
+            self.update_timer_signal(mem);
+        }
+
+        if self.reload_pending {
+            tracing::trace!(target: "timer", "TIMA reloaded with {:02X}", mem[IORegister::TMA]);
+            mem[IORegister::TIMA] = mem[IORegister::TMA];
+            mem[IORegister::IF] |= 0b0000_0100;
+            self.reload_pending = false;
+        }
+
+        self.system_counter = self.system_counter.wrapping_add(Timer::CYCLES_PER_TICK);
+        mem[IORegister::DIV] = (self.system_counter >> 8) as u8;
+        self.update_timer_signal(mem);
+
+        Ok(())
+    }
+
+    /// Number of the coming ticks that only count, and can be done at once
+    /// with `skip`: until bit 4 of DIV changes, which clocks the frame
+    /// sequencer of the audio, or until TIMA overflows.
+    pub fn idle_ticks(&self, mem: &Memory) -> u32 {
+        if self.reload_pending || mem.has_writes(self.register_writes) {
+            return 0;
+        }
+
+        let counter = u32::from(self.system_counter);
+        let cycles_per_tick = u32::from(Timer::CYCLES_PER_TICK);
+        let until_div_bit_change = (0x1000 - (counter & 0xFFF)) / cycles_per_tick;
+
+        let (bit, enabled) = Timer::selected_bit(mem);
+        if !enabled {
+            return until_div_bit_change - 1;
+        }
+        // TIMA is incremented every time the counter passes a multiple of
+        // twice the selected bit.
+        let period = (2 << bit) / cycles_per_tick;
+        let until_increment = ((2 << bit) - (counter & ((2 << bit) - 1))) / cycles_per_tick;
+        let increments_left = u32::from(0xFF - mem[IORegister::TIMA]);
+        let until_overflow = until_increment + increments_left * period;
+
+        until_div_bit_change.min(until_overflow) - 1
+    }
+
+    /// Do `ticks` ticks at once. There must be at least that many idle
+    /// ticks, see `idle_ticks`.
+    pub fn skip(&mut self, mem: &mut Memory, ticks: u32) {
+        let (bit, enabled) = Timer::selected_bit(mem);
+        let old_counter = self.system_counter;
+        self.system_counter = self
+            .system_counter
+            .wrapping_add(ticks as u16 * Timer::CYCLES_PER_TICK);
+        mem[IORegister::DIV] = (self.system_counter >> 8) as u8;
+
+        if enabled {
+            let increments = (self.system_counter >> (bit + 1)) - (old_counter >> (bit + 1));
+            mem[IORegister::TIMA] += increments as u8;
+        }
+        self.timer_signal = enabled && (self.system_counter >> bit) & 1 != 0;
+    }
+
+    /// The counter bit that clocks TIMA, and whether the timer is enabled.
+    fn selected_bit(mem: &Memory) -> (u16, bool) {
+        let tac = mem[IORegister::TAC];
+        let bit = match tac & 0b0000_0011 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        };
+        (bit, (tac & 0b0000_0100) != 0)
+    }
+
+    /// Increment TIMA if the timer signal has gone from 1 to 0.
+    fn update_timer_signal(&mut self, mem: &mut Memory) {
+        let (bit, enabled) = Timer::selected_bit(mem);
+        let timer_signal = enabled && (self.system_counter >> bit) & 1 != 0;
+
+        if self.timer_signal && !timer_signal {
+            let (incremented, overflow) = mem[IORegister::TIMA].overflowing_add(1);
+            // TIMA reads 0 for one cycle before it is reloaded.
+            mem[IORegister::TIMA] = incremented;
+            self.reload_pending = overflow;
+        }
+        self.timer_signal = timer_signal;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u16(self.system_counter);
+        state.bool(self.timer_signal);
+        state.bool(self.reload_pending);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.system_counter = state.u16()?;
+        self.timer_signal = state.bool()?;
+        self.reload_pending = state.bool()?;
+
+        Ok(())
+    }
+}
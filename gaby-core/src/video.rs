@@ -0,0 +1,872 @@
+mod tiles;
+
+use crate::events::Event;
+use crate::memory::{IORegister, Memory, WriteHook};
+use crate::palette::{Colorization, Palette};
+use crate::state::{StateReader, StateWriter};
+use std::ops::Range;
+#[cfg(feature = "files")]
+use std::{error::Error, fs::File, io::BufWriter};
+use tiles::TileCache;
+
+/// Width and height of the screen, and of the framebuffer, in pixels.
+pub const SCREEN_WIDTH: u8 = 160;
+pub const SCREEN_HEIGHT: u8 = 144;
+
+const LY_MAX: u8 = 154;
+
+const TICKS_VBLANK: u32 = 1140;
+const TICKS_HBLANK: u32 = 51;
+const TICKS_OAM: u32 = 20;
+const TICKS_TRANSFER: u32 = 43;
+const TICKS_PER_LINE: u32 = TICKS_HBLANK + TICKS_OAM + TICKS_TRANSFER;
+// The pixel transfer can take at most 289 dots, leaving an HBlank of 87 dots.
+const TICKS_TRANSFER_MAX: u32 = 73;
+
+// A dot is one cycle of the 4 MiHz pixel clock.
+const DOTS_PER_TICK: u32 = 4;
+const DOTS_TRANSFER: u32 = 172;
+
+// These constants are for both x-/y-direction.
+const TILES_PER_BACKGROUND: u16 = 32;
+const PIXELS_PER_TILE: u8 = 8;
+const PIXELS_PER_BACKGROUND: usize = PIXELS_PER_TILE as usize * TILES_PER_BACKGROUND as usize;
+const PIXELS_PER_BACKGROUND_SQUARED: usize = PIXELS_PER_BACKGROUND * PIXELS_PER_BACKGROUND;
+
+const BYTES_PER_TILE: u16 = 16;
+// Tiles fetched for a line, enough for the screen and a scrolled-out tile.
+const FETCHED_TILES: usize = 21;
+const FETCHED_PIXELS: usize = FETCHED_TILES * PIXELS_PER_TILE as usize;
+
+const OAM_ORIGIN: u16 = 0xFE00;
+pub const SPRITES_IN_OAM: u16 = 40;
+const BYTES_PER_SPRITE: u16 = 4;
+pub const MAX_SPRITES_PER_LINE: usize = 10;
+const PIXELS_PER_LINE: usize = SCREEN_WIDTH as usize;
+const PIXELS_PER_SCREEN: usize = SCREEN_HEIGHT as usize * PIXELS_PER_LINE;
+
+pub struct Video {
+    /// Pixels that are written to the screen, see `framebuffer`.
+    framebuffer: [u32; PIXELS_PER_SCREEN],
+    /// Number of frames completed, i.e. times VBlank has been entered.
+    frame_count: u64,
+    /// Hash of the framebuffer at the end of the last completed frame.
+    frame_hash: u32,
+    /// First and last line of `framebuffer` changed since the last call to
+    /// `take_dirty_lines`.
+    dirty_lines: Option<(u8, u8)>,
+    /// Output colors of the four shades, for the background and sprites.
+    colorization: Colorization,
+    /// Number of ticks left in current LCD mode.
+    mode_counter: u32,
+    /// Number of ticks left until this line is finished.
+    line_counter: u32,
+    /// Length of the pixel transfer on the current line.
+    transfer_ticks: u32,
+    /// State of the combined STAT interrupt line.
+    stat_line: bool,
+    /// Line currently being drawn. Differs from LY for most of line 153.
+    line: u8,
+    /// Line of the window to draw next. Only advances on lines where the
+    /// window is visible.
+    window_line: u8,
+    /// Which layers to draw, for debugging.
+    pub layers: Layers,
+    tiles: TileCache,
+    lyc_writes: WriteHook,
+}
+
+/// Visibility of each layer. A hidden layer is left out when composing the
+/// screen, without affecting the emulated hardware.
+#[derive(Clone, Copy)]
+pub struct Layers {
+    pub background: bool,
+    pub window: bool,
+    pub sprites: bool,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self {
+            background: true,
+            window: true,
+            sprites: true,
+        }
+    }
+}
+
+/// What to draw on top of the background map, see `Video::background_map`.
+#[derive(Clone, Copy, Default)]
+pub struct MapOverlay {
+    /// Outline of the part of the map shown on the screen, at SCX and SCY.
+    pub viewport: bool,
+    /// Lines between the tiles.
+    pub grid: bool,
+}
+
+/// Colors of the overlays, which are blended with the map under them.
+const VIEWPORT_COLOR: u32 = 0xFF00_00FF;
+const GRID_COLOR: u32 = 0x0080_FFFF;
+
+impl Video {
+    pub fn tick(&mut self, mem: &mut Memory) -> Result<(), String> {
+        {
+            let mut compare_ly = false;
+
+            if self.line_counter == 0 {
+                self.line = (self.line + 1) % LY_MAX;
+                Video::set_ly(mem, self.line);
+                compare_ly = true;
+
+                self.line_counter = TICKS_PER_LINE;
+            } else if self.line == LY_MAX - 1 && self.line_counter == TICKS_PER_LINE - 1 {
+                // LY only reads 153 during the first cycle of the last line,
+                // and 0 for the rest of it.
+                Video::set_ly(mem, 0);
+                compare_ly = true;
+            }
+
+            while mem.next_write(self.lyc_writes).is_some() {
+                compare_ly = true;
+            }
+
+            if compare_ly {
+                if mem[IORegister::LY] == mem[IORegister::LYC] {
+                    mem[IORegister::STAT] |= 0b0000_0100;
+                } else {
+                    mem[IORegister::STAT] &= 0b1111_1011;
+                }
+            }
+        }
+
+        if self.mode_counter == 0 {
+            use LCDMode::*;
+            match Video::lcd_mode(mem) {
+                HBlank => {
+                    if self.line == SCREEN_HEIGHT {
+                        self.set_lcd_mode(mem, VBlank);
+                    } else {
+                        self.set_lcd_mode(mem, OAM);
+                    }
+                }
+                VBlank => self.set_lcd_mode(mem, OAM),
+                OAM => self.set_lcd_mode(mem, Transfer),
+                Transfer => self.set_lcd_mode(mem, HBlank),
+            }
+        }
+
+        self.update_stat_line(mem);
+
+        // OAM is scanned one row of two sprites per cycle.
+        mem.oam_scan_row = match Video::lcd_mode(mem) {
+            LCDMode::OAM => Some((TICKS_OAM - self.mode_counter) as u8),
+            _ => None,
+        };
+
+        self.mode_counter -= 1;
+        self.line_counter -= 1;
+        Ok(())
+    }
+
+    /// Number of the coming ticks that only count down, and can be done at
+    /// once with `skip`: until LY or the mode changes.
+    pub fn idle_ticks(&self, mem: &Memory) -> u32 {
+        if self.line_counter == 0
+            || self.mode_counter == 0
+            || mem.has_writes(self.lyc_writes)
+            || Video::stat_line(mem) != self.stat_line
+        {
+            return 0;
+        }
+
+        let idle = self.line_counter.min(self.mode_counter);
+        if self.line == LY_MAX - 1 && self.line_counter >= TICKS_PER_LINE - 1 {
+            // LY changes from 153 to 0 early in the last line.
+            idle.min(self.line_counter - (TICKS_PER_LINE - 1))
+        } else {
+            idle
+        }
+    }
+
+    /// Do `ticks` ticks at once. There must be at least that many idle
+    /// ticks, see `idle_ticks`.
+    pub fn skip(&mut self, mem: &mut Memory, ticks: u32) {
+        // The scan is at the row of the last tick.
+        let last_mode_counter = self.mode_counter - (ticks - 1);
+        mem.oam_scan_row = match Video::lcd_mode(mem) {
+            LCDMode::OAM => Some((TICKS_OAM - last_mode_counter) as u8),
+            _ => None,
+        };
+
+        self.mode_counter -= ticks;
+        self.line_counter -= ticks;
+    }
+
+    /// The STAT interrupt sources are OR-ed together into one line, and the
+    /// interrupt is only requested when that line goes from low to high. A
+    /// source becoming active while another one already holds the line high
+    /// is therefore blocked.
+    fn update_stat_line(&mut self, mem: &mut Memory) {
+        let stat_line = Video::stat_line(mem);
+        if stat_line && !self.stat_line {
+            mem[IORegister::IF] |= 0b0000_0010;
+            tracing::trace!(
+                target: "ppu",
+                "STAT interrupt requested, STAT {:02X}",
+                mem[IORegister::STAT]
+            );
+        }
+        self.stat_line = stat_line;
+    }
+
+    /// Whether any of the enabled STAT interrupt sources is active.
+    fn stat_line(mem: &Memory) -> bool {
+        let stat = mem[IORegister::STAT];
+
+        let coincidence = (stat & 0b0100_0100) == 0b0100_0100;
+        let mode_source = match stat & 0b0000_0011 {
+            0 => stat & 0b0000_1000,
+            1 => stat & 0b0001_0000,
+            2 => stat & 0b0010_0000,
+            _ => 0,
+        };
+        coincidence || mode_source != 0
+    }
+
+    pub fn new(mem: &mut Memory) -> Self {
+        let line = mem[IORegister::LY];
+        let lyc_writes = mem.add_write_hook(IORegister::LYC..=IORegister::LYC);
+        let tiles = TileCache::new(mem);
+
+        Self {
+            framebuffer: [0; PIXELS_PER_SCREEN],
+            frame_count: 0,
+            frame_hash: 0,
+            dirty_lines: Some((0, SCREEN_HEIGHT - 1)),
+            colorization: Colorization::uniform(Palette::GRAY),
+            mode_counter: TICKS_OAM,
+            line_counter: TICKS_PER_LINE,
+            transfer_ticks: TICKS_TRANSFER,
+            stat_line: false,
+            line,
+            window_line: 0,
+            layers: Layers::default(),
+            tiles,
+            lyc_writes,
+        }
+    }
+
+    fn lcd_mode(mem: &Memory) -> LCDMode {
+        let stat = mem[IORegister::STAT];
+        let mode = stat & 0b0000_0011;
+        use LCDMode::*;
+        match mode {
+            0 => HBlank,
+            1 => VBlank,
+            2 => OAM,
+            3 => Transfer,
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_lcd_mode(&mut self, mem: &mut Memory, mode: LCDMode) {
+        use LCDMode::*;
+        let mode_mask = match mode {
+            HBlank => {
+                if let Some(destination) = mem.hblank_dma() {
+                    self.tiles.invalidate(destination);
+                }
+
+                // HBlank gets whatever is left of the line after the transfer.
+                self.mode_counter = TICKS_PER_LINE - TICKS_OAM - self.transfer_ticks;
+                0b0000_0000
+            }
+            VBlank => {
+                self.frame_count += 1;
+                self.frame_hash = Video::hash(&self.framebuffer);
+                tracing::debug!(
+                    target: "ppu",
+                    "Frame {} done, hash {:08X}",
+                    self.frame_count,
+                    self.frame_hash
+                );
+
+                mem[IORegister::IF] |= 0b0000_0001;
+                mem.callbacks.vblank(self.frame_count);
+
+                self.mode_counter = TICKS_VBLANK;
+                0b0000_0001
+            }
+            OAM => {
+                self.mode_counter = TICKS_OAM;
+                0b0000_0010
+            }
+            Transfer => {
+                self.tiles.update(mem);
+                self.render_line(mem);
+
+                self.transfer_ticks = Video::transfer_ticks(mem);
+                self.mode_counter = self.transfer_ticks;
+                0b0000_0011
+            }
+        };
+        let stat_without_mode = mem[IORegister::STAT] & 0b1111_1100;
+        mem[IORegister::STAT] = stat_without_mode | mode_mask;
+        mem.events.signal(Event::Mode(mode_mask));
+    }
+
+    /// Set LY, signalling the new line if it changed. LY is set to 0 twice
+    /// around the end of the frame, see `tick`.
+    fn set_ly(mem: &mut Memory, line: u8) {
+        if mem[IORegister::LY] != line {
+            mem[IORegister::LY] = line;
+            mem.events.signal(Event::Line(line));
+        }
+    }
+
+    fn render_line(&mut self, mem: &Memory) {
+        let y = mem[IORegister::LY];
+
+        if y < SCREEN_HEIGHT {
+            let lcdc = mem[IORegister::LCDC];
+
+            // Color indices (0-3) of the background, before palette lookup.
+            let mut background = if (lcdc & 0b0000_0001) != 0 && self.layers.background {
+                Video::background_line(mem, &self.tiles, y)
+            } else {
+                [0; SCREEN_WIDTH as usize]
+            };
+
+            if y == 0 {
+                self.window_line = 0;
+            }
+            if Video::window_visible(mem, y) {
+                if self.layers.window {
+                    Video::draw_window_line(mem, &self.tiles, self.window_line, &mut background);
+                }
+                self.window_line += 1;
+            }
+
+            // Color indices and flags of the sprites, see `sprite_line`.
+            let sprites = if (lcdc & 0b0000_0010) != 0 && self.layers.sprites {
+                Video::sprite_line(mem, &self.tiles, y)
+            } else {
+                [0; SCREEN_WIDTH as usize]
+            };
+
+            let background_colors =
+                Video::palette_colors(mem[IORegister::BGP], &self.colorization.background);
+            let object_colors = [
+                Video::palette_colors(mem[IORegister::OBP0], &self.colorization.objects[0]),
+                Video::palette_colors(mem[IORegister::OBP1], &self.colorization.objects[1]),
+            ];
+
+            let start = y as usize * PIXELS_PER_LINE;
+            let row = &mut self.framebuffer[start..start + PIXELS_PER_LINE];
+            let mut changed = false;
+            for ((pixel, &bg_color), &sprite) in row.iter_mut().zip(&background).zip(&sprites) {
+                let color = sprite & 0b0000_0011;
+                let behind_background = (sprite & 0b1000_0000) != 0;
+                let new_pixel = if color != 0 && (!behind_background || bg_color == 0) {
+                    let object = usize::from((sprite & 0b0001_0000) != 0);
+                    object_colors[object][usize::from(color)]
+                } else {
+                    background_colors[usize::from(bg_color)]
+                };
+                changed |= *pixel != new_pixel;
+                *pixel = new_pixel;
+            }
+
+            if changed {
+                self.dirty_lines = match self.dirty_lines {
+                    Some((first, last)) => Some((first.min(y), last.max(y))),
+                    None => Some((y, y)),
+                };
+            }
+        }
+    }
+
+    /// Compute the length of the pixel transfer on the current line. It is
+    /// extended by discarding the SCX % 8 first pixels and by pausing the
+    /// background fetcher for each sprite on the line.
+    fn transfer_ticks(mem: &Memory) -> u32 {
+        let y = mem[IORegister::LY];
+        let scx = mem[IORegister::SCX];
+
+        let mut dots = DOTS_TRANSFER + u32::from(scx % PIXELS_PER_TILE);
+
+        // Switching the fetcher to the window takes 6 dots.
+        if y < SCREEN_HEIGHT && Video::window_visible(mem, y) {
+            dots += 6;
+        }
+
+        if y < SCREEN_HEIGHT && (mem[IORegister::LCDC] & 0b0000_0010) != 0 {
+            for sprite in Video::sprites_on_line(mem, y) {
+                // A sprite fetch takes 6 dots, plus the time needed for the
+                // background fetcher to finish the tile the sprite starts in.
+                let offset_in_tile = (sprite.x + 8 + i16::from(scx)).rem_euclid(8) as u32;
+                dots += 6 + 5u32.saturating_sub(offset_in_tile);
+            }
+        }
+
+        // Round up to whole machine cycles, and leave at least the minimum
+        // HBlank.
+        let ticks = dots.div_ceil(DOTS_PER_TICK);
+        ticks.min(TICKS_TRANSFER_MAX)
+    }
+
+    /// Get the color indices of the background pixels on line `y`.
+    fn background_line(mem: &Memory, tiles: &TileCache, y: u8) -> [u8; SCREEN_WIDTH as usize] {
+        let bg_tile_map_origin = if (mem[IORegister::LCDC] & 0b0000_1000) != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+
+        let scx = mem[IORegister::SCX];
+        let scy = mem[IORegister::SCY];
+
+        let scrolled_y = y.wrapping_add(scy);
+
+        // Fetch whole tiles from the one at the left edge of the screen, and
+        // leave out the pixels of it that are scrolled past the edge.
+        let fetched = Video::tile_map_row(
+            mem,
+            tiles,
+            bg_tile_map_origin,
+            scx / PIXELS_PER_TILE,
+            scrolled_y,
+        );
+        let scrolled_out = usize::from(scx % PIXELS_PER_TILE);
+
+        let mut line = [0; SCREEN_WIDTH as usize];
+        line.copy_from_slice(&fetched[scrolled_out..scrolled_out + PIXELS_PER_LINE]);
+        line
+    }
+
+    /// Whether the window covers part of line `y`. The window is drawn on top
+    /// of the background from screen coordinate (WX - 7, WY).
+    fn window_visible(mem: &Memory, y: u8) -> bool {
+        let lcdc = mem[IORegister::LCDC];
+        (lcdc & 0b0010_0001) == 0b0010_0001
+            && mem[IORegister::WY] <= y
+            && mem[IORegister::WX] < SCREEN_WIDTH + 7
+    }
+
+    /// Replace the color indices in `line` that are covered by the window,
+    /// using line `window_y` of the window.
+    fn draw_window_line(
+        mem: &Memory,
+        tiles: &TileCache,
+        window_y: u8,
+        line: &mut [u8; SCREEN_WIDTH as usize],
+    ) {
+        let window_tile_map_origin = if (mem[IORegister::LCDC] & 0b0100_0000) != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+
+        let fetched = Video::tile_map_row(mem, tiles, window_tile_map_origin, 0, window_y);
+
+        // The window starts left of the screen when WX is less than 7.
+        let left = i16::from(mem[IORegister::WX]) - 7;
+        let first = left.max(0) as usize;
+        let hidden = (first as i16 - left) as usize;
+        let visible = PIXELS_PER_LINE - first;
+        line[first..].copy_from_slice(&fetched[hidden..hidden + visible]);
+    }
+
+    /// Get the color indices of row `y` of the 256×256 pixel image given by a
+    /// tile map, for `FETCHED_TILES` tiles from column `tile_x`, wrapping
+    /// around at the right edge.
+    fn tile_map_row(
+        mem: &Memory,
+        tiles: &TileCache,
+        tile_map_origin: u16,
+        tile_x: u8,
+        y: u8,
+    ) -> [u8; FETCHED_PIXELS] {
+        let (tile_data_origin, signed_tile_indices) = if (mem[IORegister::LCDC] & 0b0001_0000) != 0
+        {
+            (0x8000, false)
+        } else {
+            (0x9000, true)
+        };
+
+        let tile_y = u16::from(y / PIXELS_PER_TILE);
+        let in_tile_y = y % PIXELS_PER_TILE;
+
+        let mut pixels = [0; FETCHED_PIXELS];
+        for (column, tile_pixels) in pixels
+            .chunks_exact_mut(PIXELS_PER_TILE as usize)
+            .enumerate()
+        {
+            let tile_x = (u16::from(tile_x) + column as u16) % TILES_PER_BACKGROUND;
+            let tile_offset = tile_y * TILES_PER_BACKGROUND + tile_x;
+
+            let tile_index = mem[tile_map_origin + tile_offset];
+            let tile_data = if signed_tile_indices {
+                let offset = i32::from(tile_index as i8) * i32::from(BYTES_PER_TILE);
+                (i32::from(tile_data_origin) + offset) as u16
+            } else {
+                tile_data_origin + u16::from(tile_index) * BYTES_PER_TILE
+            };
+
+            tile_pixels.copy_from_slice(&tiles.row(tile_data, in_tile_y).to_le_bytes());
+        }
+
+        pixels
+    }
+
+    /// Get the pixels of the sprites on line `y`. Each has the color index
+    /// (0-3) of the sprite pixel drawn there in the low two bits, 0 where
+    /// there is none, and the priority and palette flags of its sprite in
+    /// bits 7 and 4, where they are in OAM.
+    fn sprite_line(mem: &Memory, tiles: &TileCache, y: u8) -> [u8; SCREEN_WIDTH as usize] {
+        let sprite_height = Sprite::height(mem);
+
+        let mut line = [0; SCREEN_WIDTH as usize];
+        for sprite in Video::sprites_on_line(mem, y) {
+            let row = sprite.row(tiles, y, sprite_height);
+            let flags = sprite.flags & 0b1001_0000;
+            for (in_sprite_x, &color) in row.to_le_bytes().iter().enumerate() {
+                // The sprites are sorted by priority, so a pixel is drawn by
+                // the first one that isn't transparent there.
+                let x = sprite.x + in_sprite_x as i16;
+                if color != 0 && (0..i16::from(SCREEN_WIDTH)).contains(&x) && line[x as usize] == 0
+                {
+                    line[x as usize] = color | flags;
+                }
+            }
+        }
+
+        line
+    }
+
+    /// Select the sprites on line `y`, sorted by drawing priority.
+    ///
+    /// The hardware scans OAM in order and picks at most 10 sprites per line.
+    /// Where sprites overlap, the one with the smaller X coordinate is drawn on
+    /// top, and if they have the same X coordinate, the one first in OAM.
+    fn sprites_on_line(mem: &Memory, y: u8) -> Vec<Sprite> {
+        let sprite_height = Sprite::height(mem);
+
+        let mut sprites: Vec<Sprite> = (0..SPRITES_IN_OAM)
+            .map(|index| Sprite::from_oam(mem, index))
+            .filter(|sprite| sprite.is_on_line(y, sprite_height))
+            .take(MAX_SPRITES_PER_LINE)
+            .collect();
+
+        // Stable sort, so that OAM order is kept for equal X coordinates.
+        sprites.sort_by_key(|sprite| sprite.x);
+
+        sprites
+    }
+
+    /// Get the color index of a pixel inside a tile, reading it from VRAM.
+    fn tile_pixel(mem: &Memory, tile_data: u16, in_tile_x: u8, in_tile_y: u8) -> u8 {
+        // Get bytes containing pixel data.
+        let pixel_data = (
+            mem[tile_data + u16::from(in_tile_y) * 2],
+            mem[tile_data + u16::from(in_tile_y) * 2 + 1],
+        );
+
+        let mask = 0x80 >> in_tile_x;
+        let low_bit = ((pixel_data.0 & mask) != 0) as u8;
+        let high_bit = ((pixel_data.1 & mask) != 0) as u8;
+        (high_bit << 1) | low_bit
+    }
+
+    /// Look up the 2-bit shade of a color index in a palette register.
+    fn palette_shade(palette: u8, color: u8) -> u8 {
+        (palette >> (2 * color)) & 0b0000_0011
+    }
+
+    /// Get the output color of each color index (0-3) with a palette register
+    /// and the colors of its shades, as in the framebuffer.
+    fn palette_colors(palette: u8, colors: &Palette) -> [u32; 4] {
+        let mut output = [0; 4];
+        for (color, pixel) in output.iter_mut().enumerate() {
+            let shade = Video::palette_shade(palette, color as u8);
+            let [red, green, blue] = colors.0[usize::from(shade)];
+            *pixel = u32::from_be_bytes([red, green, blue, 0xFF]);
+        }
+        output
+    }
+
+    /// The last frame, as `SCREEN_WIDTH` × `SCREEN_HEIGHT` pixels row by row.
+    /// Each pixel is an RGBA8888 value, i.e. 0xRRGGBBAA, with the alpha
+    /// always 0xFF.
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.framebuffer
+    }
+
+    /// Write the framebuffer to a PNG file.
+    #[cfg(feature = "files")]
+    pub fn write_screenshot(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        Video::write_png(
+            path,
+            &self.framebuffer,
+            u32::from(SCREEN_WIDTH),
+            u32::from(SCREEN_HEIGHT),
+        )
+    }
+
+    /// The whole 256×256 pixel background map selected by LCDC, not just the
+    /// part of it on the screen, in the current background palette. The
+    /// pixels are row by row, like in `framebuffer`.
+    pub fn background_map(&mut self, mem: &mut Memory, overlay: MapOverlay) -> Vec<u32> {
+        // The map may be exported in the middle of a frame, after the tiles
+        // were last decoded.
+        self.tiles.update(mem);
+
+        let bg_tile_map_origin = if (mem[IORegister::LCDC] & 0b0000_1000) != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+        let colors = Video::palette_colors(mem[IORegister::BGP], &self.colorization.background);
+
+        let mut pixels = Vec::with_capacity(PIXELS_PER_BACKGROUND_SQUARED);
+        for y in 0..=u8::MAX {
+            // A fetched row is shorter than the map, so fetch from the left
+            // edge and from the middle.
+            let half = TILES_PER_BACKGROUND as u8 / 2;
+            for tile_x in [0, half] {
+                let fetched = Video::tile_map_row(mem, &self.tiles, bg_tile_map_origin, tile_x, y);
+                let half_row = &fetched[..PIXELS_PER_BACKGROUND / 2];
+                pixels.extend(half_row.iter().map(|&color| colors[usize::from(color)]));
+            }
+        }
+
+        if overlay.grid {
+            for (index, pixel) in pixels.iter_mut().enumerate() {
+                let (x, y) = (index % PIXELS_PER_BACKGROUND, index / PIXELS_PER_BACKGROUND);
+                let tile_size = usize::from(PIXELS_PER_TILE);
+                if x % tile_size == 0 || y % tile_size == 0 {
+                    *pixel = Video::blend(*pixel, GRID_COLOR);
+                }
+            }
+        }
+
+        if overlay.viewport {
+            // The screen wraps around the edges of the map, and so does the
+            // outline.
+            let left = usize::from(mem[IORegister::SCX]);
+            let top = usize::from(mem[IORegister::SCY]);
+            let (width, height) = (PIXELS_PER_LINE, usize::from(SCREEN_HEIGHT));
+            let mut outline = |x: usize, y: usize| {
+                let x = (left + x) % PIXELS_PER_BACKGROUND;
+                let y = (top + y) % PIXELS_PER_BACKGROUND;
+                let pixel = &mut pixels[y * PIXELS_PER_BACKGROUND + x];
+                *pixel = Video::blend(*pixel, VIEWPORT_COLOR);
+            };
+            for x in 0..width {
+                outline(x, 0);
+                outline(x, height - 1);
+            }
+            for y in 1..height - 1 {
+                outline(0, y);
+                outline(width - 1, y);
+            }
+        }
+
+        pixels
+    }
+
+    /// Write the background map to a PNG file, see `background_map`.
+    #[cfg(feature = "files")]
+    pub fn write_background_map(
+        &mut self,
+        mem: &mut Memory,
+        path: &str,
+        overlay: MapOverlay,
+    ) -> Result<(), Box<dyn Error>> {
+        let pixels = self.background_map(mem, overlay);
+        let size = PIXELS_PER_BACKGROUND as u32;
+        Video::write_png(path, &pixels, size, size)
+    }
+
+    /// Write RGBA8888 pixels to a PNG file, leaving out the alpha.
+    #[cfg(feature = "files")]
+    fn write_png(
+        path: &str,
+        pixels: &[u32],
+        width: u32,
+        height: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        let bytes: Vec<u8> = pixels
+            .iter()
+            .flat_map(|pixel| {
+                let [red, green, blue, _] = pixel.to_be_bytes();
+                [red, green, blue]
+            })
+            .collect();
+
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&bytes)?;
+
+        Ok(())
+    }
+
+    /// Mix two RGBA8888 colors half and half, so that the map still shows
+    /// through the overlays.
+    fn blend(pixel: u32, color: u32) -> u32 {
+        let (pixel, color) = (pixel.to_be_bytes(), color.to_be_bytes());
+        let mut mixed = [0xFF; 4];
+        // The alpha is left opaque.
+        for ((mixed, &a), &b) in mixed.iter_mut().zip(&pixel).zip(&color).take(3) {
+            *mixed = ((u16::from(a) + u16::from(b)) / 2) as u8;
+        }
+        u32::from_be_bytes(mixed)
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Hash of the last completed frame, for comparing frames in tests. Only
+    /// the pixel colors are hashed, so it is the same on all platforms.
+    pub fn frame_hash(&self) -> u32 {
+        self.frame_hash
+    }
+
+    /// 32-bit FNV-1a hash of the pixels.
+    fn hash(framebuffer: &[u32]) -> u32 {
+        framebuffer
+            .iter()
+            .flat_map(|pixel| pixel.to_be_bytes())
+            .fold(0x811C_9DC5, |hash, byte| {
+                (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+            })
+    }
+
+    /// Get the range of lines that have changed since the last call, or None
+    /// if the screen is unchanged.
+    pub fn take_dirty_lines(&mut self) -> Option<Range<usize>> {
+        self.dirty_lines
+            .take()
+            .map(|(first, last)| usize::from(first)..usize::from(last) + 1)
+    }
+
+    pub fn set_colorization(&mut self, colorization: Colorization) {
+        self.colorization = colorization;
+    }
+
+    /// Save the state of the video system. The framebuffer is not saved, so
+    /// it shows the old picture until the next frame has been drawn.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u64(self.frame_count);
+        state.u32(self.frame_hash);
+        state.u32(self.mode_counter);
+        state.u32(self.line_counter);
+        state.u32(self.transfer_ticks);
+        state.bool(self.stat_line);
+        state.u8(self.line);
+        state.u8(self.window_line);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.frame_count = state.u64()?;
+        self.frame_hash = state.u32()?;
+        self.mode_counter = state.u32()?;
+        self.line_counter = state.u32()?;
+        self.transfer_ticks = state.u32()?;
+        self.stat_line = state.bool()?;
+        self.line = state.u8()?;
+        self.window_line = state.u8()?;
+        // VRAM was replaced along with the rest of memory.
+        self.tiles.invalidate_all();
+
+        Ok(())
+    }
+}
+
+pub enum LCDMode {
+    HBlank,
+    VBlank,
+    OAM,
+    Transfer,
+}
+
+/// Object attribute memory entry.
+pub struct Sprite {
+    /// Screen coordinates of the upper left corner.
+    pub x: i16,
+    pub y: i16,
+    pub tile_index: u8,
+    pub flags: u8,
+}
+
+impl Sprite {
+    pub fn from_oam(mem: &Memory, index: u16) -> Self {
+        let address = OAM_ORIGIN + index * BYTES_PER_SPRITE;
+        Self {
+            y: i16::from(mem[address]) - 16,
+            x: i16::from(mem[address + 1]) - 8,
+            tile_index: mem[address + 2],
+            flags: mem[address + 3],
+        }
+    }
+
+    /// Height of all sprites, 8 or 16 pixels depending on LCDC.
+    pub fn height(mem: &Memory) -> u8 {
+        if (mem[IORegister::LCDC] & 0b0000_0100) != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    pub fn is_on_line(&self, y: u8, height: u8) -> bool {
+        let line = i16::from(y);
+        self.y <= line && line < self.y + i16::from(height)
+    }
+
+    /// Get the color indices of the sprite on screen line `y`, as a row of
+    /// the tile cache, taking flipping into account.
+    fn row(&self, tiles: &TileCache, y: u8, height: u8) -> u64 {
+        let in_sprite_y = (i16::from(y) - self.y) as u8;
+        let (tile_data, _, in_tile_y) = self.tile_position(0, in_sprite_y, height);
+        let row = tiles.row(tile_data, in_tile_y);
+        if (self.flags & 0b0010_0000) != 0 {
+            // The leftmost pixel is in the least significant byte.
+            row.swap_bytes()
+        } else {
+            row
+        }
+    }
+
+    /// Get the color index of a pixel inside the sprite, taking flipping into
+    /// account.
+    pub fn pixel(&self, mem: &Memory, in_sprite_x: u8, in_sprite_y: u8, height: u8) -> u8 {
+        let (tile_data, in_tile_x, in_tile_y) =
+            self.tile_position(in_sprite_x, in_sprite_y, height);
+        Video::tile_pixel(mem, tile_data, in_tile_x, in_tile_y)
+    }
+
+    /// Find the tile data and the coordinate inside it of a pixel inside the
+    /// sprite. The Y coordinate is past the first tile for the lower half of
+    /// tall sprites.
+    fn tile_position(&self, mut in_sprite_x: u8, mut in_sprite_y: u8, height: u8) -> (u16, u8, u8) {
+        if (self.flags & 0b0010_0000) != 0 {
+            in_sprite_x = PIXELS_PER_TILE - 1 - in_sprite_x;
+        }
+        if (self.flags & 0b0100_0000) != 0 {
+            in_sprite_y = height - 1 - in_sprite_y;
+        }
+
+        // Tall sprites use two consecutive tiles, starting at an even index.
+        let tile_index = if height == 16 {
+            self.tile_index & 0xFE
+        } else {
+            self.tile_index
+        };
+        let tile_data = 0x8000 + u16::from(tile_index) * BYTES_PER_TILE;
+
+        (tile_data, in_sprite_x, in_sprite_y)
+    }
+}
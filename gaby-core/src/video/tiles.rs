@@ -0,0 +1,104 @@
+use crate::memory::{IORegister, Memory, WriteHook};
+
+/// Tile data in VRAM, 384 tiles of 8×8 pixels with 2 bits per pixel.
+const TILE_DATA: u16 = 0x8000;
+const TILE_DATA_END: u16 = 0x97FF;
+const TILES: usize = 384;
+const BYTES_PER_TILE: u16 = 16;
+
+/// The tiles in VRAM decoded into color indices, so that drawing a line
+/// doesn't have to pick the bits of every pixel out of the tile data.
+///
+/// A row of a tile is kept as a u64 with the color index of each pixel in a
+/// byte, the leftmost pixel in the least significant byte, so that a row can
+/// be copied and flipped as one value.
+///
+/// A tile is decoded again when it has been written to since it was last
+/// decoded. CPU writes are found with a write hook, but the VRAM DMA of the
+/// CGB writes behind its back, so the video system has to tell it about
+/// those.
+pub struct TileCache {
+    /// Rows of color indices (0-3) of each tile.
+    rows: [[u64; 8]; TILES],
+    /// Tiles written to since they were decoded.
+    stale: [bool; TILES],
+    tile_writes: WriteHook,
+    dma_writes: WriteHook,
+}
+
+impl TileCache {
+    pub fn new(mem: &mut Memory) -> Self {
+        let tile_writes = mem.add_write_hook(TILE_DATA..=TILE_DATA_END);
+        let dma_writes = mem.add_write_hook(IORegister::HDMA5..=IORegister::HDMA5);
+
+        Self {
+            rows: [[0; 8]; TILES],
+            stale: [true; TILES],
+            tile_writes,
+            dma_writes,
+        }
+    }
+
+    /// Decode the tiles written to since the last update.
+    pub fn update(&mut self, mem: &mut Memory) {
+        while let Some(write) = mem.next_write(self.tile_writes) {
+            self.invalidate(write.address);
+        }
+        // A general purpose DMA copies its blocks at once, somewhere in VRAM.
+        while mem.next_write(self.dma_writes).is_some() {
+            self.invalidate_all();
+        }
+
+        for tile in 0..TILES {
+            if self.stale[tile] {
+                self.decode(mem, tile);
+                self.stale[tile] = false;
+            }
+        }
+    }
+
+    /// Mark the tile containing `address` as written to, if there is one.
+    pub fn invalidate(&mut self, address: u16) {
+        if (TILE_DATA..=TILE_DATA_END).contains(&address) {
+            self.stale[usize::from((address - TILE_DATA) / BYTES_PER_TILE)] = true;
+        }
+    }
+
+    /// Mark all tiles as written to, e.g. after loading a state.
+    pub fn invalidate_all(&mut self) {
+        self.stale = [true; TILES];
+    }
+
+    fn decode(&mut self, mem: &Memory, tile: usize) {
+        let tile_data = TILE_DATA + tile as u16 * BYTES_PER_TILE;
+        for (y, row) in self.rows[tile].iter_mut().enumerate() {
+            // The first byte has the low bits of the color indices, and the
+            // second byte the high bits.
+            let low = mem[tile_data + 2 * y as u16];
+            let high = mem[tile_data + 2 * y as u16 + 1];
+            *row = spread(low) | (spread(high) << 1);
+        }
+    }
+
+    /// Get the color indices of row `in_tile_y` of the tile whose data starts
+    /// at `tile_data`, see `TileCache`. The cache must be up to date, see
+    /// `update`.
+    pub fn row(&self, tile_data: u16, in_tile_y: u8) -> u64 {
+        // Rows past the eighth are in the next tile, for tall sprites.
+        let row_data = tile_data + u16::from(in_tile_y) * 2;
+        let tile = usize::from((row_data - TILE_DATA) / BYTES_PER_TILE);
+        self.rows[tile][usize::from(in_tile_y % 8)]
+    }
+}
+
+/// Spread the bits of `byte` out over the bytes of a u64, with bit 7 in bit 0
+/// of the least significant byte and bit 0 in bit 0 of the most significant
+/// one, like the pixels of a row.
+fn spread(byte: u8) -> u64 {
+    // Copy the byte into all eight bytes and keep a different bit of it in
+    // each. Adding 0x7F then carries into bit 7 of the bytes where that bit
+    // is set, without carrying into the next byte.
+    let copies = u64::from(byte) * 0x0101_0101_0101_0101;
+    let bits = copies & 0x0102_0408_1020_4080;
+    ((bits + 0x7F7F_7F7F_7F7F_7F7F) >> 7) & 0x0101_0101_0101_0101
+}
@@ -0,0 +1,165 @@
+//! Values in memory watched by the debugger, which reports every write that
+//! changes them, with the instruction that made it. They are watched with
+//! write hooks, so only writes by the CPU are seen, not the changes the
+//! other subsystems make to their registers.
+
+use crate::memory::{Memory, WriteHook};
+use std::fmt;
+
+/// A byte, or a little-endian word, in memory.
+pub struct Watch {
+    /// Name given to the value, if any.
+    pub name: Option<String>,
+    pub address: u16,
+    /// Whether the value is a word rather than a byte.
+    pub word: bool,
+    /// The value after the last write that has been taken.
+    pub value: u16,
+    hook: WriteHook,
+}
+
+impl Watch {
+    /// Name of the value, or its address if it has none.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{:04X}", self.address),
+        }
+    }
+
+    /// The value in memory now.
+    fn read(&self, mem: &Memory) -> u16 {
+        let low = mem.peek(self.address);
+        if self.word {
+            u16::from_le_bytes([low, mem.peek(self.address + 1)])
+        } else {
+            u16::from(low)
+        }
+    }
+
+    /// The value formatted in hex, with as many digits as it has bytes.
+    pub fn format_value(&self, value: u16) -> String {
+        if self.word {
+            format!("{:04X}", value)
+        } else {
+            format!("{:02X}", value)
+        }
+    }
+}
+
+/// A write that changed a watched value.
+pub struct WatchChange {
+    pub label: String,
+    pub old: String,
+    pub new: String,
+    /// Address of the instruction that made the write.
+    pub pc: u16,
+    /// Machine cycle at which the write happened.
+    pub cycle: u64,
+}
+
+impl fmt::Display for WatchChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} → {} by {:04X}, cycle {}",
+            self.label, self.old, self.new, self.pc, self.cycle
+        )
+    }
+}
+
+/// The watched values.
+#[derive(Default)]
+pub struct Watches {
+    watches: Vec<Watch>,
+}
+
+impl Watches {
+    /// Watch the value given by `expression`: an address in hex for a
+    /// byte, with `:w` after it for a word, and optionally a name and `=`
+    /// before it, e.g. `C0A0`, `FF40` or `score=C0A0:w`.
+    pub fn add(&mut self, mem: &mut Memory, expression: &str) -> Result<(), String> {
+        let (name, location) = match expression.split_once('=') {
+            Some((name, location)) => (Some(name.trim().to_string()), location),
+            None => (None, expression),
+        };
+        let location = location.trim();
+        let (address, word) = match location.strip_suffix(":w") {
+            Some(address) => (address, true),
+            None => (location, false),
+        };
+        let address = u16::from_str_radix(address.trim_start_matches("0x"), 16)
+            .map_err(|_| format!("Invalid watch expression \"{}\".", expression))?;
+        if word && address == 0xFFFF {
+            return Err(format!(
+                "The word at {:04X} goes past the end of memory.",
+                address
+            ));
+        }
+
+        let last = if word { address + 1 } else { address };
+        let hook = mem.add_watch_hook(address..=last);
+        let mut watch = Watch {
+            name: name.filter(|name| !name.is_empty()),
+            address,
+            word,
+            value: 0,
+            hook,
+        };
+        watch.value = watch.read(mem);
+        self.watches.push(watch);
+        Ok(())
+    }
+
+    /// Stop watching the value with the given index in `iter`.
+    pub fn remove(&mut self, mem: &mut Memory, index: usize) {
+        if index < self.watches.len() {
+            let watch = self.watches.remove(index);
+            mem.remove_watch_hook(watch.hook);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watch> {
+        self.watches.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// The writes that have changed the watched values since the last call,
+    /// in the order they were made. Writes of the value it already has are
+    /// left out.
+    pub fn take_changes(&mut self, mem: &mut Memory) -> Vec<WatchChange> {
+        let mut changes = Vec::new();
+
+        for watch in &mut self.watches {
+            while let Some(write) = mem.next_write(watch.hook) {
+                let mut bytes = watch.value.to_le_bytes();
+                bytes[usize::from(write.address - watch.address)] = write.data;
+                let value = u16::from_le_bytes(bytes);
+                if value != watch.value {
+                    changes.push(WatchChange {
+                        label: watch.label(),
+                        old: watch.format_value(watch.value),
+                        new: watch.format_value(value),
+                        pc: write.pc,
+                        cycle: write.cycle,
+                    });
+                    watch.value = value;
+                }
+            }
+        }
+
+        changes.sort_by_key(|change| change.cycle);
+        changes
+    }
+
+    /// Read the values again, after the memory has been changed without
+    /// writes, like when a state is loaded.
+    pub fn refresh(&mut self, mem: &Memory) {
+        for watch in &mut self.watches {
+            watch.value = watch.read(mem);
+        }
+    }
+}
@@ -0,0 +1,477 @@
+//! A libretro core, so that Gaby can be loaded in RetroArch and other
+//! libretro frontends. The frontend owns the window, audio device and input,
+//! and calls `retro_run` once per frame. Only the parts of the libretro API
+//! that a Game Boy needs are declared here.
+
+use gaby_core::{
+    emulator::Emulator,
+    joypad::Buttons,
+    model::Model,
+    video::{SCREEN_HEIGHT, SCREEN_WIDTH},
+};
+use std::{
+    cell::RefCell,
+    ffi::c_void,
+    os::raw::{c_char, c_uint},
+    ptr, slice,
+};
+
+const API_VERSION: c_uint = 1;
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+const DEVICE_JOYPAD: c_uint = 1;
+const DEVICE_ANALOG: c_uint = 5;
+const DEVICE_INDEX_ANALOG_LEFT: c_uint = 0;
+const DEVICE_ID_ANALOG_X: c_uint = 0;
+const DEVICE_ID_ANALOG_Y: c_uint = 1;
+const MEMORY_SAVE_RAM: c_uint = 0;
+const REGION_NTSC: c_uint = 0;
+
+/// Joypad button IDs of libretro, with the Game Boy buttons they press.
+const BUTTON_IDS: [(c_uint, Buttons); 8] = [
+    (0, Buttons::B),
+    (2, Buttons::SELECT),
+    (3, Buttons::START),
+    (4, Buttons::UP),
+    (5, Buttons::DOWN),
+    (6, Buttons::LEFT),
+    (7, Buttons::RIGHT),
+    (8, Buttons::A),
+];
+
+/// Sample rate of the audio given to the frontend, which resamples it to the
+/// rate of the audio device.
+const SAMPLE_RATE: i32 = 48000;
+
+/// Space left at the end of serialized states, since the size of a state
+/// must be given before it's saved, and may vary a little during a game.
+const SERIALIZE_SLACK: usize = 1024;
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    geometry: GameGeometry,
+    timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+/// Callbacks into the frontend, set before a game is loaded.
+#[derive(Default)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+/// The loaded game.
+struct Game {
+    machine: Emulator,
+    /// State right after power-on, for resetting.
+    power_on_state: Vec<u8>,
+    /// The framebuffer in the pixel format of libretro.
+    frame: Vec<u32>,
+    /// Interleaved stereo samples for the frontend.
+    samples: Vec<i16>,
+    /// Set when the emulation has stopped on an error.
+    stopped: bool,
+}
+
+#[derive(Default)]
+struct Core {
+    callbacks: Callbacks,
+    game: Option<Game>,
+}
+
+thread_local! {
+    // The frontend calls the core from a single thread.
+    static CORE: RefCell<Core> = RefCell::new(Core::default());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.with(|core| core.borrow_mut().game = None);
+}
+
+/// # Safety
+///
+/// `info` must point to a `retro_system_info` struct.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: b"Gaby\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"gb|gbc\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+///
+/// `info` must point to a `retro_system_av_info` struct.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    let width = c_uint::from(SCREEN_WIDTH);
+    let height = c_uint::from(SCREEN_HEIGHT);
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: width,
+            base_height: height,
+            max_width: width,
+            max_height: height,
+            aspect_ratio: width as f32 / height as f32,
+        },
+        timing: SystemTiming {
+            fps: Emulator::FRAMES_PER_SECOND,
+            sample_rate: f64::from(SAMPLE_RATE),
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    CORE.with(|core| core.borrow_mut().callbacks.environment = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    CORE.with(|core| core.borrow_mut().callbacks.video_refresh = Some(callback));
+}
+
+/// Samples are always given in batches, so the single-sample callback is
+/// never used.
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    CORE.with(|core| core.borrow_mut().callbacks.audio_sample_batch = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    CORE.with(|core| core.borrow_mut().callbacks.input_poll = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    CORE.with(|core| core.borrow_mut().callbacks.input_state = Some(callback));
+}
+
+/// There is only one kind of controller, the joypad.
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+/// Reset the Game Boy to its power-on state, keeping the cartridge RAM like
+/// the hardware does.
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    CORE.with(|core| {
+        if let Some(game) = &mut core.borrow_mut().game {
+            let ram = game.machine.mem.cartridge.ram().to_vec();
+            match game.machine.load_state(&game.power_on_state) {
+                Ok(()) => {
+                    game.machine.mem.cartridge.load_ram(&ram);
+                    game.stopped = false;
+                }
+                Err(error) => eprintln!("Gaby: Can't reset: {}", error),
+            }
+        }
+    });
+}
+
+/// Run one frame with the buttons held on the first joypad, and hand the
+/// picture and sound to the frontend.
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    CORE.with(|core| {
+        let core = &mut *core.borrow_mut();
+        let (callbacks, game) = match core {
+            Core {
+                callbacks,
+                game: Some(game),
+            } => (callbacks, game),
+            _ => return,
+        };
+
+        if let (Some(input_poll), Some(input_state)) = (callbacks.input_poll, callbacks.input_state)
+        {
+            unsafe { input_poll() };
+            let buttons = BUTTON_IDS
+                .iter()
+                .filter(|&&(id, _)| unsafe { input_state(0, DEVICE_JOYPAD, 0, id) } != 0)
+                .fold(Buttons::empty(), |held, &(_, button)| held | button);
+            game.machine.set_buttons(buttons);
+
+            // The left analog stick tilts games with an accelerometer, by
+            // up to 1 g.
+            if game.machine.mem.cartridge.has_tilt_sensor() {
+                let axis = |id| {
+                    let value =
+                        unsafe { input_state(0, DEVICE_ANALOG, DEVICE_INDEX_ANALOG_LEFT, id) };
+                    f32::from(value) / f32::from(i16::MAX)
+                };
+                game.machine
+                    .set_tilt(axis(DEVICE_ID_ANALOG_X), axis(DEVICE_ID_ANALOG_Y));
+            }
+        }
+
+        if !game.stopped {
+            if let Err(error) = game.machine.run_frame() {
+                eprintln!("Gaby: The emulation stopped: {}", error);
+                game.stopped = true;
+            }
+        }
+
+        if let Some(video_refresh) = callbacks.video_refresh {
+            // The framebuffer is RGBA, with red in the most significant byte.
+            for (pixel, rgba) in game.frame.iter_mut().zip(game.machine.video.framebuffer()) {
+                *pixel = rgba >> 8;
+            }
+            let width = usize::from(SCREEN_WIDTH);
+            unsafe {
+                video_refresh(
+                    game.frame.as_ptr() as *const c_void,
+                    c_uint::from(SCREEN_WIDTH),
+                    c_uint::from(SCREEN_HEIGHT),
+                    width * 4,
+                )
+            };
+        }
+
+        game.samples.clear();
+        for sample in game.machine.audio.take_samples() {
+            let sample = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+            game.samples.extend([sample, sample]);
+        }
+        if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+            // The frontend may take fewer samples than it's given.
+            let mut frames = &game.samples[..];
+            while !frames.is_empty() {
+                let taken = unsafe { audio_sample_batch(frames.as_ptr(), frames.len() / 2) };
+                if taken == 0 {
+                    break;
+                }
+                frames = &frames[(taken * 2).min(frames.len())..];
+            }
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    CORE.with(|core| match &core.borrow().game {
+        Some(game) => 4 + game.machine.save_state().len() + SERIALIZE_SLACK,
+        None => 0,
+    })
+}
+
+/// Write a save state to `data`, prefixed by its length, since the buffer is
+/// larger than the state.
+///
+/// # Safety
+///
+/// `data` must point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    CORE.with(|core| {
+        let state = match &core.borrow().game {
+            Some(game) => game.machine.save_state(),
+            None => return false,
+        };
+        if data.is_null() || 4 + state.len() > size {
+            return false;
+        }
+
+        let buffer = slice::from_raw_parts_mut(data as *mut u8, size);
+        buffer[..4].copy_from_slice(&(state.len() as u32).to_le_bytes());
+        buffer[4..4 + state.len()].copy_from_slice(&state);
+        buffer[4 + state.len()..].fill(0);
+        true
+    })
+}
+
+/// Load a save state written by `retro_serialize`.
+///
+/// # Safety
+///
+/// `data` must point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() || size < 4 {
+        return false;
+    }
+    let buffer = slice::from_raw_parts(data as *const u8, size);
+    let mut len = [0; 4];
+    len.copy_from_slice(&buffer[..4]);
+    let state = match buffer.get(4..4 + u32::from_le_bytes(len) as usize) {
+        Some(state) => state,
+        None => return false,
+    };
+
+    CORE.with(|core| match &mut core.borrow_mut().game {
+        Some(game) => match game.machine.load_state(state) {
+            Ok(()) => {
+                game.stopped = false;
+                true
+            }
+            Err(error) => {
+                eprintln!("Gaby: Can't load state: {}", error);
+                false
+            }
+        },
+        None => false,
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+/// Cheats are not supported.
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+///
+/// `info` must point to a `retro_game_info` struct whose `data` points to
+/// `size` bytes of ROM.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(info: *const GameInfo) -> bool {
+    if info.is_null() || (*info).data.is_null() {
+        return false;
+    }
+    let rom = slice::from_raw_parts((*info).data as *const u8, (*info).size);
+
+    CORE.with(|core| {
+        let core = &mut *core.borrow_mut();
+
+        let mut format = PIXEL_FORMAT_XRGB8888;
+        let format_set = match core.callbacks.environment {
+            Some(environment) => environment(
+                ENVIRONMENT_SET_PIXEL_FORMAT,
+                &mut format as *mut c_uint as *mut c_void,
+            ),
+            None => false,
+        };
+        if !format_set {
+            eprintln!("Gaby: The frontend doesn't support the XRGB8888 pixel format.");
+            return false;
+        }
+
+        let mut machine = match Emulator::from_rom(rom, Model::DMG) {
+            Ok(machine) => machine,
+            Err(error) => {
+                eprintln!("Gaby: Can't load the ROM: {}", error);
+                return false;
+            }
+        };
+        if machine.mem.cartridge.is_cgb_only() {
+            eprintln!("Gaby: The game only runs on a Game Boy Color, which isn't supported yet.");
+            return false;
+        }
+        machine.audio.set_sample_rate(SAMPLE_RATE);
+
+        core.game = Some(Game {
+            power_on_state: machine.save_state(),
+            frame: vec![0; usize::from(SCREEN_WIDTH) * usize::from(SCREEN_HEIGHT)],
+            samples: Vec::new(),
+            stopped: false,
+            machine,
+        });
+        true
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.with(|core| core.borrow_mut().game = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    REGION_NTSC
+}
+
+/// Cartridge RAM, which the frontend loads from and saves to its own save
+/// files. The RAM never moves while the game is loaded, so the pointer stays
+/// valid until it's unloaded.
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    CORE.with(|core| match &mut core.borrow_mut().game {
+        Some(game) if id == MEMORY_SAVE_RAM => {
+            let ram = game.machine.mem.cartridge.ram_mut();
+            if ram.is_empty() {
+                ptr::null_mut()
+            } else {
+                ram.as_mut_ptr() as *mut c_void
+            }
+        }
+        _ => ptr::null_mut(),
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    CORE.with(|core| match &core.borrow().game {
+        Some(game) if id == MEMORY_SAVE_RAM => game.machine.mem.cartridge.ram().len(),
+        _ => 0,
+    })
+}
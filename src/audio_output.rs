@@ -0,0 +1,127 @@
+use crate::options::FastForwardAudio;
+use sdl2::{
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired},
+    AudioSubsystem,
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+/// Samples waiting to be played, shared between the emulation thread and the
+/// audio callback.
+type SampleBuffer = Arc<Mutex<VecDeque<f32>>>;
+
+/// Plays samples through an SDL audio callback that reads from a ring buffer.
+pub struct AudioOutput {
+    /// Sample rate and buffer size given by the device, which may differ
+    /// from the ones asked for.
+    pub sample_rate: i32,
+    pub buffer_size: u16,
+    buffer: SampleBuffer,
+    // Playback stops when the device is dropped.
+    _device: AudioDevice<Playback>,
+}
+
+impl AudioOutput {
+    /// Open the default audio device for mono output, with `buffer_size`
+    /// samples per callback.
+    pub fn open(
+        audio_subsystem: &AudioSubsystem,
+        sample_rate: i32,
+        buffer_size: u16,
+    ) -> Result<Self, String> {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(sample_rate),
+            channels: Some(1),
+            samples: Some(buffer_size),
+        };
+
+        let buffer = SampleBuffer::default();
+        let device = audio_subsystem.open_playback(None, &desired_spec, |_| Playback {
+            buffer: buffer.clone(),
+            last_sample: 0.0,
+            starved: false,
+        })?;
+        device.resume();
+
+        Ok(Self {
+            sample_rate: device.spec().freq,
+            buffer_size: device.spec().samples,
+            buffer,
+            _device: device,
+        })
+    }
+
+    /// Number of samples that haven't been played yet.
+    pub fn queued_samples(&self) -> usize {
+        self.buffer.lock().map_or(0, |buffer| buffer.len())
+    }
+
+    pub fn queue(&self, samples: &[f32]) {
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.extend(samples);
+        }
+    }
+
+    /// Queue samples made while fast-forwarding, without letting the queue
+    /// grow beyond `target` samples, so that the sound doesn't lag behind
+    /// when the emulation is back to normal speed.
+    pub fn queue_fast_forward(&self, samples: &[f32], target: usize, setting: FastForwardAudio) {
+        let mut buffer = match self.buffer.lock() {
+            Ok(buffer) => buffer,
+            Err(_) => return,
+        };
+        let room = target.saturating_sub(buffer.len()).min(samples.len());
+        if room == 0 {
+            return;
+        }
+
+        match setting {
+            FastForwardAudio::Drop => buffer.extend(&samples[..room]),
+            // Average the samples in `room` equal parts.
+            FastForwardAudio::Resample => buffer.extend((0..room).map(|i| {
+                let part = &samples[i * samples.len() / room..(i + 1) * samples.len() / room];
+                part.iter().sum::<f32>() / part.len() as f32
+            })),
+            FastForwardAudio::Mute => {}
+        }
+    }
+}
+
+struct Playback {
+    buffer: SampleBuffer,
+    last_sample: f32,
+    /// Whether the buffer ran out in the last callback, so that an under-run
+    /// is only counted once.
+    starved: bool,
+}
+
+impl AudioCallback for Playback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let mut buffer = match self.buffer.lock() {
+            Ok(buffer) => buffer,
+            Err(_) => return out.fill(0.0),
+        };
+
+        let mut ran_out = false;
+        for sample in out {
+            // On under-run, let the last sample fade out instead of jumping
+            // to silence, which would be heard as a click.
+            self.last_sample = match buffer.pop_front() {
+                Some(next) => next,
+                None => {
+                    ran_out = true;
+                    self.last_sample * 0.999
+                }
+            };
+            *sample = self.last_sample;
+        }
+        if ran_out && !self.starved {
+            crate::stats::count_underrun();
+        }
+        self.starved = ran_out;
+    }
+}
@@ -0,0 +1,184 @@
+use crate::rom_file;
+use gaby_core::cartridge::Cartridge;
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// Sizes of the real-time clock data that VBA, mGBA, BGB and others add after
+/// the RAM in the save files of MBC3 cartridges: the five clock registers and
+/// the five latched ones as 32-bit words, and a 32-bit or 64-bit UNIX
+/// timestamp of when the file was saved.
+const RTC_FOOTER_SIZES: [usize; 2] = [48, 44];
+
+/// Keeps the battery-backed RAM of a cartridge in a save file next to the ROM.
+///
+/// The file has the raw contents of the RAM, like the `.sav` files of other
+/// emulators and flashcarts. Gaby doesn't emulate a real-time clock, but if
+/// the file came from an emulator that does, the clock data after the RAM is
+/// kept and written back unchanged, so the file can be moved back.
+pub struct BatterySave {
+    path: PathBuf,
+    /// Real-time clock data from the end of the save file, see
+    /// `RTC_FOOTER_SIZES`.
+    rtc: Option<Vec<u8>>,
+    /// How long to wait after the first unsaved write before saving.
+    interval: Duration,
+    /// When the RAM was first written to after the last save.
+    dirty_since: Option<Instant>,
+}
+
+impl BatterySave {
+    pub fn new(rom_path: &Path, interval: Duration) -> Self {
+        Self {
+            path: BatterySave::path(rom_path),
+            rtc: None,
+            interval,
+            dirty_since: None,
+        }
+    }
+
+    /// Save file of the ROM at `rom_path`.
+    fn path(rom_path: &Path) -> PathBuf {
+        rom_path.with_extension("sav")
+    }
+
+    /// Load the save file into the cartridge RAM, if there is one. A file of
+    /// an unexpected size is loaded as far as it goes, with a warning.
+    pub fn load(&mut self, cartridge: &mut Cartridge) -> io::Result<()> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                match split_save(&data, cartridge.ram().len()) {
+                    Ok((ram, rtc)) => {
+                        cartridge.load_ram(ram);
+                        self.rtc = rtc.map(<[u8]>::to_vec);
+                    }
+                    Err(error) => {
+                        warn!("{}: {}", self.path.display(), error);
+                        cartridge.load_ram(&data);
+                    }
+                }
+                Ok(())
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Save the RAM if it has been written to and the interval has passed.
+    /// Call this regularly, e.g. once per frame.
+    pub fn update(&mut self, cartridge: &mut Cartridge) -> io::Result<()> {
+        if cartridge.take_ram_dirty() && self.dirty_since.is_none() {
+            self.dirty_since = Some(Instant::now());
+        }
+
+        match self.dirty_since {
+            Some(since) if since.elapsed() >= self.interval => self.flush(cartridge),
+            _ => Ok(()),
+        }
+    }
+
+    /// Save the RAM now if there are unsaved writes.
+    pub fn flush(&mut self, cartridge: &mut Cartridge) -> io::Result<()> {
+        if cartridge.take_ram_dirty() || self.dirty_since.is_some() {
+            self.save(cartridge.ram())?;
+            self.dirty_since = None;
+        }
+
+        Ok(())
+    }
+
+    /// Write to a temporary file first and then rename it over the save file,
+    /// so that a crash or power loss while saving can't corrupt the old save.
+    fn save(&self, data: &[u8]) -> io::Result<()> {
+        let temp_path = self.path.with_extension("sav.tmp");
+
+        let mut file = File::create(&temp_path)?;
+        file.write_all(data)?;
+        if let Some(rtc) = &self.rtc {
+            file.write_all(rtc)?;
+        }
+        file.sync_all()?;
+
+        fs::rename(&temp_path, &self.path)
+    }
+}
+
+/// Split the contents of a save file into the RAM and the real-time clock
+/// data after it, if there is any, for a cartridge with `ram_size` bytes of
+/// RAM.
+fn split_save(data: &[u8], ram_size: usize) -> Result<(&[u8], Option<&[u8]>), String> {
+    if data.len() == ram_size {
+        return Ok((data, None));
+    }
+    if RTC_FOOTER_SIZES.contains(&data.len().wrapping_sub(ram_size)) {
+        let (ram, rtc) = data.split_at(ram_size);
+        return Ok((ram, Some(rtc)));
+    }
+    Err(format!(
+        "The save file is {} bytes, but the cartridge has {} bytes of RAM.",
+        data.len(),
+        ram_size
+    ))
+}
+
+/// Read the ROM at `rom_path`, and get the size of its battery-backed RAM.
+fn battery_ram_size(rom_path: &Path) -> Result<usize, Box<dyn Error>> {
+    let rom = rom_file::read(rom_path)?;
+    let cartridge = Cartridge::new(&rom)?;
+    if !cartridge.has_battery() {
+        return Err(format!("{} has no battery-backed RAM.", rom_path.display()).into());
+    }
+    Ok(cartridge.ram().len())
+}
+
+/// Make the save file at `save_path`, e.g. from another emulator or a
+/// flashcart, the save file of the ROM at `rom_path`. A save file the ROM
+/// already has is kept with the extension `.sav.bak`.
+pub fn import(save_path: &Path, rom_path: &Path) -> Result<(), Box<dyn Error>> {
+    let ram_size = battery_ram_size(rom_path)?;
+    let data = fs::read(save_path)
+        .map_err(|error| format!("Can't read {}: {}", save_path.display(), error))?;
+    let (ram, rtc) = split_save(&data, ram_size)
+        .map_err(|error| format!("{}: {}", save_path.display(), error))?;
+
+    let mut battery_save = BatterySave::new(rom_path, Duration::ZERO);
+    if battery_save.path.exists() {
+        let backup = battery_save.path.with_extension("sav.bak");
+        fs::copy(&battery_save.path, &backup)?;
+        info!("Kept the old save file as {}", backup.display());
+    }
+    battery_save.rtc = rtc.map(<[u8]>::to_vec);
+    battery_save.save(ram)?;
+    info!(
+        "Imported {} to {}",
+        save_path.display(),
+        battery_save.path.display()
+    );
+    Ok(())
+}
+
+/// Write the save file of the ROM at `rom_path` to `save_path`, as raw RAM
+/// for other emulators and flashcarts. The real-time clock data is left out
+/// if `rtc` is false, for those that only want the RAM.
+pub fn export(rom_path: &Path, save_path: &Path, rtc: bool) -> Result<(), Box<dyn Error>> {
+    let ram_size = battery_ram_size(rom_path)?;
+    let path = BatterySave::path(rom_path);
+    let data =
+        fs::read(&path).map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+    let (ram, footer) =
+        split_save(&data, ram_size).map_err(|error| format!("{}: {}", path.display(), error))?;
+
+    let mut exported = ram.to_vec();
+    if let (true, Some(footer)) = (rtc, footer) {
+        exported.extend_from_slice(footer);
+    }
+    fs::write(save_path, exported)
+        .map_err(|error| format!("Can't write {}: {}", save_path.display(), error))?;
+    info!("Exported {} to {}", path.display(), save_path.display());
+    Ok(())
+}
@@ -0,0 +1,309 @@
+use crate::tilt::TiltDirection;
+use gaby_core::joypad::Buttons;
+use sdl2::keyboard::Keycode;
+use std::collections::HashMap;
+
+/// Emulator function that can be bound to a key.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Hotkey {
+    Quit,
+    ToggleInspector,
+    ToggleBackground,
+    ToggleWindow,
+    ToggleSprites,
+    ToggleRecording,
+    ExportHeatmap,
+    /// Write the whole background map to a PNG file.
+    ExportBackgroundMap,
+    NextProfile,
+    SaveState,
+    LoadState,
+    SelectSlot(u8),
+    NextSlot,
+    /// Held to run the emulation backwards.
+    Rewind,
+    Faster,
+    Slower,
+    Pause,
+    /// Run one frame and pause.
+    AdvanceFrame,
+    /// Held to run the emulation as fast as possible.
+    FastForward,
+    /// Show or hide the menu bar.
+    ToggleMenu,
+    /// Show or hide the waveforms of the audio channels over the screen.
+    ToggleAudioScope,
+    /// Start or stop logging every instruction executed.
+    ToggleTrace,
+    /// Write the last instructions executed to a file.
+    DumpTrace,
+    /// Resize the window to this multiple of the screen size.
+    Scale(u8),
+}
+
+/// What pressing a key does.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Button(Buttons),
+    /// Button that is pressed and released repeatedly while the key is held.
+    Turbo(Buttons),
+    /// Tilt the Game Boy, for games with an accelerometer.
+    Tilt(TiltDirection),
+    Hotkey(Hotkey),
+}
+
+impl Action {
+    /// Names used for the actions in the configuration file.
+    pub const NAMES: [(&'static str, Action); 52] = [
+        ("right", Action::Button(Buttons::RIGHT)),
+        ("left", Action::Button(Buttons::LEFT)),
+        ("up", Action::Button(Buttons::UP)),
+        ("down", Action::Button(Buttons::DOWN)),
+        ("a", Action::Button(Buttons::A)),
+        ("b", Action::Button(Buttons::B)),
+        ("select", Action::Button(Buttons::SELECT)),
+        ("start", Action::Button(Buttons::START)),
+        ("turbo_a", Action::Turbo(Buttons::A)),
+        ("turbo_b", Action::Turbo(Buttons::B)),
+        ("tilt_left", Action::Tilt(TiltDirection::Left)),
+        ("tilt_right", Action::Tilt(TiltDirection::Right)),
+        ("tilt_up", Action::Tilt(TiltDirection::Up)),
+        ("tilt_down", Action::Tilt(TiltDirection::Down)),
+        ("quit", Action::Hotkey(Hotkey::Quit)),
+        ("inspector", Action::Hotkey(Hotkey::ToggleInspector)),
+        ("background", Action::Hotkey(Hotkey::ToggleBackground)),
+        ("window", Action::Hotkey(Hotkey::ToggleWindow)),
+        ("sprites", Action::Hotkey(Hotkey::ToggleSprites)),
+        ("record", Action::Hotkey(Hotkey::ToggleRecording)),
+        ("heatmap", Action::Hotkey(Hotkey::ExportHeatmap)),
+        (
+            "background_map",
+            Action::Hotkey(Hotkey::ExportBackgroundMap),
+        ),
+        ("profile", Action::Hotkey(Hotkey::NextProfile)),
+        ("save_state", Action::Hotkey(Hotkey::SaveState)),
+        ("load_state", Action::Hotkey(Hotkey::LoadState)),
+        ("next_slot", Action::Hotkey(Hotkey::NextSlot)),
+        ("slot_0", Action::Hotkey(Hotkey::SelectSlot(0))),
+        ("slot_1", Action::Hotkey(Hotkey::SelectSlot(1))),
+        ("slot_2", Action::Hotkey(Hotkey::SelectSlot(2))),
+        ("slot_3", Action::Hotkey(Hotkey::SelectSlot(3))),
+        ("slot_4", Action::Hotkey(Hotkey::SelectSlot(4))),
+        ("slot_5", Action::Hotkey(Hotkey::SelectSlot(5))),
+        ("slot_6", Action::Hotkey(Hotkey::SelectSlot(6))),
+        ("slot_7", Action::Hotkey(Hotkey::SelectSlot(7))),
+        ("slot_8", Action::Hotkey(Hotkey::SelectSlot(8))),
+        ("slot_9", Action::Hotkey(Hotkey::SelectSlot(9))),
+        ("rewind", Action::Hotkey(Hotkey::Rewind)),
+        ("faster", Action::Hotkey(Hotkey::Faster)),
+        ("slower", Action::Hotkey(Hotkey::Slower)),
+        ("pause", Action::Hotkey(Hotkey::Pause)),
+        ("advance_frame", Action::Hotkey(Hotkey::AdvanceFrame)),
+        ("fast_forward", Action::Hotkey(Hotkey::FastForward)),
+        ("menu", Action::Hotkey(Hotkey::ToggleMenu)),
+        ("audio_scope", Action::Hotkey(Hotkey::ToggleAudioScope)),
+        ("trace", Action::Hotkey(Hotkey::ToggleTrace)),
+        ("dump_trace", Action::Hotkey(Hotkey::DumpTrace)),
+        ("scale_1", Action::Hotkey(Hotkey::Scale(1))),
+        ("scale_2", Action::Hotkey(Hotkey::Scale(2))),
+        ("scale_3", Action::Hotkey(Hotkey::Scale(3))),
+        ("scale_4", Action::Hotkey(Hotkey::Scale(4))),
+        ("scale_5", Action::Hotkey(Hotkey::Scale(5))),
+        ("scale_6", Action::Hotkey(Hotkey::Scale(6))),
+    ];
+
+    fn from_name(name: &str) -> Option<Action> {
+        Action::NAMES
+            .iter()
+            .find(|(action_name, _)| action_name.eq_ignore_ascii_case(name))
+            .map(|&(_, action)| action)
+    }
+}
+
+/// Mapping from keys to actions.
+pub struct KeyBindings(HashMap<Keycode, Action>);
+
+impl KeyBindings {
+    /// Start from the default bindings, and bind the actions in each of
+    /// `overrides` in turn to the given keys instead. The keys are given by
+    /// their SDL names, like `"Z"`, `"Return"` or `"Left Shift"`.
+    pub fn new(overrides: &[&HashMap<String, String>]) -> Result<Self, String> {
+        let mut bindings = KeyBindings::default();
+
+        for (name, key_name) in overrides.iter().flat_map(|keys| keys.iter()) {
+            let action = Action::from_name(name)
+                .ok_or_else(|| format!("Unknown action \"{}\" in key bindings.", name))?;
+            let keycode = Keycode::from_name(key_name)
+                .ok_or_else(|| format!("Unknown key \"{}\" for {}.", key_name, name))?;
+
+            bindings.bind(keycode, action);
+        }
+
+        Ok(bindings)
+    }
+
+    pub fn get(&self, keycode: Keycode) -> Option<Action> {
+        self.0.get(&keycode).copied()
+    }
+
+    /// Key bound to `action`, if any.
+    #[cfg_attr(not(feature = "egui"), allow(dead_code))]
+    pub fn key(&self, action: Action) -> Option<Keycode> {
+        self.0
+            .iter()
+            .find(|&(_, &bound)| bound == action)
+            .map(|(&keycode, _)| keycode)
+    }
+
+    /// Bind `action` to `keycode` instead of the key it was bound to.
+    pub fn bind(&mut self, keycode: Keycode, action: Action) {
+        self.0.retain(|_, bound| *bound != action);
+        self.0.insert(keycode, action);
+    }
+}
+
+/// Named sets of key bindings that can be switched between while running.
+/// The `default` profile has the bindings of the `[keys]` section of the
+/// configuration file, and each profile in `[profiles]` adds its own
+/// bindings on top of those.
+pub struct InputProfiles {
+    profiles: Vec<(String, KeyBindings)>,
+    current: usize,
+}
+
+impl InputProfiles {
+    pub const DEFAULT_NAME: &'static str = "default";
+
+    /// Create the profiles, starting with the one named `selected`.
+    pub fn new(
+        keys: &HashMap<String, String>,
+        profiles: &HashMap<String, HashMap<String, String>>,
+        selected: Option<&str>,
+    ) -> Result<Self, String> {
+        let mut names: Vec<&String> = profiles.keys().collect();
+        names.sort();
+
+        let mut all = vec![(
+            InputProfiles::DEFAULT_NAME.to_string(),
+            KeyBindings::new(&[keys])?,
+        )];
+        for name in names {
+            let bindings = KeyBindings::new(&[keys, &profiles[name]])
+                .map_err(|error| format!("In input profile {}: {}", name, error))?;
+            all.push((name.clone(), bindings));
+        }
+
+        let current = match selected {
+            Some(selected) => all
+                .iter()
+                .position(|(name, _)| name == selected)
+                .ok_or_else(|| format!("Unknown input profile {}", selected))?,
+            None => 0,
+        };
+
+        Ok(Self {
+            profiles: all,
+            current,
+        })
+    }
+
+    pub fn bindings(&self) -> &KeyBindings {
+        &self.profiles[self.current].1
+    }
+
+    #[cfg_attr(not(feature = "egui"), allow(dead_code))]
+    pub fn bindings_mut(&mut self) -> &mut KeyBindings {
+        &mut self.profiles[self.current].1
+    }
+
+    pub fn name(&self) -> &str {
+        &self.profiles[self.current].0
+    }
+
+    /// Names of all profiles, in the order they are switched between.
+    #[cfg_attr(not(feature = "egui"), allow(dead_code))]
+    pub fn names(&self) -> Vec<&str> {
+        self.profiles
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    #[cfg_attr(not(feature = "egui"), allow(dead_code))]
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Switch to the profile with the given index in `names`.
+    pub fn select(&mut self, index: usize) {
+        if index < self.profiles.len() {
+            self.current = index;
+        }
+    }
+
+    /// Switch to the next profile, in alphabetical order after the default.
+    pub fn select_next(&mut self) {
+        self.current = (self.current + 1) % self.profiles.len();
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self(HashMap::from([
+            (Keycode::Right, Action::Button(Buttons::RIGHT)),
+            (Keycode::Left, Action::Button(Buttons::LEFT)),
+            (Keycode::Up, Action::Button(Buttons::UP)),
+            (Keycode::Down, Action::Button(Buttons::DOWN)),
+            (Keycode::X, Action::Button(Buttons::A)),
+            (Keycode::Z, Action::Button(Buttons::B)),
+            (Keycode::Backspace, Action::Button(Buttons::SELECT)),
+            (Keycode::Return, Action::Button(Buttons::START)),
+            (Keycode::S, Action::Turbo(Buttons::A)),
+            (Keycode::A, Action::Turbo(Buttons::B)),
+            (Keycode::J, Action::Tilt(TiltDirection::Left)),
+            (Keycode::L, Action::Tilt(TiltDirection::Right)),
+            (Keycode::I, Action::Tilt(TiltDirection::Up)),
+            (Keycode::K, Action::Tilt(TiltDirection::Down)),
+            (Keycode::Escape, Action::Hotkey(Hotkey::Quit)),
+            (Keycode::F2, Action::Hotkey(Hotkey::ToggleInspector)),
+            (Keycode::F3, Action::Hotkey(Hotkey::NextProfile)),
+            (Keycode::F6, Action::Hotkey(Hotkey::ToggleBackground)),
+            (Keycode::F7, Action::Hotkey(Hotkey::ToggleWindow)),
+            (Keycode::F8, Action::Hotkey(Hotkey::ToggleSprites)),
+            (Keycode::F9, Action::Hotkey(Hotkey::ToggleRecording)),
+            (Keycode::F11, Action::Hotkey(Hotkey::ExportBackgroundMap)),
+            (Keycode::F12, Action::Hotkey(Hotkey::ExportHeatmap)),
+            (Keycode::F5, Action::Hotkey(Hotkey::SaveState)),
+            (Keycode::F4, Action::Hotkey(Hotkey::LoadState)),
+            (Keycode::F1, Action::Hotkey(Hotkey::NextSlot)),
+            (Keycode::Num0, Action::Hotkey(Hotkey::SelectSlot(0))),
+            (Keycode::Num1, Action::Hotkey(Hotkey::SelectSlot(1))),
+            (Keycode::Num2, Action::Hotkey(Hotkey::SelectSlot(2))),
+            (Keycode::Num3, Action::Hotkey(Hotkey::SelectSlot(3))),
+            (Keycode::Num4, Action::Hotkey(Hotkey::SelectSlot(4))),
+            (Keycode::Num5, Action::Hotkey(Hotkey::SelectSlot(5))),
+            (Keycode::Num6, Action::Hotkey(Hotkey::SelectSlot(6))),
+            (Keycode::Num7, Action::Hotkey(Hotkey::SelectSlot(7))),
+            (Keycode::Num8, Action::Hotkey(Hotkey::SelectSlot(8))),
+            (Keycode::Num9, Action::Hotkey(Hotkey::SelectSlot(9))),
+            (Keycode::R, Action::Hotkey(Hotkey::Rewind)),
+            (Keycode::Equals, Action::Hotkey(Hotkey::Faster)),
+            (Keycode::Minus, Action::Hotkey(Hotkey::Slower)),
+            (Keycode::P, Action::Hotkey(Hotkey::Pause)),
+            (Keycode::N, Action::Hotkey(Hotkey::AdvanceFrame)),
+            (Keycode::Tab, Action::Hotkey(Hotkey::FastForward)),
+            (Keycode::F10, Action::Hotkey(Hotkey::ToggleMenu)),
+            (Keycode::O, Action::Hotkey(Hotkey::ToggleAudioScope)),
+            (Keycode::T, Action::Hotkey(Hotkey::ToggleTrace)),
+            (Keycode::Y, Action::Hotkey(Hotkey::DumpTrace)),
+            // The number keys select save state slots, so the scales are on
+            // the keypad.
+            (Keycode::Kp1, Action::Hotkey(Hotkey::Scale(1))),
+            (Keycode::Kp2, Action::Hotkey(Hotkey::Scale(2))),
+            (Keycode::Kp3, Action::Hotkey(Hotkey::Scale(3))),
+            (Keycode::Kp4, Action::Hotkey(Hotkey::Scale(4))),
+            (Keycode::Kp5, Action::Hotkey(Hotkey::Scale(5))),
+            (Keycode::Kp6, Action::Hotkey(Hotkey::Scale(6))),
+        ]))
+    }
+}
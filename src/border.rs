@@ -0,0 +1,83 @@
+use gaby_core::video::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use sdl2::{
+    pixels::PixelFormatEnum,
+    render::{BlendMode, Texture, TextureCreator},
+    video::WindowContext,
+};
+use std::{error::Error, fs::File, io::BufReader};
+
+/// An image drawn around the screen, like the borders of the Super Game Boy.
+/// The screen is drawn over the middle of it at the same scale, so on a
+/// 256×224 SGB border it covers the pixels from (48, 40).
+pub struct Border {
+    /// RGBA pixels, row by row.
+    pixels: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+impl Border {
+    /// Load a border from a PNG file, which must be at least as large as
+    /// the screen.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path).map_err(|error| format!("Can't read {}: {}", path, error))?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+        data.truncate(info.buffer_size());
+
+        if info.width < u32::from(SCREEN_WIDTH) || info.height < u32::from(SCREEN_HEIGHT) {
+            return Err(format!(
+                "The border {} is {}×{} pixels, but must be at least as large as the screen.",
+                path, info.width, info.height
+            )
+            .into());
+        }
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => data,
+            png::ColorType::Rgb => data
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => data
+                .chunks_exact(2)
+                .flat_map(|gray| [gray[0], gray[0], gray[0], gray[1]])
+                .collect(),
+            png::ColorType::Grayscale => data
+                .iter()
+                .flat_map(|&gray| [gray, gray, gray, 0xFF])
+                .collect(),
+            // Indexed colors are expanded to RGB or RGBA by the decoder.
+            png::ColorType::Indexed => return Err("Unexpected indexed colors.".into()),
+        };
+
+        Ok(Self {
+            pixels,
+            width: info.width,
+            height: info.height,
+        })
+    }
+
+    /// Size of the border in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Make a texture of the border, for drawing it in a window.
+    pub fn texture<'a>(
+        &self,
+        texture_creator: &'a TextureCreator<WindowContext>,
+    ) -> Result<Texture<'a>, Box<dyn Error>> {
+        let mut texture = texture_creator.create_texture_static(
+            PixelFormatEnum::ABGR8888,
+            self.width,
+            self.height,
+        )?;
+        texture.update(None, &self.pixels, 4 * self.width as usize)?;
+        texture.set_blend_mode(BlendMode::Blend);
+        Ok(texture)
+    }
+}
@@ -0,0 +1,138 @@
+use gaby_core::palette::ColorCorrection;
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, fs, io, path::PathBuf};
+
+/// Settings read from the configuration file. Command line options take
+/// precedence over these.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Palette name or four hex colors, see `PaletteSetting::from_str`.
+    pub palette: Option<String>,
+    /// Palettes for specific games, by title. These override `palette`.
+    pub games: HashMap<String, String>,
+    /// Audio output sample rate in Hz.
+    pub sample_rate: Option<i32>,
+    /// Number of samples the audio device asks for at a time.
+    pub audio_buffer: Option<u16>,
+    /// Keys bound to joypad buttons and hotkeys, by action name. See
+    /// `KeyBindings::new`.
+    pub keys: HashMap<String, String>,
+    /// Input profiles, by name, with key bindings that override `keys`.
+    pub profiles: HashMap<String, HashMap<String, String>>,
+    /// Number of frames a turbo button stays pressed, and then released.
+    pub turbo_frames: Option<u32>,
+    /// What to do with the sound while fast-forwarding, see
+    /// `FastForwardAudio`.
+    pub fast_forward_audio: Option<String>,
+    /// No-Intro DAT file to look games up in, see `GameDatabase`.
+    pub game_db: Option<String>,
+    /// Make the colors of the automatic colorization look like on the CGB's
+    /// LCD, see `ColorCorrection`.
+    pub color_correction: bool,
+    /// Gamma of the CGB's LCD, for the automatic colorization.
+    pub lcd_gamma: Option<f64>,
+    /// PNG image to draw around the screen, see `Border`.
+    pub border: Option<String>,
+    /// How games with an accelerometer are tilted with the analog stick or
+    /// the tilt keys.
+    pub tilt: TiltConfig,
+}
+
+/// The `[tilt]` section, which shapes the tilt given by the left analog
+/// stick of a gamepad.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TiltConfig {
+    /// Tilt in g with the stick pushed all the way, or a tilt key held.
+    pub sensitivity: f32,
+    /// Exponent of the stick position, so that values above 1 give finer
+    /// control near the middle.
+    pub curve: f32,
+    /// Part of the range of the stick around the middle that is ignored.
+    pub dead_zone: f32,
+}
+
+impl Default for TiltConfig {
+    fn default() -> Self {
+        Self {
+            sensitivity: 1.0,
+            curve: 1.0,
+            dead_zone: 0.15,
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from the given file, or from `gaby/config.toml`
+    /// in the user's configuration directory. A missing file in the default
+    /// location gives the default configuration.
+    pub fn load(path: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let (path, is_default_path) = match path {
+            Some(path) => (PathBuf::from(path), false),
+            None => match Config::default_path() {
+                Some(path) => (path, true),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(error) if is_default_path && error.kind() == io::ErrorKind::NotFound => {
+                return Ok(Config::default())
+            }
+            Err(error) => return Err(format!("Can't read {}: {}", path.display(), error).into()),
+        };
+
+        let config: Config = toml::from_str(&text)
+            .map_err(|error| format!("Error in {}: {}", path.display(), error))?;
+        if config.sample_rate.is_some_and(|rate| rate <= 0) {
+            return Err(
+                format!("Error in {}: sample_rate must be positive.", path.display()).into(),
+            );
+        }
+        if config.audio_buffer == Some(0) {
+            return Err(format!(
+                "Error in {}: audio_buffer must be between 1 and 65535 samples.",
+                path.display()
+            )
+            .into());
+        }
+        if config.lcd_gamma.is_some_and(|gamma| gamma <= 0.0) {
+            return Err(format!("Error in {}: lcd_gamma must be positive.", path.display()).into());
+        }
+        let tilt = &config.tilt;
+        if !(tilt.sensitivity.is_finite() && tilt.sensitivity > 0.0) {
+            return Err(format!(
+                "Error in {}: tilt.sensitivity must be positive.",
+                path.display()
+            )
+            .into());
+        }
+        if !(tilt.curve.is_finite() && tilt.curve > 0.0) {
+            return Err(
+                format!("Error in {}: tilt.curve must be positive.", path.display()).into(),
+            );
+        }
+        if !(0.0..1.0).contains(&tilt.dead_zone) {
+            return Err(format!(
+                "Error in {}: tilt.dead_zone must be at least 0 and less than 1.",
+                path.display()
+            )
+            .into());
+        }
+        Ok(config)
+    }
+
+    /// The color correction given by `color_correction` and `lcd_gamma`.
+    pub fn color_correction(&self) -> ColorCorrection {
+        ColorCorrection {
+            lcd_colors: self.color_correction,
+            gamma: self.lcd_gamma.unwrap_or(ColorCorrection::DISPLAY_GAMMA),
+        }
+    }
+
+    fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("gaby").join("config.toml"))
+    }
+}
@@ -0,0 +1,109 @@
+use gaby_core::disassembler;
+use gaby_core::emulator::Emulator;
+use gaby_core::lockstep::Registers;
+use gaby_core::memory::IORegister;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Number of words of the stack in the report.
+const STACK_WORDS: u16 = 16;
+/// Number of bytes of memory before and after PC in the report.
+const MEMORY_AROUND_PC: u16 = 64;
+
+/// Write a report of the state of `machine` after a crash with `error`, to
+/// a file named after the ROM and the current time, in the same directory
+/// as the ROM. Returns the path of the file.
+pub fn write(machine: &Emulator, rom_path: &Path, title: &str, error: &str) -> io::Result<PathBuf> {
+    let path = file_path(rom_path, "crash");
+    let mut file = BufWriter::new(File::create(&path)?);
+    report(&mut file, machine, title, error)?;
+    file.flush()?;
+    Ok(path)
+}
+
+/// Write the last instructions executed by `machine`, disassembled, to a
+/// file named like the crash reports. Returns the path of the file.
+#[cfg(feature = "sdl2")]
+pub fn write_trace(machine: &Emulator, rom_path: &Path) -> io::Result<PathBuf> {
+    let path = file_path(rom_path, "trace");
+    let mut file = BufWriter::new(File::create(&path)?);
+    history(&mut file, machine)?;
+    file.flush()?;
+    Ok(path)
+}
+
+/// Path of a file named after the ROM, `kind` and the current time, in the
+/// same directory as the ROM.
+fn file_path(rom_path: &Path, kind: &str) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let stem = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    rom_path.with_file_name(format!("{}-{}-{}.txt", stem, kind, timestamp))
+}
+
+fn report(report: &mut impl Write, machine: &Emulator, title: &str, error: &str) -> io::Result<()> {
+    let cpu = &machine.cpu;
+    let mem = &machine.mem;
+    let registers = Registers::of(cpu);
+
+    writeln!(report, "Gaby {} crash report", env!("CARGO_PKG_VERSION"))?;
+    writeln!(report, "Game: {}", title)?;
+    writeln!(report, "Model: {:?}", mem.model)?;
+    writeln!(report, "Error: {}", error)?;
+    writeln!(
+        report,
+        "Frame: {}, machine cycle: {}",
+        machine.video.frame_count(),
+        mem.cycles
+    )?;
+    writeln!(report)?;
+
+    writeln!(report, "Registers: {}", registers)?;
+    writeln!(
+        report,
+        "IME: {}, IF: {:02X}, IE: {:02X}, halted: {}",
+        u8::from(cpu.ime()),
+        mem[IORegister::IF],
+        mem[IORegister::IE],
+        cpu.halted()
+    )?;
+    writeln!(report)?;
+
+    writeln!(report, "Stack:")?;
+    for index in 0..STACK_WORDS {
+        let address = registers.sp.wrapping_add(2 * index);
+        writeln!(report, "  {:04X}: {:04X}", address, mem.read_word(address))?;
+    }
+    writeln!(report)?;
+
+    writeln!(report, "Memory around PC:")?;
+    let start = registers.pc.saturating_sub(MEMORY_AROUND_PC) & 0xFFF0;
+    let end = registers.pc.saturating_add(MEMORY_AROUND_PC) | 0x000F;
+    for line in (u32::from(start)..=u32::from(end)).step_by(16) {
+        let bytes: Vec<String> = (line..line + 16)
+            .map(|address| format!("{:02X}", mem.read_byte(address as u16)))
+            .collect();
+        writeln!(report, "  {:04X}: {}", line, bytes.join(" "))?;
+    }
+    writeln!(report)?;
+
+    // The history is long, so it comes last.
+    history(report, machine)
+}
+
+/// The last instructions executed, oldest first. They are disassembled from
+/// memory as it is now, with the ROM banks mapped in now.
+fn history(report: &mut impl Write, machine: &Emulator) -> io::Result<()> {
+    let history = machine.cpu.history();
+    writeln!(report, "Last {} instructions, oldest first:", history.len())?;
+    for address in history {
+        let instruction = disassembler::disassemble(&machine.mem, address);
+        writeln!(report, "  {:04X}: {}", address, instruction.text)?;
+    }
+    Ok(())
+}
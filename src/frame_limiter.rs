@@ -0,0 +1,48 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Sleeping is only accurate to a millisecond or so on most systems, so the
+/// last part of the wait is spent spinning.
+const SPIN_TIME: Duration = Duration::from_millis(2);
+/// If the emulation falls further behind than this, e.g. after a pause or a
+/// slow frame, it continues from now instead of running fast to catch up.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// Keeps the emulation at the frame rate of the Game Boy, independently of
+/// the refresh rate of the display.
+pub struct FrameLimiter {
+    frame_time: Duration,
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(frames_per_second: f64) -> Self {
+        Self {
+            frame_time: Duration::from_secs_f64(1.0 / frames_per_second),
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// Wait until it's time for the next frame, at the given speed in
+    /// percent.
+    pub fn wait(&mut self, speed: u32) {
+        self.next_frame += self.frame_time * 100 / speed;
+
+        let now = Instant::now();
+        if now > self.next_frame + MAX_LAG {
+            self.next_frame = now;
+            return;
+        }
+
+        if let Some(remaining) = self.next_frame.checked_duration_since(now) {
+            if remaining > SPIN_TIME {
+                thread::sleep(remaining - SPIN_TIME);
+            }
+        }
+        while Instant::now() < self.next_frame {
+            std::hint::spin_loop();
+        }
+    }
+}
@@ -0,0 +1,1195 @@
+use crate::audio_output::AudioOutput;
+use crate::battery::BatterySave;
+use crate::bindings::{Action, Hotkey, InputProfiles};
+use crate::border::Border;
+use crate::config::Config;
+use crate::crash_report;
+use crate::font;
+use crate::frame_limiter::FrameLimiter;
+#[cfg(feature = "egui")]
+use crate::gui::{Gui, MenuState};
+use crate::inspector::{self, OamInspector};
+use crate::logging;
+use crate::movie_session::MovieSession;
+use crate::options::Options;
+#[cfg(feature = "egui")]
+use crate::recent_files::RecentFiles;
+use crate::recording::Recording;
+use crate::rewind::Rewind;
+use crate::save_states::{SaveStates, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+use crate::script::Script;
+use crate::tilt::TiltInput;
+use crate::window_geometry::WindowGeometry;
+use crate::Game;
+use gaby_core::audio::Audio;
+use gaby_core::emulator::Emulator;
+use gaby_core::events;
+use gaby_core::joypad::{Buttons, Joypad};
+use gaby_core::palette::PaletteSetting;
+use gaby_core::scope::Scope;
+use gaby_core::video::{self, MapOverlay};
+use sdl2::{
+    event::{Event, WindowEvent},
+    keyboard::Keycode,
+    pixels::{Color, PixelFormatEnum},
+    rect::{Point, Rect},
+    render::{BlendMode, Texture, WindowCanvas},
+};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info, warn};
+
+/// Size of the Game Boy screen in pixels.
+const SCREEN_SIZE: (u32, u32) = (video::SCREEN_WIDTH as u32, video::SCREEN_HEIGHT as u32);
+
+/// Number of samples the audio device asks for at a time, unless another
+/// size is chosen. Less than a frame at the default sample rate.
+const DEFAULT_AUDIO_BUFFER: u16 = 1024;
+/// Largest relative change of the sample rate used to keep the audio queue
+/// at the target size.
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+/// Emulation speeds, in percent, that the speed hotkeys step through.
+pub const SPEEDS: [u32; 7] = [10, 25, 50, 100, 150, 200, 400];
+/// How long to wait between redraws while paused.
+const PAUSED_FRAME_TIME: Duration = Duration::from_millis(16);
+
+/// How long the thumbnail of a save state is shown.
+const THUMBNAIL_TIME: Duration = Duration::from_secs(2);
+
+/// Colors of the two rectangle channels, the wave channel and the noise
+/// channel in the audio scope.
+const SCOPE_COLORS: [Color; 4] = [
+    Color::RGB(255, 96, 96),
+    Color::RGB(255, 208, 64),
+    Color::RGB(64, 208, 255),
+    Color::RGB(160, 255, 128),
+];
+
+/// Run the emulator in a window until it is closed, with audio output and the
+/// debugging tools that are controlled with hotkeys. A linked Game Boy is shown
+/// in a second window, and the keyboard controls the one whose window has
+/// focus.
+pub fn run(
+    game: &mut Game,
+    mut linked: Option<&mut Game>,
+    options: &Options,
+    config: &Config,
+    rom_path: &Path,
+    movie: &mut Option<MovieSession>,
+    script: &mut Option<Script>,
+) -> Result<(), Box<dyn Error>> {
+    let title = game.display_title();
+    let Game {
+        machine,
+        battery_save,
+        profiler,
+        ..
+    } = game;
+    let mut input_profiles = InputProfiles::new(
+        &config.keys,
+        &config.profiles,
+        options.input_profile.as_deref(),
+    )?;
+    let sdl_context = sdl2::init().map_err(|error| format!("Can't initialize SDL: {}", error))?;
+    let video_subsystem = sdl_context
+        .video()
+        .map_err(|error| format!("Can't initialize SDL video: {}", error))?;
+    let audio_subsystem = sdl_context
+        .audio()
+        .map_err(|error| format!("Can't initialize SDL audio: {}", error))?;
+    // The tilt of the first Game Boy, for games with an accelerometer.
+    let mut tilt = TiltInput::new(&sdl_context, &config.tilt);
+
+    // Image drawn around the screen, which the window is sized to fit.
+    let border = options
+        .border_path
+        .as_deref()
+        .map(Border::load)
+        .transpose()?;
+    let content_size = border.as_ref().map_or(SCREEN_SIZE, Border::size);
+
+    // The window opens where it was last time, unless a scale is given or
+    // the displays have changed so that it wouldn't be seen.
+    let geometry = match options.scale {
+        Some(_) => None,
+        None => WindowGeometry::load().filter(|geometry| geometry.is_on_screen(&video_subsystem)),
+    };
+    let (window_width, window_height) = match &geometry {
+        Some(geometry) => (geometry.width, geometry.height),
+        None => window_size(
+            content_size,
+            options.scale.unwrap_or(Options::DEFAULT_SCALE),
+            0,
+        ),
+    };
+    let window_title = format!("{} - {}", crate::PROGRAM_NAME, title);
+
+    let mut window_builder = video_subsystem.window(&window_title, window_width, window_height);
+    match &geometry {
+        Some(geometry) => window_builder.position(geometry.x, geometry.y),
+        None => window_builder.position_centered(),
+    };
+    let window = window_builder.resizable().build()?;
+
+    // No vsync, since the emulation speed is set by the frame limiter.
+    let mut canvas = window.into_canvas().build()?;
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    canvas.present();
+
+    // Make a texture that is to be copied into the canvas every frame.
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator.create_texture_streaming(
+        PixelFormatEnum::RGBA8888,
+        u32::from(video::SCREEN_WIDTH),
+        u32::from(video::SCREEN_HEIGHT),
+    )?;
+
+    let border_texture = border
+        .as_ref()
+        .map(|border| border.texture(&texture_creator))
+        .transpose()?;
+
+    // Thumbnail of the selected save state slot, shown in a corner of the
+    // screen for a while after the slot is selected, saved or loaded.
+    let mut thumbnail_texture = texture_creator.create_texture_streaming(
+        PixelFormatEnum::RGB24,
+        THUMBNAIL_WIDTH,
+        THUMBNAIL_HEIGHT,
+    )?;
+    let mut thumbnail_until: Option<Instant> = None;
+
+    // Window for the linked Game Boy, if there is one.
+    let mut linked_canvas = match &linked {
+        Some(linked) => {
+            let window = video_subsystem
+                .window(
+                    &format!(
+                        "{} - Player 2 - {}",
+                        crate::PROGRAM_NAME,
+                        linked.display_title()
+                    ),
+                    window_width,
+                    window_height,
+                )
+                .resizable()
+                .build()?;
+            let mut canvas = window.into_canvas().build()?;
+            canvas.set_draw_color(Color::RGB(0, 0, 0));
+            Some(canvas)
+        }
+        None => None,
+    };
+    let linked_texture_creator = linked_canvas
+        .as_ref()
+        .map(|canvas| canvas.texture_creator());
+    let mut linked_texture = match &linked_texture_creator {
+        Some(texture_creator) => Some(texture_creator.create_texture_streaming(
+            PixelFormatEnum::RGBA8888,
+            u32::from(video::SCREEN_WIDTH),
+            u32::from(video::SCREEN_HEIGHT),
+        )?),
+        None => None,
+    };
+    let linked_window_id = linked_canvas.as_ref().map(|canvas| canvas.window().id());
+
+    // The OAM inspector is shown in a separate window, hidden until F2 is
+    // pressed.
+    let inspector_window = video_subsystem
+        .window(
+            &format!("{} - OAM", crate::PROGRAM_NAME),
+            inspector::WIDTH as u32 * 2,
+            inspector::HEIGHT as u32 * 2,
+        )
+        .hidden()
+        .build()?;
+    let mut inspector_canvas = inspector_window.into_canvas().build()?;
+    let inspector_texture_creator = inspector_canvas.texture_creator();
+    let mut inspector_texture = inspector_texture_creator.create_texture_streaming(
+        PixelFormatEnum::RGB24,
+        inspector::WIDTH as u32,
+        inspector::HEIGHT as u32,
+    )?;
+    let mut inspector = OamInspector::new();
+    let mut show_inspector = false;
+    let main_window_id = canvas.window().id();
+    let inspector_window_id = inspector_canvas.window().id();
+    // Windows of the Game Boys that the keyboard can control.
+    let window_ids = [Some(main_window_id), linked_window_id];
+
+    let sample_rate = options.sample_rate.unwrap_or(Audio::DEFAULT_SAMPLE_RATE);
+    let audio_buffer = options.audio_buffer.unwrap_or(DEFAULT_AUDIO_BUFFER);
+    let audio_output = AudioOutput::open(&audio_subsystem, sample_rate, audio_buffer)
+        .map_err(|error| format!("Can't open the audio device: {}", error))?;
+    machine.audio.set_sample_rate(audio_output.sample_rate);
+    if let Some(linked) = &mut linked {
+        linked
+            .machine
+            .audio
+            .set_sample_rate(audio_output.sample_rate);
+    }
+    // Keep two callbacks' worth of samples queued.
+    let target_queued_samples = 2 * usize::from(audio_output.buffer_size);
+
+    let mut event_pump = sdl_context.event_pump()?;
+    let mut recording: Option<Recording> = None;
+    // The game can be switched by dropping a ROM on the window.
+    let mut rom_path = rom_path.to_path_buf();
+    let mut save_states = SaveStates::new(&rom_path);
+    // Rewinding one of two linked Game Boys would break the link, so there
+    // is only rewind for a single one.
+    let mut rewind = if linked.is_none() {
+        Some(Rewind::new())
+    } else {
+        None
+    };
+    let mut rewinding = false;
+    let mut advance_frame = false;
+    let mut fast_forwarding = false;
+    let fast_forward_audio = options.fast_forward_audio.unwrap_or_default();
+    let mut frame_limiter = FrameLimiter::new(Emulator::FRAMES_PER_SECOND);
+    let mut performance = Performance::new();
+    let mut status = Status::new(window_title, options.speed);
+    status.show(&mut canvas)?;
+
+    // The menu bar, and the list of recent files shown in it.
+    #[cfg(feature = "egui")]
+    let mut gui = Gui::new(&texture_creator);
+    #[cfg(feature = "egui")]
+    let mut recent_files = RecentFiles::load();
+    #[cfg(feature = "egui")]
+    recent_files.add(&rom_path);
+    let mut integer_scaling = options.integer_scaling;
+    // Palette chosen in the menu, which is kept when the game is switched.
+    let mut palette: Option<PaletteSetting> = None;
+
+    // SDL event loop.
+    let result = 'render_loop: loop {
+        if crate::stats::interrupted() {
+            break 'render_loop Ok(());
+        }
+        let frame_start = Instant::now();
+        // What to do in response to hotkeys, dropped files and the menus.
+        let mut commands = Vec::new();
+        for event in event_pump.poll_iter() {
+            #[cfg(feature = "egui")]
+            gui.handle_event(&event, main_window_id);
+            tilt.handle_event(&event);
+
+            match event {
+                // Exit the event loop if the user closes the main window.
+                Event::Quit { .. } => break 'render_loop Ok(()),
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } => {
+                    if window_id == main_window_id || Some(window_id) == linked_window_id {
+                        break 'render_loop Ok(());
+                    } else if window_id == inspector_window_id {
+                        show_inspector = false;
+                        inspector_canvas.window_mut().hide();
+                    }
+                }
+                Event::DropFile {
+                    window_id,
+                    filename,
+                    ..
+                } if window_id == main_window_id => {
+                    commands.push(Command::Open(PathBuf::from(filename)))
+                }
+                Event::KeyDown {
+                    window_id,
+                    keycode: Some(keycode @ (Keycode::Up | Keycode::Down)),
+                    ..
+                } if window_id == inspector_window_id => {
+                    if keycode == Keycode::Up {
+                        inspector.select_previous_line();
+                    } else {
+                        inspector.select_next_line();
+                    }
+                }
+                // Keys for a text field in the menus.
+                #[cfg(feature = "egui")]
+                Event::KeyDown { .. } | Event::KeyUp { .. } if gui.wants_keyboard() => {}
+                // A key to bind to an action in the controls window.
+                #[cfg(feature = "egui")]
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } if gui.binding.is_some() => {
+                    if let Some(action) = gui.binding.take() {
+                        input_profiles.bindings_mut().bind(keycode, action);
+                    }
+                }
+                Event::KeyDown {
+                    window_id,
+                    keycode: Some(keycode),
+                    repeat,
+                    ..
+                } => match input_profiles.bindings().get(keycode) {
+                    Some(Action::Button(button)) => {
+                        if let Some(joypad) =
+                            joypad_of_window(window_id, &window_ids, machine, linked.as_deref_mut())
+                        {
+                            joypad.held.insert(button);
+                        }
+                    }
+                    Some(Action::Turbo(button)) => {
+                        if let Some(joypad) =
+                            joypad_of_window(window_id, &window_ids, machine, linked.as_deref_mut())
+                        {
+                            joypad.turbo.insert(button);
+                        }
+                    }
+                    Some(Action::Tilt(direction)) if window_id == main_window_id => {
+                        tilt.set_key(direction, true)
+                    }
+                    Some(Action::Hotkey(hotkey)) if !repeat => {
+                        commands.push(Command::Hotkey(hotkey))
+                    }
+                    _ => {}
+                },
+                Event::KeyUp {
+                    window_id,
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    let joypad =
+                        joypad_of_window(window_id, &window_ids, machine, linked.as_deref_mut());
+                    match (joypad, input_profiles.bindings().get(keycode)) {
+                        (Some(joypad), Some(Action::Button(button))) => joypad.held.remove(button),
+                        (Some(joypad), Some(Action::Turbo(button))) => joypad.turbo.remove(button),
+                        (_, Some(Action::Tilt(direction))) => tilt.set_key(direction, false),
+                        (_, Some(Action::Hotkey(Hotkey::Rewind))) => rewinding = false,
+                        (_, Some(Action::Hotkey(Hotkey::FastForward))) => fast_forwarding = false,
+                        _ => {}
+                    }
+                }
+                // Key releases go to the window with focus, so release all
+                // buttons when a window loses it.
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::FocusLost,
+                    ..
+                } => {
+                    if let Some(joypad) =
+                        joypad_of_window(window_id, &window_ids, machine, linked.as_deref_mut())
+                    {
+                        joypad.held = Buttons::empty();
+                        joypad.turbo = Buttons::empty();
+                    }
+                    if window_id == main_window_id {
+                        tilt.release_keys();
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        #[cfg(feature = "egui")]
+        {
+            let state = MenuState {
+                paused: status.paused,
+                speed: status.speed,
+                slot: save_states.slot(),
+                layers: machine.video.layers,
+                muted: machine.audio.muted,
+                palette,
+                profiles: input_profiles.names(),
+                profile: input_profiles.current(),
+                bindings: input_profiles.bindings(),
+                recent_files: recent_files.files(),
+                integer_scaling,
+                recording: recording.is_some(),
+                inspector: show_inspector,
+                audio_scope: machine.audio.scope.is_some(),
+                machine,
+            };
+            commands.extend(gui.run(canvas.output_size()?, &state));
+        }
+
+        for command in commands {
+            match command {
+                Command::Hotkey(hotkey) => match hotkey {
+                    Hotkey::Quit => break 'render_loop Ok(()),
+                    Hotkey::ToggleInspector => {
+                        show_inspector = !show_inspector;
+                        if show_inspector {
+                            inspector_canvas.window_mut().show();
+                        } else {
+                            inspector_canvas.window_mut().hide();
+                        }
+                    }
+                    Hotkey::ToggleBackground => {
+                        machine.video.layers.background = !machine.video.layers.background
+                    }
+                    Hotkey::ToggleWindow => {
+                        machine.video.layers.window = !machine.video.layers.window
+                    }
+                    Hotkey::ToggleSprites => {
+                        machine.video.layers.sprites = !machine.video.layers.sprites
+                    }
+                    Hotkey::ToggleRecording => match recording.take() {
+                        Some(recording) => stop_recording(recording),
+                        None => match Recording::start(&rom_path) {
+                            Ok(started) => {
+                                info!("Recording to {}", started.path().display());
+                                recording = Some(started);
+                            }
+                            Err(error) => error!("Failed to start recording: {}", error),
+                        },
+                    },
+                    Hotkey::ExportHeatmap => crate::export_heatmap(&machine.mem, options),
+                    Hotkey::ExportBackgroundMap => {
+                        export_background_map(machine, &rom_path, options.map_overlay)
+                    }
+                    Hotkey::NextProfile => {
+                        input_profiles.select_next();
+                        // The keys may not be released with the same
+                        // bindings, so release all buttons.
+                        machine.joypad.held = Buttons::empty();
+                        machine.joypad.turbo = Buttons::empty();
+                        info!("Input profile: {}", input_profiles.name());
+                    }
+                    Hotkey::SaveState => {
+                        match save_states.save(machine) {
+                            Ok(()) => info!("Saved state in slot {}", save_states.slot()),
+                            Err(error) => error!("{}", error),
+                        }
+                        status.slot = Some(save_states.describe());
+                        thumbnail_until = show_thumbnail(&save_states, &mut thumbnail_texture)?;
+                        status.show(&mut canvas)?;
+                    }
+                    Hotkey::LoadState => match save_states.load(machine) {
+                        Ok(()) => {
+                            info!("Loaded state from slot {}", save_states.slot());
+                            status.slot = Some(save_states.describe());
+                            thumbnail_until = show_thumbnail(&save_states, &mut thumbnail_texture)?;
+                            status.show(&mut canvas)?;
+                        }
+                        Err(error) => error!("{}", error),
+                    },
+                    Hotkey::SelectSlot(slot) => {
+                        save_states.select(slot);
+                        status.slot = Some(save_states.describe());
+                        thumbnail_until = show_thumbnail(&save_states, &mut thumbnail_texture)?;
+                        status.show(&mut canvas)?;
+                    }
+                    Hotkey::NextSlot => {
+                        save_states.select_next();
+                        status.slot = Some(save_states.describe());
+                        thumbnail_until = show_thumbnail(&save_states, &mut thumbnail_texture)?;
+                        status.show(&mut canvas)?;
+                    }
+                    Hotkey::Rewind => rewinding = true,
+                    Hotkey::FastForward => fast_forwarding = true,
+                    Hotkey::Pause => {
+                        status.paused = !status.paused;
+                        status.show(&mut canvas)?;
+                    }
+                    Hotkey::AdvanceFrame => {
+                        advance_frame = true;
+                        if !status.paused {
+                            status.paused = true;
+                            status.show(&mut canvas)?;
+                        }
+                    }
+                    Hotkey::Faster | Hotkey::Slower => {
+                        status.speed = next_speed(status.speed, hotkey == Hotkey::Faster);
+                        status.show(&mut canvas)?;
+                    }
+                    Hotkey::ToggleAudioScope => {
+                        machine.audio.scope = match machine.audio.scope {
+                            Some(_) => None,
+                            None => Some(Scope::new()),
+                        }
+                    }
+                    Hotkey::ToggleTrace => {
+                        logging::set_instruction_trace(!logging::instruction_trace())
+                    }
+                    Hotkey::DumpTrace => match crash_report::write_trace(machine, &rom_path) {
+                        Ok(path) => info!("Wrote the instruction trace to {}", path.display()),
+                        Err(error) => error!("Can't write the instruction trace: {}", error),
+                    },
+                    Hotkey::ToggleMenu => {
+                        #[cfg(feature = "egui")]
+                        {
+                            gui.visible = !gui.visible;
+                        }
+                    }
+                    Hotkey::Scale(scale) => {
+                        #[cfg(feature = "egui")]
+                        let menu_height = gui.height();
+                        #[cfg(not(feature = "egui"))]
+                        let menu_height = 0;
+                        let (width, height) =
+                            window_size(content_size, u32::from(scale), menu_height);
+                        canvas.window_mut().set_size(width, height)?;
+                    }
+                },
+                Command::Open(path) => {
+                    if linked.is_some() {
+                        warn!("The game can't be switched while linked to another one.");
+                        continue;
+                    }
+                    match switch_game(machine, battery_save, &path, options, config) {
+                        Ok(title) => {
+                            if let Some(palette) = palette {
+                                let colorization = palette.colorization(
+                                    &machine.mem.cartridge,
+                                    config.color_correction(),
+                                );
+                                machine.video.set_colorization(colorization);
+                            }
+                            #[cfg(feature = "egui")]
+                            recent_files.add(&path);
+                            rom_path = path;
+                            save_states = SaveStates::new(&rom_path);
+                            rewind = Some(Rewind::new());
+                            status.title = format!("{} - {}", crate::PROGRAM_NAME, title);
+                            status.slot = None;
+                            thumbnail_until = None;
+                            status.show(&mut canvas)?;
+                        }
+                        Err(error) => error!("Can't load {}: {}", path.display(), error),
+                    }
+                }
+                Command::SetSpeed(speed) => {
+                    status.speed = speed;
+                    status.show(&mut canvas)?;
+                }
+                Command::SelectProfile(index) => {
+                    input_profiles.select(index);
+                    machine.joypad.held = Buttons::empty();
+                    machine.joypad.turbo = Buttons::empty();
+                    info!("Input profile: {}", input_profiles.name());
+                }
+                Command::SetPalette(setting) => {
+                    let colorization =
+                        setting.colorization(&machine.mem.cartridge, config.color_correction());
+                    machine.video.set_colorization(colorization);
+                    palette = Some(setting);
+                }
+                Command::ToggleChannel(channel) => {
+                    machine.audio.muted[channel] = !machine.audio.muted[channel]
+                }
+                Command::ToggleIntegerScaling => integer_scaling = !integer_scaling,
+                Command::SetPaused(paused) => {
+                    status.paused = paused;
+                    status.show(&mut canvas)?;
+                }
+                Command::Step => {
+                    machine.step()?;
+                    crate::report_watches(machine);
+                    if !status.paused {
+                        status.paused = true;
+                        status.show(&mut canvas)?;
+                    }
+                }
+                Command::ToggleBreakpoint(address) => {
+                    if !machine.breakpoints.remove(&address) {
+                        machine.breakpoints.insert(address);
+                    }
+                }
+                Command::ToggleEvent(event) => {
+                    if !machine.mem.events.unsubscribe(event) {
+                        machine.mem.events.subscribe(event);
+                    }
+                }
+                Command::AddWatch(expression) => {
+                    if let Err(error) = machine.watches.add(&mut machine.mem, &expression) {
+                        error!("{}", error);
+                    }
+                }
+                Command::RemoveWatch(index) => machine.watches.remove(&mut machine.mem, index),
+                Command::Poke(address, value) => {
+                    machine.mem.poke(address, value);
+                }
+            }
+        }
+
+        let presentation_start = Instant::now();
+        update_texture(&mut texture, machine)?;
+        canvas.clear();
+        // The screen goes below the menu bar.
+        #[cfg(feature = "egui")]
+        let menu_height = gui.height();
+        #[cfg(not(feature = "egui"))]
+        let menu_height = 0;
+        let (width, height) = canvas.output_size()?;
+        let area = (width, height.saturating_sub(menu_height));
+        let screen = match (&border, &border_texture) {
+            (Some(border), Some(border_texture)) => {
+                let mut frame = fit_rect(area, border.size(), integer_scaling);
+                frame.offset(0, menu_height as i32);
+                canvas.copy(border_texture, None, frame)?;
+                screen_in_border(frame, border.size())
+            }
+            _ => {
+                let mut screen = screen_rect(area, integer_scaling);
+                screen.offset(0, menu_height as i32);
+                screen
+            }
+        };
+        canvas.copy(&texture, None, screen)?;
+        if let Some(script) = script {
+            draw_overlay(&mut canvas, screen, &script.overlay())?;
+        }
+        if let Some(scope) = &machine.audio.scope {
+            draw_audio_scope(&mut canvas, screen, scope)?;
+        }
+        if thumbnail_until.is_some_and(|until| Instant::now() < until) {
+            draw_thumbnail(&mut canvas, screen, &thumbnail_texture)?;
+        }
+        #[cfg(feature = "egui")]
+        gui.paint(&mut canvas)?;
+        canvas.present();
+
+        if let (Some(linked), Some(canvas), Some(texture)) =
+            (&mut linked, &mut linked_canvas, &mut linked_texture)
+        {
+            update_texture(texture, &mut linked.machine)?;
+            canvas.clear();
+            let screen = screen_rect(canvas.output_size()?, integer_scaling);
+            canvas.copy(texture, None, screen)?;
+            canvas.present();
+        }
+
+        if show_inspector {
+            inspector_texture.with_lock(None, |pixels, pitch| {
+                inspector.render(&machine.mem, pixels, pitch)
+            })?;
+            inspector_canvas.copy(&inspector_texture, None, None)?;
+            inspector_canvas.present();
+        }
+        let presentation = presentation_start.elapsed();
+
+        // While paused, only redraw the screen, at about the normal frame
+        // rate, until a frame is advanced.
+        if status.paused && !advance_frame && !rewinding {
+            if status.performance.take().is_some() {
+                status.show(&mut canvas)?;
+            }
+            performance = Performance::new();
+            thread::sleep(PAUSED_FRAME_TIME);
+            continue;
+        }
+        advance_frame = false;
+
+        if let Some(recording) = &mut recording {
+            recording.add_frame(machine.video.framebuffer());
+        }
+
+        // While rewinding, go back one state per frame shown, and run a frame
+        // from it to draw the picture. Since states are taken every second
+        // frame, this rewinds at twice the normal speed.
+        let rewound = match &mut rewind {
+            Some(rewind) if rewinding => rewind.step_back(machine)?,
+            Some(rewind) => {
+                rewind.record(machine);
+                false
+            }
+            None => false,
+        };
+
+        let (tilt_x, tilt_y) = tilt.tilt();
+        machine.set_tilt(tilt_x, tilt_y);
+        crate::run_frame(machine, movie, script)?;
+        if let Some(profiler) = profiler {
+            profiler.add_frame(machine, presentation);
+        }
+        if let Some(linked) = &mut linked {
+            linked.machine.run_frame()?;
+        }
+        if machine.take_breakpoint_hit() {
+            match machine.take_event_hit() {
+                Some(event) => info!("Stopped after {} at {:#06X}", event, machine.cpu.pc()),
+                None => info!("Breakpoint at {:#06X}", machine.cpu.pc()),
+            }
+            status.paused = true;
+            status.show(&mut canvas)?;
+            #[cfg(feature = "egui")]
+            {
+                gui.show_debugger = true;
+            }
+        }
+        if performance.add_frame(frame_start.elapsed()) {
+            status.performance = Some(performance.take_summary());
+            status.show(&mut canvas)?;
+        }
+        // Wait for the time of the next frame. Then adjust the sample rate
+        // slightly to keep the audio queue at the target size, since the
+        // audio device will never play at exactly the nominal rate.
+        if !fast_forwarding {
+            frame_limiter.wait(status.speed);
+        }
+        let fill = audio_output.queued_samples() as f64 / target_queued_samples as f64;
+        let adjustment = (fill - 1.0).clamp(-1.0, 1.0) * MAX_RATE_ADJUSTMENT;
+        // At other speeds than 100%, the samples are stretched or squeezed
+        // to fill the same real time, which shifts the pitch.
+        let rate_ratio = (1.0 - adjustment) * 100.0 / f64::from(status.speed);
+        machine.audio.set_rate_ratio(rate_ratio);
+        let mut samples = machine.audio.take_samples();
+        if rewound {
+            // Play silence while rewinding.
+            samples.iter_mut().for_each(|sample| *sample = 0.0);
+        }
+        if let Some(linked) = &mut linked {
+            // Play both Game Boys at half volume.
+            linked.machine.audio.set_rate_ratio(rate_ratio);
+            let linked_samples = linked.machine.audio.take_samples();
+            for (sample, linked_sample) in samples.iter_mut().zip(linked_samples) {
+                *sample = (*sample + linked_sample) / 2.0;
+            }
+        }
+        if fast_forwarding {
+            audio_output.queue_fast_forward(&samples, target_queued_samples, fast_forward_audio);
+        } else {
+            audio_output.queue(&samples);
+        }
+
+        if let Some(battery_save) = battery_save {
+            battery_save.update(&mut machine.mem.cartridge)?;
+        }
+        if let Some(Game {
+            machine,
+            battery_save: Some(battery_save),
+            ..
+        }) = &mut linked
+        {
+            battery_save.update(&mut machine.mem.cartridge)?;
+        }
+    };
+
+    if let Some(recording) = recording {
+        stop_recording(recording);
+    }
+    WindowGeometry::of(canvas.window()).save();
+
+    result
+}
+
+/// Copy the lines of the framebuffer that have changed into the texture,
+/// while it is locked. Locking is how a streaming texture is meant to be
+/// updated, and saves the renderer a copy into a buffer of its own.
+fn update_texture(texture: &mut Texture, machine: &mut Emulator) -> Result<(), String> {
+    if let Some(lines) = machine.video.take_dirty_lines() {
+        let rect = Rect::new(
+            0,
+            lines.start as i32,
+            u32::from(video::SCREEN_WIDTH),
+            lines.len() as u32,
+        );
+        let width = usize::from(video::SCREEN_WIDTH);
+        let framebuffer = &machine.video.framebuffer()[lines.start * width..lines.end * width];
+        texture.with_lock(rect, |pixels, pitch| {
+            for (line, row) in framebuffer.chunks_exact(width).enumerate() {
+                let start = line * pitch;
+                pixels[start..start + 4 * width].copy_from_slice(bytemuck::cast_slice(row));
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Joypad of the Game Boy shown in the window, where `window_ids` are the
+/// windows of the first and the linked Game Boy.
+fn joypad_of_window<'a>(
+    window_id: u32,
+    window_ids: &[Option<u32>; 2],
+    machine: &'a mut Emulator,
+    linked: Option<&'a mut Game>,
+) -> Option<&'a mut Joypad> {
+    if Some(window_id) == window_ids[0] {
+        Some(&mut machine.joypad)
+    } else if Some(window_id) == window_ids[1] {
+        linked.map(|linked| &mut linked.machine.joypad)
+    } else {
+        None
+    }
+}
+
+/// Draw the text from a script over the screen, which is drawn at `screen`.
+/// The positions of the text are in Game Boy pixels. The text is white with
+/// a black shadow, to be readable on any background.
+fn draw_overlay(
+    canvas: &mut WindowCanvas,
+    screen: Rect,
+    overlay: &[(i64, i64, String)],
+) -> Result<(), String> {
+    let scale_x = f64::from(screen.width()) / f64::from(video::SCREEN_WIDTH);
+    let scale_y = f64::from(screen.height()) / f64::from(video::SCREEN_HEIGHT);
+    let rects: Vec<_> = overlay
+        .iter()
+        .flat_map(|(x, y, text)| {
+            font::text_pixels(text).map(move |(column, row)| {
+                let left = (*x + column as i64) as f64 * scale_x;
+                let top = (*y + row as i64) as f64 * scale_y;
+                Rect::new(
+                    screen.x() + left as i32,
+                    screen.y() + top as i32,
+                    scale_x.ceil() as u32,
+                    scale_y.ceil() as u32,
+                )
+            })
+        })
+        .filter(|rect| rect.has_intersection(screen))
+        .collect();
+
+    let shadow: Vec<_> = rects
+        .iter()
+        .map(|rect| {
+            let mut shadow = *rect;
+            shadow.offset(scale_x as i32, scale_y as i32);
+            shadow
+        })
+        .collect();
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.fill_rects(&shadow)?;
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    canvas.fill_rects(&rects)?;
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    Ok(())
+}
+
+/// Draw the latest output of the four audio channels over the bottom half
+/// of the screen, which is drawn at `screen`: a row for each channel, with
+/// its level as a bar on the left and its waveform to the right of it, on a
+/// darkened background.
+fn draw_audio_scope(canvas: &mut WindowCanvas, screen: Rect, scope: &Scope) -> Result<(), String> {
+    let row_height = screen.height() / 8;
+    let bar_width = (screen.width() / 32).max(1);
+    let gap = (screen.width() / 160).max(1);
+    let wave_left = screen.x() + (bar_width + 2 * gap) as i32;
+    let wave_width = screen.right() - gap as i32 - wave_left;
+    let top = screen.bottom() - 4 * row_height as i32;
+
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    canvas.fill_rect(Rect::new(screen.x(), top, screen.width(), 4 * row_height))?;
+    canvas.set_blend_mode(BlendMode::None);
+
+    for (channel, &color) in SCOPE_COLORS.iter().enumerate() {
+        let row_top = top + (channel as u32 * row_height) as i32;
+        let inner_height = row_height.saturating_sub(2 * gap).max(1);
+        canvas.set_draw_color(color);
+
+        let bar_height = (scope.level(channel) * inner_height as f32).round() as u32;
+        if bar_height > 0 {
+            canvas.fill_rect(Rect::new(
+                screen.x() + gap as i32,
+                row_top + (gap + inner_height - bar_height) as i32,
+                bar_width,
+                bar_height,
+            ))?;
+        }
+
+        // The waveform is centered on the row, and a channel at full volume
+        // fills its height.
+        let center = row_top as f32 + row_height as f32 / 2.0;
+        let points: Vec<Point> = scope
+            .waveform(channel)
+            .enumerate()
+            .map(|(i, sample)| {
+                let x = wave_left + (i as i32 * wave_width) / Scope::LENGTH as i32;
+                let offset = (sample / Scope::FULL_SCALE).clamp(-0.5, 0.5);
+                let y = center - offset * inner_height as f32;
+                Point::new(x, y as i32)
+            })
+            .collect();
+        canvas.draw_lines(points.as_slice())?;
+    }
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    Ok(())
+}
+
+/// Copy the thumbnail of the selected save state slot into `texture`, and
+/// get the time to show it until, or None if there is no thumbnail.
+fn show_thumbnail(
+    save_states: &SaveStates,
+    texture: &mut Texture,
+) -> Result<Option<Instant>, Box<dyn Error>> {
+    match save_states.thumbnail() {
+        Some(pixels) => {
+            texture.update(None, &pixels, 3 * THUMBNAIL_WIDTH as usize)?;
+            Ok(Some(Instant::now() + THUMBNAIL_TIME))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Draw the thumbnail of a save state in the top right corner of the screen,
+/// which is drawn at `screen`, a third of its width and with a white frame.
+fn draw_thumbnail(
+    canvas: &mut WindowCanvas,
+    screen: Rect,
+    texture: &Texture,
+) -> Result<(), String> {
+    let width = screen.width() / 3;
+    let height = width * THUMBNAIL_HEIGHT / THUMBNAIL_WIDTH;
+    let margin = (screen.width() / 40) as i32;
+    let thumbnail = Rect::new(
+        screen.right() - margin - width as i32,
+        screen.y() + margin,
+        width,
+        height,
+    );
+    let border = (screen.width() / 160).max(1);
+    let frame = Rect::new(
+        thumbnail.x() - border as i32,
+        thumbnail.y() - border as i32,
+        width + 2 * border,
+        height + 2 * border,
+    );
+
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    canvas.fill_rect(frame)?;
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.copy(texture, None, thumbnail)
+}
+
+/// Size of a window that fits the screen, or the border around it, of the
+/// given size scaled by `scale`, below a menu bar of the given height.
+fn window_size((width, height): (u32, u32), scale: u32, menu_height: u32) -> (u32, u32) {
+    (width * scale, height * scale + menu_height)
+}
+
+/// Where the screen goes in the middle of a border of `border_size` pixels
+/// that is drawn at `frame`, at the same scale as the border.
+fn screen_in_border(frame: Rect, (border_width, border_height): (u32, u32)) -> Rect {
+    let (screen_width, screen_height) = SCREEN_SIZE;
+    let scale_x = |x: u32| x * frame.width() / border_width;
+    let scale_y = |y: u32| y * frame.height() / border_height;
+    Rect::new(
+        frame.x() + scale_x((border_width - screen_width) / 2) as i32,
+        frame.y() + scale_y((border_height - screen_height) / 2) as i32,
+        scale_x(screen_width),
+        scale_y(screen_height),
+    )
+}
+
+/// Find where to draw the screen in a window of the given size. The screen is
+/// scaled up as much as possible while keeping the aspect ratio, optionally
+/// only by whole numbers, and centered with black bars around it.
+fn screen_rect(window_size: (u32, u32), integer_scaling: bool) -> Rect {
+    fit_rect(window_size, SCREEN_SIZE, integer_scaling)
+}
+
+/// Largest rectangle with the aspect ratio of an image of `image_size`
+/// pixels that fits in the middle of a window, see `screen_rect`.
+fn fit_rect(
+    (window_width, window_height): (u32, u32),
+    (image_width, image_height): (u32, u32),
+    integer_scaling: bool,
+) -> Rect {
+    let (width, height) = if integer_scaling {
+        let scale = (window_width / image_width)
+            .min(window_height / image_height)
+            .max(1);
+        (image_width * scale, image_height * scale)
+    } else if window_width * image_height > window_height * image_width {
+        // The window is wider than the image.
+        (window_height * image_width / image_height, window_height)
+    } else {
+        (window_width, window_width * image_height / image_width)
+    };
+
+    Rect::new(
+        (window_width as i32 - width as i32) / 2,
+        (window_height as i32 - height as i32) / 2,
+        width,
+        height,
+    )
+}
+
+/// Something to do in response to a hotkey, a dropped file or a choice in
+/// the menus.
+#[cfg_attr(not(feature = "egui"), allow(dead_code))]
+pub enum Command {
+    Hotkey(Hotkey),
+    /// Switch to the ROM at this path.
+    Open(PathBuf),
+    SetSpeed(u32),
+    /// Switch to the input profile with this index.
+    SelectProfile(usize),
+    SetPalette(PaletteSetting),
+    /// Mute or unmute an audio channel.
+    ToggleChannel(usize),
+    ToggleIntegerScaling,
+    SetPaused(bool),
+    /// Execute one instruction.
+    Step,
+    /// Set or clear a breakpoint at this address.
+    ToggleBreakpoint(u16),
+    /// Start or stop breaking after this event.
+    ToggleEvent(events::Event),
+    /// Watch the value given by this expression, see `Watches::add`.
+    AddWatch(String),
+    /// Stop watching the value with this index.
+    RemoveWatch(usize),
+    /// Change the byte at this address, see `Memory::poke`.
+    Poke(u16, u8),
+}
+
+/// Replace the game in `machine` with the ROM at `rom_path`, after saving the
+/// battery RAM of the old game. The serial connection, logs and heatmap
+/// carry over to the new game. Returns the title of the new game.
+fn switch_game(
+    machine: &mut Emulator,
+    battery_save: &mut Option<BatterySave>,
+    rom_path: &Path,
+    options: &Options,
+    config: &Config,
+) -> Result<String, Box<dyn Error>> {
+    let mut game = Game::load(rom_path, None, options, config)?;
+    let title = game.display_title();
+
+    if let Some(battery_save) = battery_save {
+        battery_save.flush(&mut machine.mem.cartridge)?;
+    }
+
+    game.machine.mem.heatmap = machine.mem.heatmap.take();
+    game.machine.frame_times = machine.frame_times.take();
+    game.machine.serial.peer = machine.serial.peer.take();
+    game.machine.serial.log = machine.serial.log.take();
+    game.machine.audio.stems = machine.audio.stems.take();
+    game.machine.audio.scope = machine.audio.scope.take();
+    game.machine
+        .audio
+        .set_sample_rate(machine.audio.sample_rate());
+
+    *machine = game.machine;
+    *battery_save = game.battery_save;
+    Ok(title)
+}
+
+/// What the title of the main window shows besides the game title.
+struct Status {
+    title: String,
+    /// Selected save state slot, and when it was saved.
+    slot: Option<String>,
+    /// Emulation speed in percent.
+    speed: u32,
+    paused: bool,
+    /// Frame rate and frame time measured over the last second.
+    performance: Option<String>,
+}
+
+impl Status {
+    fn new(title: String, speed: u32) -> Self {
+        Self {
+            title,
+            slot: None,
+            speed,
+            paused: false,
+            performance: None,
+        }
+    }
+
+    fn show(&self, canvas: &mut WindowCanvas) -> Result<(), Box<dyn Error>> {
+        let mut title = self.title.clone();
+        if let Some(slot) = &self.slot {
+            title += &format!(" - {}", slot);
+        }
+        if self.speed != 100 {
+            title += &format!(" - Speed {}%", self.speed);
+        }
+        if self.paused {
+            title += " - Paused";
+        }
+        if let Some(performance) = &self.performance {
+            title += &format!(" - {}", performance);
+        }
+
+        canvas.window_mut().set_title(&title)?;
+        Ok(())
+    }
+}
+
+/// Measures how fast the emulation is running.
+struct Performance {
+    since: Instant,
+    frames: u32,
+    /// Time spent emulating and drawing the frames, without waiting.
+    busy: Duration,
+}
+
+impl Performance {
+    /// How often the measurements are shown.
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            frames: 0,
+            busy: Duration::ZERO,
+        }
+    }
+
+    /// Count a frame that took `busy` to emulate and draw. Returns true when
+    /// it's time to show the measurements.
+    fn add_frame(&mut self, busy: Duration) -> bool {
+        self.frames += 1;
+        self.busy += busy;
+        self.since.elapsed() >= Performance::INTERVAL
+    }
+
+    /// Describe the frame rate, the speed relative to a real Game Boy and
+    /// the average frame time, and start measuring again.
+    fn take_summary(&mut self) -> String {
+        let frames_per_second = f64::from(self.frames) / self.since.elapsed().as_secs_f64();
+        let summary = format!(
+            "{:.1} FPS ({:.0}%), {:.1} ms per frame",
+            frames_per_second,
+            100.0 * frames_per_second / Emulator::FRAMES_PER_SECOND,
+            1000.0 * self.busy.as_secs_f64() / f64::from(self.frames)
+        );
+        *self = Performance::new();
+        summary
+    }
+}
+
+/// Next speed in `SPEEDS` that is faster or slower than `speed`, or `speed`
+/// itself when there is none.
+fn next_speed(speed: u32, faster: bool) -> u32 {
+    let next = if faster {
+        SPEEDS.iter().find(|&&step| step > speed)
+    } else {
+        SPEEDS.iter().rev().find(|&&step| step < speed)
+    };
+    next.copied().unwrap_or(speed)
+}
+
+/// Export the whole background map to a PNG file next to the ROM, named by
+/// the time like GIF recordings.
+fn export_background_map(machine: &mut Emulator, rom_path: &Path, overlay: MapOverlay) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let stem = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    let path = rom_path.with_file_name(format!("{}-map-{}.png", stem, timestamp));
+
+    let result =
+        machine
+            .video
+            .write_background_map(&mut machine.mem, &path.to_string_lossy(), overlay);
+    match result {
+        Ok(()) => info!("Exported background map to {}", path.display()),
+        Err(error) => error!("Failed to export background map: {}", error),
+    }
+}
+
+fn stop_recording(recording: Recording) {
+    let path = recording.path().to_owned();
+    match recording.stop() {
+        Ok(()) => info!("Saved recording to {}", path.display()),
+        Err(error) => error!("{}", error),
+    }
+}
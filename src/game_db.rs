@@ -0,0 +1,124 @@
+use sha1::{Digest, Sha1};
+use std::{collections::HashMap, error::Error, fs, io, path::PathBuf};
+
+/// A game found in the database.
+#[derive(Clone)]
+pub struct KnownGame {
+    /// Name of the game, e.g. `Tetris (World) (Rev 1)`.
+    pub name: String,
+    /// Region, from the first part of the name in parentheses, e.g. `World`.
+    pub region: Option<String>,
+    /// Whether the ROM is known to be a bad dump.
+    pub bad_dump: bool,
+}
+
+impl KnownGame {
+    /// Name of the game, marked if it is a bad dump.
+    pub fn describe(&self) -> String {
+        if self.bad_dump {
+            format!("{} [bad dump]", self.name)
+        } else {
+            self.name.clone()
+        }
+    }
+}
+
+/// Database of Game Boy ROMs by SHA-1, read from a No-Intro DAT file in the
+/// Logiqx XML format, by default `gaby/games.dat` in the user's
+/// configuration directory. No database is bundled, since the DAT files are
+/// updated as new dumps are verified.
+pub struct GameDatabase {
+    /// Games by the SHA-1 of their ROM, in lowercase hex.
+    games: HashMap<String, KnownGame>,
+}
+
+impl GameDatabase {
+    /// Load the database from the given file, or from the default location.
+    /// A missing file in the default location gives no database.
+    pub fn load(path: Option<&str>) -> Result<Option<Self>, Box<dyn Error>> {
+        let (path, is_default_path) = match path {
+            Some(path) => (PathBuf::from(path), false),
+            None => match dirs::config_dir() {
+                Some(dir) => (dir.join("gaby").join("games.dat"), true),
+                None => return Ok(None),
+            },
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(text) => Ok(Some(GameDatabase::parse(&text))),
+            Err(error) if is_default_path && error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(format!("Can't read {}: {}", path.display(), error).into()),
+        }
+    }
+
+    /// Read the games from a DAT file. Only the `game` elements and the
+    /// `rom` elements in them are used, so a file that isn't a DAT file
+    /// just gives an empty database.
+    fn parse(text: &str) -> Self {
+        let mut games = HashMap::new();
+
+        for game in text.split("<game ").skip(1) {
+            let game = game.split("</game>").next().unwrap_or_default();
+            let start_tag = game.split('>').next().unwrap_or_default();
+            let name = match attribute(start_tag, "name") {
+                Some(name) => name,
+                None => continue,
+            };
+            let region = name
+                .split_once(" (")
+                .and_then(|(_, rest)| rest.split_once(')'))
+                .map(|(region, _)| region.to_string());
+
+            for rom in game.split("<rom ").skip(1) {
+                let rom = rom.split('>').next().unwrap_or_default();
+                if let Some(sha1) = attribute(rom, "sha1") {
+                    let known = KnownGame {
+                        name: name.clone(),
+                        region: region.clone(),
+                        bad_dump: attribute(rom, "status").as_deref() == Some("baddump"),
+                    };
+                    games.insert(sha1.to_ascii_lowercase(), known);
+                }
+            }
+        }
+
+        Self { games }
+    }
+
+    /// The game with the given ROM, if it is in the database.
+    pub fn find(&self, rom: &[u8]) -> Option<&KnownGame> {
+        self.games.get(&sha1_hex(rom))
+    }
+}
+
+/// SHA-1 of `data` in lowercase hex, as in DAT files.
+pub fn sha1_hex(data: &[u8]) -> String {
+    Sha1::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Value of the attribute `name` in the start of an XML element, with the
+/// entities replaced by the characters they stand for.
+fn attribute(element: &str, name: &str) -> Option<String> {
+    let prefix = format!("{}=\"", name);
+    // The first attribute has no space before it, since the text starts
+    // after the element name.
+    let start = if element.starts_with(&prefix) {
+        0
+    } else {
+        element.find(&format!(" {}", prefix))? + 1
+    };
+    let value = &element[start + prefix.len()..];
+    let value = &value[..value.find('"')?];
+
+    Some(
+        value
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&"),
+    )
+}
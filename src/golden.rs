@@ -0,0 +1,207 @@
+use gaby_core::emulator::Emulator;
+use gaby_core::memory::Memory;
+use gaby_core::model::Model;
+use gaby_core::video::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+
+/// Golden-frame tests, read from a TOML file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(rename = "test")]
+    tests: Vec<Test>,
+}
+
+/// A ROM to run for a number of frames, and what the last frame should look
+/// like: a hash as printed by `--hash-frame`, a reference image, or both.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Test {
+    name: String,
+    /// Path to the ROM, relative to the manifest.
+    rom: String,
+    frames: u64,
+    /// Model to emulate. Defaults to DMG.
+    model: Option<String>,
+    /// Seed for the random contents of memory at power-on, so that the
+    /// frames are the same every time. Defaults to 0.
+    #[serde(default)]
+    seed: u64,
+    hash: Option<String>,
+    /// Path to a PNG of the expected frame, relative to the manifest.
+    image: Option<String>,
+}
+
+/// How a test ended.
+enum Outcome {
+    Passed,
+    Failed(String),
+}
+
+/// Run the golden-frame tests in the manifest at `path`, and print the
+/// outcome of each. The frames of failed tests are written to a `failed`
+/// directory next to the manifest, with images of the differences from the
+/// reference images. Returns an error unless all tests passed.
+pub fn run(path: &str) -> Result<(), Box<dyn Error>> {
+    let text =
+        fs::read_to_string(path).map_err(|error| format!("Can't read {}: {}", path, error))?;
+    let manifest: Manifest =
+        toml::from_str(&text).map_err(|error| format!("Error in {}: {}", path, error))?;
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    let mut failed = 0;
+    for test in &manifest.tests {
+        let description = match run_test(test, dir) {
+            Ok(Outcome::Passed) => "Passed".to_string(),
+            Ok(Outcome::Failed(reason)) => {
+                failed += 1;
+                format!("Failed, {}", reason)
+            }
+            Err(error) => {
+                failed += 1;
+                format!("Error: {}", error)
+            }
+        };
+        println!("{}: {}", test.name, description);
+    }
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} of {} tests failed.", failed, manifest.tests.len()).into())
+    }
+}
+
+fn run_test(test: &Test, dir: &Path) -> Result<Outcome, Box<dyn Error>> {
+    let rom = fs::read(dir.join(&test.rom))
+        .map_err(|error| format!("Can't read {}: {}", test.rom, error))?;
+    let model = match &test.model {
+        Some(model) => model.parse()?,
+        None => Model::DMG,
+    };
+    let mut mem = Memory::with_seed(model, test.seed);
+    mem.load_rom(&rom)?;
+    let mut machine = Emulator::new(mem, model);
+
+    // Run tick by tick to stop right at the end of the frame, like
+    // `--hash-frame`.
+    while machine.video.frame_count() < test.frames {
+        machine.tick()?;
+    }
+
+    let mut reasons = Vec::new();
+    let hash = format!("{:08X}", machine.video.frame_hash());
+    match &test.hash {
+        Some(expected) if !expected.eq_ignore_ascii_case(&hash) => {
+            reasons.push(format!("the hash is {} instead of {}", hash, expected))
+        }
+        Some(_) => {}
+        None if test.image.is_none() => {
+            reasons.push(format!("there is no reference, and the hash is {}", hash))
+        }
+        None => {}
+    }
+
+    let failed_dir = dir.join("failed");
+    let pixels: Vec<[u8; 3]> = machine
+        .video
+        .framebuffer()
+        .iter()
+        .map(|pixel| {
+            let [red, green, blue, _] = pixel.to_be_bytes();
+            [red, green, blue]
+        })
+        .collect();
+    if let Some(image) = &test.image {
+        let reference = read_png(&dir.join(image))
+            .map_err(|error| format!("Can't read {}: {}", image, error))?;
+        let differing = pixels
+            .iter()
+            .zip(&reference)
+            .filter(|(pixel, expected)| pixel != expected)
+            .count();
+        if differing > 0 {
+            reasons.push(format!("{} pixels differ from the image", differing));
+            fs::create_dir_all(&failed_dir)?;
+            write_png(
+                &failed_dir.join(format!("{}-diff.png", test.name)),
+                &diff(&pixels, &reference),
+            )?;
+        }
+    }
+
+    if reasons.is_empty() {
+        return Ok(Outcome::Passed);
+    }
+    fs::create_dir_all(&failed_dir)?;
+    write_png(&failed_dir.join(format!("{}.png", test.name)), &pixels)?;
+    Ok(Outcome::Failed(reasons.join(", and ")))
+}
+
+/// Image of where `pixels` differ from `reference`, with differing pixels
+/// in red over a faded gray version of the frame.
+fn diff(pixels: &[[u8; 3]], reference: &[[u8; 3]]) -> Vec<[u8; 3]> {
+    pixels
+        .iter()
+        .zip(reference)
+        .map(|(pixel, expected)| {
+            if pixel == expected {
+                let gray = pixel.iter().map(|&value| u32::from(value)).sum::<u32>() / 3;
+                let faded = (128 + gray / 2) as u8;
+                [faded; 3]
+            } else {
+                [255, 0, 0]
+            }
+        })
+        .collect()
+}
+
+/// Read a PNG the size of the screen as RGB pixels.
+fn read_png(path: &Path) -> Result<Vec<[u8; 3]>, Box<dyn Error>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    if (info.width, info.height) != (u32::from(SCREEN_WIDTH), u32::from(SCREEN_HEIGHT)) {
+        return Err(format!(
+            "The image is {}x{} pixels instead of the size of the screen.",
+            info.width, info.height
+        )
+        .into());
+    }
+
+    let bytes = &buffer[..info.buffer_size()];
+    let pixels = match info.color_type {
+        png::ColorType::Grayscale => bytes.iter().map(|&gray| [gray; 3]).collect(),
+        png::ColorType::GrayscaleAlpha => bytes.chunks(2).map(|pixel| [pixel[0]; 3]).collect(),
+        png::ColorType::Rgb => bytes
+            .chunks(3)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect(),
+        png::ColorType::Rgba => bytes
+            .chunks(4)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect(),
+        png::ColorType::Indexed => return Err("Unexpected indexed colors.".into()),
+    };
+    Ok(pixels)
+}
+
+fn write_png(path: &Path, pixels: &[[u8; 3]]) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        u32::from(SCREEN_WIDTH),
+        u32::from(SCREEN_HEIGHT),
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels.concat())?;
+    Ok(())
+}
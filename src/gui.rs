@@ -0,0 +1,805 @@
+use crate::bindings::{Action, Hotkey, KeyBindings};
+use crate::frontend::{Command, SPEEDS};
+use crate::logging;
+use crate::memory_editor::MemoryEditor;
+use crate::save_states::SaveStates;
+use egui::{epaint::Primitive, ClippedPrimitive, ImageData, Pos2, RichText, TextureId};
+use gaby_core::disassembler;
+use gaby_core::emulator::Emulator;
+use gaby_core::events;
+use gaby_core::palette::{Palette, PaletteSetting};
+use gaby_core::video::Layers;
+use sdl2::{
+    event::{Event, WindowEvent},
+    keyboard::Keycode,
+    mouse::MouseButton,
+    pixels::PixelFormatEnum,
+    rect::Rect,
+    render::{BlendMode, Texture, TextureCreator, WindowCanvas},
+    sys::{SDL_Color, SDL_FPoint, SDL_Renderer, SDL_Texture},
+    video::WindowContext,
+};
+use std::{collections::HashMap, os::raw::c_int, path::PathBuf, time::Instant};
+use tracing::error;
+
+/// Palettes that can be chosen in the menu.
+const PALETTES: [(&str, PaletteSetting); 4] = [
+    ("Gray", PaletteSetting::Fixed(Palette::GRAY)),
+    ("Green", PaletteSetting::Fixed(Palette::GREEN)),
+    ("Pocket", PaletteSetting::Fixed(Palette::POCKET)),
+    ("From cartridge", PaletteSetting::Auto),
+];
+
+/// Vertex of `SDL_RenderGeometry`, which the bindings of the sdl2 crate don't
+/// have yet.
+#[repr(C)]
+struct Vertex {
+    position: SDL_FPoint,
+    color: SDL_Color,
+    tex_coord: SDL_FPoint,
+}
+
+extern "C" {
+    fn SDL_RenderGeometry(
+        renderer: *mut SDL_Renderer,
+        texture: *mut SDL_Texture,
+        vertices: *const Vertex,
+        num_vertices: c_int,
+        indices: *const c_int,
+        num_indices: c_int,
+    ) -> c_int;
+}
+
+/// What the menus show, taken from the frontend every frame.
+pub struct MenuState<'a> {
+    pub paused: bool,
+    pub speed: u32,
+    pub slot: u8,
+    pub layers: Layers,
+    pub muted: [bool; 4],
+    /// Palette chosen in the menu, if any.
+    pub palette: Option<PaletteSetting>,
+    pub profiles: Vec<&'a str>,
+    pub profile: usize,
+    pub bindings: &'a KeyBindings,
+    pub recent_files: &'a [String],
+    pub integer_scaling: bool,
+    pub recording: bool,
+    pub inspector: bool,
+    pub audio_scope: bool,
+    /// The Game Boy shown in the debugger.
+    pub machine: &'a Emulator,
+}
+
+/// A menu bar drawn with egui at the top of the main window, for changing
+/// settings and using the hotkey functions with the mouse.
+pub struct Gui<'a> {
+    context: egui::Context,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    textures: HashMap<TextureId, Texture<'a>>,
+    /// Input since the last frame.
+    events: Vec<egui::Event>,
+    pointer: Pos2,
+    start: Instant,
+    /// Shapes to draw, from the last frame.
+    primitives: Vec<ClippedPrimitive>,
+    /// Whether the menu bar is shown.
+    pub visible: bool,
+    /// Height of the menu bar in pixels, from the last frame.
+    height: u32,
+    show_controls: bool,
+    /// Action to bind to the next key that is pressed.
+    pub binding: Option<Action>,
+    pub show_debugger: bool,
+    /// Address typed in the debugger to add a breakpoint at.
+    breakpoint: String,
+    /// Line typed in the debugger to break when LY changes to.
+    break_line: String,
+    /// Expression typed in the debugger to watch.
+    watch: String,
+    memory_editor: MemoryEditor,
+}
+
+impl<'a> Gui<'a> {
+    pub fn new(texture_creator: &'a TextureCreator<WindowContext>) -> Self {
+        Self {
+            context: egui::Context::default(),
+            texture_creator,
+            textures: HashMap::new(),
+            events: Vec::new(),
+            pointer: Pos2::ZERO,
+            start: Instant::now(),
+            primitives: Vec::new(),
+            visible: true,
+            height: 0,
+            show_controls: false,
+            binding: None,
+            show_debugger: false,
+            breakpoint: String::new(),
+            break_line: String::new(),
+            watch: String::new(),
+            memory_editor: MemoryEditor::default(),
+        }
+    }
+
+    /// Height of the menu bar in pixels, or 0 when it's hidden.
+    pub fn height(&self) -> u32 {
+        if self.visible {
+            self.height
+        } else {
+            0
+        }
+    }
+
+    /// Whether a text field has focus, so that key presses should go to it
+    /// instead of the game.
+    pub fn wants_keyboard(&self) -> bool {
+        self.context.wants_keyboard_input()
+    }
+
+    /// Pass a mouse event, or text and editing keys, in the window with
+    /// `window_id` on to egui.
+    pub fn handle_event(&mut self, event: &Event, window_id: u32) {
+        if event.get_window_id() != Some(window_id) {
+            return;
+        }
+
+        let button = |button| match button {
+            MouseButton::Left => Some(egui::PointerButton::Primary),
+            MouseButton::Right => Some(egui::PointerButton::Secondary),
+            MouseButton::Middle => Some(egui::PointerButton::Middle),
+            _ => None,
+        };
+        let event = match *event {
+            Event::MouseMotion { x, y, .. } => {
+                self.pointer = Pos2::new(x as f32, y as f32);
+                egui::Event::PointerMoved(self.pointer)
+            }
+            Event::MouseButtonDown { mouse_btn, .. } | Event::MouseButtonUp { mouse_btn, .. } => {
+                match button(mouse_btn) {
+                    Some(button) => egui::Event::PointerButton {
+                        pos: self.pointer,
+                        button,
+                        pressed: matches!(event, Event::MouseButtonDown { .. }),
+                        modifiers: egui::Modifiers::NONE,
+                    },
+                    None => return,
+                }
+            }
+            Event::MouseWheel { x, y, .. } => egui::Event::MouseWheel {
+                unit: egui::MouseWheelUnit::Line,
+                delta: egui::vec2(x as f32, y as f32),
+                modifiers: egui::Modifiers::NONE,
+            },
+            Event::Window {
+                win_event: WindowEvent::Leave,
+                ..
+            } => egui::Event::PointerGone,
+            Event::TextInput { ref text, .. } => egui::Event::Text(text.clone()),
+            Event::KeyDown {
+                keycode: Some(keycode),
+                repeat,
+                ..
+            }
+            | Event::KeyUp {
+                keycode: Some(keycode),
+                repeat,
+                ..
+            } => {
+                let key = match keycode {
+                    Keycode::Backspace => egui::Key::Backspace,
+                    Keycode::Delete => egui::Key::Delete,
+                    Keycode::Return => egui::Key::Enter,
+                    Keycode::Escape => egui::Key::Escape,
+                    Keycode::Left => egui::Key::ArrowLeft,
+                    Keycode::Right => egui::Key::ArrowRight,
+                    Keycode::Home => egui::Key::Home,
+                    Keycode::End => egui::Key::End,
+                    _ => return,
+                };
+                egui::Event::Key {
+                    key,
+                    physical_key: None,
+                    pressed: matches!(event, Event::KeyDown { .. }),
+                    repeat,
+                    modifiers: egui::Modifiers::NONE,
+                }
+            }
+            _ => return,
+        };
+        self.events.push(event);
+    }
+
+    /// Lay out the menus for a window of the given size, and return the
+    /// commands chosen in them.
+    pub fn run(&mut self, (width, height): (u32, u32), state: &MenuState) -> Vec<Command> {
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                Pos2::ZERO,
+                egui::vec2(width as f32, height as f32),
+            )),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            events: std::mem::take(&mut self.events),
+            ..Default::default()
+        };
+
+        let mut commands = Vec::new();
+        let context = self.context.clone();
+        let output = context.run(input, |context| {
+            if self.visible {
+                let show_windows = (
+                    &mut self.show_controls,
+                    &mut self.show_debugger,
+                    &mut self.memory_editor.visible,
+                );
+                let panel = egui::TopBottomPanel::top("menu").show(context, |ui| {
+                    egui::menu::bar(ui, |ui| Gui::menus(ui, state, &mut commands, show_windows));
+                });
+                self.height = panel.response.rect.height().ceil() as u32;
+            }
+            if self.show_controls {
+                self.controls(context, state);
+            }
+            if self.show_debugger {
+                self.debugger(context, state, &mut commands);
+            }
+            if self.memory_editor.visible {
+                self.memory_editor
+                    .show(context, state.machine, &mut commands);
+            }
+        });
+
+        for (id, delta) in output.textures_delta.set {
+            if let Err(error) = self.update_texture(id, &delta) {
+                error!("Can't update menu texture: {}", error);
+            }
+        }
+        for id in output.textures_delta.free {
+            self.textures.remove(&id);
+        }
+        self.primitives = context.tessellate(output.shapes, output.pixels_per_point);
+
+        commands
+    }
+
+    fn menus(
+        ui: &mut egui::Ui,
+        state: &MenuState,
+        commands: &mut Vec<Command>,
+        (show_controls, show_debugger, show_memory): (&mut bool, &mut bool, &mut bool),
+    ) {
+        let hotkey = |ui: &mut egui::Ui, commands: &mut Vec<Command>, text: &str, hotkey| {
+            if ui.button(text).clicked() {
+                commands.push(Command::Hotkey(hotkey));
+                ui.close_menu();
+            }
+        };
+        let toggle =
+            |ui: &mut egui::Ui, commands: &mut Vec<Command>, text: &str, checked, command| {
+                let mut checked = checked;
+                if ui.checkbox(&mut checked, text).clicked() {
+                    commands.push(command);
+                }
+            };
+
+        ui.menu_button("File", |ui| {
+            #[cfg(feature = "rfd")]
+            if ui.button("Open ROM…").clicked() {
+                ui.close_menu();
+                let path = rfd::FileDialog::new()
+                    .add_filter("Game Boy ROM", &crate::rom_file::EXTENSIONS)
+                    .pick_file();
+                if let Some(path) = path {
+                    commands.push(Command::Open(path));
+                }
+            }
+            ui.menu_button("Recent files", |ui| {
+                if state.recent_files.is_empty() {
+                    ui.label("None");
+                }
+                for file in state.recent_files {
+                    if ui.button(file).clicked() {
+                        commands.push(Command::Open(PathBuf::from(file)));
+                        ui.close_menu();
+                    }
+                }
+            });
+            ui.separator();
+            let recording = state.recording;
+            toggle(
+                ui,
+                commands,
+                "Record GIF",
+                recording,
+                Command::Hotkey(Hotkey::ToggleRecording),
+            );
+            hotkey(ui, commands, "Export heatmap", Hotkey::ExportHeatmap);
+            hotkey(
+                ui,
+                commands,
+                "Export background map",
+                Hotkey::ExportBackgroundMap,
+            );
+            ui.separator();
+            hotkey(ui, commands, "Quit", Hotkey::Quit);
+        });
+
+        ui.menu_button("Emulation", |ui| {
+            toggle(
+                ui,
+                commands,
+                "Pause",
+                state.paused,
+                Command::Hotkey(Hotkey::Pause),
+            );
+            hotkey(ui, commands, "Advance frame", Hotkey::AdvanceFrame);
+            if ui.button("Debugger…").clicked() {
+                *show_debugger = true;
+                ui.close_menu();
+            }
+            if ui.button("Memory…").clicked() {
+                *show_memory = true;
+                ui.close_menu();
+            }
+            ui.menu_button("Speed", |ui| {
+                for &speed in &SPEEDS {
+                    let text = format!("{}%", speed);
+                    if ui.radio(state.speed == speed, text).clicked() {
+                        commands.push(Command::SetSpeed(speed));
+                        ui.close_menu();
+                    }
+                }
+            });
+        });
+
+        ui.menu_button("State", |ui| {
+            hotkey(ui, commands, "Save state", Hotkey::SaveState);
+            hotkey(ui, commands, "Load state", Hotkey::LoadState);
+            ui.menu_button("Slot", |ui| {
+                for slot in 0..SaveStates::SLOTS {
+                    let text = format!("Slot {}", slot);
+                    if ui.radio(state.slot == slot, text).clicked() {
+                        commands.push(Command::Hotkey(Hotkey::SelectSlot(slot)));
+                        ui.close_menu();
+                    }
+                }
+            });
+        });
+
+        ui.menu_button("Input", |ui| {
+            for (index, name) in state.profiles.iter().enumerate() {
+                if ui.radio(state.profile == index, *name).clicked() {
+                    commands.push(Command::SelectProfile(index));
+                    ui.close_menu();
+                }
+            }
+            ui.separator();
+            if ui.button("Controls…").clicked() {
+                *show_controls = true;
+                ui.close_menu();
+            }
+        });
+
+        ui.menu_button("Video", |ui| {
+            for &(name, palette) in &PALETTES {
+                if ui.radio(state.palette == Some(palette), name).clicked() {
+                    commands.push(Command::SetPalette(palette));
+                    ui.close_menu();
+                }
+            }
+            ui.separator();
+            let layers = state.layers;
+            toggle(
+                ui,
+                commands,
+                "Background",
+                layers.background,
+                Command::Hotkey(Hotkey::ToggleBackground),
+            );
+            toggle(
+                ui,
+                commands,
+                "Window",
+                layers.window,
+                Command::Hotkey(Hotkey::ToggleWindow),
+            );
+            toggle(
+                ui,
+                commands,
+                "Sprites",
+                layers.sprites,
+                Command::Hotkey(Hotkey::ToggleSprites),
+            );
+            ui.separator();
+            toggle(
+                ui,
+                commands,
+                "Integer scaling",
+                state.integer_scaling,
+                Command::ToggleIntegerScaling,
+            );
+            ui.menu_button("Window size", |ui| {
+                for scale in 1..=6 {
+                    let text = format!("{}×", scale);
+                    hotkey(ui, commands, &text, Hotkey::Scale(scale));
+                }
+            });
+            toggle(
+                ui,
+                commands,
+                "OAM inspector",
+                state.inspector,
+                Command::Hotkey(Hotkey::ToggleInspector),
+            );
+        });
+
+        ui.menu_button("Audio", |ui| {
+            for (channel, &muted) in state.muted.iter().enumerate() {
+                let text = format!("Channel {}", channel + 1);
+                toggle(ui, commands, &text, !muted, Command::ToggleChannel(channel));
+            }
+            ui.separator();
+            toggle(
+                ui,
+                commands,
+                "Channel scope",
+                state.audio_scope,
+                Command::Hotkey(Hotkey::ToggleAudioScope),
+            );
+        });
+    }
+
+    /// Window listing the key bound to each action, where clicking a key
+    /// waits for a new key to bind to the action.
+    fn controls(&mut self, context: &egui::Context, state: &MenuState) {
+        let mut open = true;
+        egui::Window::new("Controls")
+            .open(&mut open)
+            .vscroll(true)
+            .show(context, |ui| {
+                ui.label("Click a key and press the new key to bind it, for this session.");
+                egui::Grid::new("bindings").striped(true).show(ui, |ui| {
+                    for &(name, action) in &Action::NAMES {
+                        ui.label(name);
+                        let text = if self.binding == Some(action) {
+                            "Press a key…".to_string()
+                        } else {
+                            state
+                                .bindings
+                                .key(action)
+                                .map_or_else(|| "None".to_string(), |keycode| keycode.name())
+                        };
+                        if ui.button(text).clicked() {
+                            self.binding = Some(action);
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        if !open {
+            self.show_controls = false;
+            self.binding = None;
+        }
+    }
+
+    /// Window with the disassembly around PC, the registers and the top of
+    /// the stack, and buttons to run and step through the game. Clicking
+    /// an instruction sets or clears a breakpoint at it.
+    fn debugger(
+        &mut self,
+        context: &egui::Context,
+        state: &MenuState,
+        commands: &mut Vec<Command>,
+    ) {
+        let machine = state.machine;
+        let cpu = &machine.cpu;
+        let register = |name| cpu.register(name).unwrap_or_default();
+        let pc = register("pc");
+        let sp = register("sp");
+        let mem = &machine.mem;
+
+        let mut open = true;
+        egui::Window::new("Debugger")
+            .open(&mut open)
+            .show(context, |ui| {
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(state.paused, egui::Button::new("Run"))
+                        .clicked()
+                    {
+                        commands.push(Command::SetPaused(false));
+                    }
+                    if ui
+                        .add_enabled(!state.paused, egui::Button::new("Pause"))
+                        .clicked()
+                    {
+                        commands.push(Command::SetPaused(true));
+                    }
+                    if ui.button("Step").clicked() {
+                        commands.push(Command::Step);
+                    }
+                    ui.separator();
+                    let mut trace = logging::instruction_trace();
+                    if ui.checkbox(&mut trace, "Trace").clicked() {
+                        commands.push(Command::Hotkey(Hotkey::ToggleTrace));
+                    }
+                    if ui.button("Dump trace").clicked() {
+                        commands.push(Command::Hotkey(Hotkey::DumpTrace));
+                    }
+                });
+                ui.separator();
+
+                ui.horizontal_top(|ui| {
+                    ui.vertical(|ui| {
+                        for instruction in disassembler::disassemble_around(mem, pc, 6, 12) {
+                            let marker = if instruction.address == pc { ">" } else { " " };
+                            let text = format!(
+                                "{} {:04X}  {}",
+                                marker, instruction.address, instruction.text
+                            );
+                            let breakpoint = machine.breakpoints.contains(&instruction.address);
+                            let label =
+                                ui.selectable_label(breakpoint, RichText::new(text).monospace());
+                            if label.clicked() {
+                                commands.push(Command::ToggleBreakpoint(instruction.address));
+                            }
+                        }
+                    });
+                    ui.separator();
+
+                    ui.vertical(|ui| {
+                        for name in ["af", "bc", "de", "hl", "sp", "pc"] {
+                            let text = format!("{}  {:04X}", name.to_uppercase(), register(name));
+                            ui.label(RichText::new(text).monospace());
+                        }
+                        let flags: String = "ZNHC"
+                            .chars()
+                            .enumerate()
+                            .map(|(bit, flag)| {
+                                if register("f") & (0x80 >> bit) != 0 {
+                                    flag
+                                } else {
+                                    '-'
+                                }
+                            })
+                            .collect();
+                        ui.label(RichText::new(format!("F   {}", flags)).monospace());
+                    });
+                    ui.separator();
+
+                    ui.vertical(|ui| {
+                        ui.label("Stack");
+                        for offset in 0..8 {
+                            let address = sp.wrapping_add(2 * offset);
+                            let text = format!("{:04X}  {:04X}", address, mem.read_word(address));
+                            ui.label(RichText::new(text).monospace());
+                        }
+                    });
+                });
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("Breakpoint at");
+                    let field = ui
+                        .add(egui::TextEdit::singleline(&mut self.breakpoint).desired_width(48.0));
+                    let entered =
+                        field.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+                    if ui.button("Add").clicked() || entered {
+                        match u16::from_str_radix(
+                            self.breakpoint.trim().trim_start_matches("0x"),
+                            16,
+                        ) {
+                            Ok(address) if !machine.breakpoints.contains(&address) => {
+                                commands.push(Command::ToggleBreakpoint(address));
+                                self.breakpoint.clear();
+                            }
+                            Ok(_) => self.breakpoint.clear(),
+                            Err(_) => {}
+                        }
+                    }
+                });
+                for &address in &machine.breakpoints {
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new(format!("{:04X}", address)).monospace());
+                        if ui.small_button("Remove").clicked() {
+                            commands.push(Command::ToggleBreakpoint(address));
+                        }
+                    });
+                }
+                ui.separator();
+
+                // Events stop the frame at the start of the next
+                // instruction, and are toggled by clicking them.
+                let hooks = &mem.events;
+                ui.horizontal(|ui| {
+                    ui.label("Break on");
+                    for event in [
+                        events::Event::Interrupt,
+                        events::Event::SerialTransfer,
+                        events::Event::Dma,
+                    ] {
+                        let text = event.to_string();
+                        if ui
+                            .selectable_label(hooks.is_subscribed(event), text)
+                            .clicked()
+                        {
+                            commands.push(Command::ToggleEvent(event));
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("PPU mode");
+                    for mode in 0..4 {
+                        let event = events::Event::Mode(mode);
+                        let text = mode.to_string();
+                        if ui
+                            .selectable_label(hooks.is_subscribed(event), text)
+                            .clicked()
+                        {
+                            commands.push(Command::ToggleEvent(event));
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("LY =");
+                    let field = ui
+                        .add(egui::TextEdit::singleline(&mut self.break_line).desired_width(32.0));
+                    let entered =
+                        field.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+                    if ui.button("Add").clicked() || entered {
+                        match self.break_line.trim().parse() {
+                            Ok(line) if !hooks.is_subscribed(events::Event::Line(line)) => {
+                                commands.push(Command::ToggleEvent(events::Event::Line(line)));
+                                self.break_line.clear();
+                            }
+                            Ok(_) => self.break_line.clear(),
+                            Err(_) => {}
+                        }
+                    }
+                });
+                for event in hooks.subscriptions() {
+                    if let events::Event::Line(_) = event {
+                        ui.horizontal(|ui| {
+                            ui.label(event.to_string());
+                            if ui.small_button("Remove").clicked() {
+                                commands.push(Command::ToggleEvent(event));
+                            }
+                        });
+                    }
+                }
+                ui.separator();
+
+                // Changes of the watched values are logged as they are made.
+                ui.horizontal(|ui| {
+                    ui.label("Watch");
+                    let field =
+                        ui.add(egui::TextEdit::singleline(&mut self.watch).desired_width(96.0));
+                    let entered =
+                        field.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+                    if (ui.button("Add").clicked() || entered) && !self.watch.trim().is_empty() {
+                        commands.push(Command::AddWatch(std::mem::take(&mut self.watch)));
+                    }
+                });
+                for (index, watch) in machine.watches.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        let text =
+                            format!("{} = {}", watch.label(), watch.format_value(watch.value));
+                        ui.label(RichText::new(text).monospace());
+                        if ui.small_button("Remove").clicked() {
+                            commands.push(Command::RemoveWatch(index));
+                        }
+                    });
+                }
+            });
+        if !open {
+            self.show_debugger = false;
+        }
+    }
+
+    /// Create or update a texture from egui.
+    fn update_texture(
+        &mut self,
+        id: TextureId,
+        delta: &egui::epaint::ImageDelta,
+    ) -> Result<(), String> {
+        let [width, height] = delta.image.size();
+        // SDL does the blending, so the pixels are given without
+        // premultiplied alpha.
+        let pixels: Vec<u8> = match &delta.image {
+            ImageData::Color(image) => image
+                .pixels
+                .iter()
+                .flat_map(|color| color.to_srgba_unmultiplied())
+                .collect(),
+            ImageData::Font(image) => image
+                .srgba_pixels(None)
+                .flat_map(|color| color.to_srgba_unmultiplied())
+                .collect(),
+        };
+
+        let (x, y) = match delta.pos {
+            Some([x, y]) => (x, y),
+            None => {
+                // ABGR8888 has the bytes in RGBA order on little-endian
+                // systems.
+                let mut texture = self
+                    .texture_creator
+                    .create_texture_static(PixelFormatEnum::ABGR8888, width as u32, height as u32)
+                    .map_err(|error| error.to_string())?;
+                texture.set_blend_mode(BlendMode::Blend);
+                self.textures.insert(id, texture);
+                (0, 0)
+            }
+        };
+        let texture = self.textures.get_mut(&id).ok_or("Unknown texture")?;
+        let rect = Rect::new(x as i32, y as i32, width as u32, height as u32);
+        texture
+            .update(rect, &pixels, 4 * width)
+            .map_err(|error| error.to_string())
+    }
+
+    /// Draw the menus laid out by the last call to `run`.
+    pub fn paint(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        for ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in &self.primitives
+        {
+            let mesh = match primitive {
+                Primitive::Mesh(mesh) => mesh,
+                Primitive::Callback(_) => continue,
+            };
+            let texture = match self.textures.get(&mesh.texture_id) {
+                Some(texture) => texture,
+                None => continue,
+            };
+
+            let vertices: Vec<Vertex> = mesh
+                .vertices
+                .iter()
+                .map(|vertex| {
+                    let [r, g, b, a] = vertex.color.to_srgba_unmultiplied();
+                    Vertex {
+                        position: SDL_FPoint {
+                            x: vertex.pos.x,
+                            y: vertex.pos.y,
+                        },
+                        color: SDL_Color { r, g, b, a },
+                        tex_coord: SDL_FPoint {
+                            x: vertex.uv.x,
+                            y: vertex.uv.y,
+                        },
+                    }
+                })
+                .collect();
+            let indices: Vec<c_int> = mesh.indices.iter().map(|&index| index as c_int).collect();
+
+            canvas.set_clip_rect(Rect::new(
+                clip_rect.min.x as i32,
+                clip_rect.min.y as i32,
+                clip_rect.width().max(0.0) as u32,
+                clip_rect.height().max(0.0) as u32,
+            ));
+            // Safety: The vertices and indices are valid for the lengths
+            // given, and the indices are within the vertices, as egui makes
+            // them.
+            let result = unsafe {
+                SDL_RenderGeometry(
+                    canvas.raw(),
+                    texture.raw(),
+                    vertices.as_ptr(),
+                    vertices.len() as c_int,
+                    indices.as_ptr(),
+                    indices.len() as c_int,
+                )
+            };
+            if result != 0 {
+                canvas.set_clip_rect(None);
+                return Err(sdl2::get_error());
+            }
+        }
+
+        canvas.set_clip_rect(None);
+        Ok(())
+    }
+}
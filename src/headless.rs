@@ -0,0 +1,238 @@
+use crate::frame_limiter::FrameLimiter;
+use crate::movie_session::MovieSession;
+use crate::options::Options;
+use crate::script::Script;
+use crate::stream::StreamServer;
+use crate::Game;
+use gaby_core::emulator::Emulator;
+use gaby_core::lockstep::{Lockstep, Registers};
+use gaby_core::movie::Movie;
+use gaby_core::reference::SimpleCore;
+use std::{
+    error::Error,
+    fs,
+    time::{Duration, Instant},
+};
+
+/// Number of instructions printed before the one where the CPU and the
+/// reference core diverge.
+const LOCKSTEP_HISTORY: usize = 32;
+
+/// Run the emulator without a window or audio device, as fast as possible.
+/// Runs for the number of frames given by `--frames`, until the frame given by
+/// `--hash-frame` is completed, or until killed. A linked Game Boy is run
+/// alongside. After `--frames`, the screen and state are written to the files
+/// given by `--exit-screenshot` and `--exit-state`. With `--stream`, the
+/// emulation runs at the normal speed instead, and the screen is streamed to
+/// the clients that control it.
+pub fn run(
+    game: &mut Game,
+    mut linked: Option<&mut Game>,
+    options: &Options,
+    movie: &mut Option<MovieSession>,
+    script: &mut Option<Script>,
+) -> Result<(), Box<dyn Error>> {
+    let Game {
+        machine,
+        battery_save,
+        profiler,
+        ..
+    } = game;
+    if let Some(frame) = options.hash_frame {
+        // Run tick by tick to stop right at the end of the frame.
+        while machine.video.frame_count() < frame {
+            machine.tick()?;
+            if let Some(linked) = &mut linked {
+                linked.machine.tick()?;
+            }
+        }
+        machine.audio.take_samples();
+
+        println!("{:08X}", machine.video.frame_hash());
+        return Ok(());
+    }
+
+    let mut stream = match options.stream_address {
+        Some(address) => Some(
+            StreamServer::open(address)
+                .map_err(|error| format!("Can't stream on {}: {}", address, error))?,
+        ),
+        None => None,
+    };
+    let mut frame_limiter = FrameLimiter::new(Emulator::FRAMES_PER_SECOND);
+
+    let mut frame = 0;
+    while options.frames != Some(frame) && !crate::stats::interrupted() {
+        if let Some(stream) = &mut stream {
+            machine.set_buttons(stream.poll());
+        }
+        crate::run_frame(machine, movie, script)?;
+        // There is no audio device to play the samples on.
+        machine.audio.take_samples();
+
+        if let Some(battery_save) = battery_save {
+            battery_save.update(&mut machine.mem.cartridge)?;
+        }
+
+        if let Some(Game {
+            machine,
+            battery_save,
+            ..
+        }) = &mut linked
+        {
+            machine.run_frame()?;
+            machine.audio.take_samples();
+            if let Some(battery_save) = battery_save {
+                battery_save.update(&mut machine.mem.cartridge)?;
+            }
+        }
+
+        // The only way a headless run shows the screen is by streaming it.
+        let mut presentation = Duration::ZERO;
+        if let Some(stream) = &mut stream {
+            let presentation_start = Instant::now();
+            stream.send_frame(machine.video.framebuffer());
+            presentation = presentation_start.elapsed();
+            frame_limiter.wait(options.speed);
+        }
+        if let Some(profiler) = profiler {
+            profiler.add_frame(machine, presentation);
+        }
+
+        frame += 1;
+    }
+
+    if let Some(path) = &options.exit_screenshot_path {
+        machine
+            .video
+            .write_screenshot(path)
+            .map_err(|error| format!("Can't write screenshot {}: {}", path, error))?;
+    }
+    if let Some(path) = &options.exit_state_path {
+        fs::write(path, machine.save_state())
+            .map_err(|error| format!("Can't write state {}: {}", path, error))?;
+    }
+
+    Ok(())
+}
+
+/// Play `movie` back on two Game Boys in lockstep, comparing their states
+/// after every frame, and compare the final state with the one the movie was
+/// recorded with. Since the emulation is deterministic, they should all be
+/// the same.
+pub fn verify_movie(
+    movie: &Movie,
+    first: &mut Emulator,
+    second: &mut Emulator,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(state) = &movie.start_state {
+        first.load_state(state)?;
+        second.load_state(state)?;
+    }
+
+    for (frame, &buttons) in movie.inputs.iter().enumerate() {
+        for machine in [&mut *first, &mut *second] {
+            machine.set_buttons(buttons);
+            machine.run_frame()?;
+            machine.audio.take_samples();
+        }
+
+        let differing: Vec<&str> = first
+            .state_hashes()
+            .iter()
+            .zip(second.state_hashes())
+            .filter(|((_, first), (_, second))| first != second)
+            .map(|((name, _), _)| *name)
+            .collect();
+        if !differing.is_empty() {
+            return Err(format!(
+                "The runs diverged in frame {}, in the state of: {}",
+                frame + 1,
+                differing.join(", ")
+            )
+            .into());
+        }
+    }
+    println!(
+        "Both runs were identical for {} frames.",
+        movie.inputs.len()
+    );
+
+    match movie.final_hash {
+        Some(hash) if hash != first.state_hash() => {
+            Err("The final state differs from the one the movie was recorded with.".into())
+        }
+        Some(_) => {
+            println!("The final state matches the recording.");
+            Ok(())
+        }
+        None => {
+            println!("The movie has no final state to compare with.");
+            Ok(())
+        }
+    }
+}
+
+/// Run the CPU in lockstep with the reference core, for the number of frames
+/// given by `--frames` or until killed, and stop at the first instruction
+/// after which their registers or memory writes differ. The instructions
+/// leading up to it are printed.
+pub fn lockstep(machine: &mut Emulator, options: &Options) -> Result<(), Box<dyn Error>> {
+    let core = SimpleCore::new(Registers::of(&machine.cpu));
+    let mut lockstep = Lockstep::new(machine, Box::new(core), LOCKSTEP_HISTORY);
+
+    while options
+        .frames
+        .is_none_or(|frames| machine.video.frame_count() < frames)
+    {
+        let divergence = match lockstep.step(machine)? {
+            Some(divergence) => divergence,
+            None => {
+                machine.audio.take_samples();
+                continue;
+            }
+        };
+
+        println!("Last instructions, with the registers before them:");
+        for step in &lockstep.history {
+            println!("  {:<24} {}", step.instruction, step.registers);
+        }
+        println!("Diverged at:");
+        println!(
+            "  {:<24} {}",
+            divergence.step.instruction, divergence.step.registers
+        );
+        println!("  {:<24} {}", "Reference core:", divergence.expected);
+        println!("  {:<24} {}", "Gaby:", divergence.actual);
+        if divergence.expected_writes != divergence.actual_writes {
+            let writes = |writes: &[(u16, u8)]| {
+                writes
+                    .iter()
+                    .map(|(address, data)| format!("({:04X}) = {:02X}", address, data))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            println!(
+                "  {:<24} {}",
+                "Reference core writes:",
+                writes(&divergence.expected_writes)
+            );
+            println!(
+                "  {:<24} {}",
+                "Gaby writes:",
+                writes(&divergence.actual_writes)
+            );
+        }
+        return Err(format!(
+            "The CPU diverged from the reference core after {} instructions.",
+            lockstep.compared
+        )
+        .into());
+    }
+
+    println!(
+        "The CPU matched the reference core for {} instructions.",
+        lockstep.compared
+    );
+    Ok(())
+}
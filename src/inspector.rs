@@ -0,0 +1,128 @@
+use crate::font::{self, CHAR_HEIGHT};
+use gaby_core::memory::{IORegister, Memory};
+use gaby_core::video::{Sprite, MAX_SPRITES_PER_LINE, SCREEN_HEIGHT, SPRITES_IN_OAM};
+
+const ROWS: usize = 20;
+const COLUMNS: usize = SPRITES_IN_OAM as usize / ROWS;
+const ENTRY_WIDTH: usize = 76;
+const ENTRY_HEIGHT: usize = 18;
+const HEADER_HEIGHT: usize = 2 * CHAR_HEIGHT;
+const PREVIEW_X: usize = 2;
+const TEXT_X: usize = 14;
+
+pub const WIDTH: usize = COLUMNS * ENTRY_WIDTH;
+pub const HEIGHT: usize = HEADER_HEIGHT + ROWS * ENTRY_HEIGHT;
+const BYTES_PER_PIXEL: usize = 3;
+
+const BACKGROUND: [u8; 3] = [0xFF, 0xFF, 0xFF];
+const ON_LINE: [u8; 3] = [0xC0, 0xF0, 0xC0];
+const DROPPED: [u8; 3] = [0xF8, 0xC0, 0xC0];
+const TEXT: [u8; 3] = [0x00, 0x00, 0x00];
+const TRANSPARENT: [u8; 3] = [0xE0, 0xD0, 0xF0];
+const SHADES: [[u8; 3]; 4] = [[255, 255, 255], [170, 170, 170], [85, 85, 85], [0, 0, 0]];
+
+/// Debug panel listing the 40 OAM entries with previews of the sprites.
+///
+/// Sprites on the selected line are highlighted in green, and those that
+/// are on the line but not drawn because of the limit of 10 sprites per line
+/// in red.
+pub struct OamInspector {
+    line: u8,
+}
+
+impl OamInspector {
+    pub fn new() -> Self {
+        Self { line: 0 }
+    }
+
+    pub fn select_previous_line(&mut self) {
+        self.line = self.line.checked_sub(1).unwrap_or(SCREEN_HEIGHT - 1);
+    }
+
+    pub fn select_next_line(&mut self) {
+        self.line = (self.line + 1) % SCREEN_HEIGHT;
+    }
+
+    /// Draw the panel into RGB24 pixel data that is `WIDTH` × `HEIGHT`
+    /// pixels, with `pitch` bytes per row, e.g. a locked texture.
+    pub fn render(&self, mem: &Memory, pixels: &mut [u8], pitch: usize) {
+        OamInspector::fill(pixels, pitch, 0, 0, WIDTH, HEIGHT, BACKGROUND);
+
+        let header = format!("LINE {:3}  (UP/DOWN TO CHANGE)", self.line);
+        font::draw_text(pixels, pitch, 2, 2, &header, TEXT);
+
+        let height = Sprite::height(mem);
+        let mut sprites_on_line = 0;
+        for index in 0..SPRITES_IN_OAM {
+            let sprite = Sprite::from_oam(mem, index);
+            let left = (index as usize / ROWS) * ENTRY_WIDTH;
+            let top = HEADER_HEIGHT + (index as usize % ROWS) * ENTRY_HEIGHT;
+
+            if sprite.is_on_line(self.line, height) {
+                let color = if sprites_on_line < MAX_SPRITES_PER_LINE {
+                    ON_LINE
+                } else {
+                    DROPPED
+                };
+                OamInspector::fill(pixels, pitch, left, top, ENTRY_WIDTH, ENTRY_HEIGHT, color);
+                sprites_on_line += 1;
+            }
+
+            let preview = (left + PREVIEW_X, top + 1);
+            OamInspector::draw_preview(mem, &sprite, height, pixels, pitch, preview);
+
+            // The coordinates are shown as they are stored in OAM.
+            let text = format!("{:02} X{:4} Y{:4}", index, sprite.x + 8, sprite.y + 16);
+            font::draw_text(pixels, pitch, left + TEXT_X, top + 2, &text, TEXT);
+            let text = format!("   T:{:02X} F:{:02X}", sprite.tile_index, sprite.flags);
+            let y = top + 2 + CHAR_HEIGHT + 2;
+            font::draw_text(pixels, pitch, left + TEXT_X, y, &text, TEXT);
+        }
+    }
+
+    /// Draw the sprite with its top left corner at `(left, top)`.
+    fn draw_preview(
+        mem: &Memory,
+        sprite: &Sprite,
+        height: u8,
+        pixels: &mut [u8],
+        pitch: usize,
+        (left, top): (usize, usize),
+    ) {
+        let palette = if (sprite.flags & 0b0001_0000) != 0 {
+            mem[IORegister::OBP1]
+        } else {
+            mem[IORegister::OBP0]
+        };
+
+        for y in 0..height {
+            for x in 0..8 {
+                let color = sprite.pixel(mem, x, y, height);
+                let rgb = if color == 0 {
+                    TRANSPARENT
+                } else {
+                    SHADES[usize::from((palette >> (2 * color)) & 0b0000_0011)]
+                };
+                let (x, y) = (left + usize::from(x), top + usize::from(y));
+                OamInspector::fill(pixels, pitch, x, y, 1, 1, rgb);
+            }
+        }
+    }
+
+    fn fill(
+        pixels: &mut [u8],
+        pitch: usize,
+        left: usize,
+        top: usize,
+        width: usize,
+        height: usize,
+        color: [u8; 3],
+    ) {
+        for y in top..top + height {
+            for x in left..left + width {
+                let index = y * pitch + x * BYTES_PER_PIXEL;
+                pixels[index..index + BYTES_PER_PIXEL].copy_from_slice(&color);
+            }
+        }
+    }
+}
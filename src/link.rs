@@ -0,0 +1,265 @@
+use crate::options;
+use gaby_core::serial::SerialPeer;
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+};
+use tracing::{info, warn};
+
+/// How to set up a link cable connection.
+pub enum LinkSetting {
+    /// Wait for the other emulator to connect to this address.
+    Listen(SocketAddr),
+    /// Connect to an emulator listening at this address.
+    Connect(String),
+}
+
+impl LinkSetting {
+    /// Parse the two values of `--link`, like `listen 5000`,
+    /// `listen 0.0.0.0:5000` or `connect localhost:5000`.
+    pub fn parse(mode: &str, address: &str) -> Result<Self, String> {
+        match mode {
+            "listen" => options::listen_address(address).map(LinkSetting::Listen),
+            "connect" => Ok(LinkSetting::Connect(address.to_string())),
+            _ => Err(format!(
+                "Unknown link mode {}. Use listen [ADDRESS:]<PORT> or connect <HOST:PORT>.",
+                mode
+            )),
+        }
+    }
+}
+
+/// Link cable to another emulator over TCP.
+///
+/// Each byte is sent in a message of two bytes, the kind of message and the
+/// data. The side that clocks a transfer sends `TRANSFER` and waits for the
+/// `REPLY` with the other side's byte, so neither emulator can run ahead of
+/// the other during a transfer. If both sides start a transfer at the same
+/// time, each takes the other's `TRANSFER` as the reply.
+///
+/// The kind is in the lowest bit of the first byte, and the number of the
+/// transfer in the other bits. A reply has the number of the transfer it
+/// answers, so one that arrives after its transfer has given up isn't taken
+/// as the reply to the next one.
+pub struct TcpLink {
+    stream: Option<TcpStream>,
+    /// Bytes received, but not yet parsed as messages.
+    received: VecDeque<u8>,
+    /// Number of the last transfer clocked by this side.
+    sequence: u8,
+}
+
+impl TcpLink {
+    const TRANSFER: u8 = 0;
+    const REPLY: u8 = 1;
+    const KIND_MASK: u8 = 0x01;
+
+    pub fn open(setting: &LinkSetting) -> io::Result<Self> {
+        let stream = match setting {
+            LinkSetting::Listen(address) => {
+                let listener = TcpListener::bind(address)?;
+                info!("Waiting for a link cable connection on {}", address);
+                let (stream, address) = listener.accept()?;
+                info!("Link cable connected to {}", address);
+                stream
+            }
+            LinkSetting::Connect(address) => {
+                let stream = TcpStream::connect(address)?;
+                info!("Link cable connected to {}", address);
+                stream
+            }
+        };
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            stream: Some(stream),
+            received: VecDeque::new(),
+            sequence: 0,
+        })
+    }
+
+    fn send(&mut self, kind: u8, data: u8) {
+        if let Some(stream) = &mut self.stream {
+            // Polling leaves the stream non-blocking, where a full send
+            // buffer would look like a pulled cable.
+            let result = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.write_all(&[kind, data]));
+            if let Err(error) = result {
+                self.disconnect(error);
+            }
+        }
+    }
+
+    /// Get the next message, waiting for it if `wait` is set.
+    fn receive(&mut self, wait: bool) -> Option<(u8, u8)> {
+        while self.received.len() < 2 {
+            let stream = self.stream.as_mut()?;
+            let mut buffer = [0; 64];
+            let result = stream
+                .set_nonblocking(!wait)
+                .and_then(|_| stream.read(&mut buffer));
+            match result {
+                Ok(0) => {
+                    self.disconnect(io::ErrorKind::UnexpectedEof.into());
+                    return None;
+                }
+                Ok(length) => self.received.extend(&buffer[..length]),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return None,
+                Err(error) => {
+                    self.disconnect(error);
+                    return None;
+                }
+            }
+        }
+
+        Some((self.received.pop_front()?, self.received.pop_front()?))
+    }
+
+    /// Carry on as if the cable was pulled out.
+    fn disconnect(&mut self, error: io::Error) {
+        if self.stream.take().is_some() {
+            warn!("Link cable disconnected: {}", error);
+        }
+    }
+}
+
+impl SerialPeer for TcpLink {
+    fn transfer(&mut self, outgoing: u8) -> u8 {
+        self.sequence = self.sequence.wrapping_add(2);
+        self.send(TcpLink::TRANSFER | self.sequence, outgoing);
+
+        loop {
+            match self.receive(true) {
+                Some((kind, incoming)) if kind & TcpLink::KIND_MASK == TcpLink::TRANSFER => {
+                    return incoming
+                }
+                Some((kind, incoming)) if kind & !TcpLink::KIND_MASK == self.sequence => {
+                    return incoming
+                }
+                // A reply to an earlier transfer, which arrived late.
+                Some(_) => {}
+                None => return 0xFF,
+            }
+        }
+    }
+
+    fn poll(&mut self, outgoing: u8) -> Option<u8> {
+        let (kind, incoming) = self.receive(false)?;
+        if kind & TcpLink::KIND_MASK == TcpLink::TRANSFER {
+            self.send(TcpLink::REPLY | kind & !TcpLink::KIND_MASK, outgoing);
+            Some(incoming)
+        } else {
+            // A reply that arrives late is ignored.
+            None
+        }
+    }
+}
+
+/// Link cable to another Game Boy in the same process. The two ends share
+/// the state of the cable: each end leaves the byte in its SB there when it
+/// polls, and a transfer clocked by one end takes that byte and leaves its
+/// own byte for the other end to receive at its next poll, like `TcpLink`
+/// without the waiting.
+pub struct LocalLink {
+    cable: Arc<Mutex<[CableEnd; 2]>>,
+    /// Index of this end in `cable`.
+    end: usize,
+}
+
+#[derive(Clone, Copy)]
+struct CableEnd {
+    /// SB of the Game Boy at this end, as of its last poll.
+    outgoing: u8,
+    /// Byte sent by the other end, not yet received by this one.
+    incoming: Option<u8>,
+}
+
+impl LocalLink {
+    /// Make the two ends of a cable.
+    pub fn pair() -> (Self, Self) {
+        let end = CableEnd {
+            outgoing: 0xFF,
+            incoming: None,
+        };
+        let cable = Arc::new(Mutex::new([end; 2]));
+        let first = Self {
+            cable: cable.clone(),
+            end: 0,
+        };
+        (first, Self { cable, end: 1 })
+    }
+}
+
+impl SerialPeer for LocalLink {
+    fn transfer(&mut self, outgoing: u8) -> u8 {
+        let mut cable = self.cable.lock().unwrap();
+        let other = &mut cable[1 - self.end];
+        // The other side only receives the byte if it is waiting for a
+        // transfer with the external clock when it polls.
+        other.incoming = Some(outgoing);
+        other.outgoing
+    }
+
+    fn poll(&mut self, outgoing: u8) -> Option<u8> {
+        let mut cable = self.cable.lock().unwrap();
+        let end = &mut cable[self.end];
+        end.outgoing = outgoing;
+        end.incoming.take()
+    }
+}
+
+/// Peer that answers transfers with a script, for testing link code
+/// deterministically without a second emulator. It never clocks a transfer
+/// itself.
+pub struct ScriptedPeer {
+    respond: Box<dyn FnMut(u8) -> u8 + Send>,
+}
+
+impl ScriptedPeer {
+    /// Answer each transfer with the byte returned by `respond`, given the
+    /// byte sent by the Game Boy.
+    pub fn new(respond: impl FnMut(u8) -> u8 + Send + 'static) -> Self {
+        Self {
+            respond: Box::new(respond),
+        }
+    }
+
+    /// Answer transfers with the bytes of `script` in order, and with 0xFF,
+    /// like a disconnected cable, when they run out.
+    pub fn from_bytes(script: Vec<u8>) -> Self {
+        let mut bytes = script.into_iter();
+        ScriptedPeer::new(move |_| bytes.next().unwrap_or(0xFF))
+    }
+
+    /// Load a script of hex bytes separated by whitespace, like `00 FE 2A`.
+    /// Everything after a `#` on a line is a comment.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(path).map_err(|error| format!("Can't read {}: {}", path, error))?;
+
+        let script = text
+            .lines()
+            .flat_map(|line| line.split('#').next().unwrap_or("").split_whitespace())
+            .map(|byte| {
+                u8::from_str_radix(byte, 16)
+                    .map_err(|_| format!("Invalid byte {} in serial script {}", byte, path))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(ScriptedPeer::from_bytes(script))
+    }
+}
+
+impl SerialPeer for ScriptedPeer {
+    fn transfer(&mut self, outgoing: u8) -> u8 {
+        (self.respond)(outgoing)
+    }
+
+    fn poll(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+}
@@ -0,0 +1,81 @@
+#[cfg(feature = "sdl2")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    io::{self, IsTerminal},
+    sync::OnceLock,
+};
+#[cfg(feature = "sdl2")]
+use tracing::{info, warn};
+use tracing_subscriber::{
+    filter::LevelFilter, fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter,
+    Registry,
+};
+
+/// Environment variable with the filter for the log, e.g. `cpu=trace`.
+const LOG_VARIABLE: &str = "GABY_LOG";
+/// Directive added to the filter to trace the instructions.
+const TRACE_DIRECTIVE: &str = "cpu=trace";
+
+/// Handle for replacing the filter while running, set by `init`.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+/// Whether the instructions are traced on top of the filter in the
+/// environment variable.
+#[cfg(feature = "sdl2")]
+static INSTRUCTION_TRACE: AtomicBool = AtomicBool::new(false);
+
+/// Log to stderr, showing warnings and status messages unless `GABY_LOG`
+/// says otherwise. The emulator core logs each subsystem with its own
+/// target: cpu, ppu, apu, timer and serial.
+pub fn init() {
+    let (filter, handle) = reload::Layer::new(filter(false));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            fmt::layer()
+                .with_writer(io::stderr)
+                .with_ansi(io::stderr().is_terminal())
+                .without_time(),
+        )
+        .init();
+    let _ = FILTER.set(handle);
+}
+
+/// The filter in the environment variable, with the instructions traced if
+/// `trace` is set.
+fn filter(trace: bool) -> EnvFilter {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .with_env_var(LOG_VARIABLE)
+        .from_env_lossy();
+    if trace {
+        filter.add_directive(TRACE_DIRECTIVE.parse().expect("Invalid trace directive"))
+    } else {
+        filter
+    }
+}
+
+/// Whether the instructions are being traced, after `set_instruction_trace`.
+#[cfg(feature = "sdl2")]
+pub fn instruction_trace() -> bool {
+    INSTRUCTION_TRACE.load(Ordering::Relaxed)
+}
+
+/// Start or stop logging every instruction executed, as with `cpu=trace` in
+/// the environment variable.
+#[cfg(feature = "sdl2")]
+pub fn set_instruction_trace(trace: bool) {
+    let handle = match FILTER.get() {
+        Some(handle) => handle,
+        None => return,
+    };
+    match handle.reload(filter(trace)) {
+        Ok(()) => {
+            INSTRUCTION_TRACE.store(trace, Ordering::Relaxed);
+            info!(
+                "Instruction trace {}",
+                if trace { "started" } else { "stopped" }
+            );
+        }
+        Err(error) => warn!("Can't change the log filter: {}", error),
+    }
+}
@@ -1,112 +1,729 @@
-mod audio;
-mod cpu;
-mod memory;
-mod timer;
-mod video;
-
-use audio::Audio;
-use cpu::CPU;
-use memory::Memory;
-use sdl2::{
-    audio::AudioSpecDesired,
-    event::Event,
-    keyboard::Keycode,
-    pixels::{Color, PixelFormatEnum},
+#[cfg(feature = "sdl2")]
+mod audio_output;
+mod battery;
+#[cfg(feature = "sdl2")]
+mod bindings;
+#[cfg(feature = "sdl2")]
+mod border;
+mod config;
+mod crash_report;
+#[cfg(feature = "sdl2")]
+mod font;
+mod frame_limiter;
+#[cfg(feature = "sdl2")]
+mod frontend;
+mod game_db;
+mod golden;
+#[cfg(all(feature = "sdl2", feature = "egui"))]
+mod gui;
+mod headless;
+#[cfg(feature = "sdl2")]
+mod inspector;
+mod link;
+mod logging;
+#[cfg(all(feature = "sdl2", feature = "egui"))]
+mod memory_editor;
+#[cfg(all(feature = "minifb", not(feature = "sdl2")))]
+mod minifb_frontend;
+mod mooneye;
+mod movie_session;
+mod options;
+mod patch;
+mod profiler;
+#[cfg(all(feature = "sdl2", feature = "egui"))]
+mod recent_files;
+#[cfg(feature = "sdl2")]
+mod recording;
+#[cfg(feature = "sdl2")]
+mod rewind;
+mod rom_file;
+#[cfg(feature = "sdl2")]
+mod save_states;
+#[cfg_attr(not(feature = "rhai"), path = "no_script.rs")]
+mod script;
+mod stats;
+mod stream;
+mod suite;
+#[cfg(feature = "sdl2")]
+mod tilt;
+#[cfg(feature = "sdl2")]
+mod window_geometry;
+
+use battery::BatterySave;
+use config::Config;
+use gaby_core::{
+    cartridge::Cartridge,
+    cpu::decode_cache::DecodeCache,
+    emulator::Emulator,
+    heatmap::Heatmap,
+    memory::Memory,
+    movie::Movie,
+    palette::{Palette, PaletteSetting},
+    serial::SerialLog,
+    stems::StemWriter,
+};
+use game_db::{GameDatabase, KnownGame};
+use link::{LocalLink, ScriptedPeer, TcpLink};
+use movie_session::MovieSession;
+use options::{InfoOptions, Options, SavOptions, TestOptions};
+use profiler::Profiler;
+use script::Script;
+use std::{
+    env,
+    error::Error,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use std::{cell::RefCell, env, error::Error, rc::Rc};
-use timer::Timer;
-use video::Video;
+use tracing::{error, info, warn};
 
+/// Name in window titles and dialogs, which headless runs have none of.
+#[cfg(any(feature = "sdl2", feature = "minifb", feature = "rfd"))]
 const PROGRAM_NAME: &str = "Gaby";
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("One Game Boy ROM file path must be given as command line argument.");
+fn main() {
+    logging::init();
+
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("test") {
+        args.next();
+        run_tests(args);
+    }
+    if args.peek().map(String::as_str) == Some("info") {
+        args.next();
+        run_info(args);
+    }
+    if args.peek().map(String::as_str) == Some("sav") {
+        args.next();
+        run_sav(args);
+    }
+
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            process::exit(2);
+        }
+    };
+
+    let windowed = !options.headless;
+    if let Err(error) = run(options) {
+        report_error(&error.to_string(), windowed);
+        process::exit(1);
+    }
+}
+
+/// Run the `test` subcommand and exit.
+fn run_tests(args: impl Iterator<Item = String>) -> ! {
+    let options = match TestOptions::parse(args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            process::exit(2);
+        }
+    };
+    if let Err(error) = suite::run(&options) {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
+    process::exit(0);
+}
+
+/// Run the `info` subcommand and exit.
+fn run_info(args: impl Iterator<Item = String>) -> ! {
+    let options = match InfoOptions::parse(args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            process::exit(2);
+        }
+    };
+    if let Err(error) = print_info(&options) {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
+    process::exit(0);
+}
+
+/// Print what the header of a ROM says, whether its checksums are right, and
+/// which game it is if it is in the game database.
+fn print_info(options: &InfoOptions) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(options.config_path.as_deref())?;
+    let database_path = options
+        .game_db_path
+        .as_deref()
+        .or(config.game_db.as_deref());
+    let database = GameDatabase::load(database_path)?;
+    let rom = rom_file::read(&options.rom_path)?;
+
+    println!("File: {}", options.rom_path.display());
+    println!("SHA-1: {}", game_db::sha1_hex(&rom));
+    match Cartridge::new(&rom) {
+        Ok(cartridge) => {
+            println!("Title: {}", cartridge.title());
+            println!("RAM: {} bytes", cartridge.ram().len());
+            println!(
+                "Battery: {}",
+                if cartridge.has_battery() { "yes" } else { "no" }
+            );
+            if cartridge.is_cgb_only() {
+                println!("Model: Game Boy Color only");
+            }
+            let problems: Vec<String> = cartridge
+                .verify_rom_size()
+                .into_iter()
+                .chain(cartridge.verify_checksums())
+                .collect();
+            if problems.is_empty() {
+                println!("Header: OK");
+            }
+            for problem in problems {
+                println!("Header: {}", problem);
+            }
+        }
+        Err(error) => println!("Header: {}", error),
+    }
+
+    match database.as_ref().map(|database| database.find(&rom)) {
+        Some(Some(known)) => {
+            println!("Name: {}", known.name);
+            if let Some(region) = &known.region {
+                println!("Region: {}", region);
+            }
+            println!("Dump: {}", if known.bad_dump { "bad" } else { "verified" });
+        }
+        Some(None) => println!("Name: not in the game database"),
+        None => println!("Name: no game database"),
+    }
+
+    Ok(())
+}
+
+/// Run the `sav` subcommand and exit.
+fn run_sav(args: impl Iterator<Item = String>) -> ! {
+    let options = match SavOptions::parse(args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            process::exit(2);
+        }
+    };
+    let result = match &options {
+        SavOptions::Import {
+            save_path,
+            rom_path,
+        } => battery::import(save_path, rom_path),
+        SavOptions::Export {
+            rom_path,
+            save_path,
+            rtc,
+        } => battery::export(rom_path, save_path, *rtc),
+    };
+    if let Err(error) = result {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
+    process::exit(0);
+}
+
+/// Print an error, and show it in a message box too when running in a
+/// window, since there may be no terminal to read it in.
+#[cfg_attr(not(feature = "sdl2"), allow(unused_variables))]
+fn report_error(message: &str, windowed: bool) {
+    eprintln!("Error: {}", message);
+
+    #[cfg(feature = "sdl2")]
+    if windowed {
+        let _ = sdl2::messagebox::show_simple_message_box(
+            sdl2::messagebox::MessageBoxFlag::ERROR,
+            PROGRAM_NAME,
+            message,
+            None,
+        );
+    }
+}
+
+fn run(mut options: Options) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(options.config_path.as_deref())?;
+    options.sample_rate = options.sample_rate.or(config.sample_rate);
+    options.audio_buffer = options.audio_buffer.or(config.audio_buffer);
+    options.game_db_path = options
+        .game_db_path
+        .take()
+        .or_else(|| config.game_db.clone());
+    options.border_path = options.border_path.take().or_else(|| config.border.clone());
+    if let (None, Some(setting)) = (options.fast_forward_audio, &config.fast_forward_audio) {
+        options.fast_forward_audio = Some(setting.parse()?);
+    }
+    if let Some(path) = &options.golden_path {
+        return golden::run(path);
+    }
+    let rom_path = match &options.rom_path {
+        Some(path) => path.clone(),
+        None => match choose_rom(&options)? {
+            Some(path) => path,
+            // The user closed the file dialog, so there is nothing to do.
+            None => return Ok(()),
+        },
+    };
+    let rom_path = rom_path.as_path();
+
+    if options.mooneye {
+        return mooneye::run(rom_path, &options);
+    }
+
+    if let Some(path) = &options.verify_movie_path {
+        let movie = Movie::load(path)?;
+        options.seed = Some(movie.seed);
+        let mut first = Game::load(rom_path, options.patch_path.as_deref(), &options, &config)?;
+        let mut second = Game::load(rom_path, options.patch_path.as_deref(), &options, &config)?;
+        return headless::verify_movie(&movie, &mut first.machine, &mut second.machine);
+    }
+
+    // A movie is played back with the seed it was recorded with, so it must
+    // be loaded before the game.
+    let played_movie = match &options.play_movie_path {
+        Some(path) => {
+            let movie = Movie::load(path)?;
+            if movie.model != options.model {
+                return Err(
+                    "The movie was recorded with another Game Boy model. Choose it with --model."
+                        .into(),
+                );
+            }
+            options.seed = Some(movie.seed);
+            Some(movie)
+        }
+        None => None,
+    };
+    if options.record_movie_path.is_some() && options.seed.is_none() {
+        options.seed = Some(random_seed());
+    }
+
+    let mut game = Game::load(rom_path, options.patch_path.as_deref(), &options, &config)?;
+    if options.lockstep {
+        return headless::lockstep(&mut game.machine, &options);
+    }
+    let machine = &mut game.machine;
+    if options.heatmap_path.is_some() {
+        machine.mem.heatmap = Some(Heatmap::new());
+    }
+    if options.stats {
+        machine.cpu.pc_counts = Some(vec![0; 0x10000]);
+    }
+    if options.profile {
+        game.profiler = Some(Profiler::new(machine));
+    }
+    if options.stats || options.profile {
+        stats::catch_interrupt()?;
+    }
+    let start = Instant::now();
+    if let Some(dir) = &options.stems_path {
+        machine.audio.stems = Some(StemWriter::create(dir)?);
+    }
+    for expression in &options.watches {
+        machine.watches.add(&mut machine.mem, expression)?;
+    }
+    if let Some(link) = &options.link {
+        machine.serial.peer = Some(Box::new(TcpLink::open(link)?));
+    }
+    if let Some(path) = &options.serial_script_path {
+        machine.serial.peer = Some(Box::new(ScriptedPeer::load(path)?));
+    }
+    if let Some(path) = &options.serial_log_path {
+        machine.serial.log = Some(SerialLog::create(path)?);
+    }
+
+    let mut movie = start_movie(&options, played_movie, machine, &game.title)?;
+    let mut script = match &options.script_path {
+        Some(path) => Some(Script::load(path, machine)?),
+        None => None,
+    };
+
+    // A second Game Boy, with the serial ports wired together.
+    let mut linked = match &options.local_link_path {
+        Some(path) => {
+            let mut linked = Game::load(Path::new(path), None, &options, &config)?;
+            let (end, other_end) = LocalLink::pair();
+            game.machine.serial.peer = Some(Box::new(end));
+            linked.machine.serial.peer = Some(Box::new(other_end));
+            Some(linked)
+        }
+        None => None,
+    };
+
+    // Panics are caught to write a crash report, like when the CPU fails.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        if options.headless {
+            headless::run(
+                &mut game,
+                linked.as_mut(),
+                &options,
+                &mut movie,
+                &mut script,
+            )
+        } else {
+            run_frontend(
+                &mut game,
+                linked.as_mut(),
+                &options,
+                &config,
+                rom_path,
+                &mut movie,
+                &mut script,
+            )
+        }
+    }));
+    let panicked = result.is_err();
+    let result = result.unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "Unknown panic".to_string());
+        Err(format!("Gaby crashed: {}", message).into())
+    });
+    if let Err(error) = &result {
+        // Report on the Game Boy whose CPU failed, or the first one after a
+        // panic.
+        let crashed = std::iter::once((&game, rom_path))
+            .chain(
+                linked
+                    .as_ref()
+                    .zip(options.local_link_path.as_deref().map(Path::new)),
+            )
+            .find(|(game, _)| game.machine.cpu.crashed())
+            .or_else(|| panicked.then_some((&game, rom_path)));
+        if let Some((game, path)) = crashed {
+            match crash_report::write(&game.machine, path, &game.title, &error.to_string()) {
+                Ok(report) => info!("Wrote a crash report to {}", report.display()),
+                Err(error) => error!("Can't write a crash report: {}", error),
+            }
+        }
+    }
+
+    if let Some(movie) = movie {
+        movie.finish(&game.machine)?;
     }
 
-    let rc_mem = Rc::new(RefCell::new(Memory::new()));
-    let title: String;
+    // Flush the battery saves of both Game Boys.
+    for Game {
+        machine,
+        battery_save,
+        ..
+    } in std::iter::once(&mut game).chain(linked.as_mut())
+    {
+        if let Some(battery_save) = battery_save {
+            battery_save.flush(&mut machine.mem.cartridge)?;
+        }
+    }
+    let machine = &mut game.machine;
+    if options.stats {
+        stats::print(machine, start.elapsed());
+    }
+    if let Some(profiler) = &game.profiler {
+        profiler.print();
+    }
+    export_heatmap(&machine.mem, &options);
+    if let (Some(stems), Some(dir)) = (machine.audio.stems.take(), &options.stems_path) {
+        stems.finish(machine.audio.sample_rate() as u32)?;
+        info!("Wrote audio stems to {}", dir);
+    }
+
+    result
+}
+
+/// Ask for a ROM with a file dialog, when none was given on the command line.
+/// Returns `None` if the dialog was closed without choosing one.
+fn choose_rom(options: &Options) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    const NO_ROM: &str = "One Game Boy ROM file path must be given as command line argument.";
+    if options.headless {
+        return Err(NO_ROM.into());
+    }
 
+    #[cfg(feature = "rfd")]
     {
-        let mut mem = rc_mem.borrow_mut();
-        mem.load_rom(&args[1])?;
-        title = mem.read_game_title();
-    }
-    println!("Title: {}", title);
-
-    let mut cpu = CPU::new(rc_mem.clone());
-    cpu.print_instructions = false;
-
-    let mut audio = Audio::new(rc_mem.clone());
-    let mut video = Video::new(rc_mem.clone());
-    let mut timer = Timer::new(rc_mem.clone());
-
-    let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
-    let audio_subsystem = sdl_context.audio()?;
-
-    let window_width = u32::from(video::SCREEN_WIDTH) * 4;
-    let window_height = u32::from(video::SCREEN_HEIGHT) * 4;
-    let window_title = format!("{} - {}", PROGRAM_NAME, title);
-
-    let window = video_subsystem
-        .window(&window_title, window_width, window_height)
-        .position_centered()
-        .build()?;
-
-    let mut canvas = window.into_canvas().present_vsync().build()?;
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.present();
-
-    // Make a texture that is to be copied into the canvas every frame.
-    let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator.create_texture_streaming(
-        PixelFormatEnum::RGB24,
-        u32::from(video::SCREEN_WIDTH),
-        u32::from(video::SCREEN_HEIGHT),
-    )?;
-
-    // Set up audio.
-    let desired_spec = AudioSpecDesired {
-        freq: Some(65536),
-        channels: Some(1),   // mono
-        samples: Some(1024), // for less than 1 frame delay
+        let path = rfd::FileDialog::new()
+            .set_title(format!("{} - Open ROM", PROGRAM_NAME))
+            .add_filter("Game Boy ROM", &rom_file::EXTENSIONS)
+            .pick_file();
+        Ok(path)
+    }
+
+    #[cfg(not(feature = "rfd"))]
+    Err(NO_ROM.into())
+}
+
+/// Start playing back `played`, or start recording a movie if asked to.
+fn start_movie(
+    options: &Options,
+    played: Option<Movie>,
+    machine: &mut Emulator,
+    title: &str,
+) -> Result<Option<MovieSession>, Box<dyn Error>> {
+    if let (Some(movie), Some(path)) = (played, &options.play_movie_path) {
+        if movie.title != title {
+            warn!("The movie was recorded with {}.", movie.title);
+        }
+        if let Some(state) = &movie.start_state {
+            machine.load_state(state)?;
+        }
+        return Ok(Some(MovieSession::play(
+            movie,
+            path,
+            options.movie_read_write,
+        )));
+    }
+
+    if let Some(path) = &options.record_movie_path {
+        let start_state = match &options.movie_start_state_path {
+            Some(state_path) => {
+                let state = fs::read(state_path)
+                    .map_err(|error| format!("Can't read state {}: {}", state_path, error))?;
+                machine.load_state(&state)?;
+                Some(state)
+            }
+            None => None,
+        };
+        let seed = options.seed.unwrap_or_default();
+        let movie = Movie::new(title.to_string(), options.model, seed, start_state);
+        return Ok(Some(MovieSession::record(movie, path)));
+    }
+
+    Ok(None)
+}
+
+/// Run a frame of `machine`, with the buttons pressed by the script and the
+/// ones recorded or played back by the movie, and report the changes of the
+/// watched values.
+fn run_frame(
+    machine: &mut Emulator,
+    movie: &mut Option<MovieSession>,
+    script: &mut Option<Script>,
+) -> Result<(), String> {
+    if let Some(script) = script {
+        script.press_buttons(&mut machine.joypad);
+    }
+    if let Some(movie) = movie {
+        movie.next_frame(&mut machine.joypad);
+    }
+    let result = match script {
+        Some(script) => script.run_frame(machine),
+        None => machine.run_frame(),
     };
+    report_watches(machine);
+    result
+}
 
-    let audio_queue = audio_subsystem.open_queue(None, &desired_spec)?;
-
-    // Start playback
-    audio_queue.resume();
-
-    let mut event_pump = sdl_context.event_pump()?;
-
-    // SDL event loop.
-    'render_loop: loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                // Exit the event loop if the user closes the window or presses
-                // the escape key.
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => break 'render_loop Ok(()),
-                _ => {}
+/// Log the changes of the watched values since the last call.
+pub fn report_watches(machine: &mut Emulator) {
+    if machine.watches.is_empty() {
+        return;
+    }
+    for change in machine.watches.take_changes(&mut machine.mem) {
+        info!("{}", change);
+    }
+}
+
+/// Seed for a movie recording, when none is given with `--seed`.
+fn random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos() as u64)
+}
+
+/// A Game Boy with a game loaded.
+pub struct Game {
+    pub machine: Emulator,
+    pub title: String,
+    /// The game in the game database with this ROM, if any.
+    pub known: Option<KnownGame>,
+    pub battery_save: Option<BatterySave>,
+    /// Frame times, collected with `--profile`.
+    pub profiler: Option<Profiler>,
+}
+
+impl Game {
+    /// Title to show in the window: the name from the game database if the
+    /// game is in it, and otherwise the title in the cartridge header.
+    #[cfg_attr(not(any(feature = "sdl2", feature = "minifb")), allow(dead_code))]
+    pub fn display_title(&self) -> String {
+        match &self.known {
+            Some(known) => known.describe(),
+            None => self.title.clone(),
+        }
+    }
+
+    /// Load the ROM with its battery save, applying the given patch or one
+    /// found next to the ROM, and set up the machine from the options and
+    /// the configuration file.
+    fn load(
+        rom_path: &Path,
+        patch_path: Option<&str>,
+        options: &Options,
+        config: &Config,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut rom = rom_file::read(rom_path)?;
+
+        // The database has the hashes of clean dumps, so the ROM is looked up
+        // before it is patched.
+        let known = match GameDatabase::load(options.game_db_path.as_deref())? {
+            Some(database) => database.find(&rom).cloned(),
+            None => None,
+        };
+        if let Some(known) = &known {
+            if known.bad_dump {
+                let error = format!(
+                    "{} is a known bad dump of {}.",
+                    rom_path.display(),
+                    known.name
+                );
+                if options.strict {
+                    return Err(error.into());
+                }
+                warn!("{}", error);
             }
         }
 
-        texture.update(None, video.pixel_data(), 3 * video::SCREEN_WIDTH as usize)?;
-        canvas.copy(&texture, None, None)?;
+        // Apply the given patch, or one found next to the ROM.
+        let patch_path = patch_path
+            .map(PathBuf::from)
+            .or_else(|| patch::find_sidecar(rom_path));
+        if let Some(patch_path) = patch_path {
+            let patch = fs::read(&patch_path)
+                .map_err(|error| format!("Can't read {}: {}", patch_path.display(), error))?;
+            rom = patch::apply(&rom, &patch)?;
+            info!("Applied patch {}", patch_path.display());
+        }
 
-        canvas.present();
+        let mut mem = match options.seed {
+            Some(seed) => Memory::with_seed(options.model, seed),
+            None => Memory::new(options.model),
+        };
+        mem.load_rom(&rom)
+            .map_err(|error| format!("Can't load {}: {}", rom_path.display(), error))?;
+        let title = mem.cartridge.title();
+
+        if let Some(warning) = mem.cartridge.verify_rom_size() {
+            warn!("{}", warning);
+        }
+        for error in mem.cartridge.verify_checksums() {
+            if options.strict {
+                return Err(error.into());
+            }
+            warn!("{}", error);
+        }
+
+        // The Game Boy Color's own registers and VRAM bank aren't emulated,
+        // so a game made only for it would draw garbage or hang.
+        if mem.cartridge.is_cgb_only() {
+            if !options.force_dmg {
+                return Err(format!(
+                    "{} only runs on a Game Boy Color, which Gaby can't fully emulate yet. \
+                     Use --force-dmg to run it on a DMG anyway.",
+                    rom_path.display()
+                )
+                .into());
+            }
+            warn!("Running a game made only for the Game Boy Color without its hardware. It will probably not work right.");
+        }
+
+        mem.oam_bug = options.oam_bug;
+
+        // A movie must start from the same cartridge RAM every time, so the
+        // battery save is left alone while one is used.
+        let battery_save = if mem.cartridge.has_battery() && !options.uses_movie() {
+            let interval = Duration::from_secs_f64(options.autosave_interval);
+            let mut battery_save = BatterySave::new(rom_path, interval);
+            battery_save.load(&mut mem.cartridge)?;
+            Some(battery_save)
+        } else {
+            None
+        };
+        info!("Title: {}", title);
+        if let Some(known) = &known {
+            info!("Game: {}", known.describe());
+        }
+
+        let mut machine = Emulator::new(mem, options.model);
+
+        let palette = match (
+            options.palette,
+            config.games.get(&title).or(config.palette.as_ref()),
+        ) {
+            (Some(palette), _) => palette,
+            (None, Some(palette)) => palette.parse()?,
+            (None, None) => PaletteSetting::Fixed(Palette::GRAY),
+        };
+        let colorization = palette.colorization(&machine.mem.cartridge, config.color_correction());
+        machine.video.set_colorization(colorization);
+        machine.video.layers = options.layers;
+        if options.decode_cache {
+            machine.cpu.decode_cache = Some(DecodeCache::new());
+        }
+        match config.turbo_frames {
+            Some(0) => return Err("turbo_frames must be a positive whole number.".into()),
+            Some(frames) => machine.joypad.turbo_frames = frames,
+            None => {}
+        }
+
+        Ok(Game {
+            machine,
+            title,
+            known,
+            battery_save,
+            profiler: None,
+        })
+    }
+}
+
+#[cfg(feature = "sdl2")]
+fn run_frontend(
+    game: &mut Game,
+    linked: Option<&mut Game>,
+    options: &Options,
+    config: &Config,
+    rom_path: &Path,
+    movie: &mut Option<MovieSession>,
+    script: &mut Option<Script>,
+) -> Result<(), Box<dyn Error>> {
+    frontend::run(game, linked, options, config, rom_path, movie, script)
+}
+
+#[cfg(all(feature = "minifb", not(feature = "sdl2")))]
+fn run_frontend(
+    game: &mut Game,
+    linked: Option<&mut Game>,
+    options: &Options,
+    _config: &Config,
+    _rom_path: &Path,
+    movie: &mut Option<MovieSession>,
+    script: &mut Option<Script>,
+) -> Result<(), Box<dyn Error>> {
+    minifb_frontend::run(game, linked, options, movie, script)
+}
+
+#[cfg(not(any(feature = "sdl2", feature = "minifb")))]
+fn run_frontend(
+    _game: &mut Game,
+    _linked: Option<&mut Game>,
+    _options: &Options,
+    _config: &Config,
+    _rom_path: &Path,
+    _movie: &mut Option<MovieSession>,
+    _script: &mut Option<Script>,
+) -> Result<(), Box<dyn Error>> {
+    Err("Gaby was built without a frontend, so only --headless is supported.".into())
+}
 
-        for _ in 0..17556 {
-            timer.tick()?;
-            video.tick()?;
-            audio.tick(&audio_queue)?;
-            cpu.tick()?;
+/// Export the memory access heatmap, if one is being recorded.
+fn export_heatmap(mem: &Memory, options: &Options) {
+    if let (Some(heatmap), Some(path)) = (&mem.heatmap, &options.heatmap_path) {
+        match heatmap.export(path) {
+            Ok(()) => info!("Exported memory access heatmap to {}", path),
+            Err(error) => error!("Failed to export memory access heatmap: {}", error),
         }
     }
 }
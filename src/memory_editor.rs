@@ -0,0 +1,195 @@
+use crate::frontend::Command;
+use egui::RichText;
+use gaby_core::emulator::Emulator;
+use gaby_core::search::{MemorySearch, SearchFilter};
+
+/// Number of rows of 16 bytes shown at a time.
+const ROWS: u16 = 16;
+/// Most search results listed, since there are thousands at the start.
+const MAX_RESULTS: usize = 64;
+
+/// Window showing the memory in hex while the game runs, where a byte is
+/// changed by clicking it and typing a new value, and RAM can be searched
+/// for the address of a game variable.
+#[derive(Default)]
+pub struct MemoryEditor {
+    pub visible: bool,
+    /// Address of the first row shown.
+    start: u16,
+    /// Address typed to go to.
+    goto: String,
+    /// Byte clicked to change.
+    selected: Option<u16>,
+    /// New value typed for the selected byte.
+    value: String,
+    search: Option<MemorySearch>,
+    /// Value typed to search for.
+    search_value: String,
+}
+
+impl MemoryEditor {
+    pub fn show(
+        &mut self,
+        context: &egui::Context,
+        machine: &Emulator,
+        commands: &mut Vec<Command>,
+    ) {
+        let mut open = true;
+        egui::Window::new("Memory")
+            .open(&mut open)
+            .show(context, |ui| {
+                self.hex_view(ui, machine, commands);
+                ui.separator();
+                self.search(ui, machine, commands);
+            });
+        if !open {
+            self.visible = false;
+        }
+    }
+
+    fn hex_view(&mut self, ui: &mut egui::Ui, machine: &Emulator, commands: &mut Vec<Command>) {
+        let MemoryEditor {
+            start,
+            goto,
+            selected,
+            value,
+            ..
+        } = self;
+
+        ui.horizontal(|ui| {
+            ui.label("Go to");
+            let field = ui.add(egui::TextEdit::singleline(goto).desired_width(48.0));
+            let entered =
+                field.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+            if ui.button("Go").clicked() || entered {
+                if let Ok(address) = u16::from_str_radix(goto.trim().trim_start_matches("0x"), 16) {
+                    *start = address & 0xFFF0;
+                    goto.clear();
+                }
+            }
+            if ui.button("▲").clicked() {
+                *start = start.wrapping_sub(16 * ROWS);
+            }
+            if ui.button("▼").clicked() {
+                *start = start.wrapping_add(16 * ROWS);
+            }
+        });
+
+        egui::Grid::new("memory")
+            .spacing([4.0, 2.0])
+            .show(ui, |ui| {
+                for row in 0..ROWS {
+                    let row_address = start.wrapping_add(16 * row);
+                    ui.label(RichText::new(format!("{:04X}", row_address)).monospace());
+                    for column in 0..16 {
+                        let address = row_address.wrapping_add(column);
+                        let byte = machine.mem.peek(address);
+                        let text = RichText::new(format!("{:02X}", byte)).monospace();
+                        if ui
+                            .selectable_label(*selected == Some(address), text)
+                            .clicked()
+                        {
+                            *selected = Some(address);
+                            *value = format!("{:02X}", byte);
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+
+        if let Some(address) = *selected {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new(format!("{:04X} =", address)).monospace());
+                if address < 0x8000 {
+                    ui.label("ROM can't be changed.");
+                    return;
+                }
+                let field = ui.add(egui::TextEdit::singleline(value).desired_width(32.0));
+                let entered =
+                    field.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+                if ui.button("Set").clicked() || entered {
+                    if let Ok(byte) = u8::from_str_radix(value.trim().trim_start_matches("0x"), 16)
+                    {
+                        commands.push(Command::Poke(address, byte));
+                    }
+                }
+            });
+        }
+    }
+
+    /// Search RAM by how the bytes change, keeping the addresses that match
+    /// each filter that is clicked. The values compared with are those of
+    /// the last step.
+    fn search(&mut self, ui: &mut egui::Ui, machine: &Emulator, commands: &mut Vec<Command>) {
+        let MemoryEditor {
+            start,
+            selected,
+            value,
+            search,
+            search_value,
+            ..
+        } = self;
+
+        ui.horizontal(|ui| {
+            if ui.button("New search").clicked() {
+                *search = Some(MemorySearch::new(&machine.mem));
+            }
+            if let Some(search) = search {
+                ui.label(format!("{} addresses", search.len()));
+            }
+        });
+        let search = match search {
+            Some(search) => search,
+            None => return,
+        };
+
+        let mut filter = None;
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(search_value).desired_width(32.0));
+            if ui.button("Equal").clicked() {
+                let text = search_value.trim();
+                let parsed = match text.strip_prefix("0x") {
+                    Some(hex) => u8::from_str_radix(hex, 16),
+                    None => text.parse(),
+                };
+                if let Ok(wanted) = parsed {
+                    filter = Some(SearchFilter::Equal(wanted));
+                }
+            }
+            for (text, other) in [
+                ("Changed", SearchFilter::Changed),
+                ("Unchanged", SearchFilter::Unchanged),
+                ("Greater", SearchFilter::Greater),
+                ("Less", SearchFilter::Less),
+            ] {
+                if ui.button(text).clicked() {
+                    filter = Some(other);
+                }
+            }
+        });
+        if let Some(filter) = filter {
+            search.filter(&machine.mem, filter);
+        }
+
+        for address in search.addresses().take(MAX_RESULTS) {
+            ui.horizontal(|ui| {
+                let byte = machine.mem.peek(address);
+                let text = format!("{:04X}  {:02X} ({})", address, byte, byte);
+                if ui
+                    .selectable_label(*selected == Some(address), RichText::new(text).monospace())
+                    .clicked()
+                {
+                    *start = address & 0xFFF0;
+                    *selected = Some(address);
+                    *value = format!("{:02X}", byte);
+                }
+                if ui.small_button("Watch").clicked() {
+                    commands.push(Command::AddWatch(format!("{:04X}", address)));
+                }
+            });
+        }
+        if search.len() > MAX_RESULTS {
+            ui.label(format!("… and {} more", search.len() - MAX_RESULTS));
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use crate::frame_limiter::FrameLimiter;
+use crate::movie_session::MovieSession;
+use crate::options::Options;
+use crate::script::Script;
+use crate::Game;
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Device, FromSample, SampleFormat, SizedSample, Stream, StreamConfig,
+};
+use gaby_core::emulator::Emulator;
+use gaby_core::joypad::Buttons;
+use gaby_core::video;
+use minifb::{Key, KeyRepeat, ScaleMode, Window, WindowOptions};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{error, warn};
+
+/// Keys for the Game Boy buttons, the same as the default bindings of the
+/// SDL frontend.
+const KEYS: [(Key, Buttons); 8] = [
+    (Key::Right, Buttons::RIGHT),
+    (Key::Left, Buttons::LEFT),
+    (Key::Up, Buttons::UP),
+    (Key::Down, Buttons::DOWN),
+    (Key::X, Buttons::A),
+    (Key::Z, Buttons::B),
+    (Key::Backspace, Buttons::SELECT),
+    (Key::Enter, Buttons::START),
+];
+/// Largest relative change of the sample rate used to keep the audio queue
+/// at the target size.
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+/// Run the emulator in a window until it is closed or Escape is pressed,
+/// using minifb for the window and input, and cpal for the audio output.
+/// Unlike the SDL frontend, there are no hotkeys, debugging tools or linked
+/// Game Boys.
+pub fn run(
+    game: &mut Game,
+    linked: Option<&mut Game>,
+    options: &Options,
+    movie: &mut Option<MovieSession>,
+    script: &mut Option<Script>,
+) -> Result<(), Box<dyn Error>> {
+    if linked.is_some() {
+        return Err("--local-link requires Gaby to be built with SDL.".into());
+    }
+    if options.border_path.is_some() {
+        warn!("Borders are only drawn when Gaby is built with SDL.");
+    }
+    let title = game.display_title();
+    let Game {
+        machine,
+        battery_save,
+        profiler,
+        ..
+    } = game;
+
+    let width = usize::from(video::SCREEN_WIDTH);
+    let height = usize::from(video::SCREEN_HEIGHT);
+    let scale = options.scale.unwrap_or(Options::DEFAULT_SCALE) as usize;
+    let mut window = Window::new(
+        &format!("{} - {}", crate::PROGRAM_NAME, title),
+        width * scale,
+        height * scale,
+        WindowOptions {
+            resize: true,
+            scale_mode: ScaleMode::AspectRatioStretch,
+            ..WindowOptions::default()
+        },
+    )
+    .map_err(|error| format!("Can't open a window: {}", error))?;
+    // The emulation speed is set by the frame limiter.
+    window.set_target_fps(0);
+
+    let audio_output =
+        AudioOutput::open().map_err(|error| format!("Can't open the audio device: {}", error))?;
+    machine.audio.set_sample_rate(audio_output.sample_rate);
+    // Keep two frames' worth of samples queued.
+    let target_queued_samples =
+        (2.0 * f64::from(audio_output.sample_rate) / Emulator::FRAMES_PER_SECOND) as usize;
+
+    let mut frame_limiter = FrameLimiter::new(Emulator::FRAMES_PER_SECOND);
+    let mut pixels = vec![0; width * height];
+    // Time it took to show the last frame.
+    let mut presentation = Duration::ZERO;
+    while window.is_open() && !window.is_key_down(Key::Escape) && !crate::stats::interrupted() {
+        // Only change the buttons on presses and releases, like the SDL
+        // frontend, so that a movie can tell when the player takes over.
+        for key in window.get_keys_pressed(KeyRepeat::No) {
+            if let Some(&(_, button)) = KEYS.iter().find(|&&(bound, _)| bound == key) {
+                machine.press(button);
+            }
+        }
+        for key in window.get_keys_released() {
+            if let Some(&(_, button)) = KEYS.iter().find(|&&(bound, _)| bound == key) {
+                machine.release(button);
+            }
+        }
+
+        crate::run_frame(machine, movie, script)?;
+        if let Some(profiler) = profiler {
+            profiler.add_frame(machine, presentation);
+        }
+
+        let presentation_start = Instant::now();
+        // The framebuffer is RGBA, and minifb wants 0RGB.
+        for (pixel, rgba) in pixels.iter_mut().zip(machine.video.framebuffer()) {
+            *pixel = rgba >> 8;
+        }
+        window
+            .update_with_buffer(&pixels, width, height)
+            .map_err(|error| format!("Can't update the window: {}", error))?;
+        presentation = presentation_start.elapsed();
+
+        frame_limiter.wait(options.speed);
+        let fill = audio_output.queued_samples() as f64 / target_queued_samples as f64;
+        let adjustment = (fill - 1.0).clamp(-1.0, 1.0) * MAX_RATE_ADJUSTMENT;
+        let rate_ratio = (1.0 - adjustment) * 100.0 / f64::from(options.speed);
+        machine.audio.set_rate_ratio(rate_ratio);
+        audio_output.queue(&machine.audio.take_samples());
+
+        if let Some(battery_save) = battery_save {
+            battery_save.update(&mut machine.mem.cartridge)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Samples waiting to be played, shared between the emulation thread and the
+/// audio callback.
+type SampleBuffer = Arc<Mutex<VecDeque<f32>>>;
+
+/// Plays samples on the default output device of cpal, at the sample rate of
+/// the device.
+struct AudioOutput {
+    sample_rate: i32,
+    buffer: SampleBuffer,
+    // Playback stops when the stream is dropped.
+    _stream: Stream,
+}
+
+impl AudioOutput {
+    fn open() -> Result<Self, Box<dyn Error>> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("There is no audio output device.")?;
+        let supported = device.default_output_config()?;
+        let config = supported.config();
+
+        let buffer = SampleBuffer::default();
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => AudioOutput::build::<f32>(&device, &config, buffer.clone())?,
+            SampleFormat::I16 => AudioOutput::build::<i16>(&device, &config, buffer.clone())?,
+            SampleFormat::U16 => AudioOutput::build::<u16>(&device, &config, buffer.clone())?,
+            format => return Err(format!("Unsupported sample format {}", format).into()),
+        };
+        stream.play()?;
+
+        Ok(Self {
+            sample_rate: config.sample_rate.0 as i32,
+            buffer,
+            _stream: stream,
+        })
+    }
+
+    /// Make a stream that plays the samples in `buffer` on all channels.
+    fn build<T: SizedSample + FromSample<f32>>(
+        device: &Device,
+        config: &StreamConfig,
+        buffer: SampleBuffer,
+    ) -> Result<Stream, Box<dyn Error>> {
+        let channels = usize::from(config.channels);
+        let mut last_sample = 0.0;
+        let mut starved = false;
+        let stream = device.build_output_stream(
+            config,
+            move |out: &mut [T], _| {
+                let mut buffer = buffer.lock().ok();
+                let mut ran_out = false;
+                for frame in out.chunks_mut(channels) {
+                    // On under-run, let the last sample fade out instead of
+                    // jumping to silence, which would be heard as a click.
+                    last_sample = match buffer.as_mut().and_then(|buffer| buffer.pop_front()) {
+                        Some(next) => next,
+                        None => {
+                            ran_out = true;
+                            last_sample * 0.999
+                        }
+                    };
+                    frame.fill(T::from_sample(last_sample));
+                }
+                if ran_out && !starved {
+                    crate::stats::count_underrun();
+                }
+                starved = ran_out;
+            },
+            |error| error!("Audio error: {}", error),
+            None,
+        )?;
+        Ok(stream)
+    }
+
+    /// Number of samples that haven't been played yet.
+    fn queued_samples(&self) -> usize {
+        self.buffer.lock().map_or(0, |buffer| buffer.len())
+    }
+
+    fn queue(&self, samples: &[f32]) {
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.extend(samples);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::options::Options;
+use gaby_core::cpu::CPU;
+use gaby_core::emulator::Emulator;
+use gaby_core::memory::Memory;
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Frames to run a test for before giving up, about a minute, unless
+/// `--frames` is given.
+const TIMEOUT_FRAMES: u64 = 3600;
+/// Registers that a Mooneye test ROM sets before `LD B, B` when it passes,
+/// the first Fibonacci numbers.
+const PASSED: [(&str, u16); 6] = [
+    ("b", 3),
+    ("c", 5),
+    ("d", 8),
+    ("e", 13),
+    ("h", 21),
+    ("l", 34),
+];
+/// Value of all the registers of `PASSED` when a test fails.
+const FAILED: u16 = 0x42;
+
+/// How a test ROM ended.
+enum Outcome {
+    Passed,
+    Failed,
+    /// `LD B, B` was executed, but the registers had neither the pass nor
+    /// the fail values.
+    Unknown,
+    TimedOut,
+}
+
+impl Outcome {
+    fn from_registers(cpu: &CPU) -> Self {
+        let register = |name| cpu.register(name).unwrap_or_default();
+        if PASSED.iter().all(|&(name, value)| register(name) == value) {
+            Outcome::Passed
+        } else if PASSED.iter().all(|&(name, _)| register(name) == FAILED) {
+            Outcome::Failed
+        } else {
+            Outcome::Unknown
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Outcome::Passed => "Passed",
+            Outcome::Failed => "Failed",
+            Outcome::Unknown => "Unknown",
+            Outcome::TimedOut => "Timeout",
+        }
+    }
+}
+
+/// Run the Mooneye test ROM at `path`, or all ROMs in the directory at
+/// `path` and its subdirectories, and print the outcome of each. Returns an
+/// error unless all of them passed.
+pub fn run(path: &Path, options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut roms = Vec::new();
+    find_roms(path, &mut roms)
+        .map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+    roms.sort();
+
+    let mut passed = 0;
+    for rom in &roms {
+        let description = match run_rom(rom, options) {
+            Ok(outcome) => {
+                if let Outcome::Passed = outcome {
+                    passed += 1;
+                }
+                outcome.describe().to_string()
+            }
+            Err(error) => format!("Error: {}", error),
+        };
+        println!("{:<8} {}", description, rom.display());
+    }
+
+    println!("{} of {} tests passed.", passed, roms.len());
+    if passed == roms.len() {
+        Ok(())
+    } else {
+        Err(format!("{} tests did not pass.", roms.len() - passed).into())
+    }
+}
+
+/// Add `path` to `roms` if it's a file, or the Game Boy ROMs in it if it's a
+/// directory.
+fn find_roms(path: &Path, roms: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        roms.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        let is_rom = matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("gb" | "gbc")
+        );
+        if path.is_dir() || is_rom {
+            find_roms(&path, roms)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run a test ROM until it executes `LD B, B`, and tell from the registers
+/// whether it passed.
+fn run_rom(path: &Path, options: &Options) -> Result<Outcome, Box<dyn Error>> {
+    let rom = fs::read(path)?;
+    let mut mem = match options.seed {
+        Some(seed) => Memory::with_seed(options.model, seed),
+        None => Memory::new(options.model),
+    };
+    mem.load_rom(&rom)?;
+    let mut machine = Emulator::new(mem, options.model);
+
+    // Check after every tick, since the registers may change after the
+    // breakpoint.
+    for _ in 0..options.frames.unwrap_or(TIMEOUT_FRAMES) {
+        for _ in 0..Emulator::TICKS_PER_FRAME {
+            machine.tick()?;
+            if machine.cpu.take_software_breakpoint() {
+                return Ok(Outcome::from_registers(&machine.cpu));
+            }
+        }
+        machine.audio.take_samples();
+    }
+
+    Ok(Outcome::TimedOut)
+}
@@ -0,0 +1,100 @@
+use gaby_core::emulator::Emulator;
+use gaby_core::joypad::{Buttons, Joypad};
+use gaby_core::movie::Movie;
+use tracing::info;
+
+enum Mode {
+    Recording,
+    /// Playing back. In read-write mode, recording takes over from the
+    /// current frame as soon as the player presses or releases a button.
+    Playing {
+        read_write: bool,
+    },
+    /// The movie has been played to the end in read-only mode.
+    Finished,
+}
+
+/// A movie being recorded or played back, one frame at a time.
+pub struct MovieSession {
+    movie: Movie,
+    path: String,
+    mode: Mode,
+    frame: usize,
+    /// Buttons set by the movie in the last frame, to tell when the player
+    /// changes them.
+    applied: Buttons,
+}
+
+impl MovieSession {
+    pub fn record(movie: Movie, path: &str) -> Self {
+        MovieSession::new(movie, path, Mode::Recording)
+    }
+
+    pub fn play(movie: Movie, path: &str, read_write: bool) -> Self {
+        MovieSession::new(movie, path, Mode::Playing { read_write })
+    }
+
+    fn new(movie: Movie, path: &str, mode: Mode) -> Self {
+        Self {
+            movie,
+            path: path.to_string(),
+            mode,
+            frame: 0,
+            applied: Buttons::empty(),
+        }
+    }
+
+    /// Record the buttons of `joypad`, or replace them with the ones from
+    /// the movie. Call this right before `Emulator::run_frame`.
+    pub fn next_frame(&mut self, joypad: &mut Joypad) {
+        if let Mode::Playing { read_write } = self.mode {
+            let touched = joypad.held != self.applied || !joypad.turbo.is_empty();
+            if self.frame == self.movie.inputs.len() || (read_write && touched) {
+                self.stop_playing(read_write, joypad);
+            }
+        }
+
+        match self.mode {
+            Mode::Recording => self.movie.inputs.push(joypad.buttons()),
+            Mode::Playing { .. } => {
+                self.applied = self.movie.inputs[self.frame];
+                joypad.held = self.applied;
+                joypad.turbo = Buttons::empty();
+            }
+            Mode::Finished => return,
+        }
+        self.frame += 1;
+    }
+
+    /// Continue from the current frame by recording in read-write mode, or
+    /// by giving the buttons back to the player in read-only mode.
+    fn stop_playing(&mut self, read_write: bool, joypad: &mut Joypad) {
+        if read_write {
+            info!("Recording movie from frame {}", self.frame);
+            self.movie.inputs.truncate(self.frame);
+            self.mode = Mode::Recording;
+        } else {
+            info!("Movie finished after {} frames", self.frame);
+            self.mode = Mode::Finished;
+            joypad.held = Buttons::empty();
+        }
+    }
+
+    /// Save the movie, if anything was recorded, with the hash of the final
+    /// state of `machine` for verifying playback.
+    pub fn finish(mut self, machine: &Emulator) -> Result<(), String> {
+        match self.mode {
+            Mode::Recording => {
+                self.movie.final_hash = Some(machine.state_hash());
+                self.movie.save(&self.path)?;
+                info!(
+                    "Saved movie of {} frames to {}",
+                    self.movie.inputs.len(),
+                    self.path
+                );
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! Stand-in for the script module when Gaby is built without Rhai.
+
+use gaby_core::emulator::Emulator;
+use gaby_core::joypad::Joypad;
+
+pub enum Script {}
+
+impl Script {
+    pub fn load(_path: &str, _machine: &mut Emulator) -> Result<Self, String> {
+        Err("Gaby was built without Rhai, so --script is not supported.".into())
+    }
+
+    pub fn press_buttons(&mut self, _joypad: &mut Joypad) {
+        match *self {}
+    }
+
+    pub fn run_frame(&mut self, _machine: &mut Emulator) -> Result<(), String> {
+        match *self {}
+    }
+
+    #[cfg_attr(not(feature = "sdl2"), allow(dead_code))]
+    pub fn overlay(&self) -> Vec<(i64, i64, String)> {
+        match *self {}
+    }
+}
@@ -0,0 +1,584 @@
+use crate::link::LinkSetting;
+use gaby_core::model::Model;
+use gaby_core::palette::PaletteSetting;
+use gaby_core::video::{Layers, MapOverlay};
+use std::convert::TryFrom;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How to play the sound while fast-forwarding, when the Game Boy makes far
+/// more samples than the audio device can play.
+#[derive(Clone, Copy, Default)]
+pub enum FastForwardAudio {
+    /// Play as much of the sound as fits in real time, at the normal pitch,
+    /// and skip the rest.
+    #[default]
+    Drop,
+    /// Squeeze all of the sound into real time, which raises the pitch.
+    Resample,
+    /// Play no sound.
+    Mute,
+}
+
+impl FromStr for FastForwardAudio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "drop" => Ok(FastForwardAudio::Drop),
+            "resample" => Ok(FastForwardAudio::Resample),
+            "mute" => Ok(FastForwardAudio::Mute),
+            _ => Err(format!(
+                "Unknown fast-forward audio setting {}. Use drop, resample or mute.",
+                s
+            )),
+        }
+    }
+}
+
+/// Options given on the command line.
+pub struct Options {
+    /// Path to the Game Boy ROM file.
+    pub rom_path: Option<PathBuf>,
+    /// Refuse to run ROMs with bad header or global checksums.
+    pub strict: bool,
+    /// Run games made only for the Game Boy Color, as well as it goes.
+    pub force_dmg: bool,
+    /// Hardware model to emulate.
+    pub model: Model,
+    /// IPS or BPS patch to apply to the ROM.
+    pub patch_path: Option<String>,
+    /// No-Intro DAT file to look games up in, see `GameDatabase`.
+    pub game_db_path: Option<String>,
+    /// Base path for exporting the memory access heatmap.
+    pub heatmap_path: Option<String>,
+    /// Seconds to wait after a write to battery-backed RAM before saving it.
+    pub autosave_interval: f64,
+    /// Emulate the DMG OAM corruption bug.
+    pub oam_bug: bool,
+    /// Configuration file to use instead of the default one.
+    pub config_path: Option<String>,
+    /// Colors of the four DMG shades.
+    pub palette: Option<PaletteSetting>,
+    /// Layers to draw at start-up.
+    pub layers: Layers,
+    /// What to draw on top of exported background maps.
+    #[cfg_attr(not(feature = "sdl2"), allow(dead_code))]
+    pub map_overlay: MapOverlay,
+    /// Run without a window and audio output.
+    pub headless: bool,
+    /// Number of frames to run before exiting.
+    pub frames: Option<u64>,
+    /// Print the hash of this frame and exit. Implies `headless`.
+    pub hash_frame: Option<u64>,
+    /// Initial size of the window, as a multiple of the screen size. Without
+    /// it, the window opens with the size and position it had when Gaby last
+    /// exited.
+    pub scale: Option<u32>,
+    /// PNG image to draw around the screen.
+    #[cfg_attr(not(any(feature = "sdl2", feature = "minifb")), allow(dead_code))]
+    pub border_path: Option<String>,
+    /// Only scale the screen by whole numbers when the window is resized.
+    pub integer_scaling: bool,
+    /// Audio output sample rate in Hz.
+    pub sample_rate: Option<i32>,
+    /// Number of samples the audio device asks for at a time.
+    pub audio_buffer: Option<u16>,
+    /// Directory to write the audio output of each channel to.
+    pub stems_path: Option<String>,
+    /// Input profile to start with.
+    pub input_profile: Option<String>,
+    /// Link cable connection to another emulator.
+    pub link: Option<LinkSetting>,
+    /// ROM for a second Game Boy, linked to the first one.
+    pub local_link_path: Option<String>,
+    /// Script of bytes to answer serial transfers with.
+    pub serial_script_path: Option<String>,
+    /// File to log serial transfers to.
+    pub serial_log_path: Option<String>,
+    /// Emulation speed in percent of the real Game Boy.
+    pub speed: u32,
+    /// What to do with the sound while fast-forwarding.
+    pub fast_forward_audio: Option<FastForwardAudio>,
+    /// PNG file to write the last frame to on exit. Implies `headless`.
+    pub exit_screenshot_path: Option<String>,
+    /// File to write a save state to on exit. Implies `headless`.
+    pub exit_state_path: Option<String>,
+    /// Seed for the random contents of memory at power-on.
+    pub seed: Option<u64>,
+    /// Movie file to record the buttons pressed to.
+    pub record_movie_path: Option<String>,
+    /// Save state to start recording the movie from.
+    pub movie_start_state_path: Option<String>,
+    /// Movie file to play back.
+    pub play_movie_path: Option<String>,
+    /// Take over recording when a button is pressed during playback.
+    pub movie_read_write: bool,
+    /// Movie to play back twice, checking that both runs are identical.
+    pub verify_movie_path: Option<String>,
+    /// Rhai script to run alongside the game.
+    pub script_path: Option<String>,
+    /// Run Mooneye test ROMs and report whether they pass, with the ROM path
+    /// being a ROM or a directory of them. Implies `headless`.
+    pub mooneye: bool,
+    /// Golden-frame tests to run instead of a game. Implies `headless`.
+    pub golden_path: Option<String>,
+    /// Address to stream the screen on, and take input from, over
+    /// WebSocket. Implies `headless`.
+    pub stream_address: Option<SocketAddr>,
+    /// Compare the CPU with a reference core, instruction by instruction.
+    /// Implies `headless`.
+    pub lockstep: bool,
+    /// Execute instructions in ROM from a cache of decoded instructions.
+    pub decode_cache: bool,
+    /// Values in memory to report the changes of, see `Watches::add`.
+    pub watches: Vec<String>,
+    /// Print statistics of the run on exit, also after Ctrl+C.
+    pub stats: bool,
+    /// Print percentiles of the time spent in each part of a frame on exit,
+    /// also after Ctrl+C.
+    pub profile: bool,
+}
+
+/// Options of the `test` subcommand, which runs a suite of test ROMs.
+pub struct TestOptions {
+    /// Directory of the test ROMs.
+    pub dir: String,
+    /// Manifest of the tests, by default `tests.toml` in the directory.
+    pub manifest_path: Option<String>,
+    /// Number of tests to run at a time, by default one per CPU core.
+    pub jobs: Option<usize>,
+}
+
+impl TestOptions {
+    /// Parse the arguments following `test`.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut dir = None;
+        let mut manifest_path = None;
+        let mut jobs = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--manifest" => manifest_path = Some(Options::value(&arg, args.next())?),
+                "--jobs" => {
+                    jobs = match Options::value(&arg, args.next())?.parse() {
+                        Ok(jobs) if jobs > 0 => Some(jobs),
+                        _ => {
+                            return Err("The number of jobs must be a positive whole number.".into())
+                        }
+                    }
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => {
+                    if dir.is_some() {
+                        return Err("Only one test ROM directory can be given.".into());
+                    }
+                    dir = Some(arg);
+                }
+            }
+        }
+
+        Ok(Self {
+            dir: dir.ok_or("A directory of test ROMs must be given after test.")?,
+            manifest_path,
+            jobs,
+        })
+    }
+}
+
+/// Options of the `info` subcommand, which describes a ROM.
+pub struct InfoOptions {
+    pub rom_path: PathBuf,
+    /// Configuration file to find the game database in.
+    pub config_path: Option<String>,
+    pub game_db_path: Option<String>,
+}
+
+impl InfoOptions {
+    /// Parse the arguments following `info`.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut rom_path = None;
+        let mut config_path = None;
+        let mut game_db_path = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => config_path = Some(Options::value(&arg, args.next())?),
+                "--game-db" => game_db_path = Some(Options::value(&arg, args.next())?),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => {
+                    if rom_path.is_some() {
+                        return Err("Only one ROM can be given.".into());
+                    }
+                    rom_path = Some(PathBuf::from(arg));
+                }
+            }
+        }
+
+        Ok(Self {
+            rom_path: rom_path.ok_or("A ROM must be given after info.")?,
+            config_path,
+            game_db_path,
+        })
+    }
+}
+
+/// Options of the `sav` subcommand, which moves battery saves between Gaby
+/// and other emulators or flashcarts.
+pub enum SavOptions {
+    /// Make a save file the save file of a ROM.
+    Import {
+        save_path: PathBuf,
+        rom_path: PathBuf,
+    },
+    /// Write the save file of a ROM to another file.
+    Export {
+        rom_path: PathBuf,
+        save_path: PathBuf,
+        /// Keep the real-time clock data after the RAM, if there is any.
+        rtc: bool,
+    },
+}
+
+impl SavOptions {
+    /// Parse the arguments following `sav`.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let action = args
+            .next()
+            .ok_or("import or export must be given after sav.")?;
+        let mut paths = Vec::new();
+        let mut rtc = true;
+
+        for arg in args {
+            match arg.as_str() {
+                "--no-rtc" if action == "export" => rtc = false,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => paths.push(PathBuf::from(arg)),
+            }
+        }
+
+        match (action.as_str(), <[PathBuf; 2]>::try_from(paths)) {
+            ("import", Ok([save_path, rom_path])) => Ok(SavOptions::Import {
+                save_path,
+                rom_path,
+            }),
+            ("export", Ok([rom_path, save_path])) => Ok(SavOptions::Export {
+                rom_path,
+                save_path,
+                rtc,
+            }),
+            ("import", Err(_)) => Err("Usage: gaby sav import <SAVE> <ROM>".into()),
+            ("export", Err(_)) => Err("Usage: gaby sav export <ROM> <SAVE> [--no-rtc]".into()),
+            _ => Err(format!(
+                "Unknown sav action {}. It must be import or export.",
+                action
+            )),
+        }
+    }
+}
+
+impl Options {
+    /// Size of the window, as a multiple of the screen size, when there is
+    /// no `--scale` or saved window size.
+    #[cfg_attr(not(any(feature = "sdl2", feature = "minifb")), allow(dead_code))]
+    pub const DEFAULT_SCALE: u32 = 4;
+
+    /// Parse options from the command line arguments, excluding the program name.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self {
+            rom_path: None,
+            strict: false,
+            force_dmg: false,
+            model: Model::DMG,
+            patch_path: None,
+            game_db_path: None,
+            heatmap_path: None,
+            autosave_interval: 5.0,
+            oam_bug: false,
+            config_path: None,
+            palette: None,
+            layers: Layers::default(),
+            map_overlay: MapOverlay::default(),
+            headless: false,
+            frames: None,
+            hash_frame: None,
+            scale: None,
+            border_path: None,
+            integer_scaling: false,
+            sample_rate: None,
+            audio_buffer: None,
+            stems_path: None,
+            input_profile: None,
+            link: None,
+            local_link_path: None,
+            serial_script_path: None,
+            serial_log_path: None,
+            speed: 100,
+            fast_forward_audio: None,
+            exit_screenshot_path: None,
+            exit_state_path: None,
+            seed: None,
+            record_movie_path: None,
+            movie_start_state_path: None,
+            play_movie_path: None,
+            movie_read_write: false,
+            verify_movie_path: None,
+            script_path: None,
+            mooneye: false,
+            golden_path: None,
+            stream_address: None,
+            lockstep: false,
+            decode_cache: false,
+            watches: Vec::new(),
+            stats: false,
+            profile: false,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--strict" => options.strict = true,
+                "--force-dmg" => options.force_dmg = true,
+                "--oam-bug" => options.oam_bug = true,
+                "--headless" => options.headless = true,
+                "--integer-scaling" => options.integer_scaling = true,
+                "--read-write" => options.movie_read_write = true,
+                "--stats" => options.stats = true,
+                "--profile" => options.profile = true,
+                "--decode-cache" => options.decode_cache = true,
+                "--lockstep" => {
+                    options.headless = true;
+                    options.lockstep = true;
+                }
+                "--mooneye" => {
+                    options.headless = true;
+                    options.mooneye = true;
+                }
+                "--scale" => {
+                    options.scale = match Options::value(&arg, args.next())?.parse() {
+                        Ok(scale) if scale > 0 => Some(scale),
+                        _ => return Err("The scale must be a positive whole number.".into()),
+                    }
+                }
+                "--speed" => {
+                    options.speed = match Options::value(&arg, args.next())?
+                        .trim_end_matches('%')
+                        .parse()
+                    {
+                        Ok(speed) if (1..=1000).contains(&speed) => speed,
+                        _ => return Err("The speed must be a percentage from 1 to 1000.".into()),
+                    }
+                }
+                "--fast-forward-audio" => {
+                    options.fast_forward_audio = Some(Options::value(&arg, args.next())?.parse()?)
+                }
+                "--hash-frame" => {
+                    options.headless = true;
+                    options.hash_frame = Some(
+                        Options::value(&arg, args.next())?
+                            .parse()
+                            .map_err(|_| "The frame number must be a whole number.")?,
+                    )
+                }
+                "--seed" => {
+                    options.seed = Some(
+                        Options::value(&arg, args.next())?
+                            .parse()
+                            .map_err(|_| "The seed must be a whole number.")?,
+                    )
+                }
+                "--record-movie" => {
+                    options.record_movie_path = Some(Options::value(&arg, args.next())?)
+                }
+                "--movie-start-state" => {
+                    options.movie_start_state_path = Some(Options::value(&arg, args.next())?)
+                }
+                "--verify-movie" => {
+                    options.headless = true;
+                    options.verify_movie_path = Some(Options::value(&arg, args.next())?)
+                }
+                "--play-movie" => {
+                    options.play_movie_path = Some(Options::value(&arg, args.next())?)
+                }
+                "--exit-screenshot" => {
+                    options.headless = true;
+                    options.exit_screenshot_path = Some(Options::value(&arg, args.next())?)
+                }
+                "--exit-state" => {
+                    options.headless = true;
+                    options.exit_state_path = Some(Options::value(&arg, args.next())?)
+                }
+                "--frames" => {
+                    options.frames = Some(
+                        Options::value(&arg, args.next())?
+                            .parse()
+                            .map_err(|_| "The number of frames must be a whole number.")?,
+                    )
+                }
+                "--stream" => {
+                    options.headless = true;
+                    options.stream_address =
+                        Some(listen_address(&Options::value(&arg, args.next())?)?)
+                }
+                "--golden" => {
+                    options.headless = true;
+                    options.golden_path = Some(Options::value(&arg, args.next())?)
+                }
+                "--watch" => options.watches.push(Options::value(&arg, args.next())?),
+                "--script" => options.script_path = Some(Options::value(&arg, args.next())?),
+                "--config" => options.config_path = Some(Options::value(&arg, args.next())?),
+                "--palette" => options.palette = Some(Options::value(&arg, args.next())?.parse()?),
+                "--model" => options.model = Options::value(&arg, args.next())?.parse()?,
+                "--patch" => options.patch_path = Some(Options::value(&arg, args.next())?),
+                "--game-db" => options.game_db_path = Some(Options::value(&arg, args.next())?),
+                "--border" => options.border_path = Some(Options::value(&arg, args.next())?),
+                "--heatmap" => options.heatmap_path = Some(Options::value(&arg, args.next())?),
+                "--input-profile" => {
+                    options.input_profile = Some(Options::value(&arg, args.next())?)
+                }
+                "--hide" => {
+                    for layer in Options::value(&arg, args.next())?.split(',') {
+                        match layer {
+                            "background" => options.layers.background = false,
+                            "window" => options.layers.window = false,
+                            "sprites" => options.layers.sprites = false,
+                            _ => return Err(format!("Unknown layer {}", layer)),
+                        }
+                    }
+                }
+                "--map-overlay" => {
+                    for overlay in Options::value(&arg, args.next())?.split(',') {
+                        match overlay {
+                            "viewport" => options.map_overlay.viewport = true,
+                            "grid" => options.map_overlay.grid = true,
+                            _ => return Err(format!("Unknown map overlay {}", overlay)),
+                        }
+                    }
+                }
+                "--sample-rate" => {
+                    options.sample_rate = match Options::value(&arg, args.next())?.parse() {
+                        Ok(rate) if rate > 0 => Some(rate),
+                        _ => return Err("The sample rate must be a positive whole number.".into()),
+                    }
+                }
+                "--audio-buffer" => {
+                    options.audio_buffer = match Options::value(&arg, args.next())?.parse() {
+                        Ok(size) if size > 0 => Some(size),
+                        _ => {
+                            return Err(
+                                "The audio buffer size must be between 1 and 65535 samples.".into(),
+                            )
+                        }
+                    }
+                }
+                "--link" => {
+                    let mode = Options::value(&arg, args.next())?;
+                    let address = Options::value(&arg, args.next())?;
+                    options.link = Some(LinkSetting::parse(&mode, &address)?);
+                }
+                "--local-link" => {
+                    options.local_link_path = Some(Options::value(&arg, args.next())?)
+                }
+                "--serial-script" => {
+                    options.serial_script_path = Some(Options::value(&arg, args.next())?)
+                }
+                "--log-serial" => {
+                    options.serial_log_path = Some(Options::value(&arg, args.next())?)
+                }
+                "--dump-audio-stems" => {
+                    options.stems_path = Some(Options::value(&arg, args.next())?)
+                }
+                "--autosave-interval" => {
+                    options.autosave_interval = match Options::value(&arg, args.next())?.parse() {
+                        // Too long an interval would overflow the duration.
+                        Ok(seconds)
+                            if seconds > 0.0 && Duration::try_from_secs_f64(seconds).is_ok() =>
+                        {
+                            seconds
+                        }
+                        _ => {
+                            return Err(
+                                "The autosave interval must be a positive number of seconds."
+                                    .into(),
+                            )
+                        }
+                    }
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => {
+                    if options.rom_path.is_some() {
+                        return Err("Only one ROM file path can be given.".into());
+                    }
+                    options.rom_path = Some(PathBuf::from(arg));
+                }
+            }
+        }
+
+        if (options.exit_screenshot_path.is_some() || options.exit_state_path.is_some())
+            && options.frames.is_none()
+        {
+            return Err("--exit-screenshot and --exit-state require --frames.".into());
+        }
+
+        let movies = [
+            options.record_movie_path.is_some(),
+            options.play_movie_path.is_some(),
+            options.verify_movie_path.is_some(),
+        ];
+        if movies.iter().filter(|&&movie| movie).count() > 1 {
+            return Err(
+                "Only one of --record-movie, --play-movie and --verify-movie can be given.".into(),
+            );
+        }
+        if options.movie_start_state_path.is_some() && options.record_movie_path.is_none() {
+            return Err("--movie-start-state requires --record-movie.".into());
+        }
+        if options.movie_read_write && options.play_movie_path.is_none() {
+            return Err("--read-write requires --play-movie.".into());
+        }
+        if options.uses_movie() && options.local_link_path.is_some() {
+            return Err("Movies can't be used with --local-link.".into());
+        }
+
+        let links = [
+            options.link.is_some(),
+            options.local_link_path.is_some(),
+            options.serial_script_path.is_some(),
+        ];
+        if links.iter().filter(|&&link| link).count() > 1 {
+            return Err(
+                "Only one of --link, --local-link and --serial-script can be given.".into(),
+            );
+        }
+
+        Ok(options)
+    }
+
+    /// Whether a movie is recorded, played back or verified.
+    pub fn uses_movie(&self) -> bool {
+        self.record_movie_path.is_some()
+            || self.play_movie_path.is_some()
+            || self.verify_movie_path.is_some()
+    }
+
+    /// Get the value following an option that requires one.
+    fn value(option: &str, value: Option<String>) -> Result<String, String> {
+        value.ok_or_else(|| format!("Option {} requires a value.", option))
+    }
+}
+
+/// Parse the address to listen on for `--stream` and `--link listen`. A port
+/// alone listens on the loopback interface only, so other computers can only
+/// connect when given an address like `0.0.0.0:5000`.
+pub fn listen_address(value: &str) -> Result<SocketAddr, String> {
+    match value.parse::<u16>() {
+        Ok(port) => Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, port))),
+        Err(_) => value.parse().map_err(|_| {
+            format!(
+                "Invalid address {}. Give a port, or an address and a port like 0.0.0.0:5000.",
+                value
+            )
+        }),
+    }
+}
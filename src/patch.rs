@@ -0,0 +1,416 @@
+use std::path::{Path, PathBuf};
+
+/// Size of the largest Game Boy ROMs, 8 MB.
+const MAX_TARGET_SIZE: usize = 0x80_0000;
+
+/// Apply an IPS or BPS patch to a ROM. The patch format is detected from the
+/// magic bytes at the start of the patch.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, patch)
+    } else {
+        Err("Unknown patch format. Only IPS and BPS patches are supported.".into())
+    }
+}
+
+/// Look for a patch file next to the ROM with the same name, but with an .ips
+/// or .bps extension.
+pub fn find_sidecar(rom_path: &Path) -> Option<PathBuf> {
+    ["ips", "bps"]
+        .iter()
+        .map(|extension| rom_path.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+/// IPS patches consist of records that overwrite data at a given offset,
+/// optionally run-length encoded.
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    const TRUNCATED: &str = "IPS patch is truncated.";
+
+    let mut output = rom.to_vec();
+    let mut reader = Reader::new(&patch[5..]);
+
+    loop {
+        let offset_bytes = reader.bytes(3).ok_or(TRUNCATED)?;
+        if offset_bytes == b"EOF" {
+            break;
+        }
+        let offset = usize::from(offset_bytes[0]) << 16
+            | usize::from(offset_bytes[1]) << 8
+            | usize::from(offset_bytes[2]);
+
+        let size = reader.be_u16().ok_or(TRUNCATED)? as usize;
+        let data = if size == 0 {
+            // Run-length encoded record.
+            let run_length = reader.be_u16().ok_or(TRUNCATED)? as usize;
+            let value = reader.byte().ok_or(TRUNCATED)?;
+            vec![value; run_length]
+        } else {
+            reader.bytes(size).ok_or(TRUNCATED)?.to_vec()
+        };
+
+        let end = offset + data.len();
+        if end > output.len() {
+            output.resize(end, 0);
+        }
+        output[offset..end].copy_from_slice(&data);
+    }
+
+    // Some patches have a truncation extension after the end-of-file marker.
+    if let Some(length_bytes) = reader.bytes(3) {
+        let length = usize::from(length_bytes[0]) << 16
+            | usize::from(length_bytes[1]) << 8
+            | usize::from(length_bytes[2]);
+        output.truncate(length);
+    }
+
+    Ok(output)
+}
+
+/// BPS patches describe the target as a sequence of copies from the source,
+/// the patch itself or the already written target. Source, target and patch
+/// are all verified with CRC32 checksums.
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    const TRUNCATED: &str = "BPS patch is truncated.";
+    const FOOTER_SIZE: usize = 12;
+
+    if patch.len() < 4 + FOOTER_SIZE {
+        return Err(TRUNCATED.into());
+    }
+
+    let footer = &patch[patch.len() - FOOTER_SIZE..];
+    let source_crc = u32::from_le_bytes([footer[0], footer[1], footer[2], footer[3]]);
+    let target_crc = u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]);
+    let patch_crc = u32::from_le_bytes([footer[8], footer[9], footer[10], footer[11]]);
+
+    if crc32(&patch[..patch.len() - 4]) != patch_crc {
+        return Err("BPS patch is corrupt (patch checksum mismatch).".into());
+    }
+    if crc32(rom) != source_crc {
+        return Err("BPS patch was made for a different ROM (source checksum mismatch).".into());
+    }
+
+    let mut reader = Reader::new(&patch[4..patch.len() - FOOTER_SIZE]);
+    let source_size = reader.bps_number().ok_or(TRUNCATED)?;
+    let target_size = reader.bps_number().ok_or(TRUNCATED)?;
+    let metadata_size = reader.bps_number().ok_or(TRUNCATED)?;
+    reader.bytes(metadata_size).ok_or(TRUNCATED)?;
+
+    if source_size != rom.len() {
+        return Err("BPS patch was made for a ROM of a different size.".into());
+    }
+    if target_size > MAX_TARGET_SIZE {
+        return Err("BPS patch makes a ROM larger than any Game Boy ROM.".into());
+    }
+
+    let mut output = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+
+    while !reader.is_empty() {
+        let data = reader.bps_number().ok_or(TRUNCATED)?;
+        let length = (data >> 2) + 1;
+        // The checksums are made by the patch author too, so a crafted patch
+        // could otherwise make the output grow without end.
+        if length > target_size - output.len() {
+            return Err("BPS patch writes past the end of the patched ROM.".into());
+        }
+
+        match data & 0b11 {
+            // SourceRead: copy from the same position in the source.
+            0 => {
+                let start = output.len();
+                let bytes = rom.get(start..start + length).ok_or(TRUNCATED)?;
+                output.extend_from_slice(bytes);
+            }
+            // TargetRead: copy from the patch.
+            1 => output.extend_from_slice(reader.bytes(length).ok_or(TRUNCATED)?),
+            // SourceCopy: copy from a relative position in the source.
+            2 => {
+                source_offset = reader.bps_offset(source_offset).ok_or(TRUNCATED)?;
+                let end = source_offset.checked_add(length).ok_or(TRUNCATED)?;
+                output.extend_from_slice(rom.get(source_offset..end).ok_or(TRUNCATED)?);
+                source_offset = end;
+            }
+            // TargetCopy: copy from a relative position in the target. The
+            // ranges may overlap, so copy byte by byte.
+            3 => {
+                target_offset = reader.bps_offset(target_offset).ok_or(TRUNCATED)?;
+                for _ in 0..length {
+                    let byte = *output.get(target_offset).ok_or(TRUNCATED)?;
+                    output.push(byte);
+                    target_offset += 1;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if output.len() != target_size || crc32(&output) != target_crc {
+        return Err("Patched ROM doesn't match the BPS patch (target checksum mismatch).".into());
+    }
+
+    Ok(output)
+}
+
+/// CRC32 as used by zip, PNG and BPS.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// Cursor over the bytes of a patch.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.position..self.position + count)?;
+        self.position += count;
+        Some(bytes)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+
+    fn be_u16(&mut self) -> Option<u16> {
+        self.bytes(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Variable-length number, where each byte holds seven bits and the high
+    /// bit marks the last byte.
+    fn bps_number(&mut self) -> Option<usize> {
+        let mut number: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()?;
+            number = number.checked_add(usize::from(byte & 0x7F).checked_mul(shift)?)?;
+            if byte & 0x80 != 0 {
+                return Some(number);
+            }
+            shift = shift.checked_shl(7)?;
+            number = number.checked_add(shift)?;
+        }
+    }
+
+    /// Signed offset relative to `base`, encoded as a number with the sign in
+    /// the lowest bit.
+    fn bps_offset(&mut self, base: usize) -> Option<usize> {
+        let data = self.bps_number()?;
+        let offset = data >> 1;
+        if data & 1 != 0 {
+            base.checked_sub(offset)
+        } else {
+            base.checked_add(offset)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    /// Encode a BPS variable-length number.
+    fn bps_number(patch: &mut Vec<u8>, mut number: usize) {
+        loop {
+            let byte = (number & 0x7F) as u8;
+            number >>= 7;
+            if number == 0 {
+                patch.push(0x80 | byte);
+                return;
+            }
+            patch.push(byte);
+            number -= 1;
+        }
+    }
+
+    fn bps_action(patch: &mut Vec<u8>, kind: usize, length: usize) {
+        bps_number(patch, (length - 1) << 2 | kind);
+    }
+
+    /// Make a BPS patch with the given actions, for a target of
+    /// `target_size` bytes with the checksum `target_crc`.
+    fn bps_patch(target_size: usize, target_crc: u32, actions: &[u8]) -> Vec<u8> {
+        let mut patch = b"BPS1".to_vec();
+        bps_number(&mut patch, ROM.len());
+        bps_number(&mut patch, target_size);
+        bps_number(&mut patch, 0);
+        patch.extend_from_slice(actions);
+        patch.extend_from_slice(&crc32(&ROM).to_le_bytes());
+        patch.extend_from_slice(&target_crc.to_le_bytes());
+        let patch_crc = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert!(apply(&ROM, b"UPS1").is_err());
+    }
+
+    #[test]
+    fn ips_records() {
+        let mut patch = b"PATCH".to_vec();
+        // Overwrite two bytes at offset 1.
+        patch.extend_from_slice(&[0, 0, 1, 0, 2, 0xAA, 0xBB]);
+        // Run of three 0xCC at offset 6, past the end of the ROM.
+        patch.extend_from_slice(&[0, 0, 6, 0, 0, 0, 3, 0xCC]);
+        patch.extend_from_slice(b"EOF");
+
+        assert_eq!(
+            apply(&ROM, &patch).unwrap(),
+            [1, 0xAA, 0xBB, 4, 5, 6, 0xCC, 0xCC, 0xCC]
+        );
+    }
+
+    #[test]
+    fn ips_truncation_extension() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(b"EOF");
+        patch.extend_from_slice(&[0, 0, 4]);
+
+        assert_eq!(apply(&ROM, &patch).unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn ips_truncated() {
+        let truncated: [&[u8]; 4] = [
+            // No end-of-file marker.
+            b"PATCH",
+            // Record shorter than its size.
+            b"PATCH\x00\x00\x01\x00\x04\xAA",
+            // Run-length encoded record without its value.
+            b"PATCH\x00\x00\x01\x00\x00\x00\x03",
+            // Record without the end-of-file marker after it.
+            b"PATCH\x00\x00\x01\x00\x01\xAA",
+        ];
+        for patch in truncated.iter() {
+            assert_eq!(apply(&ROM, patch).unwrap_err(), "IPS patch is truncated.");
+        }
+    }
+
+    #[test]
+    fn bps_actions() {
+        let target = [1, 2, 0xA, 0xB, 0xC, 5, 6, 6, 6, 6];
+        let mut actions = Vec::new();
+        // SourceRead of [1, 2].
+        bps_action(&mut actions, 0, 2);
+        // TargetRead of [0xA, 0xB, 0xC].
+        bps_action(&mut actions, 1, 3);
+        actions.extend_from_slice(&[0xA, 0xB, 0xC]);
+        // SourceCopy of [5, 6] from offset 4.
+        bps_action(&mut actions, 2, 2);
+        bps_number(&mut actions, 4 << 1);
+        // TargetCopy of the last byte, overlapping what it writes.
+        bps_action(&mut actions, 3, 3);
+        bps_number(&mut actions, 6 << 1);
+
+        let patch = bps_patch(target.len(), crc32(&target), &actions);
+        assert_eq!(apply(&ROM, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn bps_numbers() {
+        for &number in [0, 0x7F, 0x80, 0x407F, 0x4080, 0x12_3456].iter() {
+            let mut encoded = Vec::new();
+            bps_number(&mut encoded, number);
+            assert_eq!(Reader::new(&encoded).bps_number(), Some(number));
+        }
+
+        // Without the end bit, and with more bits than fit in a usize.
+        assert_eq!(Reader::new(&[0x00, 0x7F]).bps_number(), None);
+        assert_eq!(Reader::new(&[0x7F; 16]).bps_number(), None);
+    }
+
+    #[test]
+    fn bps_truncated() {
+        // A TargetRead of four bytes with only one in the patch.
+        let mut actions = Vec::new();
+        bps_action(&mut actions, 1, 4);
+        actions.push(0xA);
+        let patch = bps_patch(4, 0, &actions);
+        assert_eq!(apply(&ROM, &patch).unwrap_err(), "BPS patch is truncated.");
+
+        // A number without its end bit.
+        let patch = bps_patch(4, 0, &[0x00]);
+        assert_eq!(apply(&ROM, &patch).unwrap_err(), "BPS patch is truncated.");
+
+        assert_eq!(apply(&ROM, b"BPS1").unwrap_err(), "BPS patch is truncated.");
+    }
+
+    #[test]
+    fn bps_checksum_mismatches() {
+        let mut actions = Vec::new();
+        bps_action(&mut actions, 0, ROM.len());
+
+        let patch = bps_patch(ROM.len(), crc32(&ROM) ^ 1, &actions);
+        assert_eq!(
+            apply(&ROM, &patch).unwrap_err(),
+            "Patched ROM doesn't match the BPS patch (target checksum mismatch)."
+        );
+
+        let mut patch = bps_patch(ROM.len(), crc32(&ROM), &actions);
+        let last = patch.len() - 1;
+        patch[last] ^= 1;
+        assert_eq!(
+            apply(&ROM, &patch).unwrap_err(),
+            "BPS patch is corrupt (patch checksum mismatch)."
+        );
+
+        let patch = bps_patch(ROM.len(), crc32(&ROM), &actions);
+        assert_eq!(
+            apply(&ROM[1..], &patch).unwrap_err(),
+            "BPS patch was made for a different ROM (source checksum mismatch)."
+        );
+    }
+
+    #[test]
+    fn bps_oversized_target() {
+        let patch = bps_patch(MAX_TARGET_SIZE + 1, 0, &[]);
+        assert_eq!(
+            apply(&ROM, &patch).unwrap_err(),
+            "BPS patch makes a ROM larger than any Game Boy ROM."
+        );
+
+        // An action longer than the rest of the target.
+        let mut actions = Vec::new();
+        bps_action(&mut actions, 0, 4);
+        let patch = bps_patch(2, 0, &actions);
+        assert_eq!(
+            apply(&ROM, &patch).unwrap_err(),
+            "BPS patch writes past the end of the patched ROM."
+        );
+    }
+}
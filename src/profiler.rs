@@ -0,0 +1,68 @@
+use gaby_core::emulator::Emulator;
+use gaby_core::profile::FrameTimes;
+use std::time::Duration;
+
+/// Percentiles printed for each part of a frame.
+const PERCENTILES: [f64; 3] = [50.0, 95.0, 99.0];
+
+/// Where the time of each frame went, collected for `--profile` and printed
+/// as percentiles on exit.
+#[derive(Default)]
+pub struct Profiler {
+    /// Times of CPU, video, audio and presentation in each frame.
+    frames: Vec<[Duration; 4]>,
+}
+
+impl Profiler {
+    /// Start measuring the frames of `machine`.
+    pub fn new(machine: &mut Emulator) -> Self {
+        machine.frame_times = Some(FrameTimes::default());
+        Self::default()
+    }
+
+    /// Add the frame `machine` has just run, which took `presentation` to
+    /// show on the screen.
+    pub fn add_frame(&mut self, machine: &Emulator, presentation: Duration) {
+        if let Some(times) = machine.frame_times {
+            self.frames
+                .push([times.cpu, times.video, times.audio, presentation]);
+        }
+    }
+
+    /// Print the percentiles of the time spent in each part of a frame, and
+    /// in the whole frame.
+    pub fn print(&self) {
+        println!("Frame times in ms, over {} frames:", self.frames.len());
+        let header: Vec<String> = PERCENTILES
+            .iter()
+            .map(|percentile| format!("{:>8}", format!("p{}", percentile)))
+            .collect();
+        println!("  {:<14} {}", "", header.join(" "));
+        let names = ["CPU", "Video", "Audio", "Presentation"];
+        for (part, name) in names.iter().enumerate() {
+            let times: Vec<Duration> = self.frames.iter().map(|frame| frame[part]).collect();
+            Profiler::print_percentiles(name, times);
+        }
+        let totals = self.frames.iter().map(|frame| frame.iter().sum()).collect();
+        Profiler::print_percentiles("Total", totals);
+    }
+
+    fn print_percentiles(name: &str, mut times: Vec<Duration>) {
+        if times.is_empty() {
+            return;
+        }
+        times.sort_unstable();
+
+        let columns: Vec<String> = PERCENTILES
+            .iter()
+            .map(|percentile| {
+                // The nearest rank, i.e. the smallest time that at least
+                // this percentage of the frames are within.
+                let rank = (percentile / 100.0 * times.len() as f64).ceil() as usize;
+                let time = times[rank.clamp(1, times.len()) - 1];
+                format!("{:8.3}", time.as_secs_f64() * 1000.0)
+            })
+            .collect();
+        println!("  {:<14} {}", name, columns.join(" "));
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+/// The ROMs opened most recently, newest first, kept in the user's data
+/// directory, e.g. `~/.local/share/gaby/recent.txt`.
+pub struct RecentFiles {
+    path: PathBuf,
+    files: Vec<String>,
+}
+
+impl RecentFiles {
+    const MAX_FILES: usize = 10;
+
+    pub fn load() -> Self {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("gaby")
+            .join("recent.txt");
+        let files = fs::read_to_string(&path)
+            .map(|text| text.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        Self { path, files }
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Put `rom_path` first in the list, and save the list. Failing to save
+    /// it is only reported, since it doesn't stop the game from running.
+    /// The list is a text file, so paths that aren't valid UTF-8 are left
+    /// out of it.
+    pub fn add(&mut self, rom_path: &Path) {
+        let rom_path = fs::canonicalize(rom_path).unwrap_or_else(|_| rom_path.to_path_buf());
+        let rom_path = match rom_path.to_str() {
+            Some(rom_path) => rom_path.to_string(),
+            None => return,
+        };
+        self.files.retain(|file| *file != rom_path);
+        self.files.insert(0, rom_path);
+        self.files.truncate(RecentFiles::MAX_FILES);
+
+        let result = self
+            .path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&self.path, self.files.join("\n")));
+        if let Err(error) = result {
+            warn!(
+                "Can't save the list of recent files to {}: {}",
+                self.path.display(),
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use gaby_core::{
+    emulator::Emulator,
+    video::{SCREEN_HEIGHT, SCREEN_WIDTH},
+};
+use gif::{Encoder, Frame, Repeat};
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Many GIF viewers don't honor delays shorter than 2/100 s, so only every
+/// second frame is recorded.
+const FRAME_STEP: u32 = 2;
+
+/// Records frames to an animated GIF. Encoding happens on a separate thread,
+/// so that the color quantization doesn't slow down the emulation.
+pub struct Recording {
+    path: PathBuf,
+    frames: Sender<Vec<u8>>,
+    encoder: JoinHandle<Result<(), gif::EncodingError>>,
+    /// Frames since the last recorded one, modulo `FRAME_STEP`.
+    frame_count: u32,
+}
+
+impl Recording {
+    /// Start recording to a file named after the ROM and the current time, in
+    /// the same directory as the ROM.
+    pub fn start(rom_path: &Path) -> io::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let stem = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+        let path = rom_path.with_file_name(format!("{}-{}.gif", stem, timestamp));
+
+        let file = BufWriter::new(File::create(&path)?);
+        let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+
+        let encoder = thread::spawn(move || {
+            let width = u16::from(SCREEN_WIDTH);
+            let height = u16::from(SCREEN_HEIGHT);
+            let mut encoder = Encoder::new(file, width, height, &[])?;
+            encoder.set_repeat(Repeat::Infinite)?;
+
+            // GIF delays are in hundredths of a second, so keep track of the
+            // exact time to avoid drifting.
+            let mut time = 0.0;
+            let mut written_delay: u64 = 0;
+            for pixels in receiver {
+                time += 100.0 * f64::from(FRAME_STEP) / Emulator::FRAMES_PER_SECOND;
+                let delay = time.round() as u64 - written_delay;
+                written_delay += delay;
+
+                let mut frame = Frame::from_rgb_speed(width, height, &pixels, 10);
+                frame.delay = delay as u16;
+                encoder.write_frame(&frame)?;
+            }
+
+            Ok(())
+        });
+
+        Ok(Self {
+            path,
+            frames: sender,
+            encoder,
+            frame_count: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add a frame from the framebuffer. Call this once per emulated frame.
+    pub fn add_frame(&mut self, framebuffer: &[u32]) {
+        if self.frame_count == 0 {
+            let pixel_data = framebuffer
+                .iter()
+                .flat_map(|pixel| {
+                    let [red, green, blue, _] = pixel.to_be_bytes();
+                    [red, green, blue]
+                })
+                .collect();
+            // If the encoder has failed, the error is reported by `stop`.
+            let _ = self.frames.send(pixel_data);
+        }
+        self.frame_count = (self.frame_count + 1) % FRAME_STEP;
+    }
+
+    /// Stop recording and wait for the encoder to write the remaining frames.
+    pub fn stop(self) -> Result<(), String> {
+        // Closing the channel ends the encoder loop.
+        drop(self.frames);
+
+        match self.encoder.join() {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(error)) => Err(format!("Failed to encode GIF: {}", error)),
+            Err(_) => Err("GIF encoder thread panicked.".into()),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use gaby_core::emulator::Emulator;
+use std::collections::VecDeque;
+
+/// Save states are taken every this many frames.
+const FRAMES_PER_STATE: u32 = 2;
+/// How far back the emulation can be rewound.
+const SECONDS: u32 = 30;
+/// The Game Boy shows just below 60 frames per second.
+const FRAMES_PER_SECOND: u32 = 60;
+
+/// Ring buffer of recent save states, kept in memory, to step the emulation
+/// back in time.
+pub struct Rewind {
+    states: VecDeque<Vec<u8>>,
+    /// Frames since the last state was taken.
+    frame_count: u32,
+}
+
+impl Rewind {
+    const CAPACITY: usize = (SECONDS * FRAMES_PER_SECOND / FRAMES_PER_STATE) as usize;
+
+    pub fn new() -> Self {
+        Self {
+            states: VecDeque::with_capacity(Rewind::CAPACITY),
+            frame_count: 0,
+        }
+    }
+
+    /// Take a state if it's time for one, dropping the oldest state when the
+    /// buffer is full. Call this once per frame.
+    pub fn record(&mut self, machine: &Emulator) {
+        self.frame_count += 1;
+        if self.frame_count < FRAMES_PER_STATE {
+            return;
+        }
+        self.frame_count = 0;
+
+        if self.states.len() == Rewind::CAPACITY {
+            self.states.pop_front();
+        }
+        self.states.push_back(machine.save_state());
+    }
+
+    /// Go back to the most recent state, and drop it from the buffer. Returns
+    /// false when there are no states left.
+    pub fn step_back(&mut self, machine: &mut Emulator) -> Result<bool, String> {
+        match self.states.pop_back() {
+            Some(state) => {
+                machine.load_state(&state)?;
+                self.frame_count = 0;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use crate::patch::crc32;
+use std::{fs, path::Path};
+
+/// Extensions of the files that can be opened as ROMs, for file dialogs.
+#[cfg(feature = "rfd")]
+pub const EXTENSIONS: [&str; 3] = ["gb", "gbc", "zip"];
+
+/// Largest ROM taken out of a zip file, the largest an MBC5 can address.
+const MAX_ROM_SIZE: usize = 0x80_0000;
+
+/// Read the ROM at `path`. ROMs are often distributed in zip files, so if it
+/// is one, the first .gb or .gbc file in it is read instead.
+pub fn read(path: &Path) -> Result<Vec<u8>, String> {
+    let data =
+        fs::read(path).map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+    if data.starts_with(b"PK\x03\x04") {
+        unzip_rom(&data).map_err(|error| format!("Can't read {}: {}", path.display(), error))
+    } else {
+        Ok(data)
+    }
+}
+
+/// Find the first .gb or .gbc file in the central directory of a zip file,
+/// and inflate it. Only stored and deflated files are supported, which is
+/// what zip tools write.
+fn unzip_rom(zip: &[u8]) -> Result<Vec<u8>, String> {
+    const CORRUPT: &str = "The zip file is corrupt.";
+
+    // The end of central directory record is last in the file, but may be
+    // followed by a comment of up to 64 KiB, which could hold the signature
+    // too. The comment length of the record has to reach the end of the file.
+    let end = (0..=zip.len().saturating_sub(22))
+        .rev()
+        .take(0x1_0000 + 22)
+        .find(|&offset| {
+            zip[offset..].starts_with(b"PK\x05\x06")
+                && le_u16(zip, offset + 20).map(|length| offset + 22 + length as usize)
+                    == Some(zip.len())
+        })
+        .ok_or(CORRUPT)?;
+    let entries = le_u16(zip, end + 10).ok_or(CORRUPT)?;
+    let mut offset = le_u32(zip, end + 16).ok_or(CORRUPT)? as usize;
+
+    for _ in 0..entries {
+        if !zip.get(offset..).ok_or(CORRUPT)?.starts_with(b"PK\x01\x02") {
+            return Err(CORRUPT.into());
+        }
+        let method = le_u16(zip, offset + 10).ok_or(CORRUPT)?;
+        let crc = le_u32(zip, offset + 16).ok_or(CORRUPT)?;
+        let compressed_size = le_u32(zip, offset + 20).ok_or(CORRUPT)? as usize;
+        let size = le_u32(zip, offset + 24).ok_or(CORRUPT)? as usize;
+        let name_length = le_u16(zip, offset + 28).ok_or(CORRUPT)? as usize;
+        let extra_length = le_u16(zip, offset + 30).ok_or(CORRUPT)? as usize;
+        let comment_length = le_u16(zip, offset + 32).ok_or(CORRUPT)? as usize;
+        let header_offset = le_u32(zip, offset + 42).ok_or(CORRUPT)? as usize;
+        let name = zip
+            .get(offset + 46..offset + 46 + name_length)
+            .ok_or(CORRUPT)?;
+        offset += 46 + name_length + extra_length + comment_length;
+
+        let name = String::from_utf8_lossy(name).to_lowercase();
+        if !(name.ends_with(".gb") || name.ends_with(".gbc")) {
+            continue;
+        }
+        if size > MAX_ROM_SIZE {
+            return Err(format!("{} is too big to be a Game Boy ROM.", name));
+        }
+
+        // The data follows the local header, whose name and extra field may
+        // differ in length from those in the central directory.
+        if !zip
+            .get(header_offset..)
+            .ok_or(CORRUPT)?
+            .starts_with(b"PK\x03\x04")
+        {
+            return Err(CORRUPT.into());
+        }
+        let data_offset = header_offset
+            + 30
+            + le_u16(zip, header_offset + 26).ok_or(CORRUPT)? as usize
+            + le_u16(zip, header_offset + 28).ok_or(CORRUPT)? as usize;
+        let data = zip
+            .get(data_offset..data_offset + compressed_size)
+            .ok_or(CORRUPT)?;
+
+        let rom = match method {
+            0 => data.to_vec(),
+            8 => miniz_oxide::inflate::decompress_to_vec_with_limit(data, size)
+                .map_err(|_| CORRUPT)?,
+            _ => {
+                return Err(format!(
+                    "{} is compressed with an unsupported method ({}).",
+                    name, method
+                ))
+            }
+        };
+        if rom.len() != size || crc32(&rom) != crc {
+            return Err(CORRUPT.into());
+        }
+        return Ok(rom);
+    }
+
+    Err("The zip file has no .gb or .gbc file in it.".into())
+}
+
+fn le_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn le_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 64 bytes counting from 0 to 15 four times, deflated.
+    const DEFLATED: [u8; 21] = [
+        0x63, 0x60, 0x64, 0x62, 0x66, 0x61, 0x65, 0x63, 0xE7, 0xE0, 0xE4, 0xE2, 0xE6, 0xE1, 0xE5,
+        0xE3, 0x67, 0x20, 0x91, 0x0F, 0x00,
+    ];
+
+    fn rom() -> Vec<u8> {
+        (0..64).map(|byte| byte % 16).collect()
+    }
+
+    struct Entry<'a> {
+        name: &'a str,
+        method: u16,
+        data: &'a [u8],
+        size: u32,
+        crc: u32,
+    }
+
+    impl<'a> Entry<'a> {
+        fn stored(name: &'a str, data: &'a [u8]) -> Self {
+            Self {
+                name,
+                method: 0,
+                data,
+                size: data.len() as u32,
+                crc: crc32(data),
+            }
+        }
+
+        fn deflated(name: &'a str) -> Self {
+            Self {
+                name,
+                method: 8,
+                data: &DEFLATED,
+                size: 64,
+                crc: crc32(&rom()),
+            }
+        }
+    }
+
+    /// Make a zip file with the entries, followed by a comment.
+    fn zip(entries: &[Entry], comment: &[u8]) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut directory = Vec::new();
+        for entry in entries {
+            let header_offset = zip.len() as u32;
+            let mut header = Vec::new();
+            header.extend_from_slice(&entry.method.to_le_bytes());
+            header.extend_from_slice(&[0; 4]);
+            header.extend_from_slice(&entry.crc.to_le_bytes());
+            header.extend_from_slice(&(entry.data.len() as u32).to_le_bytes());
+            header.extend_from_slice(&entry.size.to_le_bytes());
+            header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+
+            zip.extend_from_slice(b"PK\x03\x04\x14\x00\x00\x00");
+            zip.extend_from_slice(&header);
+            zip.extend_from_slice(&[0; 2]);
+            zip.extend_from_slice(entry.name.as_bytes());
+            zip.extend_from_slice(entry.data);
+
+            directory.extend_from_slice(b"PK\x01\x02\x14\x00\x14\x00\x00\x00");
+            directory.extend_from_slice(&header);
+            directory.extend_from_slice(&[0; 12]);
+            directory.extend_from_slice(&header_offset.to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let directory_offset = zip.len() as u32;
+        zip.extend_from_slice(&directory);
+        zip.extend_from_slice(b"PK\x05\x06\x00\x00\x00\x00");
+        zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&directory_offset.to_le_bytes());
+        zip.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+        zip.extend_from_slice(comment);
+        zip
+    }
+
+    #[test]
+    fn stored_rom() {
+        let rom = rom();
+        let zip = zip(
+            &[
+                Entry::stored("readme.txt", b"Not a ROM"),
+                Entry::stored("Game.GB", &rom),
+            ],
+            b"",
+        );
+        assert_eq!(unzip_rom(&zip).unwrap(), rom);
+    }
+
+    #[test]
+    fn deflated_rom() {
+        let zip = zip(&[Entry::deflated("game.gbc")], b"");
+        assert_eq!(unzip_rom(&zip).unwrap(), rom());
+    }
+
+    #[test]
+    fn end_record_before_comment() {
+        // The comment holds a signature that mustn't be taken for the record.
+        let mut comment = b"PK\x05\x06".to_vec();
+        comment.resize(0x1000, b' ');
+        let zip = zip(&[Entry::deflated("game.gb")], &comment);
+        assert_eq!(unzip_rom(&zip).unwrap(), rom());
+    }
+
+    #[test]
+    fn no_end_record() {
+        let zip = zip(&[Entry::deflated("game.gb")], b"");
+        let truncated = &zip[..zip.len() - 1];
+        assert_eq!(
+            unzip_rom(truncated).unwrap_err(),
+            "The zip file is corrupt."
+        );
+        assert_eq!(
+            unzip_rom(b"PK\x03\x04").unwrap_err(),
+            "The zip file is corrupt."
+        );
+    }
+
+    #[test]
+    fn inflate_is_bounded_by_the_size() {
+        let mut entry = Entry::deflated("game.gb");
+        entry.size = 32;
+        let zip = zip(&[entry], b"");
+        assert_eq!(unzip_rom(&zip).unwrap_err(), "The zip file is corrupt.");
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let rom = rom();
+        let mut stored = Entry::stored("game.gb", &rom);
+        stored.crc ^= 1;
+        let mut deflated = Entry::deflated("game.gb");
+        deflated.crc ^= 1;
+
+        for zip in [zip(&[stored], b""), zip(&[deflated], b"")].iter() {
+            assert_eq!(unzip_rom(zip).unwrap_err(), "The zip file is corrupt.");
+        }
+    }
+
+    #[test]
+    fn too_big_rom() {
+        let mut entry = Entry::stored("game.gb", b"");
+        entry.size = MAX_ROM_SIZE as u32 + 1;
+        let zip = zip(&[entry], b"");
+        assert_eq!(
+            unzip_rom(&zip).unwrap_err(),
+            "game.gb is too big to be a Game Boy ROM."
+        );
+    }
+
+    #[test]
+    fn unsupported_method() {
+        let mut entry = Entry::stored("game.gb", b"");
+        entry.method = 14;
+        let zip = zip(&[entry], b"");
+        assert_eq!(
+            unzip_rom(&zip).unwrap_err(),
+            "game.gb is compressed with an unsupported method (14)."
+        );
+    }
+
+    #[test]
+    fn no_rom() {
+        let zip = zip(&[Entry::stored("readme.txt", b"Not a ROM")], b"");
+        assert_eq!(
+            unzip_rom(&zip).unwrap_err(),
+            "The zip file has no .gb or .gbc file in it."
+        );
+    }
+}
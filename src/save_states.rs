@@ -0,0 +1,167 @@
+use gaby_core::emulator::Emulator;
+use gaby_core::video::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tracing::warn;
+
+/// Size of the thumbnails of the states, half the size of the screen.
+pub const THUMBNAIL_WIDTH: u32 = SCREEN_WIDTH as u32 / 2;
+pub const THUMBNAIL_HEIGHT: u32 = SCREEN_HEIGHT as u32 / 2;
+
+/// Save state slots of a game, kept as files in a directory per ROM in the
+/// user's data directory, e.g. `~/.local/share/gaby/states/tetris/3.state`.
+/// Beside each state is a thumbnail of the screen when it was saved, e.g.
+/// `3.png`, to show what is in the slot.
+pub struct SaveStates {
+    directory: PathBuf,
+    slot: u8,
+}
+
+impl SaveStates {
+    pub const SLOTS: u8 = 10;
+
+    pub fn new(rom_path: &Path) -> Self {
+        let stem = rom_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let directory = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("gaby")
+            .join("states")
+            .join(stem);
+
+        Self { directory, slot: 0 }
+    }
+
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    pub fn select(&mut self, slot: u8) {
+        self.slot = slot % SaveStates::SLOTS;
+    }
+
+    pub fn select_next(&mut self) {
+        self.select(self.slot + 1);
+    }
+
+    fn path(&self) -> PathBuf {
+        self.directory.join(format!("{}.state", self.slot))
+    }
+
+    fn thumbnail_path(&self) -> PathBuf {
+        self.directory.join(format!("{}.png", self.slot))
+    }
+
+    /// Save the state of the emulator in the selected slot, with a thumbnail
+    /// of the screen. Failing to save the thumbnail is only reported, since
+    /// the state is saved anyway.
+    pub fn save(&self, machine: &Emulator) -> Result<(), String> {
+        let path = self.path();
+        fs::create_dir_all(&self.directory)
+            .and_then(|_| fs::write(&path, machine.save_state()))
+            .map_err(|error| format!("Can't save state to {}: {}", path.display(), error))?;
+
+        let thumbnail_path = self.thumbnail_path();
+        if let Err(error) = write_thumbnail(&thumbnail_path, machine.video.framebuffer()) {
+            warn!(
+                "Can't save thumbnail to {}: {}",
+                thumbnail_path.display(),
+                error
+            );
+        }
+        Ok(())
+    }
+
+    /// The thumbnail of the state in the selected slot, as
+    /// `THUMBNAIL_WIDTH` × `THUMBNAIL_HEIGHT` RGB24 pixels, or None if the
+    /// slot is empty or the state was saved without one.
+    pub fn thumbnail(&self) -> Option<Vec<u8>> {
+        // A thumbnail left behind by a state that can't be read any more
+        // would be misleading.
+        if !self.path().exists() {
+            return None;
+        }
+        read_thumbnail(&self.thumbnail_path()).ok()
+    }
+
+    /// Load the state in the selected slot into the emulator.
+    pub fn load(&self, machine: &mut Emulator) -> Result<(), String> {
+        let path = self.path();
+        let data = fs::read(&path)
+            .map_err(|error| format!("Can't read state {}: {}", path.display(), error))?;
+        machine.load_state(&data)
+    }
+
+    /// Describe the selected slot and when it was saved, for the window
+    /// title.
+    pub fn describe(&self) -> String {
+        let saved = fs::metadata(self.path()).and_then(|metadata| metadata.modified());
+        match saved {
+            Ok(time) => format!(
+                "Slot {} (saved {})",
+                self.slot,
+                format_age(SystemTime::now().duration_since(time).unwrap_or_default())
+            ),
+            Err(_) => format!("Slot {} (empty)", self.slot),
+        }
+    }
+}
+
+/// Write `framebuffer` to a PNG file at half the size, with each pixel the
+/// average of four on the screen.
+fn write_thumbnail(path: &Path, framebuffer: &[u32]) -> Result<(), Box<dyn Error>> {
+    let width = usize::from(SCREEN_WIDTH);
+    let mut pixels = Vec::with_capacity((3 * THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT) as usize);
+    for y in 0..THUMBNAIL_HEIGHT as usize {
+        for x in 0..THUMBNAIL_WIDTH as usize {
+            let mut sums = [0u32; 3];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let pixel = framebuffer[(2 * y + dy) * width + 2 * x + dx].to_be_bytes();
+                for (sum, &channel) in sums.iter_mut().zip(&pixel) {
+                    *sum += u32::from(channel);
+                }
+            }
+            pixels.extend(sums.iter().map(|sum| (sum / 4) as u8));
+        }
+    }
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)?;
+    Ok(())
+}
+
+fn read_thumbnail(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    let mut reader = decoder.read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+    if (info.width, info.height) != (THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT)
+        || info.color_type != png::ColorType::Rgb
+        || info.bit_depth != png::BitDepth::Eight
+    {
+        return Err("Not a thumbnail written by Gaby".into());
+    }
+    pixels.truncate(info.buffer_size());
+    Ok(pixels)
+}
+
+fn format_age(age: Duration) -> String {
+    let seconds = age.as_secs();
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} min ago", seconds / 60),
+        3600..=86399 => format!("{} h ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
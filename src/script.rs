@@ -0,0 +1,211 @@
+use gaby_core::cpu::CPU;
+use gaby_core::emulator::Emulator;
+use gaby_core::joypad::{Buttons, Joypad};
+use gaby_core::memory::Memory;
+use gaby_core::model::Model;
+use rhai::{CallFnOptions, Dynamic, Engine, FuncArgs, Scope, AST};
+use std::{cell::RefCell, fs, rc::Rc};
+
+/// What the functions of the script API work on. The registers and the frame
+/// number are copied in before each callback, since the script can't hold
+/// on to the CPU.
+struct Context {
+    /// The memory of the Game Boy while the script runs. It is swapped with
+    /// the memory of the emulator before and after, so in between it is a
+    /// spare that isn't used.
+    mem: Box<Memory>,
+    registers: Vec<(&'static str, u16)>,
+    frame: u64,
+    /// Text to draw over the screen, with its position in Game Boy pixels.
+    overlay: Vec<(i64, i64, String)>,
+    /// Buttons to press in the next frame.
+    pressed: Buttons,
+}
+
+/// A Rhai script that can read and write memory, read the CPU registers,
+/// draw text over the screen and press buttons. The script is run once when
+/// it's loaded, and then its `on_frame()` function is called after every
+/// frame and its `on_scanline(line)` function every time LY changes, if it
+/// has them.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    context: Rc<RefCell<Context>>,
+    has_on_frame: bool,
+    has_on_scanline: bool,
+    /// Buttons pressed by the script, but not by the player, in this frame.
+    added: Buttons,
+}
+
+impl Script {
+    pub fn load(path: &str, machine: &mut Emulator) -> Result<Self, String> {
+        let source =
+            fs::read_to_string(path).map_err(|error| format!("Can't read {}: {}", path, error))?;
+
+        let context = Rc::new(RefCell::new(Context {
+            mem: Box::new(Memory::with_seed(Model::DMG, 0)),
+            registers: Vec::new(),
+            frame: 0,
+            overlay: Vec::new(),
+            pressed: Buttons::empty(),
+        }));
+        let engine = Script::engine(&context);
+        let ast = engine
+            .compile(&source)
+            .map_err(|error| format!("Error in script {}: {}", path, error))?;
+
+        let has_function = |name: &str, arguments: usize| {
+            ast.iter_functions()
+                .any(|function| function.name == name && function.params.len() == arguments)
+        };
+        let mut script = Self {
+            has_on_frame: has_function("on_frame", 0),
+            has_on_scanline: has_function("on_scanline", 1),
+            engine,
+            ast,
+            scope: Scope::new(),
+            context,
+            added: Buttons::empty(),
+        };
+
+        script.update_context(machine);
+        script.swap_memory(machine);
+        let result = script
+            .engine
+            .run_ast_with_scope(&mut script.scope, &script.ast);
+        script.swap_memory(machine);
+        result.map_err(|error| format!("Error in script {}: {}", path, error))?;
+
+        Ok(script)
+    }
+
+    /// Make an engine with the functions of the script API.
+    fn engine(context: &Rc<RefCell<Context>>) -> Engine {
+        let mut engine = Engine::new();
+
+        let shared = context.clone();
+        engine.register_fn("read", move |address: i64| {
+            i64::from(shared.borrow().mem.read_byte(address as u16))
+        });
+        let shared = context.clone();
+        engine.register_fn("write", move |address: i64, value: i64| {
+            shared
+                .borrow_mut()
+                .mem
+                .write_byte(address as u16, value as u8);
+        });
+        let shared = context.clone();
+        engine.register_fn("register", move |name: &str| {
+            let context = shared.borrow();
+            let name = name.to_ascii_lowercase();
+            context
+                .registers
+                .iter()
+                .find(|(register, _)| *register == name)
+                .map_or(-1, |&(_, value)| i64::from(value))
+        });
+        let shared = context.clone();
+        engine.register_fn("frame", move || shared.borrow().frame as i64);
+        let shared = context.clone();
+        engine.register_fn("text", move |x: i64, y: i64, text: &str| {
+            shared.borrow_mut().overlay.push((x, y, text.to_string()));
+        });
+        let shared = context.clone();
+        engine.register_fn("press", move |button: &str| {
+            if let Some(button) = Script::button(button) {
+                shared.borrow_mut().pressed.insert(button);
+            }
+        });
+
+        engine
+    }
+
+    fn button(name: &str) -> Option<Buttons> {
+        let button = match name.to_ascii_lowercase().as_str() {
+            "right" => Buttons::RIGHT,
+            "left" => Buttons::LEFT,
+            "up" => Buttons::UP,
+            "down" => Buttons::DOWN,
+            "a" => Buttons::A,
+            "b" => Buttons::B,
+            "select" => Buttons::SELECT,
+            "start" => Buttons::START,
+            _ => return None,
+        };
+        Some(button)
+    }
+
+    /// Press the buttons the script asked for in this frame. Call this
+    /// before `run_frame`, and before a movie records the buttons.
+    pub fn press_buttons(&mut self, joypad: &mut Joypad) {
+        let pressed = std::mem::replace(&mut self.context.borrow_mut().pressed, Buttons::empty());
+        self.added = pressed - joypad.held;
+        joypad.held.insert(pressed);
+    }
+
+    /// Run a frame, calling the callbacks of the script.
+    pub fn run_frame(&mut self, machine: &mut Emulator) -> Result<(), String> {
+        self.context.borrow_mut().overlay.clear();
+
+        if self.has_on_scanline {
+            machine.run_frame_with(|machine, line| {
+                self.call(machine, "on_scanline", (i64::from(line),))
+            })?;
+        } else {
+            machine.run_frame()?;
+        }
+        machine.joypad.held.remove(self.added);
+
+        if self.has_on_frame {
+            self.call(machine, "on_frame", ())?;
+        }
+
+        Ok(())
+    }
+
+    fn call(
+        &mut self,
+        machine: &mut Emulator,
+        name: &str,
+        arguments: impl FuncArgs,
+    ) -> Result<(), String> {
+        self.update_context(machine);
+        // The top level of the script was run when it was loaded, and must
+        // not be run again.
+        let options = CallFnOptions::new().eval_ast(false);
+        self.swap_memory(machine);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut self.scope,
+            &self.ast,
+            name,
+            arguments,
+        );
+        self.swap_memory(machine);
+        result
+            .map(|_| ())
+            .map_err(|error| format!("Error in script function {}: {}", name, error))
+    }
+
+    /// Lend the memory of `machine` to the script, or give it back.
+    fn swap_memory(&self, machine: &mut Emulator) {
+        std::mem::swap(&mut self.context.borrow_mut().mem, &mut machine.mem);
+    }
+
+    fn update_context(&self, machine: &Emulator) {
+        let mut context = self.context.borrow_mut();
+        context.registers = CPU::REGISTER_NAMES
+            .iter()
+            .filter_map(|&name| Some((name, machine.cpu.register(name)?)))
+            .collect();
+        context.frame = machine.video.frame_count();
+    }
+
+    /// Text the script has drawn over the screen in the last frame, with its
+    /// position in Game Boy pixels.
+    #[cfg_attr(not(feature = "sdl2"), allow(dead_code))]
+    pub fn overlay(&self) -> Vec<(i64, i64, String)> {
+        self.context.borrow().overlay.clone()
+    }
+}
@@ -0,0 +1,93 @@
+use gaby_core::disassembler;
+use gaby_core::emulator::Emulator;
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Number of addresses listed in the report.
+const HOTTEST_ADDRESSES: usize = 20;
+/// Names of the interrupts, by their bit in IF.
+const INTERRUPT_NAMES: [&str; 5] = ["V-blank", "LCD status", "Timer", "Serial", "Joypad"];
+
+/// Whether Ctrl+C has been pressed, see `catch_interrupt`.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Number of times the audio device has run out of samples.
+static AUDIO_UNDERRUNS: AtomicU64 = AtomicU64::new(0);
+
+/// Make Ctrl+C stop the emulation like quitting does, so that battery saves
+/// are written and the statistics printed. A second Ctrl+C exits at once.
+pub fn catch_interrupt() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+    })
+}
+
+/// Whether Ctrl+C has been pressed and the emulation should stop.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Count an under-run of the audio device, called when it runs out of
+/// samples after having had some.
+#[cfg(any(feature = "sdl2", feature = "minifb"))]
+pub fn count_underrun() {
+    AUDIO_UNDERRUNS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Print statistics of the run of `machine`, which took `running_time`.
+pub fn print(machine: &Emulator, running_time: Duration) {
+    let cycles = machine.mem.cycles;
+    let emulated_time =
+        cycles as f64 / (Emulator::FRAMES_PER_SECOND * f64::from(Emulator::TICKS_PER_FRAME));
+    let speed = 100.0 * emulated_time / running_time.as_secs_f64().max(f64::EPSILON);
+    let interrupts: Vec<String> = INTERRUPT_NAMES
+        .iter()
+        .zip(machine.cpu.interrupts_serviced)
+        .map(|(name, count)| format!("{} {}", name, count))
+        .collect();
+
+    println!("Statistics:");
+    println!("  Frames:              {}", machine.video.frame_count());
+    println!("  Emulated time:       {:.1} s", emulated_time);
+    println!("  Running time:        {:.1} s", running_time.as_secs_f64());
+    println!("  Average speed:       {:.0}%", speed);
+    println!("  Interrupts serviced: {}", interrupts.join(", "));
+    println!(
+        "  Serial bytes:        {}",
+        machine.serial.bytes_transferred
+    );
+    println!(
+        "  Audio under-runs:    {}",
+        AUDIO_UNDERRUNS.load(Ordering::Relaxed)
+    );
+
+    let counts = match &machine.cpu.pc_counts {
+        Some(counts) => counts,
+        None => return,
+    };
+    let instructions: u64 = counts.iter().sum();
+    println!("  Instructions:        {}", instructions);
+    let mut hottest: Vec<(u16, u64)> = (0..=u16::MAX)
+        .zip(counts.iter().copied())
+        .filter(|&(_, count)| count > 0)
+        .collect();
+    hottest.sort_by_key(|&(address, count)| (std::cmp::Reverse(count), address));
+    hottest.truncate(HOTTEST_ADDRESSES);
+
+    // The disassembly is of the ROM bank mapped in now, which may not be
+    // the one that ran.
+    println!("Hottest addresses:");
+    let mem = &machine.mem;
+    for (address, count) in hottest {
+        println!(
+            "  {:04X}  {:>12}  {:>5.1}%  {}",
+            address,
+            count,
+            100.0 * count as f64 / instructions as f64,
+            disassembler::disassemble(mem, address).text
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use gaby_core::joypad::Buttons;
+use gaby_core::video;
+use sha1::{Digest, Sha1};
+use std::{
+    convert::{TryFrom, TryInto},
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+/// Page served to browsers that open the address of the server, which shows
+/// the stream and sends the keys pressed.
+const PAGE: &str = include_str!("stream.html");
+/// Appended to the key of a WebSocket handshake before hashing it.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// How long a new connection has to send its whole HTTP request, and to
+/// take the response.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Longest HTTP request taken from a new connection.
+const MAX_REQUEST_LENGTH: usize = 8 * 1024;
+/// Longest payload of a frame from a client. Clients only send the buttons,
+/// and control frames are at most this long.
+const MAX_PAYLOAD_LENGTH: usize = 125;
+/// Bytes read from a client before parsing the frames in them.
+const MAX_INCOMING_LENGTH: usize = 4 * 1024;
+
+/// Streams the screen over WebSocket to any number of clients, and takes the
+/// buttons they press.
+///
+/// Every frame is sent in a binary message with the RGBA pixels of the
+/// screen, row by row. A client that falls behind skips frames instead of
+/// slowing down the emulation. Clients send a message of one byte, the bits
+/// of the buttons they hold, whenever it changes; the Game Boy gets the
+/// buttons held on all clients.
+pub struct StreamServer {
+    listener: TcpListener,
+    /// Connections whose HTTP request hasn't all arrived yet.
+    pending: Vec<PendingConnection>,
+    clients: Vec<Client>,
+}
+
+impl StreamServer {
+    pub fn open(address: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        info!("Streaming on http://{}/", address);
+
+        Ok(Self {
+            listener,
+            pending: Vec::new(),
+            clients: Vec::new(),
+        })
+    }
+
+    /// Accept new clients and read their input. Returns the buttons held on
+    /// all clients.
+    pub fn poll(&mut self) -> Buttons {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => match stream.set_nonblocking(true) {
+                    Ok(()) => self.pending.push(PendingConnection {
+                        stream,
+                        address,
+                        request: Vec::new(),
+                        deadline: Instant::now() + HANDSHAKE_TIMEOUT,
+                    }),
+                    Err(error) => warn!("Stream connection from {} failed: {}", address, error),
+                },
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    error!("Can't accept stream connection: {}", error);
+                    break;
+                }
+            }
+        }
+
+        // The requests are read as they arrive, so a slow connection can't
+        // hold up the emulation.
+        let mut index = 0;
+        while index < self.pending.len() {
+            let result = match self.pending[index].advance() {
+                Ok(None) => {
+                    index += 1;
+                    continue;
+                }
+                Ok(Some(request)) => {
+                    let PendingConnection {
+                        stream, address, ..
+                    } = self.pending.swap_remove(index);
+                    Client::handshake(stream, &request)
+                        .map(|client| (address, client))
+                        .map_err(|error| (address, error))
+                }
+                Err(error) => Err((self.pending.swap_remove(index).address, error)),
+            };
+            match result {
+                Ok((address, Some(client))) => {
+                    info!("Stream client connected from {}", address);
+                    self.clients.push(client);
+                }
+                Ok((_, None)) => {}
+                Err((address, error)) => {
+                    warn!("Stream connection from {} failed: {}", address, error)
+                }
+            }
+        }
+
+        self.clients.retain_mut(|client| match client.read() {
+            Ok(true) => true,
+            Ok(false) => {
+                info!("Stream client disconnected");
+                false
+            }
+            Err(error) => {
+                info!("Stream client disconnected: {}", error);
+                false
+            }
+        });
+        self.clients
+            .iter()
+            .fold(Buttons::empty(), |buttons, client| buttons | client.buttons)
+    }
+
+    /// Send the screen to all clients that are ready for another frame.
+    pub fn send_frame(&mut self, framebuffer: &[u32]) {
+        if self.clients.is_empty() {
+            return;
+        }
+
+        let pixels: Vec<u8> = framebuffer
+            .iter()
+            .flat_map(|pixel| pixel.to_be_bytes())
+            .collect();
+        debug_assert_eq!(
+            pixels.len(),
+            4 * usize::from(video::SCREEN_WIDTH) * usize::from(video::SCREEN_HEIGHT)
+        );
+        let message = frame(Opcode::BINARY, &pixels);
+        self.clients.retain_mut(|client| {
+            if client.outgoing.is_empty() {
+                client.outgoing = message.clone();
+            }
+            match client.flush() {
+                Ok(()) => true,
+                Err(error) => {
+                    info!("Stream client disconnected: {}", error);
+                    false
+                }
+            }
+        });
+    }
+}
+
+struct Opcode;
+
+impl Opcode {
+    const CONTINUATION: u8 = 0x0;
+    const BINARY: u8 = 0x2;
+    const CLOSE: u8 = 0x8;
+    const PING: u8 = 0x9;
+    const PONG: u8 = 0xA;
+}
+
+/// A new connection, whose HTTP request is read without blocking.
+struct PendingConnection {
+    stream: TcpStream,
+    address: SocketAddr,
+    /// The request received so far.
+    request: Vec<u8>,
+    /// When the whole request must have arrived.
+    deadline: Instant,
+}
+
+impl PendingConnection {
+    /// Read what has arrived of the request. Returns the request once it
+    /// has all arrived, and an error if it is too long or too slow.
+    fn advance(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(length) => self.request.extend_from_slice(&buffer[..length]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            }
+            if self.request.windows(4).any(|end| end == b"\r\n\r\n") {
+                return Ok(Some(std::mem::take(&mut self.request)));
+            }
+            if self.request.len() > MAX_REQUEST_LENGTH {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "The HTTP request is too long",
+                ));
+            }
+        }
+
+        if Instant::now() > self.deadline {
+            Err(ErrorKind::TimedOut.into())
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// A browser connected over WebSocket.
+struct Client {
+    stream: TcpStream,
+    /// Bytes received, but not yet parsed as frames.
+    incoming: Vec<u8>,
+    /// Bytes that are still to be sent.
+    outgoing: Vec<u8>,
+    buttons: Buttons,
+}
+
+impl Client {
+    /// Answer the HTTP request of a new connection. A WebSocket handshake
+    /// gives a client, while other requests get the page.
+    fn handshake(mut stream: TcpStream, request: &[u8]) -> io::Result<Option<Self>> {
+        // The response fits in the send buffer of a new connection, so it is
+        // written at once, with a timeout in case it doesn't.
+        stream.set_nonblocking(false)?;
+        stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let request = String::from_utf8_lossy(request);
+
+        let key = request.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                Some(value.trim())
+            } else {
+                None
+            }
+        });
+        let key = match key {
+            Some(key) => key,
+            None => {
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    PAGE.len(),
+                    PAGE
+                )?;
+                return Ok(None);
+            }
+        };
+
+        let hash = Sha1::digest(format!("{}{}", key, WEBSOCKET_GUID).as_bytes());
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            base64(&hash)
+        )?;
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+
+        Ok(Some(Self {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            buttons: Buttons::empty(),
+        }))
+    }
+
+    /// Read and handle the messages that have arrived. Returns false when
+    /// the client has closed the connection.
+    fn read(&mut self) -> io::Result<bool> {
+        // The rest of a flood of bytes is left in the socket until the next
+        // poll, after the frames read so far have been parsed.
+        let mut buffer = [0; 1024];
+        while self.incoming.len() < MAX_INCOMING_LENGTH {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(false),
+                Ok(length) => self.incoming.extend_from_slice(&buffer[..length]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            }
+        }
+
+        while let Some((opcode, payload, length)) = parse_frame(&self.incoming)? {
+            self.incoming.drain(..length);
+            match opcode {
+                Opcode::BINARY | Opcode::CONTINUATION => {
+                    if let Some(&bits) = payload.last() {
+                        self.buttons = Buttons::from_bits_truncate(bits);
+                    }
+                }
+                Opcode::CLOSE => {
+                    let _ = self.stream.write_all(&frame(Opcode::CLOSE, &[]));
+                    return Ok(false);
+                }
+                Opcode::PING => self.outgoing.extend(frame(Opcode::PONG, &payload)),
+                _ => {}
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Send as much of the outgoing bytes as the connection takes without
+    /// blocking.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(length) => {
+                    self.outgoing.drain(..length);
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse a WebSocket frame from a client at the start of `data`. Returns the
+/// opcode, the unmasked payload and the length of the frame, or `None` if the
+/// whole frame hasn't arrived yet. A frame longer than any a client sends is
+/// an error, which closes the connection.
+fn parse_frame(data: &[u8]) -> io::Result<Option<(u8, Vec<u8>, usize)>> {
+    let (opcode, length_byte) = match data {
+        [first, second, ..] => (first & 0x0F, *second),
+        _ => return Ok(None),
+    };
+    let masked = length_byte & 0x80 != 0;
+    let (payload_length, mut position) = match length_byte & 0x7F {
+        126 => match data.get(2..4) {
+            Some(bytes) => (u64::from(u16::from_be_bytes([bytes[0], bytes[1]])), 4),
+            None => return Ok(None),
+        },
+        127 => match data.get(2..10) {
+            Some(bytes) => (u64::from_be_bytes(bytes.try_into().unwrap()), 10),
+            None => return Ok(None),
+        },
+        length => (u64::from(length), 2),
+    };
+    let payload_length = match usize::try_from(payload_length) {
+        Ok(length) if length <= MAX_PAYLOAD_LENGTH => length,
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Frame of {} bytes is too long", payload_length),
+            ))
+        }
+    };
+
+    let mask = if masked {
+        match data.get(position..position + 4) {
+            Some(mask) => {
+                position += 4;
+                mask.to_vec()
+            }
+            None => return Ok(None),
+        }
+    } else {
+        vec![0; 4]
+    };
+    let end = position
+        .checked_add(payload_length)
+        .ok_or(ErrorKind::InvalidData)?;
+    let payload = match data.get(position..end) {
+        Some(payload) => payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(byte, mask)| byte ^ mask)
+            .collect(),
+        None => return Ok(None),
+    };
+
+    Ok(Some((opcode, payload, end)))
+}
+
+/// Make a WebSocket frame with the whole message, unmasked as it goes from
+/// the server.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length @ 0..=125 => frame.push(length as u8),
+        length @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0, |bits, (index, &byte)| {
+            bits | u32::from(byte) << (16 - 8 * index)
+        });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(char::from(
+                    ALPHABET[(bits >> (18 - 6 * index) & 0x3F) as usize],
+                ));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,296 @@
+use crate::options::TestOptions;
+use gaby_core::emulator::Emulator;
+use gaby_core::memory::Memory;
+use gaby_core::model::Model;
+use gaby_core::serial::SerialPeer;
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Manifest read from `tests.toml` in the ROM directory by default.
+const DEFAULT_MANIFEST: &str = "tests.toml";
+
+/// Test ROMs to run, read from a TOML file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(rename = "test")]
+    tests: Vec<Test>,
+}
+
+/// A test ROM and the result it should give. Exactly one of `serial`,
+/// `memory` and `hash` must be given.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Test {
+    /// Defaults to the path of the ROM.
+    name: Option<String>,
+    /// Path to the ROM, relative to the ROM directory.
+    rom: String,
+    /// Frames to run the ROM for, or at most for the serial output and
+    /// memory signature.
+    frames: u64,
+    /// Model to emulate. Defaults to DMG.
+    model: Option<String>,
+    /// Seed for the random contents of memory at power-on. Defaults to 0.
+    #[serde(default)]
+    seed: u64,
+    /// Text the ROM should send over the serial port, like Blargg's tests.
+    serial: Option<String>,
+    /// Bytes the ROM should write to memory.
+    memory: Option<Signature>,
+    /// Hash of the last frame, as printed by `--hash-frame`.
+    hash: Option<String>,
+}
+
+impl Test {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.rom)
+    }
+}
+
+/// Bytes expected at an address, e.g. the result code and signature that
+/// Blargg's tests write to 0xA000.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Signature {
+    address: u16,
+    bytes: Vec<u8>,
+}
+
+/// How a test ended.
+enum Outcome {
+    Passed,
+    Failed(String),
+    /// The ROM isn't there.
+    Skipped,
+    Error(String),
+}
+
+/// Outcome of a test, with the frames it ran and how long it took.
+struct Report {
+    outcome: Outcome,
+    frames: u64,
+    time: Duration,
+}
+
+/// Collects the bytes sent over the serial port, answering every transfer
+/// with 0xFF like a disconnected port.
+struct SerialCapture(Arc<Mutex<Vec<u8>>>);
+
+impl SerialPeer for SerialCapture {
+    fn transfer(&mut self, outgoing: u8) -> u8 {
+        if let Ok(mut output) = self.0.lock() {
+            output.push(outgoing);
+        }
+        0xFF
+    }
+
+    fn poll(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// Run the tests in the manifest in parallel, headless, and print a table of
+/// the outcomes and the number of tests that passed. Returns an error unless
+/// all tests that were run passed.
+pub fn run(options: &TestOptions) -> Result<(), Box<dyn Error>> {
+    let dir = Path::new(&options.dir);
+    let manifest_path = match &options.manifest_path {
+        Some(path) => Path::new(path).to_path_buf(),
+        None => dir.join(DEFAULT_MANIFEST),
+    };
+    let text = fs::read_to_string(&manifest_path)
+        .map_err(|error| format!("Can't read {}: {}", manifest_path.display(), error))?;
+    let manifest: Manifest = toml::from_str(&text)
+        .map_err(|error| format!("Error in {}: {}", manifest_path.display(), error))?;
+    let tests = &manifest.tests;
+
+    let jobs = options
+        .jobs
+        .or_else(|| thread::available_parallelism().ok().map(usize::from))
+        .unwrap_or(1)
+        .min(tests.len().max(1));
+    // The workers take the next test from the list until it's empty.
+    let next = AtomicUsize::new(0);
+    let mut reports: Vec<(usize, Report)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut reports = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        match tests.get(index) {
+                            Some(test) => reports.push((index, run_test(test, dir))),
+                            None => return reports,
+                        }
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("A test runner thread panicked."))
+            .collect()
+    });
+    reports.sort_by_key(|&(index, _)| index);
+
+    let width = tests
+        .iter()
+        .map(|test| test.name().len())
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{:<7}  {:>6}  {:>7}  {:<width$}  Details",
+        "Result",
+        "Frames",
+        "Time",
+        "Test",
+        width = width
+    );
+    let (mut passed, mut skipped) = (0, 0);
+    for (index, report) in &reports {
+        let (result, details) = match &report.outcome {
+            Outcome::Passed => {
+                passed += 1;
+                ("Passed", "")
+            }
+            Outcome::Failed(reason) => ("Failed", reason.as_str()),
+            Outcome::Skipped => {
+                skipped += 1;
+                ("Skipped", "The ROM is missing.")
+            }
+            Outcome::Error(error) => ("Error", error.as_str()),
+        };
+        let line = format!(
+            "{:<7}  {:>6}  {:>6.1}s  {:<width$}  {}",
+            result,
+            report.frames,
+            report.time.as_secs_f64(),
+            tests[*index].name(),
+            details,
+            width = width
+        );
+        println!("{}", line.trim_end());
+    }
+
+    let run = tests.len() - skipped;
+    let score = if run == 0 {
+        100.0
+    } else {
+        100.0 * passed as f64 / run as f64
+    };
+    println!(
+        "{} of {} tests passed ({:.1}%), {} skipped.",
+        passed, run, score, skipped
+    );
+    if passed == run {
+        Ok(())
+    } else {
+        Err(format!("{} tests did not pass.", run - passed).into())
+    }
+}
+
+fn run_test(test: &Test, dir: &Path) -> Report {
+    let start = Instant::now();
+    let mut frames = 0;
+    let outcome = match run_rom(test, dir, &mut frames) {
+        Ok(outcome) => outcome,
+        Err(error) => Outcome::Error(error.to_string()),
+    };
+    Report {
+        outcome,
+        frames,
+        time: start.elapsed(),
+    }
+}
+
+/// Run the ROM of `test` until the expected result is there, or for the
+/// number of frames of the test, counting the frames run in `frames`.
+fn run_rom(test: &Test, dir: &Path, frames: &mut u64) -> Result<Outcome, Box<dyn Error>> {
+    let expectations = [
+        test.serial.is_some(),
+        test.memory.is_some(),
+        test.hash.is_some(),
+    ];
+    if expectations.iter().filter(|&&expected| expected).count() != 1 {
+        return Err("Exactly one of serial, memory and hash must be given.".into());
+    }
+
+    let rom_path = dir.join(&test.rom);
+    if !rom_path.exists() {
+        return Ok(Outcome::Skipped);
+    }
+    let rom = fs::read(&rom_path)?;
+    let model = match &test.model {
+        Some(model) => model.parse()?,
+        None => Model::DMG,
+    };
+    let mut mem = Memory::with_seed(model, test.seed);
+    mem.load_rom(&rom)?;
+    let mut machine = Emulator::new(mem, model);
+    let serial = Arc::new(Mutex::new(Vec::new()));
+    machine.serial.peer = Some(Box::new(SerialCapture(serial.clone())));
+
+    // Run tick by tick to stop right at the end of each frame, like
+    // `--hash-frame`, and check the serial output and memory after every
+    // frame.
+    while *frames < test.frames {
+        while machine.video.frame_count() <= *frames {
+            machine.tick()?;
+        }
+        machine.audio.take_samples();
+        *frames += 1;
+        if test.hash.is_none() && check(test, &machine, &serial.lock().unwrap()).is_ok() {
+            return Ok(Outcome::Passed);
+        }
+    }
+
+    if let Some(expected) = &test.hash {
+        let hash = format!("{:08X}", machine.video.frame_hash());
+        if !expected.eq_ignore_ascii_case(&hash) {
+            return Ok(Outcome::Failed(format!(
+                "The hash is {} instead of {}.",
+                hash, expected
+            )));
+        }
+        return Ok(Outcome::Passed);
+    }
+    let outcome = match check(test, &machine, &serial.lock().unwrap()) {
+        Ok(()) => Outcome::Passed,
+        Err(reason) => Outcome::Failed(reason),
+    };
+    Ok(outcome)
+}
+
+/// Check the serial output and memory against the expected result.
+fn check(test: &Test, machine: &Emulator, serial: &[u8]) -> Result<(), String> {
+    if let Some(expected) = &test.serial {
+        let output = String::from_utf8_lossy(serial);
+        if !output.contains(expected.as_str()) {
+            return Err(format!("The serial output is {:?}.", output.trim()));
+        }
+    }
+    if let Some(signature) = &test.memory {
+        let mem = &machine.mem;
+        let bytes: Vec<u8> = (0..signature.bytes.len() as u16)
+            .map(|offset| mem.read_byte(signature.address.wrapping_add(offset)))
+            .collect();
+        if bytes != signature.bytes {
+            return Err(format!(
+                "The memory at {:#06X} is {:02X?}.",
+                signature.address, bytes
+            ));
+        }
+    }
+    Ok(())
+}
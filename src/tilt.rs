@@ -0,0 +1,116 @@
+use crate::config::TiltConfig;
+use sdl2::{
+    controller::{Axis, GameController},
+    event::Event,
+    GameControllerSubsystem, Sdl,
+};
+use tracing::{info, warn};
+
+/// Direction a key tilts the Game Boy in.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TiltDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// Tilt of the Game Boy for games with an accelerometer, from the left
+/// analog stick of the connected gamepads and the tilt keys, which are added
+/// together.
+pub struct TiltInput {
+    config: TiltConfig,
+    subsystem: Option<GameControllerSubsystem>,
+    /// Gamepads are closed when dropped, so the open ones are kept.
+    controllers: Vec<GameController>,
+    /// Position of the stick, from -1 to 1 on each axis.
+    stick: (f32, f32),
+    /// Whether the keys for left, right, up and down are held.
+    keys: [bool; 4],
+}
+
+impl TiltInput {
+    pub fn new(sdl_context: &Sdl, config: &TiltConfig) -> Self {
+        // The keys still work without gamepads.
+        let subsystem = sdl_context
+            .game_controller()
+            .map_err(|error| warn!("Can't initialize SDL gamepads: {}", error))
+            .ok();
+
+        Self {
+            config: *config,
+            subsystem,
+            controllers: Vec::new(),
+            stick: (0.0, 0.0),
+            keys: [false; 4],
+        }
+    }
+
+    /// Open gamepads when they are connected, and follow their sticks.
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                let subsystem = match &self.subsystem {
+                    Some(subsystem) => subsystem,
+                    None => return,
+                };
+                match subsystem.open(which) {
+                    Ok(controller) => {
+                        info!("Gamepad connected: {}", controller.name());
+                        self.controllers.push(controller);
+                    }
+                    Err(error) => warn!("Can't open gamepad: {}", error),
+                }
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.controllers
+                    .retain(|controller| controller.instance_id() != which);
+                self.stick = (0.0, 0.0);
+            }
+            Event::ControllerAxisMotion { axis, value, .. } => {
+                let position = f32::from(value) / f32::from(i16::MAX);
+                match axis {
+                    Axis::LeftX => self.stick.0 = position,
+                    Axis::LeftY => self.stick.1 = position,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_key(&mut self, direction: TiltDirection, held: bool) {
+        self.keys[direction as usize] = held;
+    }
+
+    pub fn release_keys(&mut self) {
+        self.keys = [false; 4];
+    }
+
+    /// Tilt to the right and towards the player in g, for
+    /// `Emulator::set_tilt`.
+    pub fn tilt(&self) -> (f32, f32) {
+        let key = |direction: TiltDirection| f32::from(u8::from(self.keys[direction as usize]));
+        let x = self.shape(self.stick.0) + key(TiltDirection::Right) - key(TiltDirection::Left);
+        let y = self.shape(self.stick.1) + key(TiltDirection::Down) - key(TiltDirection::Up);
+
+        (
+            x.clamp(-1.0, 1.0) * self.config.sensitivity,
+            y.clamp(-1.0, 1.0) * self.config.sensitivity,
+        )
+    }
+
+    /// Apply the dead zone and the curve to a position of the stick.
+    fn shape(&self, position: f32) -> f32 {
+        let dead_zone = self.config.dead_zone;
+        let magnitude = (position.abs() - dead_zone) / (1.0 - dead_zone);
+        if magnitude <= 0.0 {
+            0.0
+        } else {
+            magnitude
+                .min(1.0)
+                .powf(self.config.curve)
+                .copysign(position)
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use sdl2::{rect::Rect, video::Window, VideoSubsystem};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+use tracing::warn;
+
+/// Size and position of the main window, kept in `gaby/window.toml` in the
+/// user's configuration directory, so that the window opens where it was
+/// when Gaby last exited.
+#[derive(Deserialize, Serialize)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl WindowGeometry {
+    /// The saved geometry, if there is one. A file that can't be read is
+    /// only reported, and the window gets the default size.
+    pub fn load() -> Option<Self> {
+        let path = WindowGeometry::path()?;
+        let text = fs::read_to_string(&path).ok()?;
+        match toml::from_str(&text) {
+            Ok(geometry) => Some(geometry),
+            Err(error) => {
+                warn!(
+                    "Can't read the window size from {}: {}",
+                    path.display(),
+                    error
+                );
+                None
+            }
+        }
+    }
+
+    /// The geometry of `window` now.
+    pub fn of(window: &Window) -> Self {
+        let (x, y) = window.position();
+        let (width, height) = window.size();
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Save the geometry for the next launch. Failing to save it is only
+    /// reported, like for the list of recent files.
+    pub fn save(&self) {
+        let path = match WindowGeometry::path() {
+            Some(path) => path,
+            None => return,
+        };
+        let result = toml::to_string(self)
+            .map_err(|error| error.to_string())
+            .and_then(|text| {
+                path.parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|()| fs::write(&path, text))
+                    .map_err(|error| error.to_string())
+            });
+        if let Err(error) = result {
+            warn!(
+                "Can't save the window size to {}: {}",
+                path.display(),
+                error
+            );
+        }
+    }
+
+    /// Whether the window would be at least partly on one of the displays,
+    /// which may not be the case if they have changed since it was saved.
+    pub fn is_on_screen(&self, video_subsystem: &VideoSubsystem) -> bool {
+        let rect = Rect::new(self.x, self.y, self.width, self.height);
+        let displays = video_subsystem.num_video_displays().unwrap_or(0);
+        (0..displays)
+            .filter_map(|display| video_subsystem.display_bounds(display).ok())
+            .any(|bounds| bounds.has_intersection(rect))
+    }
+
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("gaby").join("window.toml"))
+    }
+}
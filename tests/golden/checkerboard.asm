@@ -0,0 +1,74 @@
+; Source of checkerboard.gb, a golden-frame test ROM made for Gaby and
+; distributed with it. It fills the screen with a checkerboard of 8×8 pixel
+; white and black tiles, starting with white in the top left corner, and then
+; loops forever, so every frame after the first few looks the same.
+;
+; Build with RGBDS:
+;
+;   rgbasm -o checkerboard.o checkerboard.asm
+;   rgblink -o checkerboard.gb checkerboard.o
+;   rgbfix -v -p 0 -t CHECKERBOARD checkerboard.gb
+
+SECTION "Entry", ROM0[$0100]
+    nop
+    jp Start
+
+    ds $0150 - @, 0             ; Header, filled in by rgbfix.
+
+SECTION "Main", ROM0[$0150]
+Start:
+    di
+    ld sp, $FFFE
+
+    ; VRAM can only be written freely with the LCD off, which must only be
+    ; done in VBlank.
+.waitVBlank
+    ldh a, [$FF44]              ; LY
+    cp 144
+    jr c, .waitVBlank
+    xor a
+    ldh [$FF40], a              ; LCDC: LCD off
+
+    ; Tile 0 has color 0 in every pixel, and tile 1 color 3.
+    ld hl, $8000
+    ld b, 16
+.tile0
+    ld [hl+], a
+    dec b
+    jr nz, .tile0
+    ld a, $FF
+    ld b, 16
+.tile1
+    ld [hl+], a
+    dec b
+    jr nz, .tile1
+
+    ; The 32×32 background map at $9800 gets tile (column ^ row) & 1.
+    ld hl, $9800
+    ld c, 0                     ; Row.
+.row
+    ld b, 0                     ; Column.
+.column
+    ld a, b
+    xor c
+    and 1
+    ld [hl+], a
+    inc b
+    ld a, b
+    cp 32
+    jr nz, .column
+    inc c
+    ld a, c
+    cp 32
+    jr nz, .row
+
+    ld a, %11100100             ; BGP: colors 0 to 3 are white to black.
+    ldh [$FF47], a
+    xor a
+    ldh [$FF42], a              ; SCY
+    ldh [$FF43], a              ; SCX
+    ld a, %10010001             ; LCDC: LCD and background on, tiles at $8000.
+    ldh [$FF40], a
+
+.loop
+    jr .loop
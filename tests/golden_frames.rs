@@ -0,0 +1,20 @@
+//! Runs the golden-frame tests in golden.toml with the gaby program, so that
+//! `cargo test` fails when a frame no longer matches its hash or image.
+
+use std::process::Command;
+
+#[test]
+fn golden_frames() {
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden.toml");
+    let output = Command::new(env!("CARGO_BIN_EXE_gaby"))
+        .args(["--golden", manifest])
+        .output()
+        .expect("Can't run gaby");
+
+    assert!(
+        output.status.success(),
+        "Golden-frame tests failed:\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}